@@ -0,0 +1,55 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use fo_data::{
+    frm::{FrameHeader, LazyFrm},
+    testing::{gradient_palette, FrmFixture},
+    ConvertOptions, Converter, DataType, MemoryRetriever, Metrics,
+};
+
+const FRAMES: u16 = 8;
+
+/// Critter-sized FRM of six directions, pixels are a diagonal pattern.
+fn critter_frm() -> Vec<u8> {
+    let frame = FrameHeader {
+        width: 80,
+        height: 120,
+        offset_x: 0,
+        offset_y: 0,
+    };
+    let mut fixture = FrmFixture::new(10);
+    for dir in 0..6 {
+        fixture = fixture.direction((0, 0));
+        for n in 0..FRAMES {
+            fixture = fixture.frame_with(frame, |x, y| ((x + y + dir + n) % 200) as u8 + 1);
+        }
+    }
+    fixture.build()
+}
+
+fn bench_convert(c: &mut Criterion) {
+    let data = critter_frm();
+    let metrics = Metrics::new();
+    let retriever = MemoryRetriever::new().with_file("art/critters/bench.frm", data.clone());
+    let palette = gradient_palette();
+    let converter = Converter::new(&retriever, &palette).with_metrics(&metrics);
+    let rgba = ConvertOptions::builder().output(DataType::Rgba).build();
+
+    let mut group = c.benchmark_group("convert");
+    group.bench_function("frm_parse", |b| {
+        b.iter(|| LazyFrm::parse(black_box(data.clone())).unwrap())
+    });
+    group.bench_function("frm_decode", |b| {
+        b.iter(|| {
+            converter
+                .get_with(black_box("art/critters/bench.frm"), &rgba)
+                .unwrap()
+        })
+    });
+    group.bench_function("png_encode", |b| {
+        b.iter(|| converter.get_png(black_box("art/critters/bench.frm")).unwrap())
+    });
+    group.finish();
+    println!("{:#?}", metrics);
+}
+
+criterion_group!(benches, bench_convert);
+criterion_main!(benches);
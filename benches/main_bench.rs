@@ -1,12 +1,14 @@
 use std::time::Duration;
 
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use fo_data::{crawler::gather_paths, datafiles::parse_datafile};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use fo_data::{crawler::gather_paths, datafiles::parse_datafile, FoRegistry, Metrics};
+
+const CLIENT_FOLDER: &str = "../../../CL4RP";
+/// Registry cache written by [`FoRegistry::init`] into the working directory.
+const REGISTRY_CACHE: &str = "fo_data.bin";
 
 fn bench_gather_paths(c: &mut Criterion) {
-    let path = std::path::Path::new("../../../CL4RP")
-        .canonicalize()
-        .unwrap();
+    let path = std::path::Path::new(CLIENT_FOLDER).canonicalize().unwrap();
     let archives = parse_datafile(&path).expect("Parse datafiles");
 
     let mut group = c.benchmark_group("gather_paths");
@@ -29,5 +31,47 @@ fn bench_gather_paths(c: &mut Criterion) {
     group.finish();
 }
 
-criterion_group!(benches, bench_gather_paths);
+fn bench_registry(c: &mut Criterion) {
+    let mut group = c.benchmark_group("registry");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(20));
+    group.bench_function("init", |b| {
+        b.iter_batched(
+            || {
+                let _ = std::fs::remove_file(REGISTRY_CACHE);
+            },
+            |_| FoRegistry::init(black_box(CLIENT_FOLDER)).unwrap(),
+            BatchSize::PerIteration,
+        )
+    });
+    FoRegistry::init(CLIENT_FOLDER).unwrap();
+    group.bench_function("cache_load", |b| {
+        b.iter(|| FoRegistry::init(black_box(CLIENT_FOLDER)).unwrap())
+    });
+    group.finish();
+}
+
+fn bench_read_files(c: &mut Criterion) {
+    let metrics = Metrics::new();
+    let retriever = FoRegistry::init(CLIENT_FOLDER)
+        .unwrap()
+        .into_retriever()
+        .with_metrics(&metrics);
+    let registry = retriever.registry().clone();
+    let files: Vec<_> = registry.files().step_by(97).map(|(_, info)| info).collect();
+
+    let mut group = c.benchmark_group("read_files");
+    group.sample_size(10);
+    group.bench_function("every_97th", |b| {
+        b.iter(|| {
+            for info in &files {
+                black_box(retriever.file_by_info(info).unwrap());
+            }
+        })
+    });
+    group.finish();
+    println!("{:#?}", metrics);
+}
+
+criterion_group!(benches, bench_gather_paths, bench_registry, bench_read_files);
 criterion_main!(benches);
@@ -0,0 +1,13 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = fo_data::frm::frm(data);
+    if let Ok(frm) = fo_data::frm::LazyFrm::parse(data.to_vec()) {
+        for (dir, direction) in frm.header().directions.iter().enumerate() {
+            for frame in 0..direction.frames.len() {
+                let _ = frm.get_frame(dir, frame);
+            }
+        }
+    }
+});
@@ -0,0 +1,8 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((_, palette)) = fo_data::palette::palette(data) {
+        let _ = palette.rgba_lut().expand_image(4, 4, data);
+    }
+});
@@ -0,0 +1,16 @@
+use fo_data::{retriever::cas::ingest, FoRegistry};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let client_root = args.next().unwrap_or_else(|| "../../../CL4RP".into());
+    let cas_root = args.next().unwrap_or_else(|| "../../../test_assets/cas".into());
+
+    let retriever = FoRegistry::init(&client_root)
+        .expect("Init registry")
+        .into_retriever();
+    let stats = ingest(&retriever, &cas_root).expect("Ingest files");
+    println!(
+        "Ingested {} files into {:?}: {} new objects, {} new bytes",
+        stats.files, cas_root, stats.new_objects, stats.new_bytes
+    );
+}
@@ -0,0 +1,36 @@
+use fo_data::{
+    quota::{check_quotas, parse_quotas},
+    FoRegistry,
+};
+
+const OFFENDERS: usize = 10;
+
+fn megabytes(bytes: u64) -> f64 {
+    bytes as f64 / (1 << 20) as f64
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let client_root = args.next().unwrap_or_else(|| "../../../CL4RP".into());
+    let quotas_path = args.next().unwrap_or_else(|| "quotas.txt".into());
+
+    let text = std::fs::read_to_string(&quotas_path).expect("Read quotas");
+    let quotas = parse_quotas(&text).expect("Parse quotas");
+    let registry = FoRegistry::init(&client_root).expect("Init registry");
+    let violations = check_quotas(&registry, &quotas, OFFENDERS);
+    for violation in &violations {
+        println!(
+            "{:?} takes {:.2} MB, budget is {:.2} MB",
+            violation.folder,
+            megabytes(violation.used),
+            megabytes(violation.limit)
+        );
+        for (path, size) in &violation.offenders {
+            println!("    {:>10.2} MB  {}", megabytes(*size), path);
+        }
+    }
+    if !violations.is_empty() {
+        std::process::exit(1);
+    }
+    println!("All {} budgets are met", quotas.len());
+}
@@ -0,0 +1,52 @@
+use std::io::Write;
+
+use fo_data::{ConvertOptions, DiskCache, FileType, FoData};
+
+const BAR_WIDTH: usize = 40;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let client_root = args.next().unwrap_or_else(|| "../../../CL4RP".into());
+    let palette_path = args
+        .next()
+        .unwrap_or_else(|| "../../../test_assets/COLOR.PAL".into());
+    let cache_root = args
+        .next()
+        .unwrap_or_else(|| "../../../test_assets/png_cache".into());
+
+    let fo_data = FoData::init(&client_root, &palette_path).expect("Init data");
+    let cache = DiskCache::open(&cache_root).expect("Open disk cache");
+    let paths: Vec<&str> = fo_data
+        .retriever
+        .registry()
+        .files()
+        .filter(|(_, info)| matches!(info.file_type(), FileType::Frm | FileType::FoFrm))
+        .map(|(path, _)| path)
+        .collect();
+
+    let converter = fo_data.converter();
+    let stats = cache.prime(&converter, &paths, &ConvertOptions::default(), |done, total| {
+        let filled = done * BAR_WIDTH / total;
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(
+            stderr,
+            "\r[{}{}] {}/{}",
+            "#".repeat(filled),
+            " ".repeat(BAR_WIDTH - filled),
+            done,
+            total
+        );
+        let _ = stderr.flush();
+    });
+    eprintln!();
+    for path in &stats.failed {
+        println!("Failed to convert {:?}", path);
+    }
+    println!(
+        "Primed {:?}: {} converted, {} already fresh, {} failed",
+        cache_root,
+        stats.converted,
+        stats.fresh,
+        stats.failed.len()
+    );
+}
@@ -0,0 +1,135 @@
+//! Alpha blending in linear light for compositing converted sprites.
+//!
+//! Pixel values are gamma encoded, blending them directly makes semi-transparent edges
+//! darker than they should be. [`Blender`] decodes colors with a power law of the given
+//! gamma, blends and encodes the result back. Alpha is always linear.
+
+/// Close to sRGB, which the game palette and png art are authored in.
+pub const DEFAULT_GAMMA: f32 = 2.2;
+
+#[derive(Debug, Clone)]
+pub struct Blender {
+    gamma: f32,
+    to_linear: [f32; 256],
+}
+
+impl Default for Blender {
+    fn default() -> Self {
+        Self::new(DEFAULT_GAMMA)
+    }
+}
+
+impl Blender {
+    /// Gamma of 1 blends encoded values as they are.
+    pub fn new(gamma: f32) -> Self {
+        let mut to_linear = [0.0; 256];
+        for (value, linear) in to_linear.iter_mut().enumerate() {
+            *linear = (value as f32 / 255.0).powf(gamma);
+        }
+        Self { gamma, to_linear }
+    }
+
+    pub fn gamma(&self) -> f32 {
+        self.gamma
+    }
+
+    pub fn to_linear(&self, value: u8) -> f32 {
+        self.to_linear[value as usize]
+    }
+
+    pub fn to_encoded(&self, linear: f32) -> u8 {
+        (linear.clamp(0.0, 1.0).powf(self.gamma.recip()) * 255.0).round() as u8
+    }
+
+    /// Porter-Duff "over" of straight alpha pixels.
+    pub fn over(&self, target: &mut image::Rgba<u8>, source: &image::Rgba<u8>) {
+        match source[3] {
+            0 => return,
+            255 => {
+                *target = *source;
+                return;
+            }
+            _ => {}
+        }
+        let source_alpha = source[3] as f32 / 255.0;
+        let target_alpha = target[3] as f32 / 255.0 * (1.0 - source_alpha);
+        let alpha = source_alpha + target_alpha;
+        for channel in 0..3 {
+            let color = self.to_linear(source[channel]) * source_alpha
+                + self.to_linear(target[channel]) * target_alpha;
+            target[channel] = self.to_encoded(color / alpha);
+        }
+        target[3] = (alpha * 255.0).round() as u8;
+    }
+
+    /// Draws `image` over `canvas` with its top left corner at `(x, y)`,
+    /// parts outside of the canvas are skipped.
+    pub fn overlay(&self, canvas: &mut image::RgbaImage, image: &image::RgbaImage, x: i64, y: i64) {
+        let (width, height) = (canvas.width() as i64, canvas.height() as i64);
+        for (image_x, image_y, pixel) in image.enumerate_pixels() {
+            let canvas_x = x.saturating_add(image_x as i64);
+            let canvas_y = y.saturating_add(image_y as i64);
+            if canvas_x < 0 || canvas_y < 0 || canvas_x >= width || canvas_y >= height {
+                continue;
+            }
+            self.over(canvas.get_pixel_mut(canvas_x as u32, canvas_y as u32), pixel);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blend_in_linear_light() {
+        let naive = Blender::new(1.0);
+        let mut target = image::Rgba([0, 0, 0, 0]);
+        naive.over(&mut target, &image::Rgba([200, 100, 50, 128]));
+        assert_eq!(target, image::Rgba([200, 100, 50, 128]));
+        let mut target = image::Rgba([0, 0, 0, 255]);
+        naive.over(&mut target, &image::Rgba([255, 255, 255, 51]));
+        assert_eq!(target, image::Rgba([51, 51, 51, 255]));
+
+        let blender = Blender::default();
+        for value in [0, 1, 128, 254, 255] {
+            assert_eq!(blender.to_encoded(blender.to_linear(value)), value);
+        }
+        let mut target = image::Rgba([0, 0, 0, 0]);
+        blender.over(&mut target, &image::Rgba([200, 100, 50, 128]));
+        assert_eq!(target, image::Rgba([200, 100, 50, 128]));
+        // Half covered white over black is brighter than the naive mid-gray.
+        let mut target = image::Rgba([0, 0, 0, 255]);
+        blender.over(&mut target, &image::Rgba([255, 255, 255, 128]));
+        assert_eq!(target, image::Rgba([186, 186, 186, 255]));
+
+        let mut canvas = image::RgbaImage::from_pixel(2, 2, image::Rgba([0, 0, 0, 255]));
+        let white = image::RgbaImage::from_pixel(2, 2, image::Rgba([255, 255, 255, 255]));
+        blender.overlay(&mut canvas, &white, 1, -1);
+        assert_eq!(canvas.get_pixel(1, 0).0, [255, 255, 255, 255]);
+        assert_eq!(canvas.get_pixel(0, 0).0, [0, 0, 0, 255]);
+        assert_eq!(canvas.get_pixel(1, 1).0, [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn out_of_range_input() {
+        let blender = Blender::default();
+        assert_eq!(blender.to_encoded(-1.0), 0);
+        assert_eq!(blender.to_encoded(2.0), 255);
+
+        let mut target = image::Rgba([10, 20, 30, 40]);
+        blender.over(&mut target, &image::Rgba([255, 255, 255, 0]));
+        assert_eq!(target, image::Rgba([10, 20, 30, 40]));
+
+        let black = image::RgbaImage::from_pixel(2, 2, image::Rgba([0, 0, 0, 255]));
+        let white = image::RgbaImage::from_pixel(2, 2, image::Rgba([255, 255, 255, 255]));
+        let mut canvas = black.clone();
+        for &(x, y) in &[(2, 0), (0, 2), (-2, 0), (0, -2), (i64::MAX, i64::MIN)] {
+            blender.overlay(&mut canvas, &white, x, y);
+        }
+        blender.overlay(&mut canvas, &image::RgbaImage::new(0, 0), 0, 0);
+        assert_eq!(canvas, black);
+        let mut empty = image::RgbaImage::new(0, 0);
+        blender.overlay(&mut empty, &white, 0, 0);
+    }
+}
@@ -0,0 +1,171 @@
+//! One memory limit shared by all caches of the crate and of the embedding application.
+
+use std::sync::{Arc, Weak};
+
+use parking_lot::Mutex;
+
+/// Cache that can give memory back to a [`MemoryBudget`].
+pub trait Evict: Send + Sync {
+    fn name(&self) -> &str;
+    /// Bytes currently held.
+    fn usage(&self) -> u64;
+    /// Drops entries worth at least `bytes` if it can, returns bytes actually freed.
+    fn evict(&self, bytes: u64) -> u64;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BudgetUsage {
+    pub limit: u64,
+    pub used: u64,
+    /// Name and usage of every registered cache.
+    pub caches: Vec<(String, u64)>,
+}
+
+/// Cheap to clone, clones share the limit and registered caches.
+#[derive(Clone)]
+pub struct MemoryBudget {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    limit: u64,
+    caches: Mutex<Vec<Weak<dyn Evict>>>,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                limit,
+                caches: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.inner.limit
+    }
+
+    /// Cache is unregistered automatically when dropped.
+    pub fn register(&self, cache: &Arc<dyn Evict>) {
+        self.inner.caches.lock().push(Arc::downgrade(cache));
+    }
+
+    fn live_caches(&self) -> Vec<Arc<dyn Evict>> {
+        let mut caches = self.inner.caches.lock();
+        caches.retain(|cache| cache.strong_count() > 0);
+        caches.iter().filter_map(Weak::upgrade).collect()
+    }
+
+    pub fn usage(&self) -> BudgetUsage {
+        let caches: Vec<_> = self
+            .live_caches()
+            .iter()
+            .map(|cache| (cache.name().to_owned(), cache.usage()))
+            .collect();
+        BudgetUsage {
+            limit: self.inner.limit,
+            used: caches.iter().map(|(_, usage)| usage).sum(),
+            caches,
+        }
+    }
+
+    /// Evicts from the biggest caches first until `incoming` bytes fit into the limit.
+    ///
+    /// Must be called without holding a lock of any registered cache.
+    /// Returns `false` if `incoming` still doesn't fit.
+    pub fn make_room(&self, incoming: u64) -> bool {
+        let mut caches: Vec<_> = self
+            .live_caches()
+            .into_iter()
+            .map(|cache| (cache.usage(), cache))
+            .collect();
+        let used: u64 = caches.iter().map(|(usage, _)| usage).sum();
+        let mut excess = (used + incoming).saturating_sub(self.inner.limit);
+        caches.sort_by_key(|(usage, _)| std::cmp::Reverse(*usage));
+        for (_, cache) in caches {
+            if excess == 0 {
+                break;
+            }
+            excess = excess.saturating_sub(cache.evict(excess));
+        }
+        excess == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestCache {
+        name: &'static str,
+        entries: Mutex<Vec<u64>>,
+    }
+
+    impl Evict for TestCache {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn usage(&self) -> u64 {
+            self.entries.lock().iter().sum()
+        }
+
+        fn evict(&self, bytes: u64) -> u64 {
+            let mut entries = self.entries.lock();
+            let mut freed = 0;
+            while freed < bytes {
+                match entries.pop() {
+                    Some(size) => freed += size,
+                    None => break,
+                }
+            }
+            freed
+        }
+    }
+
+    fn cache(name: &'static str, entries: Vec<u64>) -> Arc<dyn Evict> {
+        Arc::new(TestCache {
+            name,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    #[test]
+    fn global_eviction() {
+        let budget = MemoryBudget::new(100);
+        let small = cache("small", vec![10, 10]);
+        let big = cache("big", vec![30, 30, 10]);
+        budget.register(&small);
+        budget.register(&big);
+        assert_eq!(budget.usage().used, 90);
+
+        assert!(budget.make_room(10));
+        assert_eq!(budget.usage().used, 90);
+        assert!(budget.make_room(30));
+        assert_eq!(big.usage(), 30);
+        assert_eq!(small.usage(), 20);
+
+        assert!(!budget.make_room(200));
+        assert_eq!(budget.usage().used, 0);
+
+        drop(big);
+        assert_eq!(budget.usage().caches, [("small".to_owned(), 0)]);
+    }
+
+    #[test]
+    fn nothing_to_evict() {
+        let budget = MemoryBudget::new(0);
+        assert_eq!(budget.usage().caches, []);
+        assert!(budget.make_room(0));
+        assert!(!budget.make_room(1));
+
+        let shared = budget.clone();
+        let empty = cache("empty", Vec::new());
+        shared.register(&empty);
+        assert_eq!(budget.usage().caches, [("empty".to_owned(), 0)]);
+        assert!(!budget.make_room(1));
+        drop(empty);
+        assert!(shared.usage().caches.is_empty());
+    }
+}
@@ -0,0 +1,372 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    crawler,
+    datafiles::{self, ClientLayout},
+    hash::HashFunction,
+    passwords::Passwords,
+    paths::PathRules,
+    CacheSettings, ChangeTime, DataInitError, Dirs, FoMetadata, FoRegistry, CACHE_PATH,
+    CACHE_VERSION,
+};
+
+/// When [`FoRegistryBuilder`] writes the registry cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CachePersistence {
+    /// Before the build returns, write errors fail the build.
+    #[default]
+    Sync,
+    /// On a worker thread after the build returns, see [`CacheWriteHandle`].
+    /// Only failing to start the worker fails the build.
+    Background,
+}
+
+/// Pending cache write of [`FoRegistryBuilder::build_with_cache_handle`].
+///
+/// Dropping the handle detaches the worker, the write still finishes unless the process
+/// exits first.
+pub struct CacheWriteHandle {
+    worker: Option<std::thread::JoinHandle<Result<(), DataInitError>>>,
+}
+
+impl CacheWriteHandle {
+    fn done() -> Self {
+        Self { worker: None }
+    }
+
+    fn spawn(path: PathBuf, cache: Vec<u8>) -> Result<Self, DataInitError> {
+        let worker = std::thread::Builder::new()
+            .name("fo_data cache".into())
+            .spawn(move || write_cache(&path, &cache))
+            .map_err(DataInitError::CacheIO)?;
+        Ok(Self {
+            worker: Some(worker),
+        })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        match &self.worker {
+            Some(worker) => worker.is_finished(),
+            None => true,
+        }
+    }
+
+    /// Blocks until the cache is on disk. Returns right away if it was written synchronously
+    /// or the registry was recovered from the cache.
+    pub fn wait(self) -> Result<(), DataInitError> {
+        match self.worker {
+            Some(worker) => worker
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Writes next to `path` first, so readers never see a partially written cache.
+fn write_cache(path: &Path, cache: &[u8]) -> Result<(), DataInitError> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    std::fs::write(&partial, cache).map_err(DataInitError::CacheIO)?;
+    std::fs::rename(&partial, path).map_err(DataInitError::CacheIO)
+}
+
+/// Why [`FoRegistryBuilder`] crawled archives instead of reading the cache.
+#[derive(Debug)]
+pub enum CacheMiss {
+    /// No cache file yet.
+    Missing,
+    /// Cache file can't be read.
+    Invalid(DataInitError),
+    /// Cache was written by an incompatible version of the crate.
+    Version(u32),
+    /// Path rules, hash function or precedence differ from the ones of the cache.
+    Settings,
+    /// Archives were added, removed or reordered.
+    Archives,
+    /// Archive changed after the cache was written.
+    Modified(PathBuf),
+    /// Registries built with an entry filter are not cached.
+    Filtered,
+}
+
+impl From<DataInitError> for CacheMiss {
+    fn from(err: DataInitError) -> Self {
+        CacheMiss::Invalid(err)
+    }
+}
+
+/// How the registry was built, see [`FoRegistry::last_init_report`].
+#[derive(Debug, Default)]
+pub struct InitReport {
+    /// Registry was read from the cache, nothing was crawled.
+    pub cache_hit: bool,
+    /// Why the cache wasn't used, `None` on a hit.
+    pub cache_miss: Option<CacheMiss>,
+    /// Every crawled archive in order, empty on a cache hit.
+    pub crawled: Vec<crawler::CrawledArchive>,
+    pub files: usize,
+    /// Files hidden by files with the same path in other archives.
+    pub shadowed: usize,
+    /// Zip entries hidden by later entries of the same zip, in archive order.
+    /// Not cached, empty on a cache hit.
+    pub duplicates: Vec<crawler::DuplicateEntry>,
+    /// Whole init, with the cache write unless it's done in the background.
+    pub duration: Duration,
+}
+
+impl FoRegistry {
+    /// Report of the init that built this registry, empty for registries built by hand.
+    pub fn last_init_report(&self) -> &InitReport {
+        &self.init_report
+    }
+}
+
+pub struct FoRegistryBuilder {
+    client_root: PathBuf,
+    client: bool,
+    layout: Option<ClientLayout>,
+    server_root: Option<PathBuf>,
+    limits: crawler::Limits,
+    settings: CacheSettings,
+    passwords: Vec<(PathBuf, Vec<u8>)>,
+    password_callback: Option<Arc<crate::passwords::PasswordCallback>>,
+    persistence: CachePersistence,
+    entry_filter: crawler::EntryFilter,
+}
+
+impl FoRegistryBuilder {
+    pub fn new(client_root: impl AsRef<Path>) -> Self {
+        Self {
+            client_root: client_root.as_ref().to_owned(),
+            client: true,
+            layout: None,
+            server_root: None,
+            limits: Default::default(),
+            settings: Default::default(),
+            passwords: Vec::new(),
+            password_callback: None,
+            persistence: Default::default(),
+            entry_filter: Default::default(),
+        }
+    }
+
+    /// Layout of the client, detected with [`FoRegistry::detect_layout`] if not set.
+    pub fn layout(mut self, layout: ClientLayout) -> Self {
+        self.layout = Some(layout);
+        self
+    }
+
+    /// Also indexes `proto`, `maps` and `text` folders of the server, registered as
+    /// `server/proto/...` and so on, see [`datafiles::SERVER_MOUNT`].
+    pub fn server_root(mut self, server_root: impl AsRef<Path>) -> Self {
+        self.server_root = Some(server_root.as_ref().to_owned());
+        self
+    }
+
+    /// Skips client archives, for registries of server data only.
+    pub fn without_client(mut self) -> Self {
+        self.client = false;
+        self
+    }
+
+    /// Safety limits for crawling, see [`crawler::Limits`].
+    pub fn limits(mut self, limits: crawler::Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Rules of conventional paths, must match the engine the data is for.
+    pub fn path_rules(mut self, path_rules: PathRules) -> Self {
+        self.settings.path_rules = path_rules;
+        self
+    }
+
+    /// Name hash of the engine the data is for, see [`FoRegistry::path_by_hash`].
+    pub fn hash_function(mut self, hash_function: HashFunction) -> Self {
+        self.settings.hash_function = hash_function;
+        self
+    }
+
+    /// Password of an encrypted archive, relative paths are resolved against client root.
+    pub fn password(mut self, archive: impl AsRef<Path>, password: impl Into<Vec<u8>>) -> Self {
+        self.passwords
+            .push((self.client_root.join(archive), password.into()));
+        self
+    }
+
+    /// Called with canonical archive path for archives without explicit password.
+    pub fn password_callback(
+        mut self,
+        callback: impl Fn(&Path) -> Option<Vec<u8>> + Send + Sync + 'static,
+    ) -> Self {
+        self.password_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Writing the cache on a worker thread speeds up init on network filesystems,
+    /// the registry is still serialized before the build returns.
+    pub fn cache_persistence(mut self, persistence: CachePersistence) -> Self {
+        self.persistence = persistence;
+        self
+    }
+
+    /// Indexes only entries `filter` accepts, called with conventional path, uncompressed
+    /// size and archive path. Such registries are neither read from nor written to the cache.
+    pub fn entry_filter(
+        mut self,
+        filter: impl Fn(&str, u64, &Path) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.entry_filter = crawler::EntryFilter::new(filter);
+        self
+    }
+
+    fn resolve_passwords(&mut self) -> Passwords {
+        let mut passwords = Passwords::default();
+        for (archive, password) in self.passwords.drain(..) {
+            let archive = archive.canonicalize().unwrap_or(archive);
+            passwords.insert(archive, password);
+        }
+        if let Some(callback) = self.password_callback.take() {
+            passwords.set_callback(callback);
+        }
+        passwords
+    }
+
+    pub fn build(self) -> Result<FoRegistry, DataInitError> {
+        self.build_with_cache_handle().map(|(registry, _cache_write)| registry)
+    }
+
+    /// Same as [`FoRegistryBuilder::build`], also returns the handle to wait for
+    /// the cache write with [`CachePersistence::Background`].
+    pub fn build_with_cache_handle(
+        mut self,
+    ) -> Result<(FoRegistry, CacheWriteHandle), DataInitError> {
+        type Error = DataInitError;
+        let start = Instant::now();
+        let passwords = self.resolve_passwords();
+        let mut archives = Vec::new();
+        if self.client {
+            let layout = match self.layout.take() {
+                Some(layout) => layout,
+                None => FoRegistry::detect_layout(&self.client_root)?,
+            };
+            self.settings.precedence = layout.precedence();
+            archives =
+                datafiles::layout_archives(&self.client_root, &layout).map_err(Error::Datafiles)?;
+        }
+        if let Some(server_root) = &self.server_root {
+            let server = datafiles::server_archives(server_root).map_err(Error::Datafiles)?;
+            archives.extend(server);
+        }
+        let recovered = if self.entry_filter.accepts_all() {
+            FoRegistry::recover_from_cache(&archives, self.settings)
+        } else {
+            Err(CacheMiss::Filtered)
+        };
+        let cache_miss = match recovered {
+            Err(miss) => {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(?miss, "registry cache not used");
+                miss
+            }
+            Ok(mut registry) => {
+                registry.passwords = passwords;
+                registry.limits = self.limits;
+                registry.init_report = InitReport {
+                    cache_hit: true,
+                    files: registry.files.len(),
+                    shadowed: registry.shadowed.values().map(Vec::len).sum(),
+                    duration: start.elapsed(),
+                    ..Default::default()
+                };
+                return Ok((registry, CacheWriteHandle::done()));
+            }
+        };
+
+        let (files, report) = crawler::gather_paths_filtered(
+            &archives,
+            &self.limits,
+            &self.settings.path_rules,
+            self.settings.precedence,
+            &self.entry_filter,
+        )
+        .map_err(Error::GatherPaths)?;
+        // also kept in `InitReport::duplicates`
+        #[cfg(feature = "tracing")]
+        for warning in report.warnings() {
+            tracing::warn!(%warning, "crawl warning");
+        }
+        let mut dirs = Dirs::default();
+        for path in files.keys() {
+            dirs.register(path, FoMetadata::File);
+        }
+
+        let init_report = InitReport {
+            cache_hit: false,
+            cache_miss: Some(cache_miss),
+            files: files.len(),
+            shadowed: report.shadowed.values().map(Vec::len).sum(),
+            duplicates: report.duplicates,
+            crawled: report.crawled,
+            duration: Duration::default(),
+        };
+        let changed = ChangeTime::now();
+        let mut fo_data = FoRegistry {
+            changed,
+            archives,
+            files: Arc::new(files),
+            dirs: Arc::new(dirs),
+            shadowed: Arc::new(report.shadowed),
+            settings: self.settings,
+            passwords,
+            by_hash: Default::default(),
+            last_changes: Default::default(),
+            init_report,
+            entry_filter: self.entry_filter,
+            limits: self.limits,
+            //palette,
+        };
+        if !fo_data.entry_filter.accepts_all() {
+            fo_data.init_report.duration = start.elapsed();
+            return Ok((fo_data, CacheWriteHandle::done()));
+        }
+        let mut cache = Vec::new();
+        bincode::serialize_into(&mut cache, &CACHE_VERSION).map_err(Error::CacheSerialize)?;
+        bincode::serialize_into(&mut cache, &self.settings).map_err(Error::CacheSerialize)?;
+        bincode::serialize_into(&mut cache, &fo_data).map_err(Error::CacheSerialize)?;
+        let cache_write = match self.persistence {
+            CachePersistence::Sync => {
+                write_cache(Path::new(CACHE_PATH), &cache)?;
+                CacheWriteHandle::done()
+            }
+            CachePersistence::Background => CacheWriteHandle::spawn(CACHE_PATH.into(), cache)?,
+        };
+        fo_data.init_report.duration = start.elapsed();
+        Ok((fo_data, cache_write))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn background_cache_write() {
+        let path = std::env::temp_dir().join("fo_data_test_cache.bin");
+        let _ = std::fs::remove_file(&path);
+        let handle = CacheWriteHandle::spawn(path.clone(), b"cache".to_vec()).unwrap();
+        handle.wait().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"cache");
+        assert!(CacheWriteHandle::done().is_finished());
+
+        let missing = std::env::temp_dir().join("fo_data_test_missing/cache.bin");
+        let handle = CacheWriteHandle::spawn(missing, Vec::new()).unwrap();
+        assert!(matches!(handle.wait(), Err(DataInitError::CacheIO(_))));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
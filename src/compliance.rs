@@ -0,0 +1,221 @@
+//! Palette compliance of png art meant for FRM workflows: how many pixels would change
+//! when the art is converted to the game palette, checked before running the conversion.
+
+use std::collections::HashMap;
+
+use crate::{
+    palette::{Palette, RgbaLut},
+    FileType, FoRetriever,
+};
+
+/// Color of pixels outside the palette in previews.
+pub const OUTSIDE_COLOR: [u8; 4] = [255, 0, 255, 255];
+
+#[derive(Debug, Clone)]
+pub struct ComplianceOptions {
+    /// Largest euclidean RGB distance to a palette color that still counts as inside.
+    pub tolerance: u32,
+    /// Number of the worst files that get a preview.
+    pub previews: usize,
+}
+
+impl Default for ComplianceOptions {
+    fn default() -> Self {
+        Self {
+            tolerance: 0,
+            previews: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Compliance {
+    /// Pixels that aren't fully transparent.
+    pub visible: u64,
+    /// Visible pixels that would change: translucent ones or ones too far from the palette.
+    pub outside: u64,
+    /// Largest euclidean distance of an opaque pixel to the palette, rounded up.
+    pub max_distance: u32,
+    /// The image as it would look converted, pixels outside the palette are [`OUTSIDE_COLOR`].
+    pub preview: Option<image::RgbaImage>,
+}
+
+impl Compliance {
+    /// Share of visible pixels outside the palette, 0 to 1.
+    pub fn outside_ratio(&self) -> f64 {
+        match self.visible {
+            0 => 0.0,
+            visible => self.outside as f64 / visible as f64,
+        }
+    }
+}
+
+/// Checks a decoded image against the palette. Fully transparent pixels map to the
+/// transparent index and always comply, translucent ones never do as FRM has no alpha.
+pub fn check_image(
+    image: &image::RgbaImage,
+    lut: &RgbaLut,
+    options: &ComplianceOptions,
+) -> Compliance {
+    let tolerance = options.tolerance.saturating_mul(options.tolerance);
+    let mut nearest: HashMap<[u8; 3], (u8, u32)> = HashMap::new();
+    let mut preview = image::RgbaImage::new(image.width(), image.height());
+    let mut compliance = Compliance {
+        visible: 0,
+        outside: 0,
+        max_distance: 0,
+        preview: None,
+    };
+    let mut max_distance = 0;
+    for (pixel, out) in image.pixels().zip(preview.pixels_mut()) {
+        let [red, green, blue, alpha] = pixel.0;
+        if alpha == 0 {
+            continue;
+        }
+        compliance.visible += 1;
+        let (index, distance) = *nearest
+            .entry([red, green, blue])
+            .or_insert_with(|| lut.nearest([red, green, blue]));
+        if alpha < 255 || distance > tolerance {
+            compliance.outside += 1;
+            out.0 = OUTSIDE_COLOR;
+        } else {
+            out.0 = lut.get(index);
+        }
+        if alpha == 255 {
+            max_distance = max_distance.max(distance);
+        }
+    }
+    compliance.max_distance = (max_distance as f64).sqrt().ceil() as u32;
+    compliance.preview = Some(preview);
+    compliance
+}
+
+#[derive(Debug, Default)]
+pub struct ComplianceReport {
+    /// Checked pngs, most outside pixels share first.
+    pub files: Vec<(String, Compliance)>,
+    /// Pngs that couldn't be read or decoded, with the reason.
+    pub failed: Vec<(String, String)>,
+}
+
+/// Checks every png under the virtual folder `dir` against `palette`, which should be
+/// scaled to 8 bits as [`crate::FoData::palette`] is.
+pub fn check_folder(
+    retriever: &FoRetriever,
+    dir: &str,
+    palette: &Palette,
+    options: &ComplianceOptions,
+) -> ComplianceReport {
+    let registry = retriever.registry();
+    let folder = registry.path_rules().normalize(dir);
+    let folder = folder.trim_matches('/');
+    let lut = palette.rgba_lut();
+    let mut report = ComplianceReport::default();
+    for (path, info) in registry.files() {
+        let inside = match path.strip_prefix(folder) {
+            Some(rest) => folder.is_empty() || rest.starts_with('/'),
+            None => false,
+        };
+        if !inside || *info.file_type() != FileType::Png {
+            continue;
+        }
+        let image = retriever
+            .file_by_info(info)
+            .map_err(|err| format!("can't read: {}", err))
+            .and_then(|data| {
+                image::load_from_memory_with_format(&data, image::ImageFormat::Png)
+                    .map_err(|err| format!("can't decode: {}", err))
+            });
+        match image {
+            Ok(image) => {
+                let compliance = check_image(&image.to_rgba8(), &lut, options);
+                report.files.push((path.to_owned(), compliance));
+            }
+            Err(err) => report.failed.push((path.to_owned(), err)),
+        }
+    }
+    report.files.sort_by(|(a_path, a), (b_path, b)| {
+        b.outside_ratio()
+            .total_cmp(&a.outside_ratio())
+            .then_with(|| a_path.cmp(b_path))
+    });
+    for (_, compliance) in report.files.iter_mut().skip(options.previews) {
+        compliance.preview = None;
+    }
+    report.failed.sort();
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn palette_compliance() {
+        let palette = crate::testing::gradient_palette();
+        let lut = palette.rgba_lut();
+        let inside = lut.get(10);
+        let near = [inside[0], inside[1], inside[2].wrapping_add(1), 255];
+        let pixels = [inside, near, [inside[0], inside[1], inside[2], 128], [0, 0, 0, 0]];
+        let image = image::RgbaImage::from_fn(4, 1, |x, _| image::Rgba(pixels[x as usize]));
+        let mut png = Vec::new();
+        crate::converter::encode_png(&image, &mut png).unwrap();
+        let mixed = png.clone();
+        let clean = image::RgbaImage::from_pixel(2, 2, image::Rgba(inside));
+        crate::converter::encode_png(&clean, &mut png).unwrap();
+        let data = crate::testing::TempData::new(&[
+            ("art/import/mixed.png", &mixed),
+            ("art/import/clean.png", &png),
+            ("art/import/broken.png", b"png"),
+            ("art/other.png", &png),
+        ]);
+        let retriever = data.retriever();
+        let options = ComplianceOptions {
+            previews: 1,
+            ..ComplianceOptions::default()
+        };
+        let report = check_folder(&retriever, "Art/Import", &palette, &options);
+        let paths: Vec<_> = report.files.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths, ["art/import/mixed.png", "art/import/clean.png"]);
+        assert_eq!(report.failed.len(), 1);
+
+        let mixed = &report.files[0].1;
+        assert_eq!((mixed.visible, mixed.outside, mixed.max_distance), (3, 2, 1));
+        let preview = mixed.preview.as_ref().unwrap();
+        assert_eq!(preview.get_pixel(0, 0).0, inside);
+        assert_eq!(preview.get_pixel(1, 0).0, OUTSIDE_COLOR);
+        assert_eq!(preview.get_pixel(2, 0).0, OUTSIDE_COLOR);
+        assert_eq!(preview.get_pixel(3, 0).0, [0, 0, 0, 0]);
+        let clean = &report.files[1].1;
+        assert_eq!(clean.outside_ratio(), 0.0);
+        assert!(clean.preview.is_none());
+
+        let tolerant = ComplianceOptions {
+            tolerance: 1,
+            ..ComplianceOptions::default()
+        };
+        assert_eq!(check_image(&image, &lut, &tolerant).outside, 1);
+        let report = check_folder(&retriever, "art/imp", &palette, &options);
+        assert!(report.files.is_empty() && report.failed.is_empty());
+    }
+
+    #[test]
+    fn nothing_visible() {
+        let palette = crate::testing::gradient_palette();
+        let lut = palette.rgba_lut();
+        let options = ComplianceOptions::default();
+        for image in [image::RgbaImage::new(0, 0), image::RgbaImage::new(3, 2)] {
+            let compliance = check_image(&image, &lut, &options);
+            let counts = (compliance.visible, compliance.outside, compliance.max_distance);
+            assert_eq!(counts, (0, 0, 0));
+            assert_eq!(compliance.outside_ratio(), 0.0);
+            let preview = compliance.preview.unwrap();
+            assert_eq!(preview.dimensions(), image.dimensions());
+        }
+
+        let retriever = crate::FoRegistry::stub().into_retriever();
+        let report = check_folder(&retriever, "", &palette, &options);
+        assert!(report.files.is_empty() && report.failed.is_empty());
+    }
+}
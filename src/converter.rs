@@ -1,6 +1,14 @@
+mod disk_cache;
+
 use std::io::Cursor;
 
-use crate::*;
+use rayon::prelude::*;
+
+pub use self::disk_cache::{DiskCache, PrimeStats};
+use crate::{metrics::measure, *};
+
+/// Error of any [`Retriever`], so third-party retrievers work with converter as is.
+pub type RetrieveError = Box<dyn std::error::Error + Send + Sync>;
 
 #[derive(Debug)]
 pub enum GetImageError {
@@ -15,14 +23,20 @@ pub enum GetImageError {
     ImageFromRaw,
     ImageWrite(image::ImageError),
     PngDecode(image::ImageError),
+    GifDecode(image::ImageError),
     Recursion(usize, Box<GetImageError>),
     RecursionLimit,
     NoPallete,
-    FoRetrieve(<FoRetriever as Retriever>::Error),
-    #[cfg(feature = "sled-retriever")]
-    SledRetrieve(<crate::retriever::sled::SledRetriever as Retriever>::Error),
+    Retrieve(RetrieveError),
+    UnknownHash(u32),
+    /// [`ConvertService`] stopped before the job was done.
+    ServiceStopped,
 }
 impl GetImageError {
+    fn retrieve(err: impl Into<RetrieveError>) -> Self {
+        GetImageError::Retrieve(err.into())
+    }
+
     fn recursion(self) -> Self {
         use GetImageError::*;
         match self {
@@ -34,27 +48,1215 @@ impl GetImageError {
 pub struct Converter<'r, 'p, R> {
     retriever: &'r R,
     palette: &'p Palette,
+    lut: once_cell::sync::OnceCell<RgbaLut>,
+    disk_cache: Option<DiskCache>,
+    metrics: Option<Metrics>,
+    #[cfg(feature = "tracing")]
+    slow_threshold: Option<std::time::Duration>,
 }
 impl<'r, 'p, R> Converter<'r, 'p, R> {
     pub fn new(retriever: &'r R, palette: &'p Palette) -> Self {
-        Self { retriever, palette }
+        Self {
+            retriever,
+            palette,
+            lut: Default::default(),
+            disk_cache: None,
+            metrics: None,
+            #[cfg(feature = "tracing")]
+            slow_threshold: None,
+        }
+    }
+
+    /// Png conversions through [`Converter::get_with`] are looked up in the cache first.
+    /// Conversions with placeholders are never cached.
+    pub fn with_disk_cache(mut self, disk_cache: DiskCache) -> Self {
+        self.disk_cache = Some(disk_cache);
+        self
+    }
+
+    /// Times conversions, FRM decoding and png encoding in `metrics`.
+    pub fn with_metrics(mut self, metrics: &Metrics) -> Self {
+        self.metrics = Some(metrics.clone());
+        self
+    }
+
+    /// Conversions through [`Converter::get_with`] taking at least `threshold` are logged
+    /// as `tracing` warnings, with the path, options and output size.
+    #[cfg(feature = "tracing")]
+    pub fn with_slow_threshold(mut self, threshold: std::time::Duration) -> Self {
+        self.slow_threshold = Some(threshold);
+        self
+    }
+
+    fn shared<'a>(&'a self, lut: &'a RgbaLut) -> Shared<'a> {
+        Shared {
+            lut: Some(lut),
+            metrics: self.metrics.as_ref(),
+        }
+    }
+
+    /// Lookup table of converter palette, computed once, or of the palette from options.
+    fn lut(&self, options: &ConvertOptions) -> std::borrow::Cow<'_, RgbaLut> {
+        use std::borrow::Cow;
+        match &options.palette {
+            Some(palette) => Cow::Owned(palette.rgba_lut()),
+            None => Cow::Borrowed(self.lut.get_or_init(|| self.palette.rgba_lut())),
+        }
     }
 }
 
 impl<'r, 'p, R: Retriever> Converter<'r, 'p, R>
 where
-    R::Error: Into<GetImageError>,
+    R::Error: Into<RetrieveError>,
 {
     pub fn get_png(&self, path: &str) -> Result<FileData, GetImageError> {
-        let raw = get_raw(self.retriever, path, 0, Some(self.palette.colors_tuples()))?;
-        raw.to_png().map_err(GetImageError::ImageWrite)
+        self.get_with(path, &ConvertOptions::default())
+    }
+
+    /// Png of the path with the [`name_hash`](crate::hash::name_hash), resolved by the retriever.
+    pub fn get_png_by_hash(&self, hash: u32) -> Result<FileData, GetImageError> {
+        self.get_by_hash_with(hash, &ConvertOptions::default())
+    }
+
+    pub fn get_by_hash_with(
+        &self,
+        hash: u32,
+        options: &ConvertOptions,
+    ) -> Result<FileData, GetImageError> {
+        let path = self
+            .retriever
+            .path_by_hash(hash)
+            .ok_or(GetImageError::UnknownHash(hash))?;
+        self.get_with(path, options)
+    }
+
+    /// Image composited for display, see [`RawImage::preview`].
+    pub fn get_preview(
+        &self,
+        path: &str,
+        options: &ConvertOptions,
+        preview: &PreviewOptions,
+    ) -> Result<FileData, GetImageError> {
+        let (raw, fingerprint, replaced_error) =
+            self.get_rgba_reusing(path, options, &mut Vec::new())?;
+        let mut hasher = FingerprintHasher(fingerprint.0);
+        preview.hash(&mut hasher);
+        let raw = raw.preview(preview);
+        let mut file = self.encode(raw, hasher.finish(), options)?;
+        file.replaced_error = replaced_error.map(Box::new);
+        Ok(file)
+    }
+
+    /// Animated GIF of the first direction, see [`Converter::get_gif_with`].
+    pub fn get_gif(&self, path: &str) -> Result<Vec<u8>, GetImageError> {
+        self.get_gif_with(path, &ConvertOptions::default())
+    }
+
+    /// Animated GIF of the direction of `options`, looped forever. Frames are placed on the
+    /// common canvas of the direction, delays come from fps of the animation, 10 if it has none.
+    /// Frame of `options` is ignored.
+    pub fn get_gif_with(
+        &self,
+        path: &str,
+        options: &ConvertOptions,
+    ) -> Result<Vec<u8>, GetImageError> {
+        use image::codecs::gif::{GifEncoder, Repeat};
+
+        const DEFAULT_FPS: u16 = 10;
+        let mut animation = self.get_animation(path, options)?;
+        if options.direction >= animation.directions.len() {
+            return Err(GetImageError::NoDirection);
+        }
+        let direction = Animation {
+            fps: animation.fps,
+            directions: vec![animation.directions.swap_remove(options.direction)],
+        };
+        let fps = match direction.fps {
+            0 => DEFAULT_FPS,
+            fps => fps,
+        };
+        // repeated frames are shown longer instead of being encoded again
+        let mut runs = direction.directions[0].frame_runs();
+        if runs.is_empty() {
+            runs.push((0, 1));
+        }
+        let mut gif = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut gif);
+            encoder
+                .set_repeat(Repeat::Infinite)
+                .map_err(GetImageError::ImageWrite)?;
+            for (frame, length) in runs {
+                let delay = image::Delay::from_numer_denom_ms(1000 * length as u32, fps as u32);
+                let raw = direction
+                    .compose_frame(0, frame)
+                    .ok_or(GetImageError::NoFrame)?;
+                encoder
+                    .encode_frame(image::Frame::from_parts(raw.image, 0, 0, delay))
+                    .map_err(GetImageError::ImageWrite)?;
+            }
+        }
+        Ok(gif)
+    }
+
+    /// Opacity mask for picking, see [`RawImage::alpha_mask`].
+    pub fn get_alpha_mask(&self, path: &str) -> Result<AlphaMask, GetImageError> {
+        Ok(self.get_rgba(path)?.alpha_mask())
     }
 
     pub fn get_rgba(&self, path: &str) -> Result<RawImage, GetImageError> {
-        get_raw(self.retriever, path, 0, Some(self.palette.colors_tuples()))
+        self.get_rgba_with(path, &ConvertOptions::default())
+    }
+
+    pub fn get_with(&self, path: &str, options: &ConvertOptions) -> Result<FileData, GetImageError> {
+        let cacheable = options.output == DataType::Png && options.placeholder.is_none();
+        #[cfg(feature = "tracing")]
+        let start = std::time::Instant::now();
+        let convert = || match &self.disk_cache {
+            Some(disk_cache) if cacheable => disk_cache.get_or_convert(self, path, options),
+            _ => self.convert(path, options),
+        };
+        let result = measure(self.metrics.as_ref(), Operation::Convert, convert, |file| {
+            file.data.len()
+        });
+        #[cfg(feature = "tracing")]
+        match self.slow_threshold {
+            Some(threshold) if start.elapsed() >= threshold => tracing::warn!(
+                path,
+                options = ?options,
+                dimensions = ?result.as_ref().map(|file| file.dimensions).ok(),
+                size = result.as_ref().map_or(0, |file| file.data.len()),
+                error = ?result.as_ref().err(),
+                elapsed_ms = start.elapsed().as_millis() as u64,
+                "slow conversion"
+            ),
+            _ => {}
+        }
+        result
+    }
+
+    fn to_png(&self, raw: RawImage, fingerprint: Fingerprint) -> Result<FileData, GetImageError> {
+        let encode = || raw.into_png(fingerprint);
+        measure(self.metrics.as_ref(), Operation::PngEncode, encode, |file| file.data.len())
+            .map_err(GetImageError::ImageWrite)
+    }
+
+    /// Encodes the image as [`ConvertOptionsBuilder::output`].
+    fn encode(
+        &self,
+        raw: RawImage,
+        fingerprint: Fingerprint,
+        options: &ConvertOptions,
+    ) -> Result<FileData, GetImageError> {
+        match options.output {
+            DataType::Png => self.to_png(raw, fingerprint),
+            DataType::Rgba => Ok(raw.into_rgba(fingerprint)),
+            #[cfg(feature = "webp")]
+            DataType::WebP => raw
+                .encode_with(fingerprint, DataType::WebP, encode_webp)
+                .map_err(GetImageError::ImageWrite),
+            #[cfg(feature = "avif")]
+            DataType::Avif => raw
+                .encode_with(fingerprint, DataType::Avif, |image, out| {
+                    encode_avif(image, options.quality, out)
+                })
+                .map_err(GetImageError::ImageWrite),
+        }
+    }
+
+    fn convert(&self, path: &str, options: &ConvertOptions) -> Result<FileData, GetImageError> {
+        let (raw, fingerprint, replaced_error) =
+            self.get_rgba_reusing(path, options, &mut Vec::new())?;
+        let mut file = self.encode(raw, fingerprint, options)?;
+        file.replaced_error = replaced_error.map(Box::new);
+        Ok(file)
+    }
+
+    pub fn get_rgba_with(
+        &self,
+        path: &str,
+        options: &ConvertOptions,
+    ) -> Result<RawImage, GetImageError> {
+        let (raw, _fingerprint, _replaced_error) =
+            self.get_rgba_reusing(path, options, &mut Vec::new())?;
+        Ok(raw)
+    }
+
+    /// Same as [`Converter::get_with`], but reuses buffers of `scratch` between calls.
+    /// Meant for batch conversions, returned data is still copied out of scratch buffers.
+    pub fn get_with_scratch(
+        &self,
+        path: &str,
+        options: &ConvertOptions,
+        scratch: &mut ConvertScratch,
+    ) -> Result<FileData, GetImageError> {
+        let (raw, fingerprint, replaced_error) =
+            self.get_rgba_reusing(path, options, &mut scratch.pixels)?;
+        let replaced_error = replaced_error.map(Box::new);
+        match options.output {
+            DataType::Png => {
+                let png = &mut scratch.png;
+                let encode = || encode_png(&raw.image, png).map(|()| png.len());
+                measure(self.metrics.as_ref(), Operation::PngEncode, encode, |len| *len)
+                    .map_err(GetImageError::ImageWrite)?;
+                let file = FileData {
+                    data: bytes::Bytes::copy_from_slice(&scratch.png),
+                    data_type: DataType::Png,
+                    dimensions: raw.image.dimensions(),
+                    offset: (raw.offset_x, raw.offset_y),
+                    fingerprint,
+                    replaced_error,
+                };
+                scratch.pixels = raw.image.into_raw();
+                Ok(file)
+            }
+            DataType::Rgba => Ok(FileData {
+                replaced_error,
+                ..raw.into_rgba(fingerprint)
+            }),
+            #[cfg(any(feature = "webp", feature = "avif"))]
+            _ => Ok(FileData {
+                replaced_error,
+                ..self.encode(raw, fingerprint, options)?
+            }),
+        }
+    }
+
+    fn get_rgba_reusing(
+        &self,
+        path: &str,
+        options: &ConvertOptions,
+        pixels: &mut Vec<u8>,
+    ) -> Result<(RawImage, Fingerprint, Option<GetImageError>), GetImageError> {
+        let lut = self.lut(options);
+        let palette = options.palette.as_ref().unwrap_or(self.palette);
+        let mut hasher = FingerprintHasher::new();
+        options.hash(palette, &mut hasher);
+        let result = get_raw(
+            self.retriever,
+            path,
+            0,
+            self.shared(&lut),
+            pixels,
+            &mut hasher,
+            options,
+        );
+        let (raw, replaced_error) = match (result, &options.placeholder) {
+            (Ok(raw), _) => (raw, None),
+            (Err(err), None) => return Err(err),
+            (Err(err), Some(placeholder)) => {
+                // sources of the failed conversion are not a part of placeholder fingerprint
+                hasher = FingerprintHasher::new();
+                options.hash(palette, &mut hasher);
+                hasher.write(b"placeholder");
+                let raw = match placeholder {
+                    Placeholder::Checkerboard { width, height } => {
+                        hasher.write(&width.to_le_bytes());
+                        hasher.write(&height.to_le_bytes());
+                        let image = checkerboard(*width, *height, 8, PLACEHOLDER_COLORS);
+                        let (offset_x, offset_y) =
+                            png_offset(*width, *height, options.anchor.anchor_for(path));
+                        RawImage {
+                            image,
+                            offset_x,
+                            offset_y,
+                        }
+                    }
+                    Placeholder::Path(placeholder_path) => {
+                        match get_raw(
+                            self.retriever,
+                            placeholder_path,
+                            0,
+                            self.shared(&lut),
+                            pixels,
+                            &mut hasher,
+                            &options.referenced(),
+                        ) {
+                            Ok(raw) => raw,
+                            Err(_) => return Err(err),
+                        }
+                    }
+                };
+                (raw, Some(err))
+            }
+        };
+        Ok((raw.scaled(options.scale), hasher.finish(), replaced_error))
+    }
+
+    /// Offset of a frame as it would be in [`RawImage`], without decoding the image itself.
+    pub fn frame_offset(
+        &self,
+        path: &str,
+        direction: usize,
+        frame: usize,
+    ) -> Result<(i16, i16), GetImageError> {
+        let options = ConvertOptions::builder()
+            .direction(direction)
+            .frame(frame)
+            .build();
+        let bounds = get_placement(self.retriever, path, 0, &options)?;
+        Ok((bounds.left as i16, bounds.top as i16))
+    }
+
+    /// Path a frame of a fofrm refers to and the rule it was resolved by.
+    /// Only the referenced file itself is looked up, it is not converted.
+    pub fn frame_reference(
+        &self,
+        path: &str,
+        options: &ConvertOptions,
+    ) -> Result<(String, references::ReferenceRule), GetImageError> {
+        let data = self
+            .retriever
+            .file_bytes_by_path(path)
+            .map_err(GetImageError::retrieve)?;
+        let frame = fofrm_frame(path, &data, options, self.retriever.path_rules())?;
+        frame.first_found(|full_path| match retriever::recognize_type(full_path) {
+            FileType::Frm => read_frm(self.retriever, full_path, &options.referenced())
+                .map(|(full_path, _direction, _data)| full_path),
+            _ => self
+                .retriever
+                .file_bytes_by_path(full_path)
+                .map(|_data| full_path.to_owned())
+                .map_err(GetImageError::retrieve),
+        })
+    }
+
+    /// Placement of every frame as [`Converter::get_animation`] would return it, read from
+    /// headers without decoding pixels. Meant for atlas packing and layouting of many files.
+    pub fn animation_layout(
+        &self,
+        path: &str,
+        options: &ConvertOptions,
+    ) -> Result<AnimationLayout, GetImageError> {
+        let file_type = retriever::recognize_type(path);
+        let (fps, directions) = match file_type {
+            FileType::Frm => {
+                let data = self
+                    .retriever
+                    .file_bytes_by_path(path)
+                    .map_err(GetImageError::retrieve)?;
+                let frm = frm::parse_header(&data).map_err(GetImageError::FrmParse)?;
+                let directions = frm
+                    .directions
+                    .iter()
+                    .map(|direction| {
+                        direction
+                            .frames
+                            .iter()
+                            .enumerate()
+                            .map(|(frame_number, frame)| {
+                                let offset = frm_header_frame_offset(direction, frame_number);
+                                let dimensions = (frame.width as u32, frame.height as u32);
+                                Bounds::new(offset, dimensions).scaled(options.scale)
+                            })
+                            .collect()
+                    })
+                    .collect();
+                (frm.fps, directions)
+            }
+            FileType::FoFrm => {
+                let data = self
+                    .retriever
+                    .file_bytes_by_path(path)
+                    .map_err(GetImageError::retrieve)?;
+                let string = std::str::from_utf8(&data).map_err(GetImageError::Utf8)?;
+                let fofrm = fofrm::parse_verbose(string).map_err(GetImageError::FoFrmParse)?;
+                let directions = fofrm
+                    .directions
+                    .iter()
+                    .enumerate()
+                    .map(|(direction, frames)| {
+                        (0..frames.frames.len())
+                            .map(|frame| {
+                                let options = ConvertOptions {
+                                    direction,
+                                    frame,
+                                    direction_map: DirectionMap::Identity,
+                                    ..options.clone()
+                                };
+                                let bounds = get_placement(self.retriever, path, 0, &options)?;
+                                Ok(bounds.scaled(options.scale))
+                            })
+                            .collect()
+                    })
+                    .collect::<Result<_, _>>()?;
+                (fofrm.fps.unwrap_or(0), directions)
+            }
+            FileType::Gif => {
+                let data = self
+                    .retriever
+                    .file_bytes_by_path(path)
+                    .map_err(GetImageError::retrieve)?;
+                let bounds = get_placement(self.retriever, path, 0, &options.referenced())?;
+                let mut fps = 0;
+                let mut frames = Vec::new();
+                for frame in gif_frames(&data)? {
+                    let (_image, delay) = frame?;
+                    if frames.is_empty() {
+                        fps = gif_fps(delay);
+                    }
+                    frames.push(bounds.scaled(options.scale));
+                }
+                (fps, vec![frames])
+            }
+            _ => {
+                let bounds = get_placement(self.retriever, path, 0, &options.referenced())?;
+                (0, vec![vec![bounds.scaled(options.scale)]])
+            }
+        };
+        Ok(AnimationLayout {
+            fps,
+            directions: remap_directions(directions, &options.direction_map)?,
+        })
+    }
+
+    /// Decodes every frame of every direction, frames of FRM images are expanded in parallel.
+    /// Direction and frame of `options` are ignored, directions are ordered by its
+    /// [`DirectionMap`].
+    pub fn get_animation(
+        &self,
+        path: &str,
+        options: &ConvertOptions,
+    ) -> Result<Animation, GetImageError> {
+        let file_type = retriever::recognize_type(path);
+        match file_type {
+            FileType::Frm => {
+                let data = self
+                    .retriever
+                    .file_bytes_by_path(path)
+                    .map_err(GetImageError::retrieve)?;
+                let frm = frm::frm(&data).map_err(GetImageError::FrmParse)?;
+                let lut = self.lut(options);
+                let directions = frm.directions[..]
+                    .par_iter()
+                    .map(|direction| {
+                        let frames = direction
+                            .frames
+                            .par_iter()
+                            .enumerate()
+                            .map(|(frame_number, frame)| {
+                                let (offset_x, offset_y) =
+                                    frm_frame_offset(direction, frame_number);
+                                let raw = RawImage {
+                                    image: frm_frame_image(&lut, frame, Vec::new())?,
+                                    offset_x,
+                                    offset_y,
+                                };
+                                Ok(raw.scaled(options.scale))
+                            })
+                            .collect::<Result<_, _>>()?;
+                        let shift = (direction.shift_x, direction.shift_y);
+                        Ok(AnimationDirection {
+                            frames,
+                            shift: scaled_shift(shift, options.scale),
+                        })
+                    })
+                    .collect::<Result<_, _>>()?;
+                Animation {
+                    fps: frm.fps,
+                    directions,
+                }
+                .remapped(&options.direction_map)
+            }
+            FileType::FoFrm => {
+                let data = self
+                    .retriever
+                    .file_bytes_by_path(path)
+                    .map_err(GetImageError::retrieve)?;
+                let string = std::str::from_utf8(&data).map_err(GetImageError::Utf8)?;
+                let fofrm = fofrm::parse_verbose(string).map_err(GetImageError::FoFrmParse)?;
+                let lut = self.lut(options);
+                let referenced = options.referenced();
+                let mut scratch = ConvertScratch::default();
+                let mut hasher = FingerprintHasher::new();
+                let rules = self.retriever.path_rules();
+                let directions = fofrm
+                    .directions
+                    .iter()
+                    .enumerate()
+                    .map(|(index, direction)| {
+                        let frames = fofrm_direction_frames(path, &fofrm, direction, rules)?
+                            .iter()
+                            .enumerate()
+                            .map(|(frame_number, frame)| {
+                                let result = frame
+                                    .first_found(|full_path| {
+                                        get_raw(
+                                            self.retriever,
+                                            full_path,
+                                            1,
+                                            self.shared(&lut),
+                                            &mut scratch.pixels,
+                                            &mut hasher,
+                                            &referenced,
+                                        )
+                                    })
+                                    .map_err(GetImageError::recursion);
+                                match (result, &options.placeholder) {
+                                    (Ok((mut raw, _rule)), _) => {
+                                        raw.offset_x = raw.offset_x.saturating_add(frame.offset.0);
+                                        raw.offset_y = raw.offset_y.saturating_add(frame.offset.1);
+                                        Ok(raw.scaled(options.scale))
+                                    }
+                                    (Err(err), None) => Err(err),
+                                    // the placeholder is chosen as for a single frame
+                                    (Err(_), Some(_)) => {
+                                        let options = ConvertOptions {
+                                            direction: index,
+                                            frame: frame_number,
+                                            direction_map: DirectionMap::Identity,
+                                            ..options.clone()
+                                        };
+                                        self.get_rgba_with(path, &options)
+                                    }
+                                }
+                            })
+                            .collect::<Result<_, _>>()?;
+                        let shift = (
+                            direction.offset_x.or(fofrm.offset_x).unwrap_or(0),
+                            direction.offset_y.or(fofrm.offset_y).unwrap_or(0),
+                        );
+                        Ok(AnimationDirection {
+                            frames,
+                            shift: scaled_shift(shift, options.scale),
+                        })
+                    })
+                    .collect::<Result<_, _>>()?;
+                Animation {
+                    fps: fofrm.fps.unwrap_or(0),
+                    directions,
+                }
+                .remapped(&options.direction_map)
+            }
+            FileType::Gif => {
+                let data = self
+                    .retriever
+                    .file_bytes_by_path(path)
+                    .map_err(GetImageError::retrieve)?;
+                let anchor = options.anchor.anchor_for(path);
+                let mut fps = 0;
+                let mut frames = Vec::new();
+                for frame in gif_frames(&data)? {
+                    let (mut image, delay) = frame?;
+                    if frames.is_empty() {
+                        fps = gif_fps(delay);
+                    }
+                    apply_color_key(&mut image, options.color_key);
+                    let (offset_x, offset_y) = png_offset(image.width(), image.height(), anchor);
+                    let raw = RawImage {
+                        image,
+                        offset_x,
+                        offset_y,
+                    };
+                    frames.push(raw.scaled(options.scale));
+                }
+                Ok(Animation {
+                    fps,
+                    directions: vec![frames.into()],
+                })
+            }
+            _ => Ok(Animation {
+                fps: 0,
+                directions: vec![vec![self.get_rgba_with(path, &options.referenced())?].into()],
+            }),
+        }
     }
 }
 
+/// All frames of an image, see [`Converter::get_animation`].
+#[derive(Debug, Clone)]
+pub struct Animation {
+    /// Frames per second, 0 if unknown.
+    pub fps: u16,
+    /// Every direction, still images have a single frame of a single direction.
+    pub directions: Vec<AnimationDirection>,
+}
+
+/// Frames of one direction of an [`Animation`].
+#[derive(Debug, Clone, Default)]
+pub struct AnimationDirection {
+    /// Offsets of frames are relative to the sprite position and already include the shift
+    /// of the direction and shifts of previous frames.
+    pub frames: Vec<RawImage>,
+    /// Shift of the whole direction, from the FRM header or `offs_x` and `offs_y` of FOFRM.
+    pub shift: (i16, i16),
+}
+
+impl AnimationDirection {
+    /// [`RawImage::content_hash`] of every frame.
+    pub fn frame_hashes(&self) -> Vec<u64> {
+        self.frames.iter().map(RawImage::content_hash).collect()
+    }
+
+    /// For every frame, index of the first earlier frame with the same pixels and offset,
+    /// `None` for frames seen for the first time.
+    pub fn duplicate_frames(&self) -> Vec<Option<usize>> {
+        let hashes = self.frame_hashes();
+        let mut first_by_hash: std::collections::HashMap<u64, Vec<usize>> = Default::default();
+        let mut duplicates = Vec::with_capacity(hashes.len());
+        for (index, hash) in hashes.into_iter().enumerate() {
+            let candidates = first_by_hash.entry(hash).or_default();
+            let frame = &self.frames[index];
+            let original = candidates
+                .iter()
+                .copied()
+                .find(|&candidate| self.frames[candidate].same_content(frame));
+            if original.is_none() {
+                candidates.push(index);
+            }
+            duplicates.push(original);
+        }
+        duplicates
+    }
+
+    /// Runs of consecutive equal frames as `(first frame, length)`, a pose held for several
+    /// frames becomes one run. Lengths add up to the number of frames.
+    pub fn frame_runs(&self) -> Vec<(usize, usize)> {
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        for (index, frame) in self.frames.iter().enumerate() {
+            match runs.last_mut() {
+                Some((first, length)) if self.frames[*first].same_content(frame) => *length += 1,
+                _ => runs.push((index, 1)),
+            }
+        }
+        runs
+    }
+}
+
+impl From<Vec<RawImage>> for AnimationDirection {
+    fn from(frames: Vec<RawImage>) -> Self {
+        Self {
+            frames,
+            shift: (0, 0),
+        }
+    }
+}
+
+impl Animation {
+    /// Union of all frames of all directions, `None` if there are no frames.
+    pub fn bounds(&self) -> Option<Bounds> {
+        self.directions
+            .iter()
+            .flat_map(|direction| &direction.frames)
+            .map(RawImage::bounds)
+            .reduce(Bounds::union)
+    }
+
+    /// Frame placed on a transparent canvas of [`Animation::bounds`], so every frame of every
+    /// direction has the same size and sprite position. Meant for GIF and video export.
+    pub fn compose_frame(&self, direction: usize, frame: usize) -> Option<RawImage> {
+        let raw = self.directions.get(direction)?.frames.get(frame)?;
+        let bounds = self.bounds()?;
+        let mut canvas = image::RgbaImage::new(bounds.width(), bounds.height());
+        image::imageops::replace(
+            &mut canvas,
+            &raw.image,
+            (raw.offset_x as i32 - bounds.left) as i64,
+            (raw.offset_y as i32 - bounds.top) as i64,
+        );
+        Some(RawImage {
+            image: canvas,
+            offset_x: bounds.left as i16,
+            offset_y: bounds.top as i16,
+        })
+    }
+
+    /// Reorders directions from FRM order to the order of `map`.
+    /// Animations with a single direction are returned as is.
+    pub fn remapped(mut self, map: &DirectionMap) -> Result<Self, GetImageError> {
+        self.directions = remap_directions(self.directions, map)?;
+        Ok(self)
+    }
+}
+
+/// Placement of all frames without pixels, see [`Converter::animation_layout`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationLayout {
+    /// Frames per second, 0 if unknown.
+    pub fps: u16,
+    /// Bounds of every frame of every direction, relative to the sprite position.
+    pub directions: Vec<Vec<Bounds>>,
+}
+
+impl AnimationLayout {
+    /// Union of all frames of all directions, `None` if there are no frames.
+    pub fn bounds(&self) -> Option<Bounds> {
+        self.directions.iter().flatten().copied().reduce(Bounds::union)
+    }
+}
+
+fn remap_directions<T>(
+    directions: Vec<T>,
+    map: &DirectionMap,
+) -> Result<Vec<T>, GetImageError> {
+    if directions.len() < 2 || *map == DirectionMap::Identity {
+        return Ok(directions);
+    }
+    let mut source: Vec<_> = directions.into_iter().map(Some).collect();
+    (0..map.len(source.len()))
+        .map(|direction| {
+            map.source(direction)
+                .and_then(|index| source.get_mut(index)?.take())
+                .ok_or(GetImageError::NoDirection)
+        })
+        .collect()
+}
+
+/// Rectangle relative to the sprite position, right and bottom edges are exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bounds {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+impl Bounds {
+    /// Bounds of an image of `dimensions` with top left corner at `offset`.
+    pub fn new(offset: (i16, i16), dimensions: (u32, u32)) -> Self {
+        let (left, top) = (offset.0 as i32, offset.1 as i32);
+        Self {
+            left,
+            top,
+            right: left + dimensions.0 as i32,
+            bottom: top + dimensions.1 as i32,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        (self.right - self.left).max(0) as u32
+    }
+
+    pub fn height(&self) -> u32 {
+        (self.bottom - self.top).max(0) as u32
+    }
+
+    pub fn union(self, other: Bounds) -> Bounds {
+        Bounds {
+            left: self.left.min(other.left),
+            top: self.top.min(other.top),
+            right: self.right.max(other.right),
+            bottom: self.bottom.max(other.bottom),
+        }
+    }
+
+    /// Same rounding as scaling of [`RawImage`].
+    fn scaled(self, scale: f32) -> Self {
+        if (scale - 1.0).abs() < f32::EPSILON {
+            return self;
+        }
+        let scale_offset = |offset: i32| (offset as f32 * scale).round() as i16;
+        let scale_dimension = |dimension: u32| ((dimension as f32 * scale).round() as u32).max(1);
+        Bounds::new(
+            (scale_offset(self.left), scale_offset(self.top)),
+            (scale_dimension(self.width()), scale_dimension(self.height())),
+        )
+    }
+}
+
+/// Maps directions of the consumer to FRM directions, which start from north-east and go
+/// clockwise: NE, E, SE, SW, W, NW.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum DirectionMap {
+    /// Consumer uses FRM order.
+    #[default]
+    Identity,
+    /// Hex directions by angle: counter-clockwise from east, i.e. E, NE, NW, W, SW, SE.
+    FalloutHex,
+    /// FRM direction of every consumer direction, each used at most once.
+    Custom(Vec<usize>),
+}
+
+impl DirectionMap {
+    const FALLOUT_HEX: [usize; 6] = [1, 0, 5, 4, 3, 2];
+
+    /// FRM direction of the consumer `direction`.
+    pub fn source(&self, direction: usize) -> Option<usize> {
+        match self {
+            DirectionMap::Identity => Some(direction),
+            DirectionMap::FalloutHex => Self::FALLOUT_HEX.get(direction).copied(),
+            DirectionMap::Custom(permutation) => permutation.get(direction).copied(),
+        }
+    }
+
+    /// Number of consumer directions for an image with `frm_directions` directions.
+    fn len(&self, frm_directions: usize) -> usize {
+        match self {
+            DirectionMap::Identity => frm_directions,
+            DirectionMap::FalloutHex => Self::FALLOUT_HEX.len(),
+            DirectionMap::Custom(permutation) => permutation.len(),
+        }
+    }
+}
+
+/// Point of an image without own offsets (png) that is placed at the sprite position.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Anchor {
+    /// Scenery, items and critters stand on their position.
+    BottomCenter,
+    Center,
+    /// Tiles and interface elements are drawn from their position.
+    TopLeft,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnchorPolicy {
+    /// Derive anchor from art directory, see [`AnchorPolicy::anchor_for`].
+    ByDirectory,
+    Fixed(Anchor),
+}
+
+impl AnchorPolicy {
+    /// Top left for `art/tiles`, `art/intrface`, `art/splash` and `art/inven`, bottom center otherwise.
+    pub fn anchor_for(&self, path: &str) -> Anchor {
+        const TOP_LEFT_DIRS: &[&str] = &["art/tiles/", "art/intrface/", "art/splash/", "art/inven/"];
+        match self {
+            AnchorPolicy::Fixed(anchor) => *anchor,
+            AnchorPolicy::ByDirectory => {
+                if TOP_LEFT_DIRS.iter().any(|dir| path.starts_with(dir)) {
+                    Anchor::TopLeft
+                } else {
+                    Anchor::BottomCenter
+                }
+            }
+        }
+    }
+}
+
+/// Buffers reused by [`Converter::get_with_scratch`], one per thread of a batch.
+#[derive(Debug, Default)]
+pub struct ConvertScratch {
+    pixels: Vec<u8>,
+    png: Vec<u8>,
+}
+
+/// Image returned instead of an error, see [`ConvertOptionsBuilder::placeholder`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Placeholder {
+    /// Magenta and black checkerboard, anchored as png images of the requested path.
+    Checkerboard { width: u32, height: u32 },
+    /// Image converted with the same options, except of direction and frame.
+    Path(String),
+}
+
+const PLACEHOLDER_COLORS: [[u8; 4]; 2] = [[255, 0, 255, 255], [0, 0, 0, 255]];
+
+/// Options of a single conversion, created with [`ConvertOptions::builder`].
+#[derive(Debug, Clone)]
+pub struct ConvertOptions {
+    direction: usize,
+    direction_map: DirectionMap,
+    frame: usize,
+    split_frm_fallback: bool,
+    anchor: AnchorPolicy,
+    color_key: Option<[u8; 3]>,
+    palette: Option<Palette>,
+    scale: f32,
+    output: DataType,
+    quality: u8,
+    placeholder: Option<Placeholder>,
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        Self {
+            direction: 0,
+            direction_map: DirectionMap::Identity,
+            frame: 0,
+            split_frm_fallback: true,
+            anchor: AnchorPolicy::ByDirectory,
+            color_key: Some([0, 0, 255]),
+            palette: None,
+            scale: 1.0,
+            output: DataType::Png,
+            quality: 80,
+            placeholder: None,
+        }
+    }
+}
+
+impl ConvertOptions {
+    pub fn builder() -> ConvertOptionsBuilder {
+        ConvertOptionsBuilder::default()
+    }
+
+    pub fn direction(&self) -> usize {
+        self.direction
+    }
+
+    pub fn direction_map(&self) -> &DirectionMap {
+        &self.direction_map
+    }
+
+    /// Direction of an image file with `directions` directions, see
+    /// [`ConvertOptionsBuilder::direction_map`]. Files with a single direction are used
+    /// as they are, same as by [`Animation::remapped`].
+    fn source_direction(&self, directions: usize) -> Result<usize, GetImageError> {
+        if directions < 2 {
+            return Ok(self.direction);
+        }
+        self.direction_map
+            .source(self.direction)
+            .ok_or(GetImageError::NoDirection)
+    }
+
+    /// Everything that affects conversion result, except of source files.
+    fn hash(&self, palette: &Palette, hasher: &mut FingerprintHasher) {
+        let direction = self.direction_map.source(self.direction).unwrap_or(usize::MAX);
+        hasher.write(&(direction as u64).to_le_bytes());
+        hasher.write(&(self.frame as u64).to_le_bytes());
+        hasher.write(&[self.split_frm_fallback as u8]);
+        match self.anchor {
+            AnchorPolicy::ByDirectory => hasher.write(&[0]),
+            AnchorPolicy::Fixed(anchor) => hasher.write(&[1, anchor as u8]),
+        }
+        match self.color_key {
+            Some([red, green, blue]) => hasher.write(&[1, red, green, blue]),
+            None => hasher.write(&[0]),
+        }
+        for &(red, green, blue) in palette.colors_tuples() {
+            hasher.write(&[red, green, blue]);
+        }
+        hasher.write(&self.scale.to_bits().to_le_bytes());
+        hasher.write(&[self.output as u8]);
+        #[cfg(feature = "avif")]
+        if self.output == DataType::Avif {
+            hasher.write(&[self.quality]);
+        }
+    }
+
+    /// Options for an image referenced by a frame of animation, it is always a single frame.
+    fn referenced(&self) -> Self {
+        Self {
+            direction: 0,
+            direction_map: DirectionMap::Identity,
+            frame: 0,
+            ..self.clone()
+        }
+    }
+
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    /// Same options with directions in FRM order.
+    pub(crate) fn in_frm_order(&self) -> Self {
+        Self {
+            direction_map: DirectionMap::Identity,
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ConvertOptionsBuilder {
+    options: ConvertOptions,
+}
+
+impl ConvertOptionsBuilder {
+    pub fn direction(mut self, direction: usize) -> Self {
+        self.options.direction = direction;
+        self
+    }
+
+    /// How [`ConvertOptionsBuilder::direction`] is mapped to directions of the image file,
+    /// FRM order by default.
+    pub fn direction_map(mut self, direction_map: DirectionMap) -> Self {
+        self.options.direction_map = direction_map;
+        self
+    }
+
+    pub fn frame(mut self, frame: usize) -> Self {
+        self.options.frame = frame;
+        self
+    }
+
+    /// Read `.fr0`..`.fr5` when `.frm` is missing and vice versa, like the engine does.
+    /// Enabled by default.
+    pub fn split_frm_fallback(mut self, split_frm_fallback: bool) -> Self {
+        self.options.split_frm_fallback = split_frm_fallback;
+        self
+    }
+
+    pub fn anchor(mut self, anchor: AnchorPolicy) -> Self {
+        self.options.anchor = anchor;
+        self
+    }
+
+    /// Color of png images that is made transparent, blue (0, 0, 255) by default.
+    pub fn color_key(mut self, color_key: Option<[u8; 3]>) -> Self {
+        self.options.color_key = color_key;
+        self
+    }
+
+    /// Overrides converter palette, should be already scaled to 8-bit colors.
+    pub fn palette(mut self, palette: Palette) -> Self {
+        self.options.palette = Some(palette);
+        self
+    }
+
+    /// Nearest-neighbor scaling of the result, offsets are scaled too.
+    pub fn scale(mut self, scale: f32) -> Self {
+        self.options.scale = scale;
+        self
+    }
+
+    pub fn output(mut self, output: DataType) -> Self {
+        self.options.output = output;
+        self
+    }
+
+    /// Quality of lossy outputs from 1 to 100, 80 by default. Png and WebP are lossless.
+    pub fn quality(mut self, quality: u8) -> Self {
+        self.options.quality = quality.clamp(1, 100);
+        self
+    }
+
+    /// Return placeholder if the image is missing or can't be converted,
+    /// original error is kept in [`FileData::replaced_error`].
+    /// Error is still returned if the placeholder itself fails.
+    pub fn placeholder(mut self, placeholder: Option<Placeholder>) -> Self {
+        self.options.placeholder = placeholder;
+        self
+    }
+
+    pub fn build(self) -> ConvertOptions {
+        self.options
+    }
+}
+
+/// Pixels of an image that are not fully transparent.
+#[derive(Debug, Clone)]
+pub struct AlphaMask {
+    /// 255 for opaque and semi-transparent pixels, 0 for transparent ones.
+    pub mask: image::GrayImage,
+    pub offset_x: i16,
+    pub offset_y: i16,
+}
+
+impl AlphaMask {
+    /// Hit test of a point relative to the sprite position.
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        self.is_opaque(x - self.offset_x as i32, y - self.offset_y as i32)
+    }
+
+    fn is_opaque(&self, x: i32, y: i32) -> bool {
+        let (width, height) = self.mask.dimensions();
+        x >= 0
+            && y >= 0
+            && (x as u32) < width
+            && (y as u32) < height
+            && self.mask.get_pixel(x as u32, y as u32)[0] != 0
+    }
+
+    /// Clockwise outer boundary pixels of the first opaque region in raster order,
+    /// relative to the sprite position. Empty for fully transparent images.
+    pub fn outline(&self) -> Vec<(i32, i32)> {
+        // Moore neighborhood clockwise, starting from west
+        const NEIGHBORS: [(i32, i32); 8] = [
+            (-1, 0),
+            (-1, -1),
+            (0, -1),
+            (1, -1),
+            (1, 0),
+            (1, 1),
+            (0, 1),
+            (-1, 1),
+        ];
+        let width = self.mask.width() as i32;
+        let start = match self.mask.pixels().position(|pixel| pixel[0] != 0) {
+            Some(index) => (index as i32 % width, index as i32 / width),
+            None => return Vec::new(),
+        };
+
+        let mut outline = vec![start];
+        let (mut current, mut backtrack) = (start, 0);
+        let max_steps = 4 * self.mask.len() + 8;
+        for _ in 0..max_steps {
+            let next = (1..=8).map(|step| (backtrack + step) % 8).find(|&dir| {
+                let (dx, dy) = NEIGHBORS[dir];
+                self.is_opaque(current.0 + dx, current.1 + dy)
+            });
+            let dir = match next {
+                Some(dir) => dir,
+                // single pixel region
+                None => break,
+            };
+            let (dx, dy) = NEIGHBORS[dir];
+            let (bx, by) = NEIGHBORS[(dir + 7) % 8];
+            let checked = (current.0 + bx, current.1 + by);
+            current = (current.0 + dx, current.1 + dy);
+            let delta = (checked.0 - current.0, checked.1 - current.1);
+            backtrack = NEIGHBORS
+                .iter()
+                .position(|&neighbor| neighbor == delta)
+                .expect("Consecutive neighbors are adjacent");
+            if current == start && backtrack == 0 {
+                break;
+            }
+            outline.push(current);
+        }
+        let (offset_x, offset_y) = (self.offset_x as i32, self.offset_y as i32);
+        outline
+            .into_iter()
+            .map(|(x, y)| (x + offset_x, y + offset_y))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Background {
+    /// Light gray checkerboard with square cells of the given size.
+    Checkerboard {
+        cell: u32,
+    },
+    Solid([u8; 3]),
+}
+
+/// Options of [`RawImage::preview`].
+#[derive(Debug, Clone)]
+pub struct PreviewOptions {
+    pub background: Background,
+    /// Color of a cross drawn at the sprite position, canvas is extended to include it.
+    pub anchor_cross: Option<[u8; 4]>,
+    /// Gamma the image is blended over the background with, see [`crate::blend`].
+    pub gamma: f32,
+}
+
+impl Default for PreviewOptions {
+    fn default() -> Self {
+        Self {
+            background: Background::Checkerboard { cell: 8 },
+            anchor_cross: Some([255, 0, 0, 255]),
+            gamma: crate::blend::DEFAULT_GAMMA,
+        }
+    }
+}
+
+impl PreviewOptions {
+    fn hash(&self, hasher: &mut FingerprintHasher) {
+        match self.background {
+            Background::Checkerboard { cell } => {
+                hasher.write(&[0]);
+                hasher.write(&cell.to_le_bytes());
+            }
+            Background::Solid([red, green, blue]) => hasher.write(&[1, red, green, blue]),
+        }
+        match self.anchor_cross {
+            Some(color) => {
+                hasher.write(&[1]);
+                hasher.write(&color);
+            }
+            None => hasher.write(&[0]),
+        }
+        hasher.write(&self.gamma.to_le_bytes());
+    }
+}
+
+const PREVIEW_COLORS: [[u8; 4]; 2] = [[255, 255, 255, 255], [204, 204, 204, 255]];
+/// Length of anchor cross arms.
+const ANCHOR_CROSS: i32 = 4;
+
 #[derive(Debug, Clone)]
 pub struct RawImage {
     pub image: image::RgbaImage,
@@ -63,31 +1265,275 @@ pub struct RawImage {
 }
 
 impl RawImage {
-    fn to_png(self) -> Result<FileData, image::ImageError> {
+    pub fn bounds(&self) -> Bounds {
+        Bounds::new((self.offset_x, self.offset_y), self.image.dimensions())
+    }
+
+    /// Stable hash of the pixels, size and offset, equal frames have equal hashes.
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = FingerprintHasher::new();
+        hasher.write(&self.offset_x.to_le_bytes());
+        hasher.write(&self.offset_y.to_le_bytes());
+        hasher.write(&self.image.width().to_le_bytes());
+        hasher.write(&self.image.height().to_le_bytes());
+        hasher.write(self.image.as_raw());
+        hasher.finish().0
+    }
+
+    fn same_content(&self, other: &RawImage) -> bool {
+        (self.offset_x, self.offset_y) == (other.offset_x, other.offset_y)
+            && self.image == other.image
+    }
+
+    /// Fallout "egg" effect: the part of this image covered by the `egg` image placed at `position`
+    /// becomes see-through. Both the position and this image are relative to the same sprite position.
+    /// Alpha of covered pixels is lowered down to `alpha` in proportion to opacity of the egg.
+    pub fn with_egg(&self, egg: &RawImage, position: (i32, i32), alpha: u8) -> RawImage {
+        let mut image = self.image.clone();
+        let egg_left = position.0 + egg.offset_x as i32 - self.offset_x as i32;
+        let egg_top = position.1 + egg.offset_y as i32 - self.offset_y as i32;
+        let (egg_width, egg_height) = egg.image.dimensions();
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            let (egg_x, egg_y) = (x as i32 - egg_left, y as i32 - egg_top);
+            if egg_x < 0 || egg_y < 0 || egg_x >= egg_width as i32 || egg_y >= egg_height as i32 {
+                continue;
+            }
+            let coverage = egg.image.get_pixel(egg_x as u32, egg_y as u32)[3] as u32;
+            let factor = 255 * 255 - coverage * (255 - alpha as u32);
+            pixel[3] = ((pixel[3] as u32 * factor + 255 * 255 / 2) / (255 * 255)) as u8;
+        }
+        RawImage {
+            image,
+            offset_x: self.offset_x,
+            offset_y: self.offset_y,
+        }
+    }
+
+    pub fn alpha_mask(&self) -> AlphaMask {
+        let (width, height) = self.image.dimensions();
+        let mask = image::GrayImage::from_fn(width, height, |x, y| {
+            let opaque = self.image.get_pixel(x, y)[3] != 0;
+            image::Luma([if opaque { 255 } else { 0 }])
+        });
+        AlphaMask {
+            mask,
+            offset_x: self.offset_x,
+            offset_y: self.offset_y,
+        }
+    }
+
+    /// Opaque image with this one alpha blended over the background, offset is adjusted
+    /// so the sprite position stays the same.
+    pub fn preview(&self, preview: &PreviewOptions) -> RawImage {
+        let (width, height) = self.image.dimensions();
+        let (offset_x, offset_y) = (self.offset_x as i32, self.offset_y as i32);
+        let (mut left, mut top) = (offset_x, offset_y);
+        let (mut right, mut bottom) = (offset_x + width as i32, offset_y + height as i32);
+        if preview.anchor_cross.is_some() {
+            left = left.min(-ANCHOR_CROSS);
+            top = top.min(-ANCHOR_CROSS);
+            right = right.max(ANCHOR_CROSS + 1);
+            bottom = bottom.max(ANCHOR_CROSS + 1);
+        }
+        let (canvas_width, canvas_height) = ((right - left) as u32, (bottom - top) as u32);
+
+        let mut canvas = match preview.background {
+            Background::Checkerboard { cell } => {
+                checkerboard(canvas_width, canvas_height, cell, PREVIEW_COLORS)
+            }
+            Background::Solid([red, green, blue]) => image::RgbaImage::from_pixel(
+                canvas_width,
+                canvas_height,
+                image::Rgba([red, green, blue, 255]),
+            ),
+        };
+        let (image_x, image_y) = ((offset_x - left) as i64, (offset_y - top) as i64);
+        let blender = crate::blend::Blender::new(preview.gamma);
+        blender.overlay(&mut canvas, &self.image, image_x, image_y);
+        if let Some(color) = preview.anchor_cross {
+            let (anchor_x, anchor_y) = (-left, -top);
+            let color = image::Rgba(color);
+            for delta in -ANCHOR_CROSS..=ANCHOR_CROSS {
+                canvas.put_pixel((anchor_x + delta) as u32, anchor_y as u32, color);
+                canvas.put_pixel(anchor_x as u32, (anchor_y + delta) as u32, color);
+            }
+        }
+        RawImage {
+            image: canvas,
+            offset_x: left as i16,
+            offset_y: top as i16,
+        }
+    }
+
+    fn scaled(self, scale: f32) -> Self {
+        if (scale - 1.0).abs() < f32::EPSILON {
+            return self;
+        }
+        let (width, height) = self.image.dimensions();
+        let scale_dimension = |dimension: u32| ((dimension as f32 * scale).round() as u32).max(1);
+        let image = image::imageops::resize(
+            &self.image,
+            scale_dimension(width),
+            scale_dimension(height),
+            image::imageops::FilterType::Nearest,
+        );
+        RawImage {
+            image,
+            offset_x: (self.offset_x as f32 * scale).round() as i16,
+            offset_y: (self.offset_y as f32 * scale).round() as i16,
+        }
+    }
+
+    fn into_rgba(self, fingerprint: Fingerprint) -> FileData {
         let dimensions = self.image.dimensions();
-        let size = (dimensions.0 as usize * dimensions.1 as usize * 4 + 512).next_power_of_two();
-        let image = image::DynamicImage::ImageRgba8(self.image);
-        let data = Vec::with_capacity(size);
-        let mut cursor = Cursor::new(data);
+        FileData {
+            data: self.image.into_raw().into(),
+            data_type: DataType::Rgba,
+            dimensions,
+            offset: (self.offset_x, self.offset_y),
+            fingerprint,
+            replaced_error: None,
+        }
+    }
 
-        image.write_to(&mut cursor, image::ImageFormat::Png)?;
+    fn into_png(self, fingerprint: Fingerprint) -> Result<FileData, image::ImageError> {
+        self.encode_with(fingerprint, DataType::Png, encode_png)
+    }
+
+    fn encode_with(
+        self,
+        fingerprint: Fingerprint,
+        data_type: DataType,
+        encode: impl FnOnce(&image::RgbaImage, &mut Vec<u8>) -> Result<(), image::ImageError>,
+    ) -> Result<FileData, image::ImageError> {
+        let dimensions = self.image.dimensions();
+        let size = (dimensions.0 as usize * dimensions.1 as usize * 4 + 512).next_power_of_two();
+        let mut data = Vec::with_capacity(size);
+        encode(&self.image, &mut data)?;
         Ok(FileData {
-            data: cursor.into_inner().into(),
-            data_type: DataType::Png,
+            data: data.into(),
+            data_type,
             dimensions,
             offset: (self.offset_x, self.offset_y),
+            fingerprint,
+            replaced_error: None,
         })
     }
 }
 
+/// Stable hash of conversion sources and options, doesn't change between runs and platforms.
+/// Suitable as an ETag of converted image without hashing the result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fingerprint(pub u64);
+
+impl Fingerprint {
+    /// Strong HTTP entity tag, with quotes.
+    pub fn etag(&self) -> String {
+        format!("\"{:016x}\"", self.0)
+    }
+}
+
+/// 64-bit FNV-1a.
+pub(crate) struct FingerprintHasher(u64);
+
+impl FingerprintHasher {
+    pub(crate) fn new() -> Self {
+        FingerprintHasher(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    /// Length prefixed, so consecutive sources can't be confused.
+    fn write_source(&mut self, path: &str, data: &[u8]) {
+        for part in &[path.as_bytes(), data] {
+            self.write(&(part.len() as u64).to_le_bytes());
+            self.write(part);
+        }
+    }
+
+    pub(crate) fn finish(&self) -> Fingerprint {
+        Fingerprint(self.0)
+    }
+}
+
+/// Checkerboard of two colors with square cells of `cell` pixels.
+fn checkerboard(width: u32, height: u32, cell: u32, colors: [[u8; 4]; 2]) -> image::RgbaImage {
+    let cell = cell.max(1);
+    image::RgbaImage::from_fn(width, height, |x, y| {
+        image::Rgba(colors[((x / cell + y / cell) % 2) as usize])
+    })
+}
+
+/// Replaces contents of `out` with png encoded image.
+pub(crate) fn encode_png(image: &image::RgbaImage, out: &mut Vec<u8>) -> Result<(), image::ImageError> {
+    use image::ImageEncoder;
+
+    out.clear();
+    let (width, height) = image.dimensions();
+    image::codecs::png::PngEncoder::new(out).write_image(
+        image.as_raw(),
+        width,
+        height,
+        image::ColorType::Rgba8,
+    )
+}
+
+#[cfg(feature = "webp")]
+fn encode_webp(image: &image::RgbaImage, out: &mut Vec<u8>) -> Result<(), image::ImageError> {
+    out.clear();
+    let (width, height) = image.dimensions();
+    image::codecs::webp::WebPEncoder::new_lossless(out).encode(
+        image.as_raw(),
+        width,
+        height,
+        image::ColorType::Rgba8,
+    )
+}
+
+#[cfg(feature = "avif")]
+fn encode_avif(
+    image: &image::RgbaImage,
+    quality: u8,
+    out: &mut Vec<u8>,
+) -> Result<(), image::ImageError> {
+    use image::ImageEncoder;
+
+    /// Default of `cavif`, slower speeds take much longer for little gain.
+    const SPEED: u8 = 4;
+
+    out.clear();
+    let (width, height) = image.dimensions();
+    image::codecs::avif::AvifEncoder::new_with_speed_quality(out, SPEED, quality).write_image(
+        image.as_raw(),
+        width,
+        height,
+        image::ColorType::Rgba8,
+    )
+}
+
+/// State of the converter passed through references.
+#[derive(Clone, Copy)]
+struct Shared<'a> {
+    lut: Option<&'a RgbaLut>,
+    metrics: Option<&'a Metrics>,
+}
+
 fn get_raw<R: Retriever>(
     retriever: &R,
     path: &str,
     recursion: usize,
-    palette: Option<&[(u8, u8, u8)]>,
+    shared: Shared<'_>,
+    pixels: &mut Vec<u8>,
+    hasher: &mut FingerprintHasher,
+    options: &ConvertOptions,
 ) -> Result<RawImage, GetImageError>
 where
-    R::Error: Into<GetImageError>,
+    R::Error: Into<RetrieveError>,
 {
     const RECURSION_LIMIT: usize = 1;
     if recursion > RECURSION_LIMIT {
@@ -97,110 +1543,995 @@ where
 
     Ok(match file_type {
         FileType::Png => {
-            let data = retriever.file_by_path(path).map_err(Into::into)?;
+            let data = retriever
+                .file_bytes_by_path(path)
+                .map_err(GetImageError::retrieve)?;
+            hasher.write_source(path, &data);
             let slice = &data[..];
 
             let dynamic = image::load_from_memory_with_format(slice, image::ImageFormat::Png)
                 .map_err(GetImageError::PngDecode)?;
             let mut image = dynamic.into_rgba8();
             let (width, height) = image.dimensions();
+            apply_color_key(&mut image, options.color_key);
 
-            image.pixels_mut().for_each(|pixel| {
-                if pixel.0 == [0, 0, 255, 255] {
-                    pixel.0 = [0, 0, 0, 0];
-                }
-            });
+            let (offset_x, offset_y) = png_offset(width, height, options.anchor.anchor_for(path));
+            RawImage {
+                image,
+                offset_x,
+                offset_y,
+            }
+        }
+        FileType::Gif => {
+            let data = retriever
+                .file_bytes_by_path(path)
+                .map_err(GetImageError::retrieve)?;
+            hasher.write_source(path, &data);
+            let (mut image, _delay) = gif_frames(&data)?
+                .nth(options.frame)
+                .ok_or(GetImageError::NoFrame)??;
+            let (width, height) = image.dimensions();
+            apply_color_key(&mut image, options.color_key);
 
+            let (offset_x, offset_y) = png_offset(width, height, options.anchor.anchor_for(path));
             RawImage {
                 image,
-                offset_x: width as i16 / -2,
-                offset_y: height as i16 * -1,
+                offset_x,
+                offset_y,
             }
         }
         FileType::Frm => {
-            let palette = palette.ok_or(GetImageError::NoPallete)?;
-            let data = retriever.file_by_path(path).map_err(Into::into)?;
-            let frm = frm::frm(&data).map_err(GetImageError::FrmParse)?;
-            let frame_number = 0;
-
-            let direction = frm.directions.get(0).ok_or(GetImageError::NoDirection)?;
-            let frame = direction
-                .frames
-                .get(frame_number)
-                .ok_or(GetImageError::NoFrame)?;
+            let lut = shared.lut.ok_or(GetImageError::NoPallete)?;
+            let (path, direction_number, data) = read_frm(retriever, path, options)?;
+            hasher.write_source(&path, &data);
+            let size = data.len();
+            let decode = || {
+                let frm = frm::LazyFrm::parse(data).map_err(GetImageError::FrmParse)?;
+                let direction = frm
+                    .header()
+                    .directions
+                    .get(direction_number)
+                    .ok_or(GetImageError::NoDirection)?;
+                let frame = frm
+                    .get_frame(direction_number, options.frame)
+                    .ok_or(GetImageError::NoFrame)?;
 
-            let offsets = direction.frames.iter().skip(1).take(frame_number);
-            let offset_x: i16 = offsets.clone().map(|frame| frame.offset_x).sum();
-            let offset_y: i16 = offsets.map(|frame| frame.offset_y).sum();
-
-            let image = image::GrayImage::from_raw(
-                frame.width as u32,
-                frame.height as u32,
-                frame.data.to_owned(),
-            )
-            .ok_or(GetImageError::ImageFromRaw)?;
-            let image = image.expand_palette(palette, Some(0));
+                let image = frm_frame_image(lut, &frame, std::mem::take(pixels))?;
+                Ok((image, frm_header_frame_offset(direction, options.frame)))
+            };
+            let (image, (offset_x, offset_y)) =
+                measure(shared.metrics, Operation::FrmDecode, decode, |_| size)?;
             RawImage {
                 image,
-                offset_x: direction.shift_x + offset_x - frame.width as i16 / 2,
-                offset_y: direction.shift_y + offset_y - frame.height as i16,
+                offset_x,
+                offset_y,
             }
         }
         FileType::FoFrm => {
-            let mut full_path = std::path::Path::new(path)
-                .parent()
-                .ok_or(GetImageError::NoParentFolder)?
-                .to_owned();
-            let data = retriever.file_by_path(path).map_err(Into::into)?;
+            let data = retriever
+                .file_bytes_by_path(path)
+                .map_err(GetImageError::retrieve)?;
+            hasher.write_source(path, &data);
+            let frame = fofrm_frame(path, &data, options, retriever.path_rules())?;
+
+            let (mut image, _rule) = frame
+                .first_found(|full_path| {
+                    get_raw(
+                        retriever,
+                        full_path,
+                        recursion + 1,
+                        shared,
+                        pixels,
+                        hasher,
+                        &options.referenced(),
+                    )
+                })
+                .map_err(GetImageError::recursion)?;
+            image.offset_x = image.offset_x.saturating_add(frame.offset.0);
+            image.offset_y = image.offset_y.saturating_add(frame.offset.1);
+            image
+        }
+        _ => return Err(GetImageError::FileType(file_type)),
+    })
+}
 
-            let string = std::str::from_utf8(&data).map_err(GetImageError::Utf8)?;
-            let fofrm = fofrm::parse_verbose(&string).map_err(GetImageError::FoFrmParse)?;
-            let frame_number = 0;
+/// Unscaled bounds of the frame from headers of the file and of files it references.
+fn get_placement<R: Retriever>(
+    retriever: &R,
+    path: &str,
+    recursion: usize,
+    options: &ConvertOptions,
+) -> Result<Bounds, GetImageError>
+where
+    R::Error: Into<RetrieveError>,
+{
+    const RECURSION_LIMIT: usize = 1;
+    if recursion > RECURSION_LIMIT {
+        return Err(GetImageError::RecursionLimit);
+    }
+    let file_type = retriever::recognize_type(path);
+
+    let (direction_number, data) = match file_type {
+        FileType::Frm => {
+            let (_path, direction_number, data) = read_frm(retriever, path, options)?;
+            (direction_number, data)
+        }
+        FileType::Png | FileType::Gif | FileType::FoFrm => {
+            let data = retriever
+                .file_bytes_by_path(path)
+                .map_err(GetImageError::retrieve)?;
+            (0, data)
+        }
+        _ => return Err(GetImageError::FileType(file_type)),
+    };
+    Ok(match file_type {
+        FileType::Png => {
+            let (width, height) =
+                image::io::Reader::with_format(Cursor::new(&data), image::ImageFormat::Png)
+                    .into_dimensions()
+                    .map_err(GetImageError::PngDecode)?;
+            let offset = png_offset(width, height, options.anchor.anchor_for(path));
+            Bounds::new(offset, (width, height))
+        }
+        FileType::Gif => {
+            use image::ImageDecoder;
 
-            let direction = fofrm.directions.get(0).ok_or(GetImageError::NoDirection)?;
+            let decoder = image::codecs::gif::GifDecoder::new(Cursor::new(&data))
+                .map_err(GetImageError::GifDecode)?;
+            let (width, height) = decoder.dimensions();
+            let offset = png_offset(width, height, options.anchor.anchor_for(path));
+            Bounds::new(offset, (width, height))
+        }
+        FileType::Frm => {
+            let frm = frm::parse_header(&data).map_err(GetImageError::FrmParse)?;
+            let direction = frm
+                .directions
+                .get(direction_number)
+                .ok_or(GetImageError::NoDirection)?;
             let frame = direction
                 .frames
-                .get(frame_number)
+                .get(options.frame)
                 .ok_or(GetImageError::NoFrame)?;
+            let offset = frm_header_frame_offset(direction, options.frame);
+            Bounds::new(offset, (frame.width as u32, frame.height as u32))
+        }
+        _ => {
+            let frame = fofrm_frame(path, &data, options, retriever.path_rules())?;
+            let (bounds, _rule) = frame
+                .first_found(|full_path| {
+                    get_placement(retriever, full_path, recursion + 1, &options.referenced())
+                })
+                .map_err(GetImageError::recursion)?;
+            let offset = (
+                (bounds.left as i16).saturating_add(frame.offset.0),
+                (bounds.top as i16).saturating_add(frame.offset.1),
+            );
+            Bounds::new(offset, (bounds.width(), bounds.height()))
+        }
+    })
+}
 
-            let offsets = direction.frames.iter().skip(1).take(frame_number);
-            let mut offset_x: i16 = offsets.clone().map(|frame| frame.next_x.unwrap_or(0)).sum();
-            let mut offset_y: i16 = offsets.map(|frame| frame.next_y.unwrap_or(0)).sum();
-
-            offset_x += direction.offset_x.or(fofrm.offset_x).unwrap_or(0);
-            offset_y += direction.offset_y.or(fofrm.offset_y).unwrap_or(0);
-
-            let relative_path = frame.frm.ok_or(GetImageError::NoFrame)?;
-            //dbg!(&full_path, &relative_path);
-            for component in std::path::Path::new(relative_path).components() {
-                use std::path::Component;
-                if !match component {
-                    Component::ParentDir => full_path.pop(),
-                    Component::Normal(str) => {
-                        full_path.push(str);
-                        true
-                    }
-                    _ => false,
-                } {
-                    return Err(GetImageError::InvalidRelativePath(
-                        path.into(),
-                        relative_path.into(),
-                    ));
+/// Reads FRM at `path` or its split counterpart, see
+/// [`ConvertOptionsBuilder::split_frm_fallback`]. Returns the path actually read
+/// and the direction inside of it.
+fn read_frm<R: Retriever>(
+    retriever: &R,
+    path: &str,
+    options: &ConvertOptions,
+) -> Result<(String, usize, bytes::Bytes), GetImageError>
+where
+    R::Error: Into<RetrieveError>,
+{
+    let err = match retriever.file_bytes_by_path(path) {
+        Ok(data) => {
+            // broken headers are reported when the file is decoded
+            let header = frm::parse_header(&data);
+            let directions = header.map_or(1, |header| header.directions.len());
+            let direction = options.source_direction(directions)?;
+            return Ok((path.to_owned(), direction, data));
+        }
+        Err(err) => GetImageError::retrieve(err),
+    };
+    // split files come in sets of all directions
+    let direction = options.source_direction(crate::critters::DIRECTIONS as usize)?;
+    let counterpart = match references::split_frm_counterpart(path, direction) {
+        Some(counterpart) if options.split_frm_fallback => counterpart,
+        _ => return Err(err),
+    };
+    let (counterpart, direction) = counterpart;
+    match retriever.file_bytes_by_path(&counterpart) {
+        Ok(data) => Ok((counterpart, direction, data)),
+        // error of the requested path is more telling
+        Err(_) => Err(err),
+    }
+}
+
+/// Expands frame into `pixels`, reusing its allocation.
+fn frm_frame_image(
+    lut: &RgbaLut,
+    frame: &frm::Frame,
+    mut pixels: Vec<u8>,
+) -> Result<image::RgbaImage, GetImageError> {
+    let (width, height) = (frame.width as u32, frame.height as u32);
+    let indices = frame
+        .data
+        .get(..width as usize * height as usize)
+        .ok_or(GetImageError::ImageFromRaw)?;
+    pixels.clear();
+    lut.expand_into(indices, &mut pixels);
+    image::RgbaImage::from_raw(width, height, pixels).ok_or(GetImageError::ImageFromRaw)
+}
+
+/// Makes pixels of `color_key` transparent.
+fn apply_color_key(image: &mut image::RgbaImage, color_key: Option<[u8; 3]>) {
+    if let Some([red, green, blue]) = color_key {
+        image.pixels_mut().for_each(|pixel| {
+            if pixel.0 == [red, green, blue, 255] {
+                pixel.0 = [0, 0, 0, 0];
+            }
+        });
+    }
+}
+
+/// Frame of a GIF composed on its whole canvas and its delay in milliseconds.
+type GifFrame = (image::RgbaImage, u32);
+
+/// Frames of a GIF, decoded lazily.
+fn gif_frames(
+    data: &[u8],
+) -> Result<impl Iterator<Item = Result<GifFrame, GetImageError>> + '_, GetImageError> {
+    use image::AnimationDecoder;
+
+    let decoder =
+        image::codecs::gif::GifDecoder::new(Cursor::new(data)).map_err(GetImageError::GifDecode)?;
+    Ok(decoder.into_frames().map(|frame| {
+        let frame = frame.map_err(GetImageError::GifDecode)?;
+        let (numerator, denominator) = frame.delay().numer_denom_ms();
+        Ok((frame.into_buffer(), numerator / denominator.max(1)))
+    }))
+}
+
+/// Frames per second of a GIF by the delay of its first frame, 0 if it has no delay.
+fn gif_fps(delay: u32) -> u16 {
+    match delay {
+        0 => 0,
+        delay => (1000 / delay).clamp(1, u16::MAX as u32) as u16,
+    }
+}
+
+fn png_offset(width: u32, height: u32, anchor: Anchor) -> (i16, i16) {
+    match anchor {
+        Anchor::BottomCenter => (width as i16 / -2, -(height as i16)),
+        Anchor::Center => (width as i16 / -2, height as i16 / -2),
+        Anchor::TopLeft => (0, 0),
+    }
+}
+
+/// Engine places frame N at the position of frame 0 moved by shifts of frames 1..=N,
+/// shift of frame 0 itself is never applied.
+fn accumulate_shifts(shifts: impl Iterator<Item = (i16, i16)>, frame_number: usize) -> (i16, i16) {
+    shifts
+        .skip(1)
+        .take(frame_number)
+        .fold((0, 0), |(x, y), (shift_x, shift_y)| {
+            (x.saturating_add(shift_x), y.saturating_add(shift_y))
+        })
+}
+
+fn frm_frame_offset(direction: &frm::Direction, frame_number: usize) -> (i16, i16) {
+    let frames = direction.frames.iter().map(frm::Frame::header);
+    placed_frame_offset((direction.shift_x, direction.shift_y), frames, frame_number)
+}
+
+/// Same as [`frm_frame_offset`] for a direction parsed by [`frm::parse_header`].
+fn frm_header_frame_offset(direction: &frm::DirectionHeader, frame_number: usize) -> (i16, i16) {
+    let frames = direction.frames.iter().copied();
+    placed_frame_offset((direction.shift_x, direction.shift_y), frames, frame_number)
+}
+
+fn scaled_shift((shift_x, shift_y): (i16, i16), scale: f32) -> (i16, i16) {
+    let scale_shift = |shift: i16| (shift as f32 * scale).round() as i16;
+    (scale_shift(shift_x), scale_shift(shift_y))
+}
+
+fn placed_frame_offset(
+    (shift_x, shift_y): (i16, i16),
+    frames: impl Iterator<Item = frm::FrameHeader> + Clone,
+    frame_number: usize,
+) -> (i16, i16) {
+    let frame = frames.clone().nth(frame_number).expect("Frame number is checked");
+    let shifts = frames.map(|frame| (frame.offset_x, frame.offset_y));
+    let (frame_shift_x, frame_shift_y) = accumulate_shifts(shifts, frame_number);
+    (
+        shift_x
+            .saturating_add(frame_shift_x)
+            .saturating_sub(frame.width as i16 / 2),
+        shift_y
+            .saturating_add(frame_shift_y)
+            .saturating_sub(frame.height as i16),
+    )
+}
+
+struct FoFrmFrame {
+    /// Offset on top of the referenced image own offset.
+    offset: (i16, i16),
+    /// Never empty, see [`references::reference_candidates`].
+    candidates: Vec<(String, references::ReferenceRule)>,
+}
+
+impl FoFrmFrame {
+    fn new(
+        path: &str,
+        frame: &fofrm::Frame<'_>,
+        offset: (i16, i16),
+        rules: &paths::PathRules,
+    ) -> Result<Self, GetImageError> {
+        let relative_path = frame.frm.ok_or(GetImageError::NoFrame)?;
+        let candidates = references::reference_candidates(path, relative_path, rules);
+        if candidates.is_empty() {
+            return Err(GetImageError::InvalidRelativePath(path.into(), relative_path.into()));
+        }
+        Ok(Self { offset, candidates })
+    }
+
+    /// Result of `read` for the first candidate that isn't missing.
+    fn first_found<T>(
+        &self,
+        mut read: impl FnMut(&str) -> Result<T, GetImageError>,
+    ) -> Result<(T, references::ReferenceRule), GetImageError> {
+        let mut missing = None;
+        for (full_path, rule) in &self.candidates {
+            match read(full_path) {
+                Ok(found) => return Ok((found, *rule)),
+                Err(err @ GetImageError::Retrieve(_)) => {
+                    missing.get_or_insert(err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Err(missing.expect("Candidates are never empty"))
+    }
+}
+
+fn fofrm_frame(
+    path: &str,
+    data: &[u8],
+    options: &ConvertOptions,
+    rules: &paths::PathRules,
+) -> Result<FoFrmFrame, GetImageError> {
+    let string = std::str::from_utf8(data).map_err(GetImageError::Utf8)?;
+    let fofrm = fofrm::parse_verbose(string).map_err(GetImageError::FoFrmParse)?;
+
+    let direction = fofrm
+        .directions
+        .get(options.source_direction(fofrm.directions.len())?)
+        .ok_or(GetImageError::NoDirection)?;
+    let frame = direction
+        .frames
+        .get(options.frame)
+        .ok_or(GetImageError::NoFrame)?;
+
+    let shifts = direction.frames.iter().map(|frame| {
+        (frame.next_x.unwrap_or(0), frame.next_y.unwrap_or(0))
+    });
+    let (shift_x, shift_y) = accumulate_shifts(shifts, options.frame);
+    let offset = (
+        shift_x.saturating_add(direction.offset_x.or(fofrm.offset_x).unwrap_or(0)),
+        shift_y.saturating_add(direction.offset_y.or(fofrm.offset_y).unwrap_or(0)),
+    );
+
+    FoFrmFrame::new(path, frame, offset, rules)
+}
+
+/// Every frame of a direction of a parsed fofrm, shifts are accumulated in a single pass.
+fn fofrm_direction_frames(
+    path: &str,
+    fofrm: &fofrm::FoFrmRaw<'_>,
+    direction: &fofrm::Direction<'_>,
+    rules: &paths::PathRules,
+) -> Result<Vec<FoFrmFrame>, GetImageError> {
+    let base_x = direction.offset_x.or(fofrm.offset_x).unwrap_or(0);
+    let base_y = direction.offset_y.or(fofrm.offset_y).unwrap_or(0);
+    let (mut shift_x, mut shift_y) = (0i16, 0i16);
+    direction
+        .frames
+        .iter()
+        .enumerate()
+        .map(|(frame_number, frame)| {
+            if frame_number > 0 {
+                shift_x = shift_x.saturating_add(frame.next_x.unwrap_or(0));
+                shift_y = shift_y.saturating_add(frame.next_y.unwrap_or(0));
+            }
+            let offset = (shift_x.saturating_add(base_x), shift_y.saturating_add(base_y));
+            FoFrmFrame::new(path, frame, offset, rules)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shifts_of_frames_after_first() {
+        let shifts = [(5, 5), (1, -2), (3, 4), (10, 10)];
+        assert_eq!(accumulate_shifts(shifts.iter().copied(), 0), (0, 0));
+        assert_eq!(accumulate_shifts(shifts.iter().copied(), 1), (1, -2));
+        assert_eq!(accumulate_shifts(shifts.iter().copied(), 2), (4, 2));
+        assert_eq!(accumulate_shifts(shifts.iter().copied(), 3), (14, 12));
+    }
+
+    #[test]
+    fn direction_maps() {
+        let frame = |direction: u32| RawImage {
+            image: image::RgbaImage::new(direction + 1, 1),
+            offset_x: 0,
+            offset_y: 0,
+        };
+        let animation = Animation {
+            fps: 10,
+            directions: (0..6).map(|direction| vec![frame(direction)].into()).collect(),
+        };
+        let widths = |animation: &Animation| -> Vec<u32> {
+            let first = |direction: &AnimationDirection| direction.frames[0].image.width() - 1;
+            animation.directions.iter().map(first).collect()
+        };
+
+        let identity = animation.clone().remapped(&DirectionMap::Identity).unwrap();
+        assert_eq!(widths(&identity), [0, 1, 2, 3, 4, 5]);
+        let hex = animation.clone().remapped(&DirectionMap::FalloutHex).unwrap();
+        assert_eq!(widths(&hex), [1, 0, 5, 4, 3, 2]);
+        let custom = DirectionMap::Custom(vec![5, 0, 1, 2, 3, 4]);
+        assert_eq!(widths(&animation.clone().remapped(&custom).unwrap()), [5, 0, 1, 2, 3, 4]);
+        let repeated = DirectionMap::Custom(vec![0, 0]);
+        assert!(animation.remapped(&repeated).is_err());
+
+        let options = ConvertOptions::builder()
+            .direction(2)
+            .direction_map(DirectionMap::FalloutHex)
+            .build();
+        assert_eq!(options.source_direction(6).unwrap(), 5);
+        let options = ConvertOptions::builder()
+            .direction(6)
+            .direction_map(DirectionMap::FalloutHex)
+            .build();
+        assert!(options.source_direction(6).is_err());
+
+        // tiles and items have a single direction, which every map leaves as it is
+        let frame = frm::FrameHeader {
+            width: 3,
+            height: 2,
+            offset_x: 0,
+            offset_y: 0,
+        };
+        let data = crate::testing::frm_fixture(10, (0, 0), &[frame], 1);
+        let retriever = MemoryRetriever::new().with_file("art/tiles/a.frm", data);
+        let palette = crate::testing::gradient_palette();
+        let converter = Converter::new(&retriever, &palette);
+        let options = ConvertOptions::builder()
+            .direction_map(DirectionMap::FalloutHex)
+            .output(DataType::Rgba)
+            .build();
+        assert_eq!(converter.get_with("art/tiles/a.frm", &options).unwrap().dimensions, (3, 2));
+        let animation = converter.get_animation("art/tiles/a.frm", &options).unwrap();
+        assert_eq!(animation.directions.len(), 1);
+    }
+
+    #[test]
+    fn repeated_frames() {
+        let frame = |value: u8, offset_x: i16| RawImage {
+            image: image::RgbaImage::from_pixel(2, 2, image::Rgba([value, 0, 0, 255])),
+            offset_x,
+            offset_y: 0,
+        };
+        let idle = AnimationDirection::from(vec![
+            frame(1, 0),
+            frame(1, 0),
+            frame(2, 0),
+            frame(1, 0),
+            frame(1, 1),
+        ]);
+        let hashes = idle.frame_hashes();
+        assert_eq!(hashes[0], hashes[1]);
+        assert_eq!(hashes[0], hashes[3]);
+        assert_ne!(hashes[0], hashes[2]);
+        assert_ne!(hashes[0], hashes[4], "offsets are part of the content");
+        assert_eq!(idle.duplicate_frames(), [None, Some(0), None, Some(0), None]);
+        assert_eq!(idle.frame_runs(), [(0, 2), (2, 1), (3, 1), (4, 1)]);
+        assert!(AnimationDirection::default().frame_runs().is_empty());
+    }
+
+    #[test]
+    fn compose_on_common_canvas() {
+        let red = image::Rgba([255, 0, 0, 255]);
+        let animation = Animation {
+            fps: 10,
+            directions: vec![
+                vec![RawImage {
+                    image: image::RgbaImage::from_pixel(2, 3, red),
+                    offset_x: -1,
+                    offset_y: -3,
+                }]
+                .into(),
+                vec![RawImage {
+                    image: image::RgbaImage::from_pixel(4, 1, red),
+                    offset_x: 0,
+                    offset_y: -5,
+                }]
+                .into(),
+            ],
+        };
+        let bounds = animation.bounds().unwrap();
+        assert_eq!((bounds.left, bounds.top, bounds.right, bounds.bottom), (-1, -5, 4, 0));
+
+        let composed = animation.compose_frame(0, 0).unwrap();
+        assert_eq!(composed.bounds(), bounds);
+        assert_eq!(composed.image.get_pixel(0, 1)[3], 0);
+        assert_eq!(*composed.image.get_pixel(0, 2), red);
+        assert_eq!(*composed.image.get_pixel(1, 4), red);
+        assert_eq!(composed.image.get_pixel(2, 2)[3], 0);
+        let composed = animation.compose_frame(1, 0).unwrap();
+        assert_eq!(*composed.image.get_pixel(4, 0), red);
+        assert!(animation.compose_frame(1, 1).is_none());
+    }
+
+    /// Serves a 3x2 png and a fofrm of two directions referencing it.
+    struct PngAndFoFrm;
+
+    impl Retriever for PngAndFoFrm {
+        type Error = std::io::Error;
+
+        fn file_by_path(&self, path: &str) -> Result<Vec<u8>, Self::Error> {
+            match path {
+                "art/a.png" => {
+                    let image = image::RgbaImage::from_pixel(3, 2, image::Rgba([1, 2, 3, 255]));
+                    let mut png = Vec::new();
+                    encode_png(&image, &mut png).unwrap();
+                    Ok(png)
                 }
+                "art/a.fofrm" => Ok(b"fps=5\ncount=2\n\
+                    [dir_0]\nfrm_0=a.png\nfrm_1=a.png\nnext_x_1=4\n\
+                    [dir_1]\noffs_y=-1\nfrm_0=a.png\nfrm_1=a.png\n"
+                    .to_vec()),
+                _ => Err(std::io::ErrorKind::NotFound.into()),
+            }
+        }
+    }
+
+    #[test]
+    fn frm_layout_from_headers() {
+        struct OneFrm(Vec<u8>);
+        impl Retriever for OneFrm {
+            type Error = std::io::Error;
+
+            fn file_by_path(&self, _path: &str) -> Result<Vec<u8>, Self::Error> {
+                Ok(self.0.clone())
             }
-            let full_path = nom_prelude::make_path_conventional(
-                full_path
-                    .to_str()
-                    .expect("Convert full path back to string"),
+        }
+
+        let frames = [
+            frm::FrameHeader {
+                width: 4,
+                height: 6,
+                offset_x: 9,
+                offset_y: 9,
+            },
+            frm::FrameHeader {
+                width: 2,
+                height: 3,
+                offset_x: 1,
+                offset_y: -2,
+            },
+        ];
+        let retriever = OneFrm(crate::testing::frm_fixture(10, (3, 4), &frames, 1));
+        let palette = Palette::default();
+        let converter = Converter::new(&retriever, &palette);
+        let options = ConvertOptions::default();
+        let layout = converter.animation_layout("art/a.frm", &options).unwrap();
+        let animation = converter.get_animation("art/a.frm", &options).unwrap();
+        assert_eq!(layout.directions[0][1], Bounds::new((3, -1), (2, 3)));
+        assert_eq!(layout.directions[0][1], animation.directions[0].frames[1].bounds());
+        assert_eq!(animation.directions[0].shift, (3, 4));
+        assert_eq!(converter.frame_offset("art/a.frm", 0, 1).unwrap(), (3, -1));
+    }
+
+    #[test]
+    fn conversion_metrics() {
+        let frame = frm::FrameHeader {
+            width: 4,
+            height: 3,
+            offset_x: 0,
+            offset_y: 0,
+        };
+        let data = crate::testing::frm_fixture(10, (0, 0), &[frame], 1);
+        let size = data.len() as u64;
+        let retriever = MemoryRetriever::new().with_file("art/a.frm", data);
+        let palette = crate::testing::gradient_palette();
+        let metrics = Metrics::new();
+        let converter = Converter::new(&retriever, &palette).with_metrics(&metrics);
+
+        let png = converter.get_png("art/a.frm").unwrap();
+        assert!(converter.get_png("art/b.frm").is_err());
+        let decode = metrics.get(Operation::FrmDecode);
+        assert_eq!((decode.count, decode.bytes), (1, size));
+        let encode = metrics.get(Operation::PngEncode);
+        assert_eq!((encode.count, encode.bytes), (1, png.data.len() as u64));
+        assert_eq!(metrics.get(Operation::Convert).count, 1);
+    }
+
+    #[cfg(feature = "webp")]
+    #[test]
+    fn webp_output() {
+        let frame = frm::FrameHeader {
+            width: 4,
+            height: 3,
+            offset_x: 0,
+            offset_y: 0,
+        };
+        let data = crate::testing::frm_fixture(10, (0, 0), &[frame], 1);
+        let retriever = MemoryRetriever::new().with_file("art/a.frm", data);
+        let palette = crate::testing::gradient_palette();
+        let converter = Converter::new(&retriever, &palette);
+
+        let options = ConvertOptions::builder().output(DataType::WebP).build();
+        let webp = converter.get_with("art/a.frm", &options).unwrap();
+        assert_eq!(webp.data_type, DataType::WebP);
+        let png = converter.get_png("art/a.frm").unwrap();
+        assert_ne!(webp.fingerprint, png.fingerprint);
+        let decode = |file| crate::testing::decode(file).unwrap();
+        assert_eq!(decode(&webp), decode(&png));
+    }
+
+    #[test]
+    fn split_frm_fallback() {
+        struct SplitFrm(Vec<u8>);
+        impl Retriever for SplitFrm {
+            type Error = std::io::Error;
+
+            fn file_by_path(&self, path: &str) -> Result<Vec<u8>, Self::Error> {
+                match path {
+                    "art/a.fr0" | "art/a.fr2" | "art/b.frm" => Ok(self.0.clone()),
+                    "art/c.fofrm" => Ok(b"frm=a.frm\n".to_vec()),
+                    _ => Err(std::io::ErrorKind::NotFound.into()),
+                }
+            }
+        }
+
+        let frame = frm::FrameHeader {
+            width: 2,
+            height: 2,
+            offset_x: 0,
+            offset_y: 0,
+        };
+        let retriever = SplitFrm(crate::testing::frm_fixture(10, (0, 0), &[frame], 1));
+        let palette = Palette::default();
+        let converter = Converter::new(&retriever, &palette);
+        let direction = |direction| ConvertOptions::builder().direction(direction);
+        let options = direction(2).output(DataType::Rgba).build();
+        assert_eq!(converter.get_with("art/a.frm", &options).unwrap().dimensions, (2, 2));
+        assert!(converter.get_with("art/a.frm", &direction(1).build()).is_err());
+        assert!(converter.get_png("art/b.fr0").is_ok());
+        assert!(converter.get_png("art/b.fr1").is_err());
+        assert!(converter.frame_offset("art/a.frm", 2, 0).is_ok());
+        assert!(converter.get_png("art/c.fofrm").is_ok());
+
+        let options = direction(2).split_frm_fallback(false).build();
+        assert!(converter.get_with("art/a.frm", &options).is_err());
+        let options = direction(0).split_frm_fallback(false).build();
+        assert!(converter.get_with("art/c.fofrm", &options).is_err());
+    }
+
+    #[test]
+    fn root_relative_references() {
+        struct RootRefs;
+        impl Retriever for RootRefs {
+            type Error = std::io::Error;
+
+            fn file_by_path(&self, path: &str) -> Result<Vec<u8>, Self::Error> {
+                match path {
+                    "art/a.png" => PngAndFoFrm.file_by_path(path),
+                    "art/critters/root.fofrm" => Ok(b"frm=art/a.png\n".to_vec()),
+                    "art/critters/slash.fofrm" => Ok(b"frm=/art/a.png\n".to_vec()),
+                    "art/critters/missing.fofrm" => Ok(b"frm=art/b.png\n".to_vec()),
+                    _ => Err(std::io::ErrorKind::NotFound.into()),
+                }
+            }
+        }
+
+        let palette = Palette::default();
+        let converter = Converter::new(&RootRefs, &palette);
+        let options = ConvertOptions::default();
+        for path in &["art/critters/root.fofrm", "art/critters/slash.fofrm"] {
+            assert_eq!(converter.get_png(path).unwrap().dimensions, (3, 2));
+            assert_eq!(
+                converter.frame_reference(path, &options).unwrap(),
+                ("art/a.png".into(), references::ReferenceRule::RootRelative)
             );
-            //dbg!(&full_path);
+        }
+        assert!(converter.frame_offset("art/critters/root.fofrm", 0, 0).is_ok());
+        match converter.get_png("art/critters/missing.fofrm") {
+            Err(GetImageError::Recursion(0, err)) => {
+                assert!(matches!(*err, GetImageError::Retrieve(_)))
+            }
+            _ => panic!("missing reference must fail with the retrieve error"),
+        }
+    }
 
-            let mut image = get_raw(retriever, &full_path, recursion + 1, palette)
-                .map_err(GetImageError::recursion)?;
-            image.offset_x += offset_x;
-            image.offset_y += offset_y;
-            image
+    #[test]
+    fn layout_from_headers() {
+        let palette = Palette::default();
+        let converter = Converter::new(&PngAndFoFrm, &palette);
+        let options = ConvertOptions::default();
+        let layout = converter.animation_layout("art/a.png", &options).unwrap();
+        assert_eq!(layout.directions, [[Bounds::new((-1, -2), (3, 2))]]);
+
+        let layout = converter.animation_layout("art/a.fofrm", &options).unwrap();
+        let animation = converter.get_animation("art/a.fofrm", &options).unwrap();
+        assert_eq!(layout.fps, animation.fps);
+        let placed: Vec<Vec<_>> = animation
+            .directions
+            .iter()
+            .map(|direction| direction.frames.iter().map(RawImage::bounds).collect())
+            .collect();
+        assert_eq!(layout.directions, placed);
+        assert_eq!(layout.bounds(), animation.bounds());
+        for (direction, frames) in animation.directions.iter().enumerate() {
+            for (frame, raw) in frames.frames.iter().enumerate() {
+                let options = ConvertOptions::builder()
+                    .direction(direction)
+                    .frame(frame)
+                    .build();
+                let single = converter.get_rgba_with("art/a.fofrm", &options).unwrap();
+                assert_eq!(raw.bounds(), single.bounds());
+                assert_eq!(raw.image, single.image);
+            }
         }
-        _ => return Err(GetImageError::FileType(file_type)),
-    })
+    }
+
+    #[test]
+    fn gif_frames_and_offsets() {
+        use image::{codecs::gif::GifEncoder, Delay, Frame};
+
+        let mut gif = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut gif);
+            for color in [[200, 0, 0, 255], [0, 0, 200, 255]] {
+                let image = image::RgbaImage::from_pixel(4, 2, image::Rgba(color));
+                let delay = Delay::from_numer_denom_ms(100, 1);
+                encoder.encode_frame(Frame::from_parts(image, 0, 0, delay)).unwrap();
+            }
+        }
+        let retriever = crate::retriever::memory::MemoryRetriever::new()
+            .with_file("art/scenery/fire.gif", gif.clone())
+            .with_file("art/intrface/fire.gif", gif);
+        let palette = Palette::default();
+        let converter = Converter::new(&retriever, &palette);
+        let options = ConvertOptions::builder().frame(1).build();
+        let raw = converter.get_rgba_with("art/scenery/fire.gif", &options).unwrap();
+        assert_eq!((raw.offset_x, raw.offset_y), (-2, -2));
+        assert_eq!(raw.image.dimensions(), (4, 2));
+        assert_eq!(raw.image.get_pixel(0, 0).0, [0, 0, 200, 255]);
+        let raw = converter
+            .get_rgba_with("art/intrface/fire.gif", &options)
+            .unwrap();
+        assert_eq!((raw.offset_x, raw.offset_y), (0, 0));
+        let options = ConvertOptions::builder().frame(2).build();
+        assert!(matches!(
+            converter.get_rgba_with("art/scenery/fire.gif", &options),
+            Err(GetImageError::NoFrame)
+        ));
+
+        let options = ConvertOptions::default();
+        let animation = converter.get_animation("art/scenery/fire.gif", &options).unwrap();
+        let layout = converter
+            .animation_layout("art/scenery/fire.gif", &options)
+            .unwrap();
+        assert_eq!((animation.fps, animation.directions[0].frames.len()), (10, 2));
+        assert_eq!(layout.fps, animation.fps);
+        assert_eq!(layout.bounds(), animation.bounds());
+        let png = converter.get_png("art/scenery/fire.gif").unwrap();
+        assert_eq!(png.dimensions, (4, 2));
+    }
+
+    #[test]
+    fn animated_gif_of_direction() {
+        let palette = Palette::default();
+        let converter = Converter::new(&PngAndFoFrm, &palette);
+        let gif = converter.get_gif("art/a.fofrm").unwrap();
+        let frames: Vec<_> = gif_frames(&gif).unwrap().map(Result::unwrap).collect();
+        assert_eq!(frames.len(), 2);
+        for (image, delay) in &frames {
+            assert_eq!((image.dimensions(), *delay), ((7, 2), 200));
+        }
+        assert_eq!(frames[0].0.get_pixel(0, 0).0, [1, 2, 3, 255]);
+        assert_eq!(frames[0].0.get_pixel(6, 0).0[3], 0);
+        assert_eq!(frames[1].0.get_pixel(0, 0).0[3], 0);
+
+        let options = ConvertOptions::builder().direction(1).build();
+        let gif = converter.get_gif_with("art/a.fofrm", &options).unwrap();
+        let frames: Vec<_> = gif_frames(&gif).unwrap().map(Result::unwrap).collect();
+        assert_eq!(frames.len(), 1, "equal frames are merged");
+        assert_eq!((frames[0].0.dimensions(), frames[0].1), ((3, 2), 400));
+        let options = ConvertOptions::builder().direction(2).build();
+        assert!(matches!(
+            converter.get_gif_with("art/a.fofrm", &options),
+            Err(GetImageError::NoDirection)
+        ));
+    }
+
+    #[test]
+    fn anchor_by_directory() {
+        let policy = AnchorPolicy::ByDirectory;
+        assert_eq!(policy.anchor_for("art/tiles/grid000.png"), Anchor::TopLeft);
+        assert_eq!(policy.anchor_for("art/intrface/iface.png"), Anchor::TopLeft);
+        assert_eq!(policy.anchor_for("art/scenery/tree.png"), Anchor::BottomCenter);
+        assert_eq!(png_offset(10, 20, Anchor::BottomCenter), (-5, -20));
+        assert_eq!(png_offset(10, 20, Anchor::Center), (-5, -10));
+        assert_eq!(png_offset(10, 20, Anchor::TopLeft), (0, 0));
+    }
+
+    struct NoFiles;
+
+    impl Retriever for NoFiles {
+        type Error = std::io::Error;
+
+        fn file_by_path(&self, _path: &str) -> Result<Vec<u8>, Self::Error> {
+            Err(std::io::ErrorKind::NotFound.into())
+        }
+    }
+
+    #[test]
+    fn placeholder_instead_of_error() {
+        let palette = Palette::default();
+        let converter = Converter::new(&NoFiles, &palette);
+        assert!(converter.get_png("art/missing.frm").is_err());
+
+        let options = ConvertOptions::builder()
+            .placeholder(Some(Placeholder::Checkerboard {
+                width: 16,
+                height: 10,
+            }))
+            .output(DataType::Rgba)
+            .build();
+        let file = converter.get_with("art/missing.frm", &options).unwrap();
+        assert_eq!(file.dimensions, (16, 10));
+        assert_eq!(file.offset, (-8, -10));
+        assert_eq!(&file.data[..4], &PLACEHOLDER_COLORS[0]);
+        assert_eq!(&file.data[8 * 4..9 * 4], &PLACEHOLDER_COLORS[1]);
+        assert!(matches!(
+            file.replaced_error.as_deref(),
+            Some(GetImageError::Retrieve(_))
+        ));
+
+        let options = ConvertOptions::builder()
+            .placeholder(Some(Placeholder::Path("art/missing.png".into())))
+            .build();
+        assert!(converter.get_with("art/missing.frm", &options).is_err());
+    }
+
+    #[test]
+    fn preview_includes_anchor() {
+        let raw = RawImage {
+            image: image::RgbaImage::from_pixel(2, 2, image::Rgba([0, 0, 0, 128])),
+            offset_x: 3,
+            offset_y: -2,
+        };
+        let preview = PreviewOptions {
+            background: Background::Solid([255, 255, 255]),
+            anchor_cross: None,
+            gamma: 1.0,
+        };
+        let image = raw.preview(&preview);
+        assert_eq!((image.offset_x, image.offset_y), (3, -2));
+        assert_eq!(image.image.get_pixel(0, 0).0, [127, 127, 127, 255]);
+
+        let image = raw.preview(&PreviewOptions::default());
+        assert_eq!((image.offset_x, image.offset_y), (-4, -4));
+        assert_eq!(image.image.dimensions(), (9, 9));
+        assert_eq!(image.image.get_pixel(4, 4).0, [255, 0, 0, 255]);
+        assert_eq!(image.image.get_pixel(8, 4).0, [255, 0, 0, 255]);
+        // Blended in linear light, brighter than the naive mid-gray above.
+        assert_eq!(image.image.get_pixel(7, 2).0, [186, 186, 186, 255]);
+    }
+
+    #[test]
+    fn alpha_mask_outline() {
+        let mut image = image::RgbaImage::new(4, 4);
+        for &(x, y) in &[(1, 1), (2, 1), (1, 2), (2, 2), (3, 3)] {
+            image.put_pixel(x, y, image::Rgba([1, 2, 3, 100]));
+        }
+        let raw = RawImage {
+            image,
+            offset_x: -2,
+            offset_y: -4,
+        };
+        let mask = raw.alpha_mask();
+        assert!(mask.contains(-1, -3));
+        assert!(!mask.contains(-2, -4));
+        assert!(!mask.contains(10, 10));
+        assert_eq!(
+            mask.outline(),
+            [(-1, -3), (0, -3), (0, -2), (1, -1), (0, -2), (-1, -2)]
+        );
+
+        let single = RawImage {
+            image: image::RgbaImage::from_pixel(1, 1, image::Rgba([0, 0, 0, 255])),
+            offset_x: 0,
+            offset_y: 0,
+        };
+        assert_eq!(single.alpha_mask().outline(), [(0, 0)]);
+        let empty = RawImage {
+            image: image::RgbaImage::new(2, 2),
+            offset_x: 0,
+            offset_y: 0,
+        };
+        assert!(empty.alpha_mask().outline().is_empty());
+    }
+
+    #[test]
+    fn egg_lowers_alpha_of_covered_pixels() {
+        let scenery = RawImage {
+            image: image::RgbaImage::from_pixel(4, 1, image::Rgba([10, 20, 30, 255])),
+            offset_x: -2,
+            offset_y: -1,
+        };
+        let mut egg = image::RgbaImage::new(2, 1);
+        egg.put_pixel(0, 0, image::Rgba([0, 0, 0, 255]));
+        egg.put_pixel(1, 0, image::Rgba([0, 0, 0, 128]));
+        let egg = RawImage {
+            image: egg,
+            offset_x: -1,
+            offset_y: 0,
+        };
+        let result = scenery.with_egg(&egg, (0, -1), 0);
+        let alphas: Vec<_> = result.image.pixels().map(|pixel| pixel[3]).collect();
+        assert_eq!(alphas, [255, 0, 127, 255]);
+        assert_eq!(result.image.get_pixel(1, 0).0[..3], [10, 20, 30]);
+
+        let result = scenery.with_egg(&egg, (0, -1), 100);
+        assert_eq!(result.image.get_pixel(1, 0)[3], 100);
+        let result = scenery.with_egg(&egg, (10, 10), 0);
+        assert!(result.image.pixels().all(|pixel| pixel[3] == 255));
+    }
+
+    #[test]
+    fn frame_image_reuses_buffer() {
+        let lut = Palette::default().rgba_lut();
+        let frame = frm::Frame {
+            width: 2,
+            height: 2,
+            offset_x: 0,
+            offset_y: 0,
+            data: &[0, 1, 2, 3],
+        };
+        let pixels = Vec::with_capacity(64);
+        let pointer = pixels.as_ptr();
+        let image = frm_frame_image(&lut, &frame, pixels).unwrap();
+        assert_eq!(image.dimensions(), (2, 2));
+        assert_eq!(image.as_raw().as_ptr(), pointer);
+
+        let mut png = vec![1, 2, 3];
+        encode_png(&image, &mut png).unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+    }
+
+    #[test]
+    fn fingerprint_depends_on_sources_and_options() {
+        let fingerprint = |options: &ConvertOptions, sources: &[(&str, &[u8])]| {
+            let mut hasher = FingerprintHasher::new();
+            options.hash(&Palette::default(), &mut hasher);
+            for (path, data) in sources {
+                hasher.write_source(path, data);
+            }
+            hasher.finish()
+        };
+        let options = ConvertOptions::default();
+        let base = fingerprint(&options, &[("art/a.frm", b"data")]);
+        assert_eq!(base, fingerprint(&options, &[("art/a.frm", b"data")]));
+        assert_ne!(base, fingerprint(&options, &[("art/a.frm", b"date")]));
+        assert_ne!(base, fingerprint(&options, &[("art/a.fr", b"mdata")]));
+        let scaled = ConvertOptions::builder().scale(2.0).build();
+        assert_ne!(base, fingerprint(&scaled, &[("art/a.frm", b"data")]));
+        assert_eq!(Fingerprint(0xab).etag(), "\"00000000000000ab\"");
+    }
 }
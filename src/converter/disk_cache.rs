@@ -0,0 +1,267 @@
+//! Converted pngs kept on disk between runs, keyed by path and options and
+//! validated by source stamps of every file the conversion read.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use parking_lot::Mutex;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+/// Bumped on every change of the entry layout or of conversion output.
+const ENTRY_VERSION: u64 = 1;
+
+/// Directory of converted pngs, see [`Converter::with_disk_cache`].
+///
+/// Entries are reused only if the retriever reports [`Retriever::source_stamp`]
+/// for every source and none of the stamps changed.
+#[derive(Debug, Clone)]
+pub struct DiskCache {
+    root: PathBuf,
+}
+
+/// Outcome of [`DiskCache::prime`].
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PrimeStats {
+    /// Entries already fresh, left by a previous or interrupted run.
+    pub fresh: usize,
+    pub converted: usize,
+    /// Paths that failed to convert, they are retried by the next run.
+    pub failed: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    sources: Vec<(String, u64)>,
+    dimensions: (u32, u32),
+    offset: (i16, i16),
+    fingerprint: u64,
+    png: Vec<u8>,
+}
+
+impl DiskCache {
+    pub fn open(root: impl Into<PathBuf>) -> io::Result<Self> {
+        let root = root.into();
+        std::fs::create_dir_all(&root)?;
+        Ok(Self { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Removes all entries.
+    pub fn clear(&self) -> io::Result<()> {
+        std::fs::remove_dir_all(&self.root)?;
+        std::fs::create_dir_all(&self.root)
+    }
+
+    fn entry_path(&self, path: &str, options: &ConvertOptions, palette: &Palette) -> PathBuf {
+        let mut hasher = FingerprintHasher::new();
+        hasher.write(&ENTRY_VERSION.to_le_bytes());
+        hasher.write_source(path, &[]);
+        options.hash(palette, &mut hasher);
+        self.root.join(format!("{:016x}.bin", hasher.finish().0))
+    }
+
+    fn read<R: Retriever>(&self, entry_path: &Path, retriever: &R) -> Option<FileData> {
+        let file = std::fs::File::open(entry_path).ok()?;
+        let entry: Entry = bincode::deserialize_from(io::BufReader::new(file)).ok()?;
+        let fresh = !entry.sources.is_empty()
+            && entry
+                .sources
+                .iter()
+                .all(|(path, stamp)| retriever.source_stamp(path) == Some(*stamp));
+        if !fresh {
+            return None;
+        }
+        Some(FileData {
+            data_type: DataType::Png,
+            data: entry.png.into(),
+            dimensions: entry.dimensions,
+            offset: entry.offset,
+            fingerprint: Fingerprint(entry.fingerprint),
+            replaced_error: None,
+        })
+    }
+
+    /// Written into a temporary file first, so readers never see a partial entry.
+    fn write(&self, entry_path: &Path, entry: &Entry) -> io::Result<()> {
+        let data = bincode::serialize(entry).map_err(io::Error::other)?;
+        let temp = entry_path.with_extension(format!("tmp{}", std::process::id()));
+        std::fs::write(&temp, data)?;
+        std::fs::rename(&temp, entry_path)
+    }
+
+    pub(super) fn get_or_convert<R: Retriever>(
+        &self,
+        converter: &Converter<'_, '_, R>,
+        path: &str,
+        options: &ConvertOptions,
+    ) -> Result<FileData, GetImageError>
+    where
+        R::Error: Into<RetrieveError>,
+    {
+        let palette = options.palette.as_ref().unwrap_or(converter.palette);
+        let entry_path = self.entry_path(path, options, palette);
+        if let Some(file) = self.read(&entry_path, converter.retriever) {
+            return Ok(file);
+        }
+
+        let recording = Recording {
+            inner: converter.retriever,
+            paths: Mutex::new(Vec::new()),
+        };
+        let file = Converter::new(&recording, converter.palette).convert(path, options)?;
+        let sources: Option<Vec<_>> = recording
+            .paths
+            .into_inner()
+            .into_iter()
+            .map(|path| {
+                let stamp = converter.retriever.source_stamp(&path)?;
+                Some((path, stamp))
+            })
+            .collect();
+        if let (Some(sources), None) = (sources, &file.replaced_error) {
+            let entry = Entry {
+                sources,
+                dimensions: file.dimensions,
+                offset: file.offset,
+                fingerprint: file.fingerprint.0,
+                png: file.data.to_vec(),
+            };
+            // cache is an optimization, conversion result is returned anyway
+            let _ = self.write(&entry_path, &entry);
+        }
+        Ok(file)
+    }
+}
+
+impl DiskCache {
+    /// Converts all `paths` missing from the cache in parallel, meant to run once after
+    /// a content update. Entries are written as soon as they are converted, so an
+    /// interrupted run resumes where it stopped.
+    ///
+    /// `progress` is called with the number of processed paths and the total after every path.
+    pub fn prime<R>(
+        &self,
+        converter: &Converter<'_, '_, R>,
+        paths: &[&str],
+        options: &ConvertOptions,
+        progress: impl Fn(usize, usize) + Sync,
+    ) -> PrimeStats
+    where
+        R: Retriever + Sync,
+        R::Error: Into<RetrieveError>,
+    {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let options = ConvertOptions {
+            output: DataType::Png,
+            ..options.clone()
+        };
+        let palette = options.palette.as_ref().unwrap_or(converter.palette);
+        let done = AtomicUsize::new(0);
+        let stats = Mutex::new(PrimeStats::default());
+        paths.par_iter().for_each(|&path| {
+            let entry_path = self.entry_path(path, &options, palette);
+            if self.read(&entry_path, converter.retriever).is_some() {
+                stats.lock().fresh += 1;
+            } else if self.get_or_convert(converter, path, &options).is_ok() {
+                stats.lock().converted += 1;
+            } else {
+                stats.lock().failed.push(path.to_owned());
+            }
+            progress(done.fetch_add(1, Ordering::Relaxed) + 1, paths.len());
+        });
+        let mut stats = stats.into_inner();
+        stats.failed.sort();
+        stats
+    }
+}
+
+/// Remembers every path read through it.
+struct Recording<'a, R> {
+    inner: &'a R,
+    paths: Mutex<Vec<String>>,
+}
+
+impl<R: Retriever> Retriever for Recording<'_, R> {
+    type Error = R::Error;
+
+    fn file_by_path(&self, path: &str) -> Result<Vec<u8>, Self::Error> {
+        self.paths.lock().push(path.to_owned());
+        self.inner.file_by_path(path)
+    }
+
+    fn path_by_hash(&self, hash: u32) -> Option<&str> {
+        self.inner.path_by_hash(hash)
+    }
+
+    fn path_rules(&self) -> &paths::PathRules {
+        self.inner.path_rules()
+    }
+
+    fn source_stamp(&self, path: &str) -> Option<u64> {
+        self.inner.source_stamp(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{png_fixture, TempData};
+
+    fn png(width: u32) -> Vec<u8> {
+        png_fixture(width, 1, [5, 6, 7, 255])
+    }
+
+    #[test]
+    fn reuse_until_source_changes() {
+        let data = TempData::new(&[("art/a.png", &png(2))]);
+        let retriever = data.retriever();
+        let palette = Palette::default();
+        let cache_dir = TempData::new(&[]);
+        let cache = DiskCache::open(cache_dir.root()).unwrap();
+        let converter = Converter::new(&retriever, &palette).with_disk_cache(cache.clone());
+        let converted = converter.get_png("art/a.png").unwrap();
+        assert_eq!(std::fs::read_dir(cache.root()).unwrap().count(), 1);
+        let cached = converter.get_png("art/a.png").unwrap();
+        assert_eq!(cached.data, converted.data);
+        assert_eq!(cached.fingerprint, converted.fingerprint);
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        data.write("art/a.png", &png(3));
+        assert_eq!(converter.get_png("art/a.png").unwrap().dimensions, (3, 1));
+        cache.clear().unwrap();
+        assert_eq!(std::fs::read_dir(cache.root()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn prime_and_resume() {
+        let data = TempData::new(&[("art/a.png", &png(2))]);
+        let retriever = data.retriever();
+        let palette = Palette::default();
+        let cache_dir = TempData::new(&[]);
+        let cache = DiskCache::open(cache_dir.root()).unwrap();
+        let converter = Converter::new(&retriever, &palette);
+        let paths = ["art/a.png", "art/missing.png"];
+        let options = ConvertOptions::default();
+        let last = Mutex::new((0, 0));
+
+        let stats = cache.prime(&converter, &paths, &options, |done, total| {
+            let mut last = last.lock();
+            *last = (*last).max((done, total));
+        });
+        assert_eq!((stats.fresh, stats.converted), (0, 1));
+        assert_eq!(stats.failed, ["art/missing.png"]);
+        assert_eq!(*last.lock(), (2, 2));
+
+        let stats = cache.prime(&converter, &paths, &options, |_, _| {});
+        assert_eq!((stats.fresh, stats.converted, stats.failed.len()), (1, 0, 1));
+    }
+}
@@ -1,87 +1,706 @@
-use std::{io::BufReader, path::Path};
+use std::{
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use crate::{FileInfo, FileLocation, PathMap};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
-#[derive(Debug)]
-pub enum Error {}
+use crate::{
+    paths::PathRules, retriever::recognize_type, ArchiveKind, FileInfo, FileLocation, PathError,
+    PathMap,
+};
+
+/// Gitignore-style file that excludes paths of a local data folder from indexing.
+/// Honored at the data folder root and in every subdirectory, matched case-insensitively.
+pub const IGNORE_FILE: &str = ".foignore";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("can't walk data folder {0:?}: {1}")]
+    Walk(PathBuf, ignore::Error),
+    #[error("can't read tar archive {0:?}: {1}")]
+    Tar(PathBuf, std::io::Error),
+    #[error("can't read dat archive {0:?}: {1}")]
+    Dat(PathBuf, std::io::Error),
+    #[error("can't read zip archive {0:?}: {1}")]
+    Zip(PathBuf, zip::result::ZipError),
+    #[error("path is not valid utf-8: {0:?}")]
+    NonUtf8Path(PathBuf),
+    #[error("crawl limit exceeded: more than {0} files, is data root correct?")]
+    TooManyFiles(usize),
+    #[error("crawl limit exceeded: more than {0} bytes of files, is data root correct?")]
+    TooLargeTotalSize(u64),
+    #[error("crawl limit exceeded: path {0:?} is longer than {1} bytes")]
+    PathTooLong(String, usize),
+}
+
+/// Safety limits for crawling, `None` disables the corresponding check.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    pub max_files: Option<usize>,
+    /// Total uncompressed size of all crawled files, in bytes.
+    pub max_total_size: Option<u64>,
+    pub max_path_len: Option<usize>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_files: Some(1_000_000),
+            max_total_size: Some(32 * 1024 * 1024 * 1024),
+            max_path_len: Some(1024),
+        }
+    }
+}
+
+impl Limits {
+    pub fn unlimited() -> Self {
+        Self {
+            max_files: None,
+            max_total_size: None,
+            max_path_len: None,
+        }
+    }
+}
+
+/// Which file is used when several archives have the same path.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Precedence {
+    /// Later archives override earlier ones, as with `DataFiles.cfg` of classic clients.
+    #[default]
+    LastWins,
+    /// Earlier archives override later ones, as with resources folders of FOnline 2
+    /// and Reloaded engines, which look files up in packs in name order.
+    FirstWins,
+}
+
+/// Called with conventional path, uncompressed size and archive path of an entry.
+pub type FilterFn = dyn Fn(&str, u64, &Path) -> bool + Send + Sync;
+
+/// Decides which entries are indexed, accepts every entry by default.
+///
+/// Skipped entries are not counted against [`Limits`] and are not in the [`CrawlReport`].
+#[derive(Clone, Default)]
+pub struct EntryFilter(Option<Arc<FilterFn>>);
+
+impl EntryFilter {
+    pub fn new(filter: impl Fn(&str, u64, &Path) -> bool + Send + Sync + 'static) -> Self {
+        Self(Some(Arc::new(filter)))
+    }
+
+    pub fn accepts_all(&self) -> bool {
+        self.0.is_none()
+    }
+
+    pub fn accepts(&self, path: &str, size: u64, archive: &Path) -> bool {
+        match &self.0 {
+            Some(filter) => filter(path, size, archive),
+            None => true,
+        }
+    }
+}
+
+impl std::fmt::Debug for EntryFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("EntryFilter")
+            .field(&if self.accepts_all() { "all" } else { "custom" })
+            .finish()
+    }
+}
+
+/// Zip entry hidden by a later entry with the same conventional path in the same zip.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateEntry {
+    pub archive: PathBuf,
+    pub path: String,
+    /// Index of the hidden entry in the central directory.
+    pub ignored: u32,
+    /// Index of the entry the registry points to, the last one wins.
+    pub authoritative: u32,
+}
+
+/// Problems found while crawling that don't stop it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CrawlReport {
+    pub duplicates: Vec<DuplicateEntry>,
+    /// Files hidden by files with the same path in other archives, by precedence,
+    /// the lowest first.
+    pub shadowed: PathMap<String, Vec<FileInfo>>,
+    /// Every archive in order.
+    pub crawled: Vec<CrawledArchive>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrawledArchive {
+    pub path: PathBuf,
+    /// Files of the archive accepted by the entry filter, before precedence is applied.
+    pub files: usize,
+    pub duration: std::time::Duration,
+}
+
+impl CrawlReport {
+    /// Whether [`CrawlReport::warnings`] has anything, shadowed files are expected and aren't
+    /// warnings.
+    pub fn has_warnings(&self) -> bool {
+        !self.duplicates.is_empty()
+    }
+
+    pub fn warnings(&self) -> impl '_ + Iterator<Item = String> {
+        self.duplicates.iter().map(|duplicate| {
+            format!(
+                "{:?} has several entries for {:?}: entry #{} is ignored, #{} is used",
+                duplicate.archive, duplicate.path, duplicate.ignored, duplicate.authoritative
+            )
+        })
+    }
+}
+
+struct Tally<'a> {
+    limits: &'a Limits,
+    files: usize,
+    total_size: u64,
+    report: CrawlReport,
+    rules: PathRules,
+}
+
+impl<'a> Tally<'a> {
+    fn new(limits: &'a Limits) -> Self {
+        Self {
+            limits,
+            files: 0,
+            total_size: 0,
+            report: CrawlReport::default(),
+            rules: PathRules::FONLINE,
+        }
+    }
+
+    fn count(&mut self, path: &str, size: u64) -> Result<(), Error> {
+        self.files += 1;
+        self.total_size = self.total_size.saturating_add(size);
+        match self.limits {
+            Limits {
+                max_files: Some(max),
+                ..
+            } if self.files > *max => Err(Error::TooManyFiles(*max)),
+            Limits {
+                max_total_size: Some(max),
+                ..
+            } if self.total_size > *max => Err(Error::TooLargeTotalSize(*max)),
+            Limits {
+                max_path_len: Some(max),
+                ..
+            } if path.len() > *max => Err(Error::PathTooLong(path.to_owned(), *max)),
+            _ => Ok(()),
+        }
+    }
+}
 
 pub fn gather_paths(archives: &[crate::FoArchive]) -> Result<PathMap<String, FileInfo>, Error> {
-    assert!(archives.len() <= u16::max_value() as usize);
+    gather_paths_limited(archives, &Limits::default())
+}
 
+pub fn gather_paths_limited(
+    archives: &[crate::FoArchive],
+    limits: &Limits,
+) -> Result<PathMap<String, FileInfo>, Error> {
+    gather_paths_reported(archives, limits, &PathRules::FONLINE).map(|(path_map, _report)| path_map)
+}
+
+/// Same as [`gather_paths_limited`] with custom path rules, also returns non-fatal
+/// problems found on the way.
+pub fn gather_paths_reported(
+    archives: &[crate::FoArchive],
+    limits: &Limits,
+    rules: &PathRules,
+) -> Result<(PathMap<String, FileInfo>, CrawlReport), Error> {
+    gather_paths_with_precedence(archives, limits, rules, Precedence::LastWins)
+}
+
+/// Files sent by a crawling thread at once.
+const BATCH: usize = 4096;
+/// Batches waiting to be merged, bounds memory when archives are crawled faster than merged.
+const QUEUED_BATCHES: usize = 8;
+
+/// Called for every file of an archive, `false` stops the crawl.
+type Emit<'e> = dyn FnMut(String, FileInfo) -> bool + 'e;
+
+/// Message of a crawling thread to the merge.
+enum Crawled {
+    Files(Vec<(String, FileInfo)>),
+    Done(u32, CrawledArchive),
+    Failed(Error),
+}
+
+/// Same as [`gather_paths_reported`], with files of archives overriding each other
+/// according to `precedence`.
+pub fn gather_paths_with_precedence(
+    archives: &[crate::FoArchive],
+    limits: &Limits,
+    rules: &PathRules,
+    precedence: Precedence,
+) -> Result<(PathMap<String, FileInfo>, CrawlReport), Error> {
+    let filter = EntryFilter::default();
+    gather_paths_filtered(archives, limits, rules, precedence, &filter)
+}
+
+/// Same as [`gather_paths_with_precedence`], only entries accepted by `filter` are indexed.
+///
+/// Archives are crawled on several threads, which stream files to the merge in batches
+/// through a bounded queue, so only a few batches are held besides the result.
+pub fn gather_paths_filtered(
+    archives: &[crate::FoArchive],
+    limits: &Limits,
+    rules: &PathRules,
+    precedence: Precedence,
+    filter: &EntryFilter,
+) -> Result<(PathMap<String, FileInfo>, CrawlReport), Error> {
+    use std::sync::{atomic::AtomicUsize, mpsc};
+
+    assert!(archives.len() <= u32::MAX as usize);
+
+    let threads = std::thread::available_parallelism()
+        .map_or(1, usize::from)
+        .min(archives.len());
+    let next_archive = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::sync_channel(QUEUED_BATCHES);
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            let sender = sender.clone();
+            let next_archive = &next_archive;
+            scope.spawn(move || crawl_worker(archives, rules, filter, next_archive, sender));
+        }
+        drop(sender);
+        // returning early drops the receiver, which stops the workers
+        let mut merge = Merge::new(archives, limits, precedence);
+        for crawled in receiver {
+            merge.add(crawled)?;
+        }
+        Ok(merge.finish())
+    })
+}
+
+/// Crawls archives by index from `next_archive` until there are none left
+/// or the merge is gone.
+fn crawl_worker(
+    archives: &[crate::FoArchive],
+    rules: &PathRules,
+    filter: &EntryFilter,
+    next_archive: &std::sync::atomic::AtomicUsize,
+    sender: std::sync::mpsc::SyncSender<Crawled>,
+) {
+    use std::sync::atomic::Ordering;
+
+    loop {
+        let index = next_archive.fetch_add(1, Ordering::Relaxed);
+        let archive = match archives.get(index) {
+            Some(archive) => archive,
+            None => return,
+        };
+        let start = std::time::Instant::now();
+        let mut batch = Vec::new();
+        let mut files = 0;
+        let mut connected = true;
+        let result = crawl_entries(index as u32, archive, rules, &mut |path, file_info| {
+            let path = match accept(archive, filter, path, &file_info) {
+                Some(path) => path,
+                None => return true,
+            };
+            files += 1;
+            batch.push((path, file_info));
+            if batch.len() >= BATCH {
+                connected = sender.send(Crawled::Files(std::mem::take(&mut batch))).is_ok();
+            }
+            connected
+        });
+        let message = match result {
+            Ok(()) => {
+                if !batch.is_empty() && sender.send(Crawled::Files(batch)).is_err() {
+                    return;
+                }
+                let crawled = CrawledArchive {
+                    path: archive.path.clone(),
+                    files,
+                    duration: start.elapsed(),
+                };
+                Crawled::Done(index as u32, crawled)
+            }
+            Err(err) => Crawled::Failed(err),
+        };
+        if !connected || sender.send(message).is_err() {
+            return;
+        }
+    }
+}
+
+/// Registry path of an entry of `archive` if `filter` accepts it.
+fn accept(
+    archive: &crate::FoArchive,
+    filter: &EntryFilter,
+    path: String,
+    file_info: &FileInfo,
+) -> Option<String> {
+    let path = match archive.mount() {
+        Some(mount) => format!("{}/{}", mount, path),
+        None => path,
+    };
+    filter
+        .accepts(&path, file_info.uncompressed_size, &archive.path)
+        .then_some(path)
+}
+
+/// Files of one archive as [`gather_paths_filtered`] indexes them, before precedence
+/// is applied.
+pub(crate) fn gather_archive(
+    archive_index: u32,
+    archive: &crate::FoArchive,
+    limits: &Limits,
+    rules: &PathRules,
+    filter: &EntryFilter,
+) -> Result<PathMap<String, FileInfo>, Error> {
+    let mut tally = Tally::new(limits);
     let mut path_map = PathMap::new();
+    let mut counted = Ok(());
+    crawl_entries(archive_index, archive, rules, &mut |path, file_info| {
+        let path = match accept(archive, filter, path, &file_info) {
+            Some(path) => path,
+            None => return true,
+        };
+        counted = tally.count(&file_info.original_path, file_info.uncompressed_size);
+        path_map.insert(path, file_info);
+        counted.is_ok()
+    })?;
+    counted.map(|()| path_map)
+}
+
+/// Files of archives crawled in any order, with the result of crawling them one by one.
+struct Merge<'a> {
+    archives: &'a [crate::FoArchive],
+    precedence: Precedence,
+    tally: Tally<'a>,
+    path_map: PathMap<String, FileInfo>,
+    duplicates: Vec<(u32, DuplicateEntry)>,
+    crawled: Vec<(u32, CrawledArchive)>,
+    /// Files replaced by a later file of the same path in the same archive.
+    replaced: Vec<usize>,
+}
 
-    /*use rayon::prelude::{
-        IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelExtend,
-        ParallelIterator,
-    };*/
-    path_map.extend(
-        archives
-            .iter()
-            .enumerate()
-            .flat_map(|(archive_index, archive)| {
-                println!("Crawling {:?}", archive.path);
-                let archive_file = std::fs::File::open(&archive.path).unwrap();
-                let buf_reader = BufReader::with_capacity(1024, archive_file);
-                let mut archive_zip = zip::ZipArchive::new(buf_reader).unwrap();
-                let mut local_path_map = PathMap::new();
-                for i in 0..archive_zip.len() {
-                    let entry = archive_zip.by_index(i).unwrap();
-                    if entry.is_dir() {
-                        continue;
-                    }
-                    let entry_name = entry.name();
-                    local_path_map.insert(
-                        nom_prelude::make_path_conventional(entry_name),
-                        FileInfo {
-                            location: FileLocation::Archive(archive_index as u16),
-                            original_path: entry_name.to_owned(),
-                            ..Default::default()
-                        },
-                    );
+impl<'a> Merge<'a> {
+    fn new(archives: &'a [crate::FoArchive], limits: &'a Limits, precedence: Precedence) -> Self {
+        Self {
+            archives,
+            precedence,
+            tally: Tally::new(limits),
+            path_map: PathMap::new(),
+            duplicates: Vec::new(),
+            crawled: Vec::new(),
+            replaced: vec![0; archives.len()],
+        }
+    }
+
+    fn add(&mut self, crawled: Crawled) -> Result<(), Error> {
+        match crawled {
+            Crawled::Files(files) => {
+                for (path, file_info) in files {
+                    self.tally
+                        .count(&file_info.original_path, file_info.uncompressed_size)?;
+                    self.insert(path, file_info);
                 }
-                local_path_map
-            }),
-    );
-    Ok(path_map)
+            }
+            Crawled::Done(index, crawled) => self.crawled.push((index, crawled)),
+            Crawled::Failed(err) => return Err(err),
+        }
+        Ok(())
+    }
+
+    /// Files of one archive arrive in order, so the later of the same path replaces
+    /// the earlier one. Files of different archives are ordered by `precedence`.
+    fn insert(&mut self, path: String, file_info: FileInfo) {
+        use std::collections::btree_map::Entry;
+
+        let index = file_info.location.archive_index();
+        let mut entry = match self.path_map.entry(path) {
+            Entry::Vacant(entry) => {
+                entry.insert(file_info);
+                return;
+            }
+            Entry::Occupied(entry) => entry,
+        };
+        let current = entry.get().location.archive_index();
+        let path = entry.key().clone();
+        if current == index {
+            let ignored = entry.insert(file_info);
+            let authoritative = entry.get().clone();
+            self.duplicate(index, path, &ignored, &authoritative);
+            return;
+        }
+        let wins = match self.precedence {
+            Precedence::LastWins => index > current,
+            Precedence::FirstWins => index < current,
+        };
+        let hidden = if wins { entry.insert(file_info) } else { file_info };
+        let hidden_index = hidden.location.archive_index();
+        let shadowed = self.tally.report.shadowed.entry(path.clone()).or_default();
+        let earlier = shadowed
+            .iter_mut()
+            .find(|file_info| file_info.location.archive_index() == hidden_index);
+        match earlier {
+            Some(earlier) => {
+                let ignored = std::mem::replace(earlier, hidden);
+                let authoritative = earlier.clone();
+                self.duplicate(hidden_index, path, &ignored, &authoritative);
+            }
+            None => shadowed.push(hidden),
+        }
+    }
+
+    fn duplicate(&mut self, index: u32, path: String, ignored: &FileInfo, used: &FileInfo) {
+        self.replaced[index as usize] += 1;
+        if let (
+            FileLocation::Archive { entry: ignored, .. },
+            FileLocation::Archive {
+                entry: authoritative,
+                ..
+            },
+        ) = (&ignored.location, &used.location)
+        {
+            let duplicate = DuplicateEntry {
+                archive: self.archives[index as usize].path.clone(),
+                path,
+                ignored: *ignored,
+                authoritative: *authoritative,
+            };
+            self.duplicates.push((index, duplicate));
+        }
+    }
+
+    fn finish(mut self) -> (PathMap<String, FileInfo>, CrawlReport) {
+        let mut report = self.tally.report;
+        for shadowed in report.shadowed.values_mut() {
+            match self.precedence {
+                Precedence::LastWins => shadowed.sort_by_key(|info| info.location.archive_index()),
+                Precedence::FirstWins => {
+                    shadowed.sort_by_key(|info| std::cmp::Reverse(info.location.archive_index()))
+                }
+            }
+        }
+        self.duplicates.sort_by_key(|(index, _)| *index);
+        report.duplicates = self.duplicates.into_iter().map(|(_, duplicate)| duplicate).collect();
+        self.crawled.sort_by_key(|(index, _)| *index);
+        let replaced = &self.replaced;
+        report.crawled = self
+            .crawled
+            .into_iter()
+            .map(|(index, mut crawled)| {
+                crawled.files -= replaced[index as usize];
+                crawled
+            })
+            .collect();
+        (self.path_map, report)
+    }
+}
+
+/// Every file of an archive, as [`gather_paths_with_precedence`] finds them.
+fn crawl_archive(
+    archive_index: u32,
+    archive: &crate::FoArchive,
+    tally: &mut Tally,
+) -> Result<PathMap<String, FileInfo>, Error> {
+    let rules = tally.rules;
+    let mut local_path_map = PathMap::new();
+    let mut counted = Ok(());
+    crawl_entries(archive_index, archive, &rules, &mut |path, file_info| {
+        counted = tally.count(&file_info.original_path, file_info.uncompressed_size);
+        local_path_map.insert(path, file_info);
+        counted.is_ok()
+    })?;
+    counted.map(|()| local_path_map)
+}
+
+fn crawl_entries(
+    archive_index: u32,
+    archive: &crate::FoArchive,
+    rules: &PathRules,
+    emit: &mut Emit,
+) -> Result<(), Error> {
+    #[cfg(feature = "tracing")]
+    tracing::trace!(path = ?archive.path, "crawling archive");
+    match archive.kind() {
+        ArchiveKind::Folder => return crawl_folder(archive_index, &archive.path, rules, emit),
+        ArchiveKind::Tar => return crawl_tar(archive_index, &archive.path, false, rules, emit),
+        ArchiveKind::TarGz => return crawl_tar(archive_index, &archive.path, true, rules, emit),
+        ArchiveKind::Dat1 | ArchiveKind::Dat2 => {
+            return crawl_dat(archive_index, &archive.path, rules, emit)
+        }
+        ArchiveKind::Zip => {}
+    }
+    let path = &archive.path;
+    let archive_file = std::fs::File::open(path)
+        .map_err(zip::result::ZipError::Io)
+        .path_err(path, Error::Zip)?;
+    let buf_reader = BufReader::with_capacity(1024, archive_file);
+    let mut archive_zip = zip::ZipArchive::new(buf_reader).path_err(path, Error::Zip)?;
+    for i in 0..archive_zip.len() {
+        // raw access doesn't need passwords of encrypted entries
+        let entry = archive_zip.by_index_raw(i).path_err(path, Error::Zip)?;
+        if entry.is_dir() {
+            continue;
+        }
+        let entry_name = entry.name();
+        let file_info = FileInfo {
+            location: FileLocation::Archive {
+                archive: archive_index,
+                entry: i as u32,
+            },
+            original_path: entry_name.to_owned(),
+            compressed_size: entry.compressed_size(),
+            uncompressed_size: entry.size(),
+            file_type: recognize_type(entry_name),
+        };
+        if !emit(rules.normalize(entry_name), file_info) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn crawl_folder(
+    archive_index: u32,
+    root: &Path,
+    rules: &PathRules,
+    emit: &mut Emit,
+) -> Result<(), Error> {
+    let walker = ignore::WalkBuilder::new(root)
+        .standard_filters(false)
+        .add_custom_ignore_filename(IGNORE_FILE)
+        .ignore_case_insensitive(true)
+        .build();
+
+    for entry in walker {
+        let entry = entry.path_err(root, Error::Walk)?;
+        let is_file = entry.file_type().is_some_and(|file_type| file_type.is_file());
+        if !is_file || entry.file_name() == IGNORE_FILE {
+            continue;
+        }
+        let relative_path = entry
+            .path()
+            .strip_prefix(root)
+            .expect("Walker yields paths inside of the root");
+        let relative_path = relative_path
+            .to_str()
+            .ok_or_else(|| Error::NonUtf8Path(entry.path().into()))?;
+        let size = entry.metadata().path_err(entry.path(), Error::Walk)?.len();
+        let file_info = FileInfo {
+            location: FileLocation::Local(archive_index),
+            original_path: relative_path.to_owned(),
+            compressed_size: size,
+            uncompressed_size: size,
+            file_type: recognize_type(relative_path),
+        };
+        if !emit(rules.normalize(relative_path), file_info) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn crawl_tar(
+    archive_index: u32,
+    path: &Path,
+    gzip: bool,
+    rules: &PathRules,
+    emit: &mut Emit,
+) -> Result<(), Error> {
+    use std::io::Read;
+
+    let file = std::fs::File::open(path).path_err(path, Error::Tar)?;
+    let reader: Box<dyn Read> = if gzip {
+        Box::new(flate2::read::GzDecoder::new(BufReader::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+    let mut tar = tar::Archive::new(reader);
+
+    for entry in tar.entries().path_err(path, Error::Tar)? {
+        let entry = entry.path_err(path, Error::Tar)?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let entry_path = entry.path().path_err(path, Error::Tar)?;
+        let entry_name = entry_path
+            .to_str()
+            .ok_or_else(|| Error::NonUtf8Path(entry_path.to_path_buf()))?
+            .to_owned();
+        let size = entry.size();
+        let file_info = FileInfo {
+            location: FileLocation::Tar {
+                archive: archive_index,
+                offset: entry.raw_file_position(),
+            },
+            file_type: recognize_type(&entry_name),
+            original_path: entry_name,
+            compressed_size: size,
+            uncompressed_size: size,
+        };
+        if !emit(rules.normalize(&file_info.original_path), file_info) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn crawl_dat(
+    archive_index: u32,
+    path: &Path,
+    rules: &PathRules,
+    emit: &mut Emit,
+) -> Result<(), Error> {
+    let file = std::fs::File::open(path).path_err(path, Error::Dat)?;
+    let entries = crate::dat::read_index(&mut BufReader::new(file));
+    let entries = entries.path_err(path, Error::Dat)?;
+
+    for entry in entries {
+        let size = entry.size as u64;
+        let file_info = FileInfo {
+            location: FileLocation::Dat {
+                archive: archive_index,
+                offset: entry.offset as u64,
+                packed: entry.packed,
+            },
+            compressed_size: if entry.packed { entry.packed_size as u64 } else { size },
+            uncompressed_size: size,
+            file_type: recognize_type(&entry.name),
+            original_path: entry.name,
+        };
+        if !emit(rules.normalize(&file_info.original_path), file_info) {
+            break;
+        }
+    }
+    Ok(())
 }
 
 pub fn shadowed_files(
     archives: &[crate::FoArchive],
 ) -> Result<Vec<(String, u64, &Path, &Path)>, Error> {
-    assert!(archives.len() <= u16::max_value() as usize);
+    assert!(archives.len() <= u32::MAX as usize);
 
     let mut path_map = PathMap::new();
     let mut shadowed = Vec::with_capacity(512);
+    let limits = Limits::unlimited();
+    let mut tally = Tally::new(&limits);
 
     for (archive_index, archive) in archives.iter().enumerate() {
-        println!("Crawling {:?}", archive.path);
-        let archive_file = std::fs::File::open(&archive.path).unwrap();
-        let buf_reader = BufReader::with_capacity(1024, archive_file);
-        let mut archive = zip::ZipArchive::new(buf_reader).unwrap();
-        for i in 0..archive.len() {
-            let entry = archive.by_index(i).unwrap();
-            if entry.is_dir() {
-                continue;
-            }
-            let entry_name = entry.name();
-            let old = path_map.insert(
-                nom_prelude::make_path_conventional(entry_name),
-                FileInfo {
-                    location: FileLocation::Archive(archive_index as u16),
-                    original_path: entry_name.to_owned(),
-                    compressed_size: entry.compressed_size(),
-                    ..Default::default()
-                },
-            );
+        for (path, file_info) in crawl_archive(archive_index as u32, archive, &mut tally)? {
+            let old = path_map.insert(path, file_info);
             if let Some(old) = old {
-                if let FileLocation::Archive(old_index) = old.location {
-                    shadowed.push((
-                        old.original_path,
-                        old.compressed_size,
-                        archives[old_index as usize].path.as_path(),
-                        archives[archive_index].path.as_path(),
-                    ));
-                }
+                let old_index = old.location.archive_index();
+                shadowed.push((
+                    old.original_path,
+                    old.compressed_size,
+                    archives[old_index as usize].path.as_path(),
+                    archives[archive_index].path.as_path(),
+                ));
             }
         }
     }
@@ -91,19 +710,204 @@ pub fn shadowed_files(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::{self, TempData};
     #[test]
     fn test_gather_paths() {
         let archives = crate::datafiles::parse_datafile(crate::CLIENT_FOLDER).unwrap();
         let res = gather_paths(&archives).unwrap();
         for (entry_name, info) in &res {
             match info.location {
-                FileLocation::Local => {
-                    println!("{:?} => local", entry_name);
+                FileLocation::Local(index) => {
+                    println!("{:?} => local {:?}", entry_name, &archives[index as usize]);
                 }
-                FileLocation::Archive(index) => {
+                FileLocation::Archive { archive: index, .. }
+                | FileLocation::Tar { archive: index, .. }
+                | FileLocation::Dat { archive: index, .. } => {
                     println!("{:?} => {:?}", entry_name, &archives[index as usize]);
                 }
             }
         }
     }
+
+    #[test]
+    fn test_foignore() {
+        let data = TempData::new(&[
+            (IGNORE_FILE, b"*.psd\n*~\n"),
+            (&format!("art/{}", IGNORE_FILE), b"tmp/\n"),
+            ("art/tiles/tile.frm", b"data"),
+            ("art/tiles/tile.frm~", b"data"),
+            ("art/tiles/source.PSD", b"data"),
+            ("art/tmp/scratch.png", b"data"),
+            ("art/tiles/tmp.png", b"data"),
+        ]);
+
+        let limits = Limits::unlimited();
+        let archive = testing::archive(data.root(), None);
+        let files = crawl_archive(0, &archive, &mut Tally::new(&limits)).unwrap();
+        let paths: Vec<_> = files.keys().map(String::as_str).collect();
+        assert_eq!(paths, ["art/tiles/tile.frm", "art/tiles/tmp.png"]);
+    }
+
+    #[test]
+    fn test_broken_zip() {
+        let data = TempData::new(&[("data.zip", b"not a zip archive")]);
+        let path = data.root().join("data.zip");
+        let archives = vec![testing::archive(&path, None)];
+        let err = gather_paths(&archives).unwrap_err();
+        assert!(matches!(&err, Error::Zip(broken, _) if *broken == path), "{:?}", err);
+
+        std::fs::remove_file(&path).unwrap();
+        let err = gather_paths(&archives).unwrap_err();
+        assert!(
+            matches!(&err, Error::Zip(_, zip::result::ZipError::Io(_))),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_duplicate_zip_entries() {
+        use std::io::Write;
+
+        use crate::Retriever;
+
+        let data = TempData::new(&[]);
+        let path = data.root().join("data.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+        for (name, data) in &[("Art/A.txt", b"first"), ("art/a.txt", b"again")] {
+            zip.start_file(*name, Default::default()).unwrap();
+            zip.write_all(*data).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let archives = vec![testing::archive(&path, None)];
+        let (files, report) =
+            gather_paths_reported(&archives, &Limits::unlimited(), &PathRules::FONLINE).unwrap();
+        assert_eq!(report.crawled.len(), 1);
+        assert_eq!((&report.crawled[0].path, report.crawled[0].files), (&path, 1));
+        assert_eq!(
+            report.duplicates,
+            [DuplicateEntry {
+                archive: path,
+                path: "art/a.txt".into(),
+                ignored: 0,
+                authoritative: 1,
+            }]
+        );
+        assert_eq!(report.warnings().count(), 1);
+        assert!(report.has_warnings());
+
+        assert_eq!(files.len(), 1);
+        let retriever = testing::registry(archives).into_retriever();
+        assert_eq!(retriever.file_by_path("art/a.txt").unwrap(), b"again");
+    }
+
+    #[test]
+    fn test_streamed_precedence() {
+        let data = TempData::new(&[]);
+        let archives: Vec<_> = (0..6)
+            .map(|index| {
+                data.write(&format!("{}/art/shared.frm", index), b"data");
+                let path = data.root().join(index.to_string());
+                for file in 0..index * 1000 {
+                    std::fs::write(path.join(format!("art/{}_{}.frm", index, file)), b"").unwrap();
+                }
+                testing::archive(path, None)
+            })
+            .collect();
+        let shadowed_order = |report: &CrawlReport| -> Vec<u32> {
+            report.shadowed["art/shared.frm"]
+                .iter()
+                .map(|file_info| file_info.location.archive_index())
+                .collect()
+        };
+
+        let limits = Limits::unlimited();
+        let rules = PathRules::FONLINE;
+        let (files, report) =
+            gather_paths_with_precedence(&archives, &limits, &rules, Precedence::LastWins).unwrap();
+        assert_eq!(files.len(), 15_001);
+        assert_eq!(files["art/shared.frm"].location, FileLocation::Local(5));
+        assert_eq!(shadowed_order(&report), [0, 1, 2, 3, 4]);
+        let crawled: Vec<_> = report.crawled.iter().map(|crawled| crawled.files).collect();
+        assert_eq!(crawled, [1, 1001, 2001, 3001, 4001, 5001]);
+        assert!(!report.has_warnings());
+
+        let first_wins = Precedence::FirstWins;
+        let (files, report) =
+            gather_paths_with_precedence(&archives, &limits, &rules, first_wins).unwrap();
+        assert_eq!(files["art/shared.frm"].location, FileLocation::Local(0));
+        assert_eq!(shadowed_order(&report), [5, 4, 3, 2, 1]);
+
+        let limits = Limits {
+            max_files: Some(10_000),
+            ..Limits::unlimited()
+        };
+        assert!(matches!(
+            gather_paths_with_precedence(&archives, &limits, &rules, Precedence::LastWins),
+            Err(Error::TooManyFiles(10_000))
+        ));
+    }
+
+    #[test]
+    fn test_entry_filter() {
+        let data = TempData::new(&[
+            ("art/tile.frm", b"frm"),
+            ("art/huge.frm", &[0; 1024]),
+            ("art/notes.txt", b"txt"),
+        ]);
+        let root = data.root().to_owned();
+        let archives = vec![testing::archive(&root, Some("mod"))];
+
+        let filter = EntryFilter::new(|path, size, _| path.ends_with(".frm") && size < 1024);
+        assert!(!filter.accepts_all());
+        let limits = Limits {
+            max_files: Some(1),
+            ..Limits::unlimited()
+        };
+        let (files, report) = gather_paths_filtered(
+            &archives,
+            &limits,
+            &Default::default(),
+            Default::default(),
+            &filter,
+        )
+        .unwrap();
+        let paths: Vec<_> = files.keys().map(String::as_str).collect();
+        assert_eq!(paths, ["mod/art/tile.frm"]);
+        assert_eq!(report.crawled[0].files, 1);
+
+        let filter = EntryFilter::new(move |_, _, archive| archive != root);
+        let (files, _) = gather_paths_filtered(
+            &archives,
+            &limits,
+            &Default::default(),
+            Default::default(),
+            &filter,
+        )
+        .unwrap();
+        assert!(files.is_empty());
+    }
+
+    #[test]
+    fn test_crawl_limits() {
+        let limits = Limits {
+            max_files: Some(2),
+            max_total_size: Some(100),
+            max_path_len: Some(10),
+        };
+        let mut tally = Tally::new(&limits);
+        tally.count("a.frm", 10).unwrap();
+        assert!(matches!(
+            tally.count("long/path.frm", 10),
+            Err(Error::PathTooLong(_, 10))
+        ));
+        assert!(matches!(tally.count("b.frm", 10), Err(Error::TooManyFiles(2))));
+
+        let mut tally = Tally::new(&limits);
+        assert!(matches!(
+            tally.count("c.frm", 101),
+            Err(Error::TooLargeTotalSize(100))
+        ));
+    }
 }
@@ -0,0 +1,144 @@
+//! Conventional paths of critter animations.
+//!
+//! Animation of a critter is stored in `art/critters/<base><anim1><anim2>.<ext>`,
+//! where `base` is a critter type name (e.g. `hmwarr`), `anim1` is a weapon letter
+//! and `anim2` is an action letter. Extensions are searched in the engine order:
+//! `fofrm`, then `frm` with all directions, then `fr0`..`fr5` with a single direction.
+
+use crate::FoRegistry;
+
+pub const CRITTERS_DIR: &str = "art/critters";
+pub const DIRECTIONS: u8 = 6;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CritterAnim<'a> {
+    pub base: &'a str,
+    pub anim1: char,
+    pub anim2: char,
+}
+
+/// Animation file found in a registry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimFile {
+    pub path: String,
+    /// Direction inside of the file, `.fr0`..`.fr5` files contain only one.
+    pub direction: usize,
+}
+
+/// Letter of a numeric animation code, engine codes start from 1 for `a`.
+pub fn code_letter(code: u8) -> Option<char> {
+    if (1..=26).contains(&code) {
+        Some((b'a' + code - 1) as char)
+    } else {
+        None
+    }
+}
+
+impl<'a> CritterAnim<'a> {
+    pub fn new(base: &'a str, anim1: char, anim2: char) -> Self {
+        Self { base, anim1, anim2 }
+    }
+
+    pub fn from_codes(base: &'a str, anim1: u8, anim2: u8) -> Option<Self> {
+        Some(Self::new(base, code_letter(anim1)?, code_letter(anim2)?))
+    }
+
+    fn stem(&self) -> String {
+        nom_prelude::make_path_conventional(&format!(
+            "{}/{}{}{}",
+            CRITTERS_DIR, self.base, self.anim1, self.anim2
+        ))
+    }
+
+    pub fn fofrm_path(&self) -> String {
+        self.stem() + ".fofrm"
+    }
+
+    pub fn frm_path(&self) -> String {
+        self.stem() + ".frm"
+    }
+
+    pub fn direction_frm_path(&self, direction: u8) -> String {
+        format!("{}.fr{}", self.stem(), direction)
+    }
+
+    /// Paths that may contain `direction`, in search order.
+    pub fn candidates(&self, direction: u8) -> Vec<AnimFile> {
+        if direction >= DIRECTIONS {
+            return Vec::new();
+        }
+        vec![
+            AnimFile {
+                path: self.fofrm_path(),
+                direction: direction as usize,
+            },
+            AnimFile {
+                path: self.frm_path(),
+                direction: direction as usize,
+            },
+            AnimFile {
+                path: self.direction_frm_path(direction),
+                direction: 0,
+            },
+        ]
+    }
+
+    pub fn find(&self, registry: &FoRegistry, direction: u8) -> Option<AnimFile> {
+        self.candidates(direction)
+            .into_iter()
+            .find(|candidate| registry.file_info(&candidate.path).is_some())
+    }
+
+    /// Directions that have an animation file in the registry.
+    pub fn existing_directions(&self, registry: &FoRegistry) -> Vec<u8> {
+        (0..DIRECTIONS)
+            .filter(|&direction| self.find(registry, direction).is_some())
+            .collect()
+    }
+}
+
+fn is_anim_extension(ext: &str) -> bool {
+    ext == "fofrm"
+        || ext == "frm"
+        || (0..DIRECTIONS).any(|direction| ext == format!("fr{}", direction))
+}
+
+/// Every animation of the critter type with a file in the registry, sorted by letters.
+pub fn find_anims<'a>(registry: &FoRegistry, base: &'a str) -> Vec<CritterAnim<'a>> {
+    let prefix = CritterAnim::new(base, 'a', 'a').stem();
+    let prefix = &prefix[..prefix.len() - 2];
+    let mut anims: Vec<_> = registry
+        .files()
+        .filter_map(|(path, _)| {
+            let (stem, ext) = path.strip_prefix(prefix)?.split_once('.')?;
+            let mut letters = stem.chars();
+            match (letters.next(), letters.next(), letters.next()) {
+                (Some(anim1), Some(anim2), None) if is_anim_extension(ext) => Some((anim1, anim2)),
+                _ => None,
+            }
+        })
+        .collect();
+    anims.sort_unstable();
+    anims.dedup();
+    anims
+        .into_iter()
+        .map(|(anim1, anim2)| CritterAnim::new(base, anim1, anim2))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conventional_paths() {
+        let anim = CritterAnim::from_codes("HMWARR", 1, 1).unwrap();
+        assert_eq!(anim, CritterAnim::new("HMWARR", 'a', 'a'));
+        assert_eq!(anim.fofrm_path(), "art/critters/hmwarraa.fofrm");
+        assert_eq!(anim.frm_path(), "art/critters/hmwarraa.frm");
+        assert_eq!(anim.direction_frm_path(3), "art/critters/hmwarraa.fr3");
+        assert_eq!(anim.candidates(6), vec![]);
+        assert_eq!(code_letter(0), None);
+        assert_eq!(code_letter(27), None);
+    }
+}
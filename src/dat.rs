@@ -0,0 +1,377 @@
+//! Fallout DAT archives, like `master.dat` and `critter.dat` shipped with clients.
+//!
+//! DAT2 of Fallout 2 ends with the directory tree followed by two little-endian `u32`: the
+//! size of the tree and the size of the whole archive. The tree is a count of files and an
+//! entry per file: name length, name with `\` separators, compression flag, real size,
+//! packed size and offset of the data. DAT2 compresses entries with zlib.
+//!
+//! DAT1 of Fallout 1 is big-endian and starts with the tree: a header with the count of
+//! folders, their names, then for every folder a header with the count of files and an entry
+//! per file: name, attributes, offset, real size and packed size. DAT1 compresses entries
+//! into LZSS blocks, see [`decompress_lzss`].
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatVersion {
+    Dat1,
+    Dat2,
+}
+
+/// Entry of the directory tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatEntry {
+    /// Path inside the archive as stored, usually with `\` separators.
+    pub name: String,
+    /// Data is compressed, the codec depends on the version: zlib in DAT2, LZSS in DAT1.
+    pub packed: bool,
+    pub size: u32,
+    pub packed_size: u32,
+    pub offset: u32,
+}
+
+/// `format` is "DAT1", "DAT2" or just "DAT" before the version is known.
+fn invalid(format: &str, message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid {}: {}", format, message))
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u32_be(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+/// Name prefixed with its length in a byte, as DAT1 stores them.
+fn read_short_name(reader: &mut impl Read) -> io::Result<String> {
+    let mut len = [0];
+    reader.read_exact(&mut len)?;
+    let mut name = vec![0; len[0] as usize];
+    reader.read_exact(&mut name)?;
+    String::from_utf8(name).map_err(|_| invalid("DAT1", "name is not utf-8"))
+}
+
+/// DAT2 if the archive ends with its own size, DAT1 otherwise.
+pub fn detect_version<R: Read + Seek>(reader: &mut R) -> io::Result<DatVersion> {
+    let archive_size = reader.seek(SeekFrom::End(0))?;
+    if archive_size < 8 {
+        return Err(invalid("DAT", "file is too short"));
+    }
+    reader.seek(SeekFrom::Start(archive_size - 4))?;
+    Ok(if read_u32(reader)? as u64 == archive_size {
+        DatVersion::Dat2
+    } else {
+        DatVersion::Dat1
+    })
+}
+
+pub fn read_index<R: Read + Seek>(reader: &mut R) -> io::Result<Vec<DatEntry>> {
+    match detect_version(reader)? {
+        DatVersion::Dat1 => read_dat1_index(reader),
+        DatVersion::Dat2 => read_dat2_index(reader),
+    }
+}
+
+/// Reads the directory tree, entries are in archive order.
+pub fn read_dat2_index<R: Read + Seek>(reader: &mut R) -> io::Result<Vec<DatEntry>> {
+    let archive_size = reader.seek(SeekFrom::End(0))?;
+    if archive_size < 8 {
+        return Err(invalid("DAT2", "file is too short"));
+    }
+    reader.seek(SeekFrom::Start(archive_size - 8))?;
+    let tree_size = read_u32(reader)? as u64;
+    if read_u32(reader)? as u64 != archive_size {
+        return Err(invalid("DAT2", "declared size doesn't match the file"));
+    }
+    if tree_size < 4 || tree_size > archive_size - 8 {
+        return Err(invalid("DAT2", "tree is out of bounds"));
+    }
+    reader.seek(SeekFrom::Start(archive_size - 8 - tree_size))?;
+    let mut tree = vec![0; tree_size as usize];
+    reader.read_exact(&mut tree)?;
+    let mut tree = &tree[..];
+
+    let count = read_u32(&mut tree)?;
+    // Every entry takes at least 17 bytes, don't trust the count for preallocation.
+    let mut entries = Vec::with_capacity((count as usize).min(tree.len() / 17));
+    let data_end = archive_size - 8 - tree_size;
+    for _ in 0..count {
+        let name_len = read_u32(&mut tree)? as usize;
+        if name_len > tree.len() {
+            return Err(invalid("DAT2", "name is out of bounds"));
+        }
+        let (name, rest) = tree.split_at(name_len);
+        tree = rest;
+        let name = std::str::from_utf8(name).map_err(|_| invalid("DAT2", "name is not utf-8"))?;
+        let mut packed = [0];
+        tree.read_exact(&mut packed)?;
+        let entry = DatEntry {
+            name: name.to_owned(),
+            packed: packed[0] != 0,
+            size: read_u32(&mut tree)?,
+            packed_size: read_u32(&mut tree)?,
+            offset: read_u32(&mut tree)?,
+        };
+        let stored = if entry.packed { entry.packed_size } else { entry.size };
+        if entry.offset as u64 + stored as u64 > data_end {
+            return Err(invalid("DAT2", "data is out of bounds"));
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Reads the directory tree, entries are in archive order with names joined by `\`.
+pub fn read_dat1_index<R: Read + Seek>(reader: &mut R) -> io::Result<Vec<DatEntry>> {
+    let archive_size = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let folder_count = read_u32_be(reader)?;
+    // Every folder name takes at least a byte.
+    if folder_count as u64 > archive_size {
+        return Err(invalid("DAT1", "too many folders"));
+    }
+    reader.seek(SeekFrom::Current(12))?;
+    let folders = (0..folder_count)
+        .map(|_| read_short_name(reader))
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut entries = Vec::new();
+    for folder in folders {
+        let file_count = read_u32_be(reader)?;
+        if file_count as u64 > archive_size {
+            return Err(invalid("DAT1", "too many files"));
+        }
+        reader.seek(SeekFrom::Current(12))?;
+        for _ in 0..file_count {
+            let name = read_short_name(reader)?;
+            let attributes = read_u32_be(reader)?;
+            let entry = DatEntry {
+                name: match folder.as_str() {
+                    "." => name,
+                    folder => format!("{}\\{}", folder, name),
+                },
+                packed: attributes & DAT1_PACKED != 0,
+                offset: read_u32_be(reader)?,
+                size: read_u32_be(reader)?,
+                packed_size: read_u32_be(reader)?,
+            };
+            let stored = if entry.packed { entry.packed_size } else { entry.size };
+            if entry.offset as u64 + stored as u64 > archive_size {
+                return Err(invalid("DAT1", "data is out of bounds"));
+            }
+            if entry.packed && entry.size as u64 > entry.packed_size as u64 * MAX_LZSS_RATIO {
+                return Err(invalid("DAT1", "real size can't be decompressed from packed size"));
+            }
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// Attribute of LZSS-compressed DAT1 entries, stored ones have `0x20`.
+const DAT1_PACKED: u32 = 0x40;
+/// LZSS expands 17 bytes to 144 at most: a flag byte and 8 references of 18 bytes each.
+const MAX_LZSS_RATIO: u64 = 9;
+
+/// Decompresses a DAT1 entry: big-endian `i16` block lengths, negative ones are followed by
+/// stored bytes, positive ones by LZSS data with a fresh 4 KiB dictionary, 0 ends the data.
+/// Stops after `size` bytes.
+pub fn decompress_lzss(mut input: &[u8], size: usize) -> io::Result<Vec<u8>> {
+    const DICTIONARY: usize = 4096;
+    const MAX_MATCH: usize = 18;
+    const THRESHOLD: usize = 2;
+
+    fn take<'a>(input: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+        if len > input.len() {
+            return Err(invalid("DAT1", "LZSS block is out of bounds"));
+        }
+        let (taken, rest) = input.split_at(len);
+        *input = rest;
+        Ok(taken)
+    }
+
+    // `size` comes from the archive, don't trust it for preallocation
+    let max_preallocation = crate::retriever::fo::MAX_PREALLOCATION as usize;
+    let mut out = Vec::with_capacity(size.min(max_preallocation));
+    while out.len() < size && !input.is_empty() {
+        let length = take(&mut input, 2)?;
+        let length = i16::from_be_bytes([length[0], length[1]]);
+        if length == 0 {
+            break;
+        }
+        if length < 0 {
+            out.extend_from_slice(take(&mut input, length.unsigned_abs() as usize)?);
+            continue;
+        }
+        let mut block = take(&mut input, length as usize)?;
+        let mut dictionary = [b' '; DICTIONARY];
+        let mut position = DICTIONARY - MAX_MATCH;
+        let mut push = |out: &mut Vec<u8>, dictionary: &mut [u8; DICTIONARY], byte| {
+            out.push(byte);
+            dictionary[position] = byte;
+            position = (position + 1) & (DICTIONARY - 1);
+        };
+        while let Some((&flags, rest)) = block.split_first() {
+            block = rest;
+            for bit in 0..8 {
+                if flags >> bit & 1 == 1 {
+                    match block.split_first() {
+                        Some((&byte, rest)) => {
+                            block = rest;
+                            push(&mut out, &mut dictionary, byte);
+                        }
+                        None => break,
+                    }
+                } else {
+                    if block.len() < 2 {
+                        break;
+                    }
+                    let (low, high) = (block[0] as usize, block[1] as usize);
+                    block = &block[2..];
+                    let offset = low | (high & 0xF0) << 4;
+                    for index in 0..(high & 0x0F) + THRESHOLD + 1 {
+                        let byte = dictionary[(offset + index) & (DICTIONARY - 1)];
+                        push(&mut out, &mut dictionary, byte);
+                    }
+                }
+            }
+        }
+    }
+    out.truncate(size);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        retriever::Retriever,
+        testing::{self, TempData},
+    };
+    use std::io::Write;
+
+    /// DAT2 with `(name, data, compress)` entries.
+    fn dat2(files: &[(&str, &[u8], bool)]) -> Vec<u8> {
+        let mut dat = Vec::new();
+        let mut tree = (files.len() as u32).to_le_bytes().to_vec();
+        for &(name, data, compress) in files {
+            let offset = dat.len() as u32;
+            if compress {
+                let mut encoder =
+                    flate2::write::ZlibEncoder::new(&mut dat, flate2::Compression::best());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap();
+            } else {
+                dat.extend_from_slice(data);
+            }
+            tree.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            tree.extend_from_slice(name.as_bytes());
+            tree.push(compress as u8);
+            tree.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            tree.extend_from_slice(&(dat.len() as u32 - offset).to_le_bytes());
+            tree.extend_from_slice(&offset.to_le_bytes());
+        }
+        dat.extend_from_slice(&tree);
+        dat.extend_from_slice(&(tree.len() as u32).to_le_bytes());
+        let size = dat.len() as u32 + 4;
+        dat.extend_from_slice(&size.to_le_bytes());
+        dat
+    }
+
+    #[test]
+    fn index_and_read_dat2() {
+        let text = b"{100}{}{Hello}".repeat(10);
+        let dat = dat2(&[("ART\\Tiles\\A.FRM", b"frm", false), ("text\\game.msg", &text, true)]);
+        let entries = read_dat2_index(&mut io::Cursor::new(&dat)).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name, "ART\\Tiles\\A.FRM");
+        assert!(entries[1].packed && entries[1].packed_size < entries[1].size);
+        let mut truncated = dat.clone();
+        truncated.remove(0);
+        assert!(read_dat2_index(&mut io::Cursor::new(&truncated)).is_err());
+
+        let data = TempData::new(&[("master.dat", &dat)]);
+        let archive = testing::archive(data.root().join("master.dat"), None);
+        assert_eq!(archive.kind(), crate::ArchiveKind::Dat2);
+        let retriever = testing::registry(vec![archive]).into_retriever();
+        let info = retriever.registry().file_info("text/game.msg").unwrap();
+        let packed_size = entries[1].packed_size as u64;
+        assert_eq!((info.compressed_size(), info.uncompressed_size()), (packed_size, 140));
+        assert_eq!(retriever.file_by_path("art/tiles/a.frm").unwrap(), b"frm");
+        assert_eq!(retriever.file_by_path("text/game.msg").unwrap(), text);
+        let mut written = Vec::new();
+        retriever.write_file_by_info(info, &mut written).unwrap();
+        assert_eq!(written, text);
+    }
+
+    #[test]
+    fn index_and_read_dat1() {
+        // "abc", then a reference to the 6 bytes from its start, then a stored block.
+        let lzss = [0, 6, 0x07, b'a', b'b', b'c', 0xEE, 0xF3, 0xFF, 0xFD, b'x', b'y', b'z', 0, 0];
+        assert_eq!(decompress_lzss(&lzss, 12).unwrap(), b"abcabcabcxyz");
+        assert_eq!(decompress_lzss(&lzss, 4).unwrap(), b"abca");
+        assert!(decompress_lzss(&lzss[..5], 12).is_err());
+
+        let be = |value: u32| value.to_be_bytes();
+        let mut dat = Vec::new();
+        let folder_header = |dat: &mut Vec<u8>, count| {
+            dat.extend_from_slice(&be(count));
+            dat.extend_from_slice(&[be(0), be(0x10), be(0)].concat());
+        };
+        folder_header(&mut dat, 2);
+        dat.extend_from_slice(b"\x01.\x03ART");
+        let files = [(".", "README.TXT", &b"fo1"[..], false), ("ART", "A.FRM", &lzss[..], true)];
+        let tree_size: usize = files.iter().map(|file| 16 + 1 + file.1.len() + 16).sum();
+        let mut offset = (dat.len() + tree_size) as u32;
+        for (_, name, data, packed) in &files {
+            folder_header(&mut dat, 1);
+            dat.push(name.len() as u8);
+            dat.extend_from_slice(name.as_bytes());
+            let (attributes, size) = if *packed { (0x40, 12) } else { (0x20, data.len()) };
+            dat.extend_from_slice(&be(attributes));
+            dat.extend_from_slice(&be(offset));
+            dat.extend_from_slice(&be(size as u32));
+            dat.extend_from_slice(&be(if *packed { data.len() as u32 } else { 0 }));
+            offset += data.len() as u32;
+        }
+        for (_, _, data, _) in &files {
+            dat.extend_from_slice(data);
+        }
+        let mut cursor = io::Cursor::new(&dat);
+        assert_eq!(detect_version(&mut cursor).unwrap(), DatVersion::Dat1);
+        let entries = read_index(&mut cursor).unwrap();
+        assert_eq!(entries[0].name, "README.TXT");
+        assert_eq!(entries[1].name, "ART\\A.FRM");
+        assert!(entries[1].packed);
+
+        let data = TempData::new(&[("fo1.dat", &dat)]);
+        let archive = testing::archive(data.root().join("fo1.dat"), None);
+        assert_eq!(archive.kind(), crate::ArchiveKind::Dat1);
+        // detected once, the header isn't read again
+        data.write("fo1.dat", b"garbage");
+        assert_eq!(archive.kind(), crate::ArchiveKind::Dat1);
+        data.write("fo1.dat", &dat);
+        let retriever = testing::registry(vec![archive]).into_retriever();
+        assert_eq!(retriever.file_by_path("readme.txt").unwrap(), b"fo1");
+        assert_eq!(retriever.file_by_path("art/a.frm").unwrap(), b"abcabcabcxyz");
+        let limited = retriever.with_max_file_size(Some(8));
+        assert!(matches!(
+            limited.file_by_path("art/a.frm"),
+            Err(crate::retriever::fo::Error::FileTooLarge(12, 8))
+        ));
+        assert!(matches!(
+            limited.stream_by_path("art/a.frm"),
+            Err(crate::retriever::fo::Error::FileTooLarge(12, 8))
+        ));
+
+        // real size far beyond what the packed data can expand to
+        let name_end = dat.windows(5).position(|name| name == b"A.FRM").unwrap() + 5;
+        dat[name_end + 8..name_end + 12].copy_from_slice(&be(u32::MAX));
+        let err = read_index(&mut io::Cursor::new(&dat)).unwrap_err();
+        assert!(err.to_string().starts_with("invalid DAT1"));
+    }
+}
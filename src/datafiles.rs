@@ -2,9 +2,19 @@ use std::path::{Path, PathBuf};
 
 use nom_prelude::{complete::*, *};
 
-use crate::PathError;
+use serde::{Deserialize, Serialize};
+
+use crate::{crawler::Precedence, PathError};
 
 const DATAFILES_CFG: &str = "DataFiles.cfg";
+/// Where engine versions and forks keep the list of archives, in order of preference.
+const DATAFILES_CANDIDATES: &[&str] = &[DATAFILES_CFG, "data/DataFiles.cfg", "Data/DataFiles.cfg"];
+/// Folders of newer engine versions that hold archives themselves.
+const RESOURCES_CANDIDATES: &[&str] = &["Resources", "resources"];
+/// Folders of server data useful for cross-checking client data.
+const SERVER_FOLDERS: &[&str] = &["proto", "maps", "text"];
+/// Conventional folder server data is registered in, so it doesn't mix with client files.
+pub const SERVER_MOUNT: &str = "server";
 
 #[derive(Debug)]
 pub enum Error {
@@ -13,6 +23,120 @@ pub enum Error {
     Metadata(PathBuf, std::io::Error),
     //Nom(nom::Err<(String, nom::error::ErrorKind)>),
     Nom(nom::Err<String>),
+    ReadDir(PathBuf, std::io::Error),
+    /// Client root has neither a list of archives nor a resources folder.
+    UnknownLayout(PathBuf),
+}
+
+/// How a client lists its archives, differs between engine versions.
+/// Paths are relative to the client root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ClientLayout {
+    /// Archives listed in a config, relative to its folder: `DataFiles.cfg` of classic clients.
+    DataFiles(PathBuf),
+    /// Every archive and folder inside of the folder, in name order:
+    /// `resources/*.zip` of FOnline 2 and Reloaded engines.
+    Resources(PathBuf),
+}
+
+impl ClientLayout {
+    /// How the engine picks between archives with the same file.
+    pub fn precedence(&self) -> Precedence {
+        match self {
+            ClientLayout::DataFiles(_) => Precedence::LastWins,
+            ClientLayout::Resources(_) => Precedence::FirstWins,
+        }
+    }
+
+}
+
+/// Looks for known layouts in `client_root`, a list of archives is preferred over a folder.
+pub fn detect_layout<P: AsRef<Path>>(client_root: P) -> Result<ClientLayout, Error> {
+    let client_root = client_root.as_ref();
+    if let Some(config) = DATAFILES_CANDIDATES
+        .iter()
+        .find(|config| client_root.join(config).is_file())
+    {
+        return Ok(ClientLayout::DataFiles(config.into()));
+    }
+    if let Some(folder) = RESOURCES_CANDIDATES
+        .iter()
+        .find(|folder| client_root.join(folder).is_dir())
+    {
+        return Ok(ClientLayout::Resources(folder.into()));
+    }
+    Err(Error::UnknownLayout(client_root.to_owned()))
+}
+
+/// Folders of server data, registered under [`SERVER_MOUNT`] with their own names.
+pub fn server_archives<P: AsRef<Path>>(server_root: P) -> Result<Vec<crate::FoArchive>, Error> {
+    let server_root = server_root.as_ref();
+    let archives: Vec<_> = SERVER_FOLDERS
+        .iter()
+        .filter(|folder| server_root.join(folder).is_dir())
+        .map(|folder| {
+            let path = server_root.join(folder);
+            let path = path
+                .canonicalize()
+                .map_err(|err| Error::Canonicalize(path, err))?;
+            Ok(crate::FoArchive {
+                mount: Some(format!("{}/{}", SERVER_MOUNT, folder)),
+                kind: Default::default(),
+                ..gather_metadata(path)?
+            })
+        })
+        .collect::<Result<_, Error>>()?;
+    if archives.is_empty() {
+        return Err(Error::UnknownLayout(server_root.to_owned()));
+    }
+    Ok(archives)
+}
+
+/// Archives of the client, in the order they are crawled.
+pub fn layout_archives<P: AsRef<Path>>(
+    client_root: P,
+    layout: &ClientLayout,
+) -> Result<Vec<crate::FoArchive>, Error> {
+    let client_root = client_root.as_ref();
+    match layout {
+        ClientLayout::DataFiles(config) => {
+            let config = client_root.join(config);
+            let parent = config.parent().unwrap_or(client_root);
+            parse_datafile_at(parent, &config)
+        }
+        ClientLayout::Resources(folder) => {
+            let folder = client_root.join(folder);
+            let mut paths = Vec::new();
+            for entry in std::fs::read_dir(&folder).path_err(&folder, Error::ReadDir)? {
+                let entry = entry.path_err(&folder, Error::ReadDir)?;
+                if is_resource(&entry.path()) {
+                    paths.push(entry.path());
+                }
+            }
+            paths.sort();
+            paths
+                .into_iter()
+                .map(|path| {
+                    let path = path
+                        .canonicalize()
+                        .map_err(|err| Error::Canonicalize(path, err))?;
+                    gather_metadata(path)
+                })
+                .collect()
+        }
+    }
+}
+
+/// Folders and archives of supported kinds, hidden entries are skipped.
+fn is_resource(path: &Path) -> bool {
+    let name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name.to_ascii_lowercase(),
+        None => return false,
+    };
+    if name.starts_with('.') {
+        return false;
+    }
+    path.is_dir() || [".zip", ".tar", ".tar.gz", ".tgz"].iter().any(|ext| name.ends_with(ext))
 }
 
 fn datafile_path(parent_folder: &Path) -> Result<PathBuf, Error> {
@@ -22,9 +146,32 @@ fn datafile_path(parent_folder: &Path) -> Result<PathBuf, Error> {
         .path_err(&parent_folder, Error::Canonicalize)
 }
 
-fn changetime(path: &Path) -> Result<crate::ChangeTime, Error> {
+/// Newest modification time of `path`, of every entry inside of it for folders:
+/// editing a nested file changes neither the time of its folder nor of the root.
+pub(crate) fn changetime(path: &Path) -> Result<crate::ChangeTime, Error> {
     let metadata = path.metadata().path_err(path, Error::Metadata)?;
-    metadata.modified().path_err(path, Error::Metadata)
+    let changed = metadata.modified().path_err(path, Error::Metadata)?;
+    if metadata.is_dir() {
+        return newest_in_folder(path, changed);
+    }
+    Ok(changed)
+}
+
+fn newest_in_folder(
+    folder: &Path,
+    mut newest: crate::ChangeTime,
+) -> Result<crate::ChangeTime, Error> {
+    for entry in std::fs::read_dir(folder).path_err(folder, Error::ReadDir)? {
+        let path = entry.path_err(folder, Error::ReadDir)?.path();
+        // symlinks are not followed, same as the crawler does
+        let metadata = path.symlink_metadata().path_err(&path, Error::Metadata)?;
+        let changed = metadata.modified().path_err(&path, Error::Metadata)?;
+        newest = newest.max(changed);
+        if metadata.is_dir() {
+            newest = newest_in_folder(&path, newest)?;
+        }
+    }
+    Ok(newest)
 }
 
 pub fn datafiles_changetime<P: AsRef<Path>>(parent_folder: P) -> Result<crate::ChangeTime, Error> {
@@ -34,23 +181,40 @@ pub fn datafiles_changetime<P: AsRef<Path>>(parent_folder: P) -> Result<crate::C
 
 pub fn parse_datafile<P: AsRef<Path>>(parent_folder: P) -> Result<Vec<crate::FoArchive>, Error> {
     let datafiles = datafile_path(parent_folder.as_ref())?;
-    let file = std::fs::read_to_string(&datafiles).path_err(&datafiles, Error::Io)?;
-    //parse_datafile_inner::<(&str, nom::error::ErrorKind)>(&file)
-    parse_datafile_inner::<nom::error::VerboseError<_>>(&file)
+    parse_datafile_at(parent_folder.as_ref(), &datafiles)
+}
+
+fn parse_datafile_at(
+    parent_folder: &Path,
+    datafiles: &Path,
+) -> Result<Vec<crate::FoArchive>, Error> {
+    let file = std::fs::read_to_string(datafiles).path_err(datafiles, Error::Io)?;
+    datafile_entries(&file).and_then(|vec| {
+        let res: Result<Vec<crate::FoArchive>, Error> = vec
+            .into_iter()
+            .map(|path| datapath(parent_folder, path).and_then(gather_metadata))
+            .collect();
+        res
+    })
+}
+
+/// Data paths listed in the text of `DataFiles.cfg`, without comments and includes.
+pub fn datafile_entries(text: &str) -> Result<Vec<&str>, Error> {
+    //parse_datafile_inner::<(&str, nom::error::ErrorKind)>(text)
+    parse_datafile_inner::<nom::error::VerboseError<_>>(text)
         //.map_err(|err| Error::Nom(owned_err(err)))
-        .map_err(|err| Error::Nom(err.map(|err| nom::error::convert_error(&file, err))))
-        .and_then(|(_rest, vec)| {
-            let res: Result<Vec<crate::FoArchive>, Error> = vec
-                .into_iter()
-                .map(|path| datapath(parent_folder.as_ref(), path).and_then(gather_metadata))
-                .collect();
-            res
-        })
+        .map_err(|err| Error::Nom(err.map(|err| nom::error::convert_error(text, err))))
+        .map(|(_rest, vec)| vec)
 }
 
 fn gather_metadata(path: PathBuf) -> Result<crate::FoArchive, Error> {
     let changed = changetime(&path)?;
-    Ok(crate::FoArchive { changed, path })
+    Ok(crate::FoArchive {
+        changed,
+        path,
+        mount: None,
+        kind: Default::default(),
+    })
 }
 
 fn parse_datafile_inner<'a, E: std::fmt::Debug + ParseError<&'a str>>(
@@ -113,6 +277,86 @@ fn datapath(parent: &Path, datapath: &str) -> Result<PathBuf, Error> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[test]
+    fn malformed_input_is_error() {
+        let text = "# comment\n#\ninclude other.cfg\r\n\ndata/a.zip\r\n./b\nc.dat\r";
+        for end in 0..=text.len() {
+            let _ = datafile_entries(&text[..end]);
+        }
+    }
+
+    #[test]
+    fn nested_changes_of_folders() {
+        let root = std::env::temp_dir().join("fo_data_test_folder_changetime");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("art/tiles")).unwrap();
+        std::fs::write(root.join("art/tiles/tile.frm"), b"tile").unwrap();
+        let before = changetime(&root).unwrap();
+
+        let later = before + std::time::Duration::from_secs(60);
+        let nested = std::fs::File::options()
+            .write(true)
+            .open(root.join("art/tiles/tile.frm"))
+            .unwrap();
+        nested.set_modified(later).unwrap();
+        assert_eq!(changetime(&root).unwrap(), later);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn layouts() {
+        let root = std::env::temp_dir().join("fo_data_test_layouts");
+        let _ = std::fs::remove_dir_all(&root);
+        assert!(matches!(detect_layout(&root), Err(Error::UnknownLayout(_))));
+
+        let resources = root.join("Resources");
+        std::fs::create_dir_all(resources.join("b_folder")).unwrap();
+        for name in &["c.zip", "a.TGZ", ".hidden.zip", "readme.txt"] {
+            std::fs::write(resources.join(name), b"").unwrap();
+        }
+        let layout = detect_layout(&root).unwrap();
+        assert_eq!(layout, ClientLayout::Resources("Resources".into()));
+        let names: Vec<_> = layout_archives(&root, &layout)
+            .unwrap()
+            .iter()
+            .map(|archive| archive.path.file_name().unwrap().to_owned())
+            .collect();
+        assert_eq!(names, ["a.TGZ", "b_folder", "c.zip"]);
+
+        std::fs::create_dir_all(root.join("data")).unwrap();
+        std::fs::write(root.join("data/DataFiles.cfg"), "# list\n../Resources/c.zip\n").unwrap();
+        let layout = detect_layout(&root).unwrap();
+        assert_eq!(layout, ClientLayout::DataFiles("data/DataFiles.cfg".into()));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn server_data() {
+        let root = std::env::temp_dir().join("fo_data_test_server_data");
+        let _ = std::fs::remove_dir_all(&root);
+        assert!(matches!(server_archives(&root), Err(Error::UnknownLayout(_))));
+
+        std::fs::create_dir_all(root.join("proto/items")).unwrap();
+        std::fs::create_dir_all(root.join("maps")).unwrap();
+        std::fs::write(root.join("proto/items/a.fopro"), b"[Proto]").unwrap();
+        std::fs::write(root.join("maps/m.fomap"), b"[Header]").unwrap();
+        let archives = server_archives(&root).unwrap();
+        let mounts: Vec<_> = archives.iter().map(|archive| archive.mount()).collect();
+        assert_eq!(mounts, [Some("server/proto"), Some("server/maps")]);
+
+        let (files, _) = crate::crawler::gather_paths_with_precedence(
+            &archives,
+            &Default::default(),
+            &Default::default(),
+            Default::default(),
+        )
+        .unwrap();
+        let mut paths: Vec<_> = files.keys().map(String::as_str).collect();
+        paths.sort_unstable();
+        assert_eq!(paths, ["server/maps/m.fomap", "server/proto/items/a.fopro"]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
     #[test]
     fn test_parse_datafile() {
         let datafiles = parse_datafile(crate::CLIENT_FOLDER).unwrap();
@@ -0,0 +1,180 @@
+//! Index of art references in both directions, to see what uses a file before
+//! changing or deleting it.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{references, FoRegistry, FoRetriever};
+
+#[derive(Debug, Clone, Default)]
+pub struct DepIndex {
+    /// Referencing file to referenced art, sorted.
+    deps: BTreeMap<String, Vec<String>>,
+    /// Art to files referencing it, sorted.
+    dependents: BTreeMap<String, Vec<String>>,
+    /// Referencing files that can't be read or parsed, with the reason.
+    unparsable: Vec<(String, String)>,
+}
+
+impl DepIndex {
+    /// Reads and parses every referencing file of the registry.
+    pub fn build(retriever: &FoRetriever) -> Self {
+        let mut index = Self::default();
+        for (path, info) in retriever.registry().files() {
+            if !references::can_reference(path) {
+                continue;
+            }
+            let deps = retriever
+                .file_by_info(info)
+                .map_err(|err| format!("can't read: {}", err))
+                .and_then(|data| {
+                    let text = crate::text::decode_text(&data, crate::TextEncoding::Auto);
+                    references::art_references_of(path, &text)
+                });
+            match deps {
+                Ok(deps) => index.insert(path, deps),
+                Err(err) => index.unparsable.push((path.to_owned(), err)),
+            }
+        }
+        for dependents in index.dependents.values_mut() {
+            dependents.sort_unstable();
+        }
+        index
+    }
+
+    fn insert(&mut self, path: &str, deps: Vec<String>) {
+        if deps.is_empty() {
+            return;
+        }
+        for dep in &deps {
+            self.dependents
+                .entry(dep.clone())
+                .or_default()
+                .push(path.to_owned());
+        }
+        self.deps.insert(path.to_owned(), deps);
+    }
+
+    /// Art referenced by the file.
+    pub fn deps_of(&self, path: &str) -> &[String] {
+        self.deps.get(path).map_or(&[], Vec::as_slice)
+    }
+
+    /// Files referencing the art, whether it exists or not.
+    pub fn dependents_of(&self, path: &str) -> &[String] {
+        self.dependents.get(path).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn unparsable(&self) -> &[(String, String)] {
+        &self.unparsable
+    }
+
+    /// What deleting `paths` would do, nothing is deleted. References are considered
+    /// broken only if no split frm counterpart is left, as the engine falls back to it.
+    pub fn plan_delete(&self, registry: &FoRegistry, paths: &[&str]) -> DeletePlan {
+        let rules = registry.path_rules();
+        let deleted: BTreeSet<String> = paths.iter().map(|path| rules.normalize(path)).collect();
+        let remains =
+            |path: &str| !deleted.contains(path) && registry.file_info(path).is_some();
+        let mut plan = DeletePlan::default();
+
+        for path in &deleted {
+            match registry.file_info(path) {
+                Some(info) => plan.reclaimed += info.compressed_size(),
+                None => plan.missing.push(path.clone()),
+            }
+            for dependent in self.dependents_of(path) {
+                if deleted.contains(dependent) {
+                    continue;
+                }
+                if !references::exists_with_counterparts(path, remains) {
+                    plan.breaks
+                        .entry(dependent.clone())
+                        .or_default()
+                        .push(path.clone());
+                }
+            }
+            for dep in self.deps_of(path) {
+                let still_used = self
+                    .dependents_of(dep)
+                    .iter()
+                    .any(|dependent| !deleted.contains(dependent));
+                if !still_used && remains(dep) {
+                    plan.orphaned.insert(dep.clone());
+                }
+            }
+        }
+        plan
+    }
+}
+
+/// Outcome of deleting files, see [`DepIndex::plan_delete`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeletePlan {
+    /// Remaining files that would have broken references, with the deleted art they reference.
+    pub breaks: BTreeMap<String, Vec<String>>,
+    /// Remaining art that was referenced only by deleted files.
+    pub orphaned: BTreeSet<String>,
+    /// Archive bytes of deleted files, what players stop downloading.
+    pub reclaimed: u64,
+    /// Paths to delete that are not in the registry.
+    pub missing: Vec<String>,
+}
+
+impl DeletePlan {
+    /// Nothing would break.
+    pub fn is_safe(&self) -> bool {
+        self.breaks.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempData;
+
+    #[test]
+    fn plan_deletions() {
+        let data = TempData::new(&[
+            ("art/a.fofrm", b"frm_0=a.png\nfrm_1=shared.png\n"),
+            ("art/b.fofrm", b"frm_0=shared.png\nfrm_1=split.frm\n"),
+            ("art/a.png", b"aaaa"),
+            ("art/shared.png", b"ss"),
+            ("art/split.frm", b"frm"),
+            ("art/split.fr0", b"fr0"),
+        ]);
+        let retriever = data.retriever();
+        let registry = retriever.registry();
+        let index = DepIndex::build(&retriever);
+        assert_eq!(index.dependents_of("art/shared.png"), ["art/a.fofrm", "art/b.fofrm"]);
+        assert_eq!(index.deps_of("art/a.fofrm"), ["art/a.png", "art/shared.png"]);
+
+        let plan = index.plan_delete(registry, &["art/shared.png", "art/split.frm", "art/x.png"]);
+        assert_eq!(plan.breaks.keys().collect::<Vec<_>>(), ["art/a.fofrm", "art/b.fofrm"]);
+        assert_eq!(plan.breaks["art/b.fofrm"], ["art/shared.png"]);
+        assert_eq!(plan.reclaimed, 5);
+        assert_eq!(plan.missing, ["art/x.png"]);
+        assert!(!plan.is_safe());
+
+        let plan = index.plan_delete(registry, &["Art/A.fofrm"]);
+        assert!(plan.is_safe());
+        assert_eq!(plan.orphaned.iter().collect::<Vec<_>>(), ["art/a.png"]);
+    }
+
+    #[test]
+    fn unparsable_and_empty() {
+        let data = TempData::new(&[
+            ("text/broken.msg", b"{100}{}{unterminated"),
+            ("text/empty.msg", b""),
+        ]);
+        let retriever = data.retriever();
+        let index = DepIndex::build(&retriever);
+        let unparsable: Vec<_> = index.unparsable().iter().map(|(path, _)| path).collect();
+        assert_eq!(unparsable, ["text/broken.msg"]);
+        assert!(index.deps_of("text/empty.msg").is_empty());
+        assert!(index.dependents_of("art/none.png").is_empty());
+
+        let plan = index.plan_delete(retriever.registry(), &[]);
+        assert_eq!(plan, DeletePlan::default());
+        assert!(plan.is_safe());
+    }
+}
@@ -0,0 +1,825 @@
+//! Export of art for review without external tools: critter animations as png sequences
+//! and uncompressed y4m video, which players like mpv and ffplay open directly, and
+//! contact sheets of folders for quick visual audits of tile and scenery sets,
+//! and spritesheets with a descriptor for engines and web viewers.
+
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    converter::encode_png,
+    critters::{self, CritterAnim},
+    Animation, AnimationDirection, ConvertOptions, Converter, FileType, FoRegistry,
+    GetImageError, RetrieveError, Retriever,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoFormat {
+    /// Numbered png files in a folder, transparency is kept.
+    PngSequence,
+    /// YUV4MPEG2 video with full chroma, transparent pixels are blended over `background`.
+    Y4m { background: [u8; 3] },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoLayout {
+    /// A separate video for every direction.
+    PerDirection,
+    /// All directions in one video, left to right in rows of `columns`.
+    /// Shorter directions are looped.
+    Grid { columns: u32 },
+}
+
+#[derive(Debug, Clone)]
+pub struct VideoOptions {
+    pub format: VideoFormat,
+    pub layout: VideoLayout,
+    /// Frames per second of animations that don't declare their own.
+    pub default_fps: u16,
+    /// Direction, frame and direction map are ignored, directions are in FRM order.
+    pub convert: ConvertOptions,
+}
+
+impl Default for VideoOptions {
+    fn default() -> Self {
+        Self {
+            format: VideoFormat::PngSequence,
+            layout: VideoLayout::Grid { columns: 3 },
+            default_fps: 10,
+            convert: ConvertOptions::default(),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum VideoError {
+    #[error("critter {0:?} has no animations")]
+    NoAnimations(String),
+    #[error("can't convert {0:?}: {1:?}")]
+    Convert(String, GetImageError),
+    #[error("can't encode {0:?}: {1}")]
+    Encode(PathBuf, image::ImageError),
+    #[error("can't write {0:?}: {1}")]
+    Write(PathBuf, io::Error),
+}
+
+/// Frames of every direction of the animation in FRM order, empty for missing directions.
+fn load_directions<R: Retriever>(
+    converter: &Converter<'_, '_, R>,
+    registry: &FoRegistry,
+    anim: &CritterAnim<'_>,
+    options: &ConvertOptions,
+) -> Result<Animation, VideoError>
+where
+    R::Error: Into<RetrieveError>,
+{
+    let options = options.in_frm_order();
+    let mut loaded: Vec<(String, Animation)> = Vec::new();
+    let mut animation = Animation {
+        fps: 0,
+        directions: Vec::new(),
+    };
+    for direction in 0..critters::DIRECTIONS {
+        let file = match anim.find(registry, direction) {
+            Some(file) => file,
+            None => {
+                animation.directions.push(AnimationDirection::default());
+                continue;
+            }
+        };
+        let index = match loaded.iter().position(|(path, _)| *path == file.path) {
+            Some(index) => index,
+            None => {
+                let source = converter
+                    .get_animation(&file.path, &options)
+                    .map_err(|err| VideoError::Convert(file.path.clone(), err))?;
+                loaded.push((file.path, source));
+                loaded.len() - 1
+            }
+        };
+        let source = &loaded[index].1;
+        if animation.fps == 0 {
+            animation.fps = source.fps;
+        }
+        let frames = source.directions.get(file.direction).cloned();
+        animation.directions.push(frames.unwrap_or_default());
+    }
+    Ok(animation)
+}
+
+fn direction_frames(animation: &Animation, direction: usize) -> Vec<image::RgbaImage> {
+    (0..animation.directions[direction].frames.len())
+        .filter_map(|frame| animation.compose_frame(direction, frame))
+        .map(|raw| raw.image)
+        .collect()
+}
+
+fn grid_frames(animation: &Animation, columns: u32) -> Vec<image::RgbaImage> {
+    let bounds = match animation.bounds() {
+        Some(bounds) => bounds,
+        None => return Vec::new(),
+    };
+    let (cell_width, cell_height) = (bounds.width(), bounds.height());
+    let cells = animation.directions.len() as u32;
+    let columns = columns.clamp(1, cells.max(1));
+    let rows = cells.div_ceil(columns);
+    let count = animation
+        .directions
+        .iter()
+        .map(|direction| direction.frames.len())
+        .max()
+        .unwrap_or(0);
+    (0..count)
+        .map(|frame| {
+            let mut canvas = image::RgbaImage::new(cell_width * columns, cell_height * rows);
+            for (direction, AnimationDirection { frames, .. }) in
+                animation.directions.iter().enumerate()
+            {
+                if frames.is_empty() {
+                    continue;
+                }
+                if let Some(raw) = animation.compose_frame(direction, frame % frames.len()) {
+                    let cell = direction as u32;
+                    let x = (cell % columns * cell_width) as i64;
+                    let y = (cell / columns * cell_height) as i64;
+                    image::imageops::replace(&mut canvas, &raw.image, x, y);
+                }
+            }
+            canvas
+        })
+        .collect()
+}
+
+/// Studio range BT.601, same as most players assume for y4m without a color tag.
+fn rgb_to_yuv([red, green, blue]: [f32; 3]) -> [u8; 3] {
+    let y = 16.0 + (65.481 * red + 128.553 * green + 24.966 * blue) / 255.0;
+    let u = 128.0 + (-37.797 * red - 74.203 * green + 112.0 * blue) / 255.0;
+    let v = 128.0 + (112.0 * red - 93.786 * green - 18.214 * blue) / 255.0;
+    [y.round() as u8, u.round() as u8, v.round() as u8]
+}
+
+fn write_y4m(
+    mut out: impl Write,
+    fps: u16,
+    frames: &[image::RgbaImage],
+    background: [u8; 3],
+) -> io::Result<()> {
+    let (width, height) = frames.first().map_or((0, 0), |frame| frame.dimensions());
+    writeln!(out, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", width, height, fps)?;
+    let plane = width as usize * height as usize;
+    let mut planes = vec![0; plane * 3];
+    for frame in frames {
+        for (index, pixel) in frame.pixels().enumerate() {
+            let alpha = pixel[3] as f32 / 255.0;
+            let mut rgb = [0.0; 3];
+            for channel in 0..3 {
+                rgb[channel] = pixel[channel] as f32 * alpha
+                    + background[channel] as f32 * (1.0 - alpha);
+            }
+            let [y, u, v] = rgb_to_yuv(rgb);
+            planes[index] = y;
+            planes[plane + index] = u;
+            planes[plane * 2 + index] = v;
+        }
+        out.write_all(b"FRAME\n")?;
+        out.write_all(&planes)?;
+    }
+    out.flush()
+}
+
+/// Writes `frames` as a video named `name` in `out_dir`, returns its path.
+fn write_video(
+    out_dir: &Path,
+    name: &str,
+    fps: u16,
+    frames: &[image::RgbaImage],
+    format: VideoFormat,
+) -> Result<PathBuf, VideoError> {
+    let write_err = |path: &Path| {
+        let path = path.to_owned();
+        move |err| VideoError::Write(path, err)
+    };
+    match format {
+        VideoFormat::PngSequence => {
+            let folder = out_dir.join(name);
+            std::fs::create_dir_all(&folder).map_err(write_err(&folder))?;
+            let mut png = Vec::new();
+            for (index, frame) in frames.iter().enumerate() {
+                let path = folder.join(format!("{:04}.png", index));
+                encode_png(frame, &mut png).map_err(|err| VideoError::Encode(path.clone(), err))?;
+                std::fs::write(&path, &png).map_err(write_err(&path))?;
+            }
+            Ok(folder)
+        }
+        VideoFormat::Y4m { background } => {
+            std::fs::create_dir_all(out_dir).map_err(write_err(out_dir))?;
+            let path = out_dir.join(format!("{}.y4m", name));
+            let file = std::fs::File::create(&path).map_err(write_err(&path))?;
+            write_y4m(io::BufWriter::new(file), fps, frames, background)
+                .map_err(write_err(&path))?;
+            Ok(path)
+        }
+    }
+}
+
+/// Exports every animation of the critter type found by [`critters::find_anims`] into
+/// `out_dir`. Videos are named after animation files, e.g. `hmwarraa` for the grid
+/// layout and `hmwarraa_2` for direction 2. Returns paths of written videos.
+pub fn export_critter<R: Retriever>(
+    converter: &Converter<'_, '_, R>,
+    registry: &FoRegistry,
+    base: &str,
+    out_dir: &Path,
+    options: &VideoOptions,
+) -> Result<Vec<PathBuf>, VideoError>
+where
+    R::Error: Into<RetrieveError>,
+{
+    let anims = critters::find_anims(registry, base);
+    if anims.is_empty() {
+        return Err(VideoError::NoAnimations(base.to_owned()));
+    }
+    let mut written = Vec::new();
+    for anim in &anims {
+        let animation = load_directions(converter, registry, anim, &options.convert)?;
+        let name = format!("{}{}{}", base.to_lowercase(), anim.anim1, anim.anim2);
+        let fps = match animation.fps {
+            0 => options.default_fps,
+            fps => fps,
+        };
+        match options.layout {
+            VideoLayout::PerDirection => {
+                for direction in 0..animation.directions.len() {
+                    let frames = direction_frames(&animation, direction);
+                    if frames.is_empty() {
+                        continue;
+                    }
+                    let name = format!("{}_{}", name, direction);
+                    written.push(write_video(out_dir, &name, fps, &frames, options.format)?);
+                }
+            }
+            VideoLayout::Grid { columns } => {
+                let frames = grid_frames(&animation, columns);
+                written.push(write_video(out_dir, &name, fps, &frames, options.format)?);
+            }
+        }
+    }
+    Ok(written)
+}
+
+#[derive(Debug, Clone)]
+pub struct SheetOptions {
+    /// Thumbnails are scaled down to fit a square of this size, never up.
+    pub thumb_size: u32,
+    pub columns: u32,
+    pub rows: u32,
+    /// Draws paths relative to the folder under thumbnails.
+    pub labels: bool,
+    pub background: [u8; 4],
+    pub convert: ConvertOptions,
+}
+
+impl Default for SheetOptions {
+    fn default() -> Self {
+        Self {
+            thumb_size: 96,
+            columns: 8,
+            rows: 6,
+            labels: true,
+            background: [48, 48, 48, 255],
+            convert: ConvertOptions::default(),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SheetError {
+    #[error("folder {0:?} has no images")]
+    NoImages(String),
+    #[error("can't encode {0:?}: {1}")]
+    Encode(PathBuf, image::ImageError),
+    #[error("can't write {0:?}: {1}")]
+    Write(PathBuf, io::Error),
+}
+
+#[derive(Debug, Default)]
+pub struct SheetReport {
+    /// Written pages, in order.
+    pub pages: Vec<PathBuf>,
+    pub images: usize,
+    /// Images that couldn't be converted, their cells are filled with red.
+    pub failed: Vec<(String, GetImageError)>,
+}
+
+const SHEET_PADDING: u32 = 4;
+const LABEL_COLOR: [u8; 4] = [220, 220, 220, 255];
+const FAILED_COLOR: [u8; 4] = [160, 24, 24, 255];
+const GLYPH_WIDTH: u32 = 3;
+const GLYPH_HEIGHT: u32 = 5;
+const GLYPH_SCALE: u32 = 2;
+
+/// Rows of a 3x5 glyph, the highest of 3 bits is the leftmost pixel. Conventional paths
+/// are lowercase, other characters are drawn as `?`.
+fn glyph(char: char) -> [u8; 5] {
+    match char.to_ascii_lowercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'a' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'b' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'c' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'd' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'e' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'f' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'g' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'h' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'i' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'j' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'k' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'l' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'm' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'n' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'o' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'p' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'r' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        's' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        't' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'u' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'v' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'w' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'x' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        ' ' => [0; 5],
+        _ => [0b111, 0b001, 0b010, 0b000, 0b010],
+    }
+}
+
+fn fill(
+    canvas: &mut image::RgbaImage,
+    (x, y): (u32, u32),
+    (width, height): (u32, u32),
+    color: [u8; 4],
+) {
+    for y in y..(y + height).min(canvas.height()) {
+        for x in x..(x + width).min(canvas.width()) {
+            canvas.put_pixel(x, y, image::Rgba(color));
+        }
+    }
+}
+
+/// Draws `text` at `(x, y)`, keeping its end if it's wider than `max_width`.
+fn draw_label(canvas: &mut image::RgbaImage, (x, y): (u32, u32), text: &str, max_width: u32) {
+    let advance = (GLYPH_WIDTH + 1) * GLYPH_SCALE;
+    let fits = ((max_width + GLYPH_SCALE) / advance) as usize;
+    let chars: Vec<char> = text.chars().collect();
+    let shown = &chars[chars.len().saturating_sub(fits)..];
+    for (index, &char) in shown.iter().enumerate() {
+        let left = x + index as u32 * advance;
+        for (row, bits) in glyph(char).iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - column)) != 0 {
+                    let at = (left + column * GLYPH_SCALE, y + row as u32 * GLYPH_SCALE);
+                    fill(canvas, at, (GLYPH_SCALE, GLYPH_SCALE), LABEL_COLOR);
+                }
+            }
+        }
+    }
+}
+
+/// Draws the image centered in a `size` square at `(x, y)`, scaled down to fit.
+fn draw_thumb(
+    canvas: &mut image::RgbaImage,
+    (x, y): (u32, u32),
+    size: u32,
+    image: &image::RgbaImage,
+) {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return;
+    }
+    let scale = (size as f32 / width.max(height) as f32).min(1.0);
+    let scaled = |dimension: u32| ((dimension as f32 * scale).round() as u32).clamp(1, size);
+    let (thumb_width, thumb_height) = (scaled(width), scaled(height));
+    let left = (x + (size - thumb_width) / 2) as i64;
+    let top = (y + (size - thumb_height) / 2) as i64;
+    if (thumb_width, thumb_height) == (width, height) {
+        image::imageops::overlay(canvas, image, left, top);
+    } else {
+        let thumb = image::imageops::resize(
+            image,
+            thumb_width,
+            thumb_height,
+            image::imageops::FilterType::Nearest,
+        );
+        image::imageops::overlay(canvas, &thumb, left, top);
+    }
+}
+
+/// Renders png, gif, frm and fofrm images under the virtual folder `dir`, recursively, into
+/// pages of `columns` x `rows` thumbnails written to `out_dir` as `<dir>_<page>.png`.
+/// All pages have the same size. Images that fail to convert don't stop the export.
+pub fn contact_sheets<R: Retriever>(
+    converter: &Converter<'_, '_, R>,
+    registry: &FoRegistry,
+    dir: &str,
+    out_dir: &Path,
+    options: &SheetOptions,
+) -> Result<SheetReport, SheetError>
+where
+    R::Error: Into<RetrieveError>,
+{
+    let folder = registry.path_rules().normalize(dir);
+    let folder = folder.trim_matches('/');
+    let prefix = if folder.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", folder)
+    };
+    let mut paths: Vec<&str> = registry
+        .files()
+        .filter(|(_, info)| {
+            let file_type = info.file_type();
+            matches!(file_type, FileType::Png | FileType::Gif | FileType::Frm | FileType::FoFrm)
+        })
+        .map(|(path, _)| path)
+        .filter(|path| path.starts_with(prefix.as_str()))
+        .collect();
+    if paths.is_empty() {
+        return Err(SheetError::NoImages(dir.to_owned()));
+    }
+    paths.sort_unstable();
+
+    let write_err = |path: &Path| {
+        let path = path.to_owned();
+        move |err| SheetError::Write(path, err)
+    };
+    std::fs::create_dir_all(out_dir).map_err(write_err(out_dir))?;
+    let name = match folder {
+        "" => "sheet".to_owned(),
+        folder => folder.replace('/', "_"),
+    };
+    let (columns, rows) = (options.columns.max(1), options.rows.max(1));
+    let size = options.thumb_size.max(1);
+    let label_height = match options.labels {
+        true => GLYPH_HEIGHT * GLYPH_SCALE + SHEET_PADDING,
+        false => 0,
+    };
+    let (cell_width, cell_height) = (size + SHEET_PADDING, size + label_height + SHEET_PADDING);
+    let page_width = columns * cell_width + SHEET_PADDING;
+    let page_height = rows * cell_height + SHEET_PADDING;
+
+    let mut report = SheetReport {
+        images: paths.len(),
+        ..SheetReport::default()
+    };
+    let mut png = Vec::new();
+    for (page, page_paths) in paths.chunks((columns * rows) as usize).enumerate() {
+        let mut canvas =
+            image::RgbaImage::from_pixel(page_width, page_height, image::Rgba(options.background));
+        for (index, path) in page_paths.iter().enumerate() {
+            let index = index as u32;
+            let x = SHEET_PADDING + index % columns * cell_width;
+            let y = SHEET_PADDING + index / columns * cell_height;
+            match converter.get_rgba_with(path, &options.convert) {
+                Ok(raw) => draw_thumb(&mut canvas, (x, y), size, &raw.image),
+                Err(err) => {
+                    fill(&mut canvas, (x, y), (size, size), FAILED_COLOR);
+                    report.failed.push((path.to_string(), err));
+                }
+            }
+            if options.labels {
+                let label = &path[prefix.len()..];
+                draw_label(&mut canvas, (x, y + size + SHEET_PADDING), label, size);
+            }
+        }
+        let path = out_dir.join(format!("{}_{:03}.png", name, page + 1));
+        encode_png(&canvas, &mut png).map_err(|err| SheetError::Encode(path.clone(), err))?;
+        std::fs::write(&path, &png).map_err(write_err(&path))?;
+        report.pages.push(path);
+    }
+    Ok(report)
+}
+
+#[derive(Debug, Clone)]
+pub struct SpritesheetOptions {
+    /// Transparent pixels around every frame, so filtering doesn't bleed neighbours in.
+    pub padding: u32,
+    /// Frames per second of animations that don't declare their own.
+    pub default_fps: u16,
+    /// Direction and frame are ignored, directions are ordered by the direction map.
+    pub convert: ConvertOptions,
+}
+
+impl Default for SpritesheetOptions {
+    fn default() -> Self {
+        Self {
+            padding: 1,
+            default_fps: 10,
+            convert: ConvertOptions::default(),
+        }
+    }
+}
+
+/// Placement of a frame in the sheet.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpriteFrame {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Top left corner relative to the sprite position, shifts are already applied.
+    pub offset_x: i16,
+    pub offset_y: i16,
+    pub duration_ms: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpriteDirection {
+    /// Index of the first frame of the direction in [`SpritesheetMeta::frames`].
+    pub first_frame: usize,
+    pub frame_count: usize,
+    pub shift: (i16, i16),
+}
+
+/// Descriptor of a spritesheet, serializable with any serde format.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpritesheetMeta {
+    /// Conventional path of the source image.
+    pub source: String,
+    pub width: u32,
+    pub height: u32,
+    pub fps: u16,
+    /// Frames of all directions, direction after direction.
+    pub frames: Vec<SpriteFrame>,
+    pub directions: Vec<SpriteDirection>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Spritesheet {
+    pub image: image::RgbaImage,
+    pub meta: SpritesheetMeta,
+}
+
+impl Spritesheet {
+    pub fn to_png(&self) -> Result<Vec<u8>, image::ImageError> {
+        let mut png = Vec::new();
+        encode_png(&self.image, &mut png)?;
+        Ok(png)
+    }
+}
+
+/// Packs every frame of every direction of the image into one sheet, a row per direction.
+pub fn spritesheet<R: Retriever>(
+    converter: &Converter<'_, '_, R>,
+    path: &str,
+    options: &SpritesheetOptions,
+) -> Result<Spritesheet, GetImageError>
+where
+    R::Error: Into<RetrieveError>,
+{
+    let animation = converter.get_animation(path, &options.convert)?;
+    let padding = options.padding;
+    let fps = match animation.fps {
+        0 => options.default_fps.max(1),
+        fps => fps,
+    };
+    let mut meta = SpritesheetMeta {
+        source: path.to_owned(),
+        width: 0,
+        height: padding,
+        fps,
+        frames: Vec::new(),
+        directions: Vec::new(),
+    };
+    for direction in &animation.directions {
+        meta.directions.push(SpriteDirection {
+            first_frame: meta.frames.len(),
+            frame_count: direction.frames.len(),
+            shift: direction.shift,
+        });
+        let mut x = padding;
+        let mut row_height = 0;
+        for raw in &direction.frames {
+            let (width, height) = raw.image.dimensions();
+            meta.frames.push(SpriteFrame {
+                x,
+                y: meta.height,
+                width,
+                height,
+                offset_x: raw.offset_x,
+                offset_y: raw.offset_y,
+                duration_ms: 1000 / fps as u32,
+            });
+            x += width + padding;
+            row_height = row_height.max(height);
+        }
+        meta.width = meta.width.max(x);
+        meta.height += row_height + padding;
+    }
+    let mut image = image::RgbaImage::new(meta.width, meta.height);
+    let raws = animation.directions.iter().flat_map(|direction| &direction.frames);
+    for (frame, raw) in meta.frames.iter().zip(raws) {
+        image::imageops::replace(&mut image, &raw.image, frame.x as i64, frame.y as i64);
+    }
+    Ok(Spritesheet { image, meta })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        frm::FrameHeader,
+        retriever::memory::MemoryRetriever,
+        testing::{FrmFixture, TempData},
+    };
+
+    #[test]
+    fn export_videos() {
+        let frame = |width| FrameHeader {
+            width,
+            height: 2,
+            offset_x: 0,
+            offset_y: 0,
+        };
+        let single = FrmFixture::new(5)
+            .direction((0, 0))
+            .frame(frame(2), 1)
+            .frame(frame(3), 2)
+            .build();
+        let full = (0..6)
+            .fold(FrmFixture::new(0), |frm, _| frm.direction((0, 0)).frame(frame(4), 3))
+            .build();
+        let data = TempData::new(&[
+            ("art/critters/hmtestaa.fr0", &single),
+            ("art/critters/hmtestaa.fr2", &single),
+            ("art/critters/hmtestab.frm", &full),
+            ("art/critters/hmtestabc.frm", &full),
+        ]);
+        let retriever = data.retriever();
+        let registry = retriever.registry();
+        let palette = crate::testing::gradient_palette();
+        let converter = Converter::new(&retriever, &palette);
+        let anims: Vec<_> = critters::find_anims(registry, "HMTEST")
+            .iter()
+            .map(|anim| (anim.anim1, anim.anim2))
+            .collect();
+        assert_eq!(anims, [('a', 'a'), ('a', 'b')]);
+
+        let output = TempData::new(&[]);
+        let out = output.root().to_owned();
+        let options = VideoOptions::default();
+        let written = export_critter(&converter, registry, "hmtest", &out, &options).unwrap();
+        assert_eq!(written, [out.join("hmtestaa"), out.join("hmtestab")]);
+        let grid = image::open(out.join("hmtestaa/0001.png")).unwrap();
+        assert_eq!((grid.width(), grid.height()), (9, 4));
+        assert!(!out.join("hmtestaa/0002.png").exists());
+
+        let options = VideoOptions {
+            format: VideoFormat::Y4m {
+                background: [0, 0, 0],
+            },
+            layout: VideoLayout::PerDirection,
+            ..VideoOptions::default()
+        };
+        let written = export_critter(&converter, registry, "hmtest", &out, &options).unwrap();
+        assert_eq!(written.len(), 2 + 6);
+        let y4m = std::fs::read(out.join("hmtestaa_2.y4m")).unwrap();
+        let header = b"YUV4MPEG2 W3 H2 F5:1 Ip A1:1 C444\n";
+        assert!(y4m.starts_with(header));
+        assert_eq!(y4m.len(), header.len() + 2 * (6 + 3 * 2 * 3));
+        assert!(matches!(
+            export_critter(&converter, registry, "nothing", &out, &options),
+            Err(VideoError::NoAnimations(_))
+        ));
+    }
+
+    #[test]
+    fn contact_sheet_pages() {
+        let frame = |width, height| FrameHeader {
+            width,
+            height,
+            offset_x: 0,
+            offset_y: 0,
+        };
+        let frm = |width, height| {
+            FrmFixture::new(0)
+                .direction((0, 0))
+                .frame(frame(width, height), 7)
+                .build()
+        };
+        let data = TempData::new(&[
+            ("art/tiles/wide.frm", &frm(200, 100)),
+            ("art/tiles/desert/small.frm", &frm(2, 2)),
+            ("art/tiles/broken.frm", b"frm"),
+            ("art/tiles/readme.txt", b"tiles"),
+        ]);
+        let retriever = data.retriever();
+        let palette = crate::testing::gradient_palette();
+        let converter = Converter::new(&retriever, &palette);
+        let output = TempData::new(&[]);
+        let out = output.root().to_owned();
+        let options = SheetOptions {
+            thumb_size: 50,
+            columns: 2,
+            rows: 1,
+            ..SheetOptions::default()
+        };
+        let registry = retriever.registry();
+        let report = contact_sheets(&converter, registry, "Art/Tiles/", &out, &options).unwrap();
+        assert_eq!(report.pages, [out.join("art_tiles_001.png"), out.join("art_tiles_002.png")]);
+        assert_eq!(report.images, 3);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, "art/tiles/broken.frm");
+
+        // broken.frm, desert/small.frm | wide.frm
+        let first = image::open(&report.pages[0]).unwrap().to_rgba8();
+        let second = image::open(&report.pages[1]).unwrap().to_rgba8();
+        assert_eq!(first.dimensions(), (2 * 54 + 4, 50 + 14 + 4 + 4));
+        assert_eq!(second.dimensions(), first.dimensions());
+        assert_eq!(first.get_pixel(4, 4).0, FAILED_COLOR);
+        let palette_color = |x, y| second.get_pixel(x, y).0 != options.background;
+        assert!(palette_color(4, 4 + 12) && palette_color(4 + 49, 4 + 36));
+        assert!(!palette_color(4, 4 + 11) && !palette_color(4, 4 + 37));
+        assert!(!palette_color(54 + 4 + 25, 4 + 25));
+        assert!(first.pixels().any(|pixel| pixel.0 == LABEL_COLOR));
+        assert!(matches!(
+            contact_sheets(&converter, registry, "art/walls", &out, &options),
+            Err(SheetError::NoImages(_))
+        ));
+    }
+
+    #[test]
+    fn packed_spritesheet() {
+        let frame = |width, height| FrameHeader {
+            width,
+            height,
+            offset_x: 1,
+            offset_y: 0,
+        };
+        let frm = FrmFixture::new(5)
+            .direction((0, 0))
+            .frame(frame(2, 3), 1)
+            .frame(frame(4, 1), 2)
+            .direction((7, -2))
+            .frame(frame(3, 2), 3)
+            .frame(frame(1, 1), 4)
+            .build();
+        let retriever = MemoryRetriever::new().with_file("art/a.frm", frm);
+        let palette = crate::testing::gradient_palette();
+        let converter = Converter::new(&retriever, &palette);
+        let sheet = spritesheet(&converter, "art/a.frm", &SpritesheetOptions::default()).unwrap();
+        let meta = &sheet.meta;
+        assert_eq!((meta.width, meta.height), (9, 8));
+        assert_eq!(sheet.image.dimensions(), (9, 8));
+        assert_eq!(meta.fps, 5);
+        let rects: Vec<_> = meta
+            .frames
+            .iter()
+            .map(|frame| (frame.x, frame.y, frame.width, frame.height))
+            .collect();
+        assert_eq!(rects, [(1, 1, 2, 3), (4, 1, 4, 1), (1, 5, 3, 2), (5, 5, 1, 1)]);
+        let offsets: Vec<_> = meta
+            .frames
+            .iter()
+            .map(|frame| (frame.offset_x, frame.offset_y))
+            .collect();
+        assert_eq!(offsets, [(-1, -3), (-1, -1), (6, -4), (8, -3)]);
+        assert_eq!(meta.frames[0].duration_ms, 200);
+        assert_eq!(
+            meta.directions,
+            [
+                SpriteDirection {
+                    first_frame: 0,
+                    frame_count: 2,
+                    shift: (0, 0)
+                },
+                SpriteDirection {
+                    first_frame: 2,
+                    frame_count: 2,
+                    shift: (7, -2)
+                },
+            ]
+        );
+        assert_eq!(sheet.image.get_pixel(0, 0).0[3], 0);
+        assert_eq!(sheet.image.get_pixel(1, 1).0[3], 255);
+        assert!(sheet.to_png().is_ok());
+    }
+}
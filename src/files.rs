@@ -0,0 +1,122 @@
+//! Set of registry files for audit tools: start with every file, mark the ones
+//! something uses, whatever is left is unused.
+
+use crate::{FileInfo, FoRegistry, PathMap};
+
+/// Files of a registry not marked as used yet.
+///
+/// Marking is idempotent and never fails: unknown paths and already marked
+/// files are ignored, the return value tells if a file was actually removed.
+pub struct Files<'a> {
+    registry: &'a FoRegistry,
+    unused: PathMap<&'a str, &'a FileInfo>,
+}
+
+impl<'a> Files<'a> {
+    /// Every file of the registry, none marked.
+    pub fn new(registry: &'a FoRegistry) -> Self {
+        Self {
+            registry,
+            unused: registry.files().collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.unused.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.unused.is_empty()
+    }
+
+    pub fn is_unused(&self, path: &str) -> bool {
+        self.unused.contains_key(path)
+    }
+
+    /// `path` must be conventional.
+    pub fn mark_used(&mut self, path: &str) -> bool {
+        self.unused.remove(path).is_some()
+    }
+
+    /// Marks the file with the registry's name hash, returns its path if it wasn't marked yet.
+    pub fn mark_used_by_hash(&mut self, hash: u32) -> Option<&'a str> {
+        let path = self.registry.path_by_hash(hash)?;
+        if self.mark_used(path) {
+            Some(path)
+        } else {
+            None
+        }
+    }
+
+    /// Marks every file `keep` returns `false` for.
+    pub fn retain_unused(&mut self, mut keep: impl FnMut(&str, &FileInfo) -> bool) {
+        self.unused.retain(|path, info| keep(path, info));
+    }
+
+    /// Unused files in path order.
+    pub fn unused(&self) -> impl '_ + Iterator<Item = (&'a str, &'a FileInfo)> {
+        self.unused.iter().map(|(path, info)| (*path, *info))
+    }
+
+    /// Unused files in order of the registry's name hashes, see [`FoRegistry::files_by_hash`].
+    pub fn unused_by_hash(&self) -> Vec<(u32, &'a str, &'a FileInfo)> {
+        crate::by_hash_order(self.registry.hash_function(), self.unused())
+    }
+
+    /// Takes all unused files in path order, leaving the set empty.
+    pub fn drain(&mut self) -> impl Iterator<Item = (&'a str, &'a FileInfo)> {
+        std::mem::take(&mut self.unused).into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FileLocation;
+
+    #[test]
+    fn mark_and_drain() {
+        let mut registry = FoRegistry::stub();
+        for path in &["art/a.frm", "art/b.frm", "art/c.png", "data/d.msg"] {
+            let info = FileInfo {
+                location: FileLocation::Local(0),
+                original_path: path.to_string(),
+                compressed_size: 0,
+                uncompressed_size: 0,
+                file_type: crate::retriever::recognize_type(path),
+            };
+            registry.insert_file(path.to_string(), info);
+        }
+
+        let mut files = Files::new(&registry);
+        assert_eq!(files.len(), 4);
+        let by_hash = files.unused_by_hash();
+        assert_eq!(by_hash, registry.files_by_hash());
+        assert!(by_hash.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        assert_eq!(by_hash[0].0, registry.hash_function().hash(by_hash[0].1));
+        assert!(files.mark_used("art/a.frm"));
+        assert!(!files.mark_used("art/a.frm"));
+        assert!(!files.mark_used("art/missing.frm"));
+        let hash = registry.hash_function().hash("art/b.frm");
+        assert_eq!(files.mark_used_by_hash(hash), Some("art/b.frm"));
+        assert_eq!(files.mark_used_by_hash(hash), None);
+        assert!(!files.is_unused("art/b.frm"));
+
+        files.retain_unused(|path, _info| path.starts_with("art/"));
+        assert_eq!(files.unused().map(|(path, _)| path).collect::<Vec<_>>(), ["art/c.png"]);
+        assert_eq!(files.drain().count(), 1);
+        assert!(files.is_empty());
+    }
+
+    #[test]
+    fn empty_registry() {
+        let registry = FoRegistry::stub();
+        let mut files = Files::new(&registry);
+        assert!(files.is_empty());
+        assert!(files.unused_by_hash().is_empty());
+        assert!(!files.mark_used(""));
+        assert_eq!(files.mark_used_by_hash(0), None);
+        files.retain_unused(|_, _| unreachable!());
+        assert_eq!(files.drain().count(), 0);
+    }
+}
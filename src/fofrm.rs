@@ -13,6 +13,37 @@ pub struct FoFrmRaw<'a> {
 }
 
 impl<'a> FoFrmRaw<'a> {
+    /// FOFRM text that parses back to the same value. Frames of the first direction are
+    /// written without a section, as in most FOFRM files, other directions get `[dir_N]`.
+    pub fn to_text(&self) -> String {
+        use std::fmt::{Display, Write};
+
+        fn line(text: &mut String, key: impl Display, value: Option<impl Display>) {
+            if let Some(value) = value {
+                writeln!(text, "{}={}", key, value).unwrap();
+            }
+        }
+        let mut text = String::new();
+        line(&mut text, "fps", self.fps);
+        line(&mut text, "count", self.count);
+        line(&mut text, "effect", self.effect);
+        line(&mut text, "offs_x", self.offset_x);
+        line(&mut text, "offs_y", self.offset_y);
+        for (dir, direction) in self.directions.iter().enumerate() {
+            if dir > 0 || direction.frames.is_empty() {
+                writeln!(text, "[dir_{}]", dir).unwrap();
+            }
+            for (index, frame) in direction.frames.iter().enumerate() {
+                line(&mut text, format_args!("frm_{}", index), frame.frm);
+                line(&mut text, format_args!("next_x_{}", index), frame.next_x);
+                line(&mut text, format_args!("next_y_{}", index), frame.next_y);
+            }
+            line(&mut text, "offs_x", direction.offset_x);
+            line(&mut text, "offs_y", direction.offset_y);
+        }
+        text
+    }
+
     fn without_directions(&mut self) -> Result<&mut Self, FoFrmErrorKind> {
         if self.directions.is_empty() {
             Ok(self)
@@ -24,16 +55,13 @@ impl<'a> FoFrmRaw<'a> {
     fn end_direction(&mut self) -> Result<(), FoFrmErrorKind> {
         if let Some(direction) = self.directions.last_mut() {
             direction.end_frame()?;
-            let len = direction.frames.len() as u16;
+            let len = direction.frames.len();
             match &mut self.count {
-                Some(count) if *count < len => {
-                    return Err(FoFrmErrorKind::WrongNumberOfFrames(
-                        direction.frames.len(),
-                        *count,
-                    ));
+                Some(count) if (*count as usize) < len => {
+                    return Err(FoFrmErrorKind::WrongNumberOfFrames(len, *count));
                 }
-                Some(count) if *count > len => {
-                    *count = len;
+                Some(count) if *count as usize > len => {
+                    *count = len as u16;
                 }
                 _ => {}
             }
@@ -45,6 +73,9 @@ impl<'a> FoFrmRaw<'a> {
         if self.directions.len() != dir as usize {
             return Err(FoFrmErrorKind::WrongDirOrder(self.directions.len(), dir));
         }
+        if self.directions.is_full() {
+            return Err(FoFrmErrorKind::TooManyDirections(dir));
+        }
         self.end_direction()?;
         self.directions.push(Default::default());
         Ok(())
@@ -85,10 +116,10 @@ impl<'a> Direction<'a> {
     }
 
     fn get_frame(&mut self, frame: u16) -> Result<&mut Frame<'a>, FoFrmErrorKind> {
-        let len: u16 = self.frames.len() as u16;
-        if len > frame + 1 {
-            Err(FoFrmErrorKind::WrongFrameOrder(self.frames.len(), frame))
-        } else if len == frame + 1 {
+        let len = self.frames.len();
+        if len > frame as usize + 1 {
+            Err(FoFrmErrorKind::WrongFrameOrder(len, frame))
+        } else if len == frame as usize + 1 {
             Ok(self.frames.last_mut().unwrap())
         } else {
             self.new_frame()
@@ -193,6 +224,7 @@ pub enum FoFrmErrorKind {
     RepeatedToken,
     UnexpectedToken,
     WrongDirOrder(usize, u8),
+    TooManyDirections(u8),
     WrongFrameOrder(usize, u16),
     FrameWithoutPath(usize),
     WrongNumberOfFrames(usize, u16),
@@ -280,12 +312,35 @@ mod test {
         );
     }
 
+    #[test]
+    fn malformed_input_is_error() {
+        let directions = (0..7).map(|dir| format!("[dir_{}]\nfrm=a.frm\n", dir));
+        assert!(matches!(
+            parse_verbose(&directions.collect::<String>()),
+            Err(FoFrmError::Verify(_, FoFrmErrorKind::TooManyDirections(6)))
+        ));
+        assert!(parse_verbose("frm_65535=a.frm\nnext_x_65535=1\n").is_err());
+        assert!(parse_verbose("count=2\nfrm_0=a.frm\nfrm_1=b.frm\nfrm_2=c.frm\n").is_err());
+        assert!(parse_verbose("").is_err());
+
+        let text = crate::testing::FoFrmFixture::new()
+            .fps(10)
+            .offset((1, -1))
+            .frame("a.frm", (1, 2))
+            .direction()
+            .frame("b.frm", (3, 4))
+            .build();
+        for end in 0..text.len() {
+            let _ = parse_verbose(&text[..end]);
+        }
+    }
+
     #[test]
     fn parse_all_fofrm() {
         let registry = crate::FoRegistry::init(crate::CLIENT_FOLDER).unwrap();
         let retriever = registry.into_retriever();
         //let retriever = crate::test_retriever();
-        for (path, file_info) in &retriever.registry().files {
+        for (path, file_info) in retriever.registry().files() {
             if crate::retriever::recognize_type(path) == crate::FileType::FoFrm {
                 let bytes = retriever.file_by_info(file_info).unwrap();
                 let string = std::str::from_utf8(&bytes).unwrap();
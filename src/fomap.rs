@@ -0,0 +1,318 @@
+//! FOnline `.fomap` text maps: `[Header]`, `[Tiles]` and `[Objects]` sections.
+//!
+//! Header and objects are `Key Value` lines, objects are started by `MapObjType`.
+//! Tiles are `tile|roof[_o][_l][_ol] HexX HexY [OffsX OffsY] [Layer] Name`.
+//! Keys without a typed field are kept as is, so nothing is lost.
+
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum Error {
+    #[error("line {0}: invalid number in {1:?}")]
+    InvalidNumber(usize, String),
+    #[error("line {0}: invalid tile")]
+    InvalidTile(usize),
+    #[error("line {0}: object field before MapObjType")]
+    FieldWithoutObject(usize),
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Header<'a> {
+    pub version: Option<u32>,
+    pub max_hex_x: Option<u16>,
+    pub max_hex_y: Option<u16>,
+    pub work_hex_x: Option<u16>,
+    pub work_hex_y: Option<u16>,
+    /// Other `Key Value` pairs in the file order.
+    pub extra: Vec<(&'a str, &'a str)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TileKind {
+    Tile,
+    Roof,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tile<'a> {
+    pub kind: TileKind,
+    pub hex_x: u16,
+    pub hex_y: u16,
+    pub offset: (i16, i16),
+    pub layer: Option<u8>,
+    /// Path relative to data root, as written in the map.
+    pub name: &'a str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ObjectKind {
+    Critter,
+    Item,
+    Scenery,
+    Unknown(i32),
+}
+
+impl From<i32> for ObjectKind {
+    fn from(value: i32) -> Self {
+        match value {
+            0 => ObjectKind::Critter,
+            1 => ObjectKind::Item,
+            2 => ObjectKind::Scenery,
+            other => ObjectKind::Unknown(other),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapObject<'a> {
+    pub kind: ObjectKind,
+    pub proto_id: Option<u16>,
+    pub proto_name: Option<&'a str>,
+    pub hex_x: Option<u16>,
+    pub hex_y: Option<u16>,
+    /// Other `Key Value` pairs in the file order.
+    pub extra: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> MapObject<'a> {
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.extra
+            .iter()
+            .find(|(extra_key, _)| *extra_key == key)
+            .map(|(_, value)| *value)
+    }
+}
+
+/// Section that is not parsed, with its lines.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section<'a> {
+    pub name: &'a str,
+    pub lines: Vec<&'a str>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Fomap<'a> {
+    pub header: Header<'a>,
+    pub tiles: Vec<Tile<'a>>,
+    pub objects: Vec<MapObject<'a>>,
+    pub other_sections: Vec<Section<'a>>,
+}
+
+fn key_value(line: &str) -> (&str, &str) {
+    match line.find(char::is_whitespace) {
+        Some(pos) => (&line[..pos], line[pos..].trim()),
+        None => (line, ""),
+    }
+}
+
+fn number<T: std::str::FromStr>(line_number: usize, key: &str, value: &str) -> Result<T, Error> {
+    value
+        .parse()
+        .map_err(|_| Error::InvalidNumber(line_number, key.to_owned()))
+}
+
+fn parse_tile(line_number: usize, line: &str) -> Result<Tile<'_>, Error> {
+    let invalid = || Error::InvalidTile(line_number);
+    let mut tokens = line.split_whitespace();
+    let kind_token = tokens.next().ok_or_else(invalid)?;
+    let (kind, suffix) = if let Some(suffix) = kind_token.strip_prefix("tile") {
+        (TileKind::Tile, suffix)
+    } else if let Some(suffix) = kind_token.strip_prefix("roof") {
+        (TileKind::Roof, suffix)
+    } else {
+        return Err(invalid());
+    };
+    let (has_offset, has_layer) = match suffix {
+        "" => (false, false),
+        "_o" => (true, false),
+        "_l" => (false, true),
+        "_ol" => (true, true),
+        _ => return Err(invalid()),
+    };
+    let mut next = || tokens.next().ok_or_else(invalid);
+    let hex_x = number(line_number, "HexX", next()?)?;
+    let hex_y = number(line_number, "HexY", next()?)?;
+    let offset = if has_offset {
+        let offset_x = number(line_number, "OffsX", next()?)?;
+        (offset_x, number(line_number, "OffsY", next()?)?)
+    } else {
+        (0, 0)
+    };
+    let layer = if has_layer {
+        Some(number(line_number, "Layer", next()?)?)
+    } else {
+        None
+    };
+    let name = next()?;
+    if tokens.next().is_some() {
+        return Err(invalid());
+    }
+    Ok(Tile {
+        kind,
+        hex_x,
+        hex_y,
+        offset,
+        layer,
+        name,
+    })
+}
+
+pub fn parse_fomap(text: &str) -> Result<Fomap<'_>, Error> {
+    let mut fomap = Fomap::default();
+    let mut section = "";
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            section = name;
+            if !matches!(section, "Header" | "Tiles" | "Objects") {
+                fomap.other_sections.push(Section {
+                    name,
+                    lines: Vec::new(),
+                });
+            }
+            continue;
+        }
+        match section {
+            "Header" => {
+                let header = &mut fomap.header;
+                let (key, value) = key_value(line);
+                match key {
+                    "Version" => header.version = Some(number(line_number, key, value)?),
+                    "MaxHexX" => header.max_hex_x = Some(number(line_number, key, value)?),
+                    "MaxHexY" => header.max_hex_y = Some(number(line_number, key, value)?),
+                    "WorkHexX" => header.work_hex_x = Some(number(line_number, key, value)?),
+                    "WorkHexY" => header.work_hex_y = Some(number(line_number, key, value)?),
+                    _ => header.extra.push((key, value)),
+                }
+            }
+            "Tiles" => fomap.tiles.push(parse_tile(line_number, line)?),
+            "Objects" => {
+                let (key, value) = key_value(line);
+                if key == "MapObjType" {
+                    let kind = number::<i32>(line_number, key, value)?.into();
+                    fomap.objects.push(MapObject {
+                        kind,
+                        proto_id: None,
+                        proto_name: None,
+                        hex_x: None,
+                        hex_y: None,
+                        extra: Vec::new(),
+                    });
+                    continue;
+                }
+                let object = fomap
+                    .objects
+                    .last_mut()
+                    .ok_or(Error::FieldWithoutObject(line_number))?;
+                match key {
+                    "ProtoId" => object.proto_id = Some(number(line_number, key, value)?),
+                    "ProtoName" => object.proto_name = Some(value),
+                    "MapX" => object.hex_x = Some(number(line_number, key, value)?),
+                    "MapY" => object.hex_y = Some(number(line_number, key, value)?),
+                    _ => object.extra.push((key, value)),
+                }
+            }
+            _ => {
+                if let Some(other) = fomap.other_sections.last_mut() {
+                    other.lines.push(line);
+                }
+            }
+        }
+    }
+    Ok(fomap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP: &str = "[Header]
+Version              4
+MaxHexX              200
+MaxHexY              100
+ScriptModule         -
+
+[Tiles]
+tile       10   20     art\\tiles\\EDG5000.FRM
+roof_ol    12   14  5  -3  1  art/tiles/roof.frm
+
+[Objects]
+MapObjType           0
+ProtoId              12
+MapX                 50
+MapY                 60
+Critter_Cond         1
+
+MapObjType           2
+ProtoId              2000
+MapX                 51
+MapY                 61
+PicMapName           art/scenery/Tree.frm
+
+[Custom]
+some line
+";
+
+    #[test]
+    fn parse_sections() {
+        let fomap = parse_fomap(MAP).unwrap();
+        assert_eq!(fomap.header.version, Some(4));
+        assert_eq!(fomap.header.max_hex_y, Some(100));
+        assert_eq!(fomap.header.extra, [("ScriptModule", "-")]);
+
+        assert_eq!(fomap.tiles.len(), 2);
+        assert_eq!(
+            fomap.tiles[1],
+            Tile {
+                kind: TileKind::Roof,
+                hex_x: 12,
+                hex_y: 14,
+                offset: (5, -3),
+                layer: Some(1),
+                name: "art/tiles/roof.frm",
+            }
+        );
+
+        assert_eq!(fomap.objects.len(), 2);
+        assert_eq!(fomap.objects[0].kind, ObjectKind::Critter);
+        assert_eq!(fomap.objects[0].get("Critter_Cond"), Some("1"));
+        assert_eq!(fomap.objects[1].kind, ObjectKind::Scenery);
+        assert_eq!(fomap.objects[1].hex_x, Some(51));
+
+        assert_eq!(fomap.other_sections[0].name, "Custom");
+        assert_eq!(fomap.other_sections[0].lines, ["some line"]);
+
+        use crate::references::ReferencesArt;
+        assert_eq!(
+            fomap.art_references("maps/test.fomap"),
+            [
+                "art/scenery/tree.frm",
+                "art/tiles/edg5000.frm",
+                "art/tiles/roof.frm"
+            ]
+        );
+    }
+
+    #[test]
+    fn report_errors() {
+        assert_eq!(
+            parse_fomap("[Tiles]\ntile 1 x a.frm"),
+            Err(Error::InvalidNumber(2, "HexY".into()))
+        );
+        assert_eq!(
+            parse_fomap("[Tiles]\nwall 1 2 a.frm"),
+            Err(Error::InvalidTile(2))
+        );
+        assert_eq!(
+            parse_fomap("[Objects]\nProtoId 1"),
+            Err(Error::FieldWithoutObject(2))
+        );
+    }
+}
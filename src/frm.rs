@@ -32,8 +32,7 @@ pub fn frm<'a>(buf: &'a [u8]) -> Result<Frm<'a>, FrmParseError> {
 }
 
 fn parse_frm<'a, Error: ParseError<&'a [u8]>>(i: &'a [u8]) -> IResult<&'a [u8], Frm<'a>, Error> {
-    let (i, version) = be_u32(i)?;
-    assert_eq!(version, 4);
+    let (i, version) = context("version", verify(be_u32, |version| *version == 4))(i)?;
     let (i, fps) = be_u16(i)?;
     let (i, action_frame) = be_u16(i)?;
     let (i, number_of_frames_per_direction) = be_u16(i)?;
@@ -76,6 +75,183 @@ fn parse_frm<'a, Error: ParseError<&'a [u8]>>(i: &'a [u8]) -> IResult<&'a [u8],
     ))
 }
 
+/// Everything of an FRM except of pixel data, see [`parse_header`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrmHeader {
+    pub version: u32,
+    pub fps: u16,
+    pub action_frame: u16,
+    pub directions: Arr6<DirectionHeader>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirectionHeader {
+    pub shift_x: i16,
+    pub shift_y: i16,
+    pub frames: Vec<FrameHeader>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub width: u16,
+    pub height: u16,
+    pub offset_x: i16,
+    pub offset_y: i16,
+}
+
+/// Reads counts, fps, sizes and offsets, pixel data of frames is skipped over
+/// without being read.
+pub fn parse_header(buf: &[u8]) -> Result<FrmHeader, FrmParseError> {
+    err_to_kind(parse_frm_header(buf))
+}
+
+fn parse_frm_header<'a, Error: ParseError<&'a [u8]>>(
+    i: &'a [u8],
+) -> IResult<&'a [u8], FrmHeader, Error> {
+    let (i, version) = context("version", verify(be_u32, |version| *version == 4))(i)?;
+    let (i, fps) = be_u16(i)?;
+    let (i, action_frame) = be_u16(i)?;
+    let (i, number_of_frames_per_direction) = be_u16(i)?;
+    let (i, x_shifts_per_direction): (_, Arr6<_>) = context("x_shifts", count_array(be_i16))(i)?;
+    let (i, y_shifts_per_direction): (_, Arr6<_>) = context("y_shifts", count_array(be_i16))(i)?;
+    let (i, _memory_offsets_of_first_frame_per_direction) = count(be_u32, 6)(i)?;
+    let (i, _size_of_frame_area) = be_u32(i)?;
+    let (i, directions_frames): (_, Arr6<_>) = context(
+        "directions_frames",
+        verify(
+            many_array(
+                1,
+                count_cap(
+                    context("parse_frame_header", parse_frame_header),
+                    number_of_frames_per_direction as usize,
+                ),
+            ),
+            |directions: &Arr6<_>| !directions.is_empty(),
+        ),
+    )(i)?;
+
+    let directions = itertools::multizip((
+        x_shifts_per_direction.iter(),
+        y_shifts_per_direction.iter(),
+        directions_frames.into_iter(),
+    ))
+    .map(|(&shift_x, &shift_y, frames)| DirectionHeader {
+        shift_x,
+        shift_y,
+        frames,
+    })
+    .collect();
+
+    Ok((
+        i,
+        FrmHeader {
+            version,
+            fps,
+            action_frame,
+            directions,
+        },
+    ))
+}
+
+/// FRM with pixel data of frames located on demand, see [`LazyFrm::get_frame`].
+#[derive(Debug, Clone)]
+pub struct LazyFrm {
+    data: bytes::Bytes,
+    header: FrmHeader,
+    /// Start of pixel data of every frame of every direction.
+    frame_data: Vec<Vec<usize>>,
+}
+
+impl LazyFrm {
+    const HEADER_SIZE: usize = 0x3e;
+    const FRAME_HEADER_SIZE: usize = 12;
+
+    /// Reads only headers, same as [`parse_header`], `data` is kept without copying.
+    pub fn parse(data: impl Into<bytes::Bytes>) -> Result<Self, FrmParseError> {
+        let data = data.into();
+        let header = parse_header(&data)?;
+        let mut position = Self::HEADER_SIZE;
+        let frame_data = header
+            .directions
+            .iter()
+            .map(|direction| {
+                direction
+                    .frames
+                    .iter()
+                    .map(|frame| {
+                        let start = position + Self::FRAME_HEADER_SIZE;
+                        position = start + frame.width as usize * frame.height as usize;
+                        start
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(Self {
+            data,
+            header,
+            frame_data,
+        })
+    }
+
+    pub fn header(&self) -> &FrmHeader {
+        &self.header
+    }
+
+    /// Frame with its pixel data, `None` if there is no such direction or frame.
+    pub fn get_frame(&self, direction: usize, frame: usize) -> Option<Frame<'_>> {
+        let header = self.header.directions.get(direction)?.frames.get(frame)?;
+        let start = *self.frame_data.get(direction)?.get(frame)?;
+        let len = header.width as usize * header.height as usize;
+        Some(Frame {
+            width: header.width,
+            height: header.height,
+            offset_x: header.offset_x,
+            offset_y: header.offset_y,
+            data: self.data.get(start..start + len)?,
+        })
+    }
+}
+
+/// Writes shifts of directions and offsets of frames of `header` into the FRM in place,
+/// everything else of `header` is ignored. Fails if directions or frames of `header`
+/// don't match the ones of `buf`.
+pub fn patch_offsets(buf: &mut [u8], header: &FrmHeader) -> Result<(), FrmParseError> {
+    const X_SHIFTS: usize = 10;
+    const Y_SHIFTS: usize = 22;
+
+    let parsed = parse_header(buf)?;
+    let same_frames = parsed.directions.len() == header.directions.len()
+        && parsed
+            .directions
+            .iter()
+            .zip(&header.directions)
+            .all(|(parsed, patch)| parsed.frames.len() == patch.frames.len());
+    if !same_frames {
+        return Err(ErrorKind::Verify);
+    }
+    let mut write = |position: usize, value: i16| {
+        buf[position..position + 2].copy_from_slice(&value.to_be_bytes());
+    };
+    let mut position = LazyFrm::HEADER_SIZE;
+    for (index, (parsed, patch)) in parsed.directions.iter().zip(&header.directions).enumerate() {
+        write(X_SHIFTS + index * 2, patch.shift_x);
+        write(Y_SHIFTS + index * 2, patch.shift_y);
+        for (parsed, patch) in parsed.frames.iter().zip(&patch.frames) {
+            write(position + 8, patch.offset_x);
+            write(position + 10, patch.offset_y);
+            position += LazyFrm::FRAME_HEADER_SIZE + parsed.width as usize * parsed.height as usize;
+        }
+    }
+    Ok(())
+}
+
+fn parse_frame_header<'a, Error: ParseError<&'a [u8]>>(
+    i: &'a [u8],
+) -> IResult<&'a [u8], FrameHeader, Error> {
+    let (i, frame) = parse_frame(i)?;
+    Ok((i, frame.header()))
+}
+
 #[derive(Default, Debug)]
 pub struct Direction<'a> {
     pub shift_x: i16,
@@ -104,6 +280,17 @@ pub struct Frame<'a> {
     pub data: &'a [u8],
 }
 
+impl<'a> Frame<'a> {
+    pub fn header(&self) -> FrameHeader {
+        FrameHeader {
+            width: self.width,
+            height: self.height,
+            offset_x: self.offset_x,
+            offset_y: self.offset_y,
+        }
+    }
+}
+
 impl<'a> std::fmt::Debug for Frame<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         impl_debug_for_struct!(Frame, f, self,
@@ -140,6 +327,99 @@ pub fn parse_frame<'a, Error: ParseError<&'a [u8]>>(
 mod tests {
     use super::*;
 
+    #[test]
+    fn header_matches_full_parse() {
+        let frames = [
+            FrameHeader {
+                width: 3,
+                height: 2,
+                offset_x: 0,
+                offset_y: 0,
+            },
+            FrameHeader {
+                width: 1,
+                height: 4,
+                offset_x: 2,
+                offset_y: -1,
+            },
+        ];
+        let data = crate::testing::frm_fixture(12, (5, -7), &frames, 1);
+        let header = parse_header(&data).unwrap();
+        let full = frm(&data).unwrap();
+        assert_eq!(header.fps, 12);
+        assert_eq!(header.directions.len(), full.directions.len());
+        assert_eq!((header.directions[0].shift_x, header.directions[0].shift_y), (5, -7));
+        assert_eq!(header.directions[0].frames, frames);
+
+        let lazy = LazyFrm::parse(data.clone()).unwrap();
+        for (number, frame) in full.directions[0].frames.iter().enumerate() {
+            let lazy_frame = lazy.get_frame(0, number).unwrap();
+            assert_eq!(lazy_frame.header(), frame.header());
+            assert_eq!(lazy_frame.data, frame.data);
+        }
+        assert!(lazy.get_frame(0, 2).is_none());
+        assert!(lazy.get_frame(1, 0).is_none());
+
+        let mut old = data.clone();
+        old[3] = 3;
+        assert!(parse_header(&old).is_err());
+        assert!(parse_header(&data[..data.len() - 1]).is_err());
+
+        let mut patched = data.clone();
+        let mut patch = header.clone();
+        patch.directions[0].shift_y = 3;
+        patch.directions[0].frames[1].offset_x = -9;
+        patch_offsets(&mut patched, &patch).unwrap();
+        assert_eq!(parse_header(&patched).unwrap(), patch);
+        patch.directions[0].frames.pop();
+        assert!(patch_offsets(&mut patched, &patch).is_err());
+    }
+
+    #[test]
+    fn malformed_input_is_error() {
+        let frame = |width, height| FrameHeader {
+            width,
+            height,
+            offset_x: 0,
+            offset_y: 0,
+        };
+        let data = crate::testing::FrmFixture::new(10)
+            .direction((0, 0))
+            .frame(frame(3, 2), 1)
+            .frame(frame(0, 0), 1)
+            .direction((1, 1))
+            .frame(frame(1, 4), 2)
+            .frame(frame(2, 2), 3)
+            .build();
+        for end in 0..data.len() {
+            let truncated = &data[..end];
+            let _ = frm(truncated);
+            let _ = LazyFrm::parse(truncated.to_vec());
+            assert!(end > LazyFrm::HEADER_SIZE || parse_header(truncated).is_err());
+        }
+        let mut corrupted = data.clone();
+        let mut state = 0x2545_f491_u32;
+        for _ in 0..2000 {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            let position = state as usize % corrupted.len();
+            corrupted[position] = (state >> 24) as u8;
+            let _ = frm(&corrupted);
+            if let Ok(lazy) = LazyFrm::parse(corrupted.clone()) {
+                for dir in 0..7 {
+                    let _ = lazy.get_frame(dir, 0);
+                    let _ = lazy.get_frame(dir, 1);
+                }
+            }
+        }
+
+        let mut version_3 = data;
+        version_3[3] = 3;
+        assert!(frm(&version_3).is_err());
+        assert!(parse_header(&version_3).is_err());
+    }
+
     #[test]
     fn parse_edg1001() {
         let file = std::fs::read("../../../test_assets/EDG1001.FRM").unwrap();
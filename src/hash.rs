@@ -0,0 +1,96 @@
+//! Name hashes the engine uses to address resources instead of paths.
+
+use serde::{Deserialize, Serialize};
+
+/// Hash of a resource name as the engine computes it: MurmurHash2 with zero seed
+/// of the lowercase path with forward slashes.
+pub fn name_hash(path: &str) -> u32 {
+    HashFunction::Murmur2.name_hash(path)
+}
+
+/// Name hash of the target engine, forks don't always use the upstream one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HashFunction {
+    /// MurmurHash2 with zero seed, upstream FOnline.
+    #[default]
+    Murmur2,
+    /// CRC-32 (IEEE).
+    Crc32,
+}
+
+impl HashFunction {
+    /// Hash of an already conventional path.
+    pub fn hash(&self, conventional_path: &str) -> u32 {
+        let data = conventional_path.as_bytes();
+        match self {
+            HashFunction::Murmur2 => murmur_hash2(data, 0),
+            HashFunction::Crc32 => crc32fast::hash(data),
+        }
+    }
+
+    /// Hash of a path made conventional with default rules.
+    pub fn name_hash(&self, path: &str) -> u32 {
+        self.hash(&crate::paths::make_path_conventional(path))
+    }
+}
+
+fn murmur_hash2(data: &[u8], seed: u32) -> u32 {
+    const M: u32 = 0x5bd1_e995;
+    const R: u32 = 24;
+
+    let mut hash = seed ^ data.len() as u32;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        hash = hash.wrapping_mul(M) ^ k;
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, &byte) in tail.iter().enumerate() {
+            hash ^= (byte as u32) << (8 * i);
+        }
+        hash = hash.wrapping_mul(M);
+    }
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(M);
+    hash ^= hash >> 15;
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_of_conventional_path() {
+        assert_eq!(murmur_hash2(b"", 0), 0);
+        assert_eq!(
+            name_hash("ART\\Critters\\HMJMPSAA.FRM"),
+            name_hash("art/critters/hmjmpsaa.frm")
+        );
+        assert_ne!(name_hash("art/a.frm"), name_hash("art/b.frm"));
+        assert_eq!(HashFunction::Crc32.name_hash("A"), 0xe8b7_be43);
+        assert_eq!(HashFunction::Crc32.hash("a"), HashFunction::Crc32.name_hash("A"));
+    }
+
+    #[test]
+    fn tails_and_empty_paths() {
+        let hashes: Vec<_> = ["a", "ab", "abc", "abcd", "abcde"]
+            .iter()
+            .map(|data| murmur_hash2(data.as_bytes(), 0))
+            .collect();
+        assert_eq!(hashes, [0x9268_5f5e, 0x1aa1_4063, 0x1357_7c9b, 0x2687_3021, 0x5f09_a8de]);
+        assert_ne!(murmur_hash2(b"", 1), murmur_hash2(b"", 0));
+
+        for function in [HashFunction::Murmur2, HashFunction::Crc32] {
+            assert_eq!(function.name_hash(""), 0);
+            // only ascii letters are folded, as the engine does
+            let hash = function.name_hash("Арт\\Ёж.FRM");
+            assert_eq!(hash, function.name_hash("Арт/Ёж.frm"));
+            assert_ne!(hash, function.name_hash("арт/ёж.frm"));
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! Art references of interface layout configs (`default.ini` and alike).
+//!
+//! Layout files are ini-like: `[section]` headers, `key = value` lines and
+//! `#` or `;` comments. Any value token with an art extension is a reference
+//! to a picture relative to `art/intrface`.
+
+pub const INTRFACE_DIR: &str = "art/intrface";
+
+const ART_EXTENSIONS: &[&str] = &["frm", "fofrm", "png", "gif", "bmp", "tga", "jpg"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IniReference<'a> {
+    pub section: Option<&'a str>,
+    pub key: &'a str,
+    pub name: &'a str,
+    /// 1-based line number.
+    pub line: usize,
+}
+
+impl<'a> IniReference<'a> {
+    /// Conventional path of the referenced picture.
+    pub fn path(&self) -> String {
+        nom_prelude::make_path_conventional(&format!("{}/{}", INTRFACE_DIR, self.name))
+    }
+}
+
+pub(crate) fn is_art_name(token: &str) -> bool {
+    match token.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => ART_EXTENSIONS
+            .iter()
+            .any(|art_ext| ext.eq_ignore_ascii_case(art_ext)),
+        _ => false,
+    }
+}
+
+pub fn parse_references(text: &str) -> Vec<IniReference<'_>> {
+    let mut section = None;
+    let mut references = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+        {
+            section = Some(name.trim());
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some(pair) => pair,
+            None => continue,
+        };
+        let value = value
+            .split(['#', ';'])
+            .next()
+            .unwrap_or_default();
+        references.extend(
+            value
+                .split_whitespace()
+                .filter(|token| is_art_name(token))
+                .map(|name| IniReference {
+                    section,
+                    key: key.trim(),
+                    name,
+                    line: index + 1,
+                }),
+        );
+    }
+    references
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_pictures() {
+        let text = "\
+            # main panel\n\
+            [Main]\n\
+            IntMainPic = iface.png\n\
+            IntAimPic=aim.frm ; comment.frm\n\
+            IntX = 10\n\
+            [Inventory]\n\
+            InvScrUpPic = invup.png invdn.PNG 1 2\n\
+        ";
+        let references = parse_references(text);
+        let names: Vec<_> = references.iter().map(|reference| reference.name).collect();
+        assert_eq!(names, ["iface.png", "aim.frm", "invup.png", "invdn.PNG"]);
+        assert_eq!(references[0].section, Some("Main"));
+        assert_eq!(references[0].key, "IntMainPic");
+        assert_eq!(references[0].line, 3);
+        assert_eq!(references[3].section, Some("Inventory"));
+        assert_eq!(references[3].path(), "art/intrface/invdn.png");
+    }
+}
@@ -0,0 +1,322 @@
+//! Refreshing the registry from disk and the journal of what changed, so
+//! hot-reload consumers and caches of converted assets can invalidate precisely.
+
+use std::{path::PathBuf, sync::Arc};
+
+use crate::{
+    crawler, datafiles, ArchiveKind, ChangeTime, DataInitError, Dirs, FileInfo, FileLocation,
+    FoMetadata, FoRegistry, PathMap,
+};
+
+/// Conventional paths changed by the last refresh, each list is sorted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Changes {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<String>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+
+    /// All paths whose cached data should be dropped.
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.added
+            .iter()
+            .chain(&self.removed)
+            .chain(&self.modified)
+            .map(String::as_str)
+    }
+
+    fn diff(
+        old: &PathMap<String, FileInfo>,
+        new: &PathMap<String, FileInfo>,
+        is_modified: impl Fn(&FileInfo) -> bool,
+    ) -> Self {
+        let mut changes = Changes::default();
+        for (path, info) in new {
+            match old.get(path) {
+                None => changes.added.push(path.clone()),
+                Some(old_info) if old_info != info || is_modified(info) => {
+                    changes.modified.push(path.clone())
+                }
+                Some(_) => {}
+            }
+        }
+        changes.removed = old
+            .keys()
+            .filter(|path| !new.contains_key(*path))
+            .cloned()
+            .collect();
+        changes
+    }
+}
+
+impl FoRegistry {
+    /// Changes made by the last [`FoRegistry::refresh`], empty before the first one.
+    pub fn last_changes(&self) -> &Changes {
+        &self.last_changes
+    }
+
+    /// Crawls archives again if any of them changed since the last refresh.
+    ///
+    /// Data folders are always crawled again, walking them costs about as much as
+    /// checking change times of their entries.
+    pub fn refresh(&mut self) -> Result<&Changes, DataInitError> {
+        let refreshed = ChangeTime::now();
+        let mut archives = self.archives.clone();
+        let mut changed_archives = Vec::with_capacity(archives.len());
+        for archive in &mut archives {
+            let changed = datafiles::changetime(&archive.path).map_err(DataInitError::Datafiles)?;
+            changed_archives.push(changed > archive.changed);
+            if changed > archive.changed {
+                archive.kind = Default::default();
+            }
+            archive.changed = changed;
+        }
+        let has_folders = archives
+            .iter()
+            .any(|archive| archive.kind() == ArchiveKind::Folder);
+        if !has_folders && !changed_archives.contains(&true) {
+            self.last_changes = Changes::default();
+            return Ok(&self.last_changes);
+        }
+
+        let (files, report) = crawler::gather_paths_filtered(
+            &archives,
+            &self.limits,
+            self.path_rules(),
+            self.precedence(),
+            &self.entry_filter,
+        )
+        .map_err(DataInitError::GatherPaths)?;
+        let last_refresh = self.changed;
+        let changes = Changes::diff(&self.files, &files, |info| match info.location {
+            FileLocation::Local(index) => archives[index as usize]
+                .path
+                .join(&info.original_path)
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .map_or(true, |changed| changed > last_refresh),
+            FileLocation::Archive { archive: index, .. }
+            | FileLocation::Tar { archive: index, .. }
+            | FileLocation::Dat { archive: index, .. } => {
+                changed_archives[index as usize]
+            }
+        });
+
+        if !changes.is_empty() {
+            let mut dirs = Dirs::default();
+            for path in files.keys() {
+                dirs.register(path, FoMetadata::File);
+            }
+            self.files = Arc::new(files);
+            self.dirs = Arc::new(dirs);
+            self.shadowed = Arc::new(report.shadowed);
+            self.by_hash = Default::default();
+        }
+        self.archives = archives;
+        self.changed = refreshed;
+        self.last_changes = changes;
+        Ok(&self.last_changes)
+    }
+
+    /// Crawls only archives at `indices` again and splices their files into the registry,
+    /// for watchers that know which archives changed. Missing archives count as empty.
+    ///
+    /// Local files at `touched` paths, joined to archive paths as the registry has them,
+    /// are reported as modified even if their size is the same. Entries of refreshed
+    /// packed archives always are. Fails with [`DataInitError::NoArchive`] before changing
+    /// anything if an index is out of range.
+    pub fn refresh_archives(
+        &mut self,
+        indices: &[usize],
+        touched: &[PathBuf],
+    ) -> Result<&Changes, DataInitError> {
+        if let Some(&index) = indices.iter().find(|&&index| index >= self.archives.len()) {
+            return Err(DataInitError::NoArchive(index));
+        }
+        let mut crawled: PathMap<String, Vec<FileInfo>> = PathMap::new();
+        for &index in indices {
+            let archive = &mut self.archives[index];
+            if !archive.path.exists() {
+                continue;
+            }
+            archive.changed =
+                datafiles::changetime(&archive.path).map_err(DataInitError::Datafiles)?;
+            archive.kind = Default::default();
+            let files = crawler::gather_archive(
+                index as u32,
+                archive,
+                &self.limits,
+                &self.settings.path_rules,
+                &self.entry_filter,
+            )
+            .map_err(DataInitError::GatherPaths)?;
+            for (path, file_info) in files {
+                crawled.entry(path).or_default().push(file_info);
+            }
+        }
+
+        let refreshed: Vec<u32> = indices.iter().map(|&index| index as u32).collect();
+        let is_refreshed = |info: &FileInfo| refreshed.contains(&info.location.archive_index());
+        let mut affected: Vec<String> = crawled.keys().cloned().collect();
+        let replaced = self.files.iter().filter(|(_, info)| is_refreshed(info));
+        affected.extend(replaced.map(|(path, _)| path.clone()));
+        let hidden = self.shadowed.iter();
+        let hidden = hidden.filter(|(_, infos)| infos.iter().any(is_refreshed));
+        affected.extend(hidden.map(|(path, _)| path.clone()));
+        affected.sort_unstable();
+        affected.dedup();
+
+        // same order as crawling all archives gives, the winner last
+        let precedence = self.settings.precedence;
+        let files = Arc::make_mut(&mut self.files);
+        let shadowed = Arc::make_mut(&mut self.shadowed);
+        let mut old = PathMap::new();
+        let mut new = PathMap::new();
+        for path in affected {
+            let mut candidates = shadowed.remove(&path).unwrap_or_default();
+            if let Some(winner) = files.remove(&path) {
+                old.insert(path.clone(), winner.clone());
+                candidates.push(winner);
+            }
+            candidates.retain(|info| !is_refreshed(info));
+            candidates.extend(crawled.remove(&path).unwrap_or_default());
+            candidates.sort_by_key(|info| info.location.archive_index());
+            if precedence == crawler::Precedence::FirstWins {
+                candidates.reverse();
+            }
+            if let Some(winner) = candidates.pop() {
+                new.insert(path.clone(), winner.clone());
+                files.insert(path.clone(), winner);
+            }
+            if !candidates.is_empty() {
+                shadowed.insert(path, candidates);
+            }
+        }
+
+        let archives = &self.archives;
+        let changes = Changes::diff(&old, &new, |info| match info.location {
+            FileLocation::Local(index) => {
+                let path = archives[index as usize].path.join(&info.original_path);
+                touched.contains(&path)
+            }
+            FileLocation::Archive { archive: index, .. }
+            | FileLocation::Tar { archive: index, .. }
+            | FileLocation::Dat { archive: index, .. } => refreshed.contains(&index),
+        });
+        if !changes.is_empty() {
+            let mut dirs = Dirs::default();
+            for path in self.files.keys() {
+                dirs.register(path, FoMetadata::File);
+            }
+            self.dirs = Arc::new(dirs);
+            self.by_hash = Default::default();
+        }
+        self.last_changes = changes;
+        Ok(&self.last_changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempData};
+
+    #[test]
+    fn refresh_journal() {
+        let data = TempData::new(&[
+            ("art/kept.frm", b"kept"),
+            ("art/removed.frm", b"removed"),
+            ("art/modified.frm", b"old"),
+        ]);
+        let mut registry = data.registry();
+        assert!(registry.last_changes().is_empty());
+
+        std::fs::remove_file(data.root().join("art/removed.frm")).unwrap();
+        data.write("art/modified.frm", b"new data");
+        data.write("art/added.frm", b"added");
+        let changes = registry.refresh().unwrap();
+        assert_eq!(changes.added, ["art/added.frm"]);
+        assert_eq!(changes.removed, ["art/removed.frm"]);
+        assert_eq!(changes.modified, ["art/modified.frm"]);
+        assert_eq!(changes.paths().count(), 3);
+        assert!(registry.file_info("art/added.frm").is_some());
+        assert!(registry.metadata("art/removed.frm").is_none());
+
+        assert!(registry.refresh().unwrap().is_empty());
+
+        // limits of the builder still apply
+        registry.limits.max_files = Some(2);
+        let err = registry.refresh().unwrap_err();
+        assert!(
+            matches!(err, DataInitError::GatherPaths(crawler::Error::TooManyFiles(2))),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn refresh_single_archive() {
+        let data = TempData::new(&[
+            ("base/art/tile.frm", b"base"),
+            ("base/art/base.frm", b"base"),
+            ("mod/art/tile.frm", b"mod"),
+        ]);
+        let root = data.root();
+        let archives: Vec<_> = ["base", "mod"]
+            .iter()
+            .map(|name| testing::archive(root.join(name), None))
+            .collect();
+        let (files, report) = crawler::gather_paths_reported(
+            &archives,
+            &Default::default(),
+            &Default::default(),
+        )
+        .unwrap();
+        let mut registry = FoRegistry {
+            archives,
+            files: Arc::new(files),
+            shadowed: Arc::new(report.shadowed),
+            ..FoRegistry::stub()
+        };
+        let archive_of = |registry: &FoRegistry, path| {
+            registry.file_info(path).map(|info| info.location.archive_index())
+        };
+        assert_eq!(archive_of(&registry, "art/tile.frm"), Some(1));
+
+        std::fs::remove_file(root.join("mod/art/tile.frm")).unwrap();
+        data.write("mod/art/new.frm", b"new");
+        // untouched archives are not crawled again
+        data.write("base/art/unseen.frm", b"unseen");
+        let changes = registry.refresh_archives(&[1], &[]).unwrap();
+        assert_eq!(changes.added, ["art/new.frm"]);
+        assert_eq!(changes.modified, ["art/tile.frm"]);
+        assert!(changes.removed.is_empty());
+        assert_eq!(archive_of(&registry, "art/tile.frm"), Some(0));
+        assert!(registry.shadowed.is_empty());
+        assert!(registry.file_info("art/unseen.frm").is_none());
+
+        data.write("mod/art/tile.frm", b"mod");
+        data.write("base/art/base.frm", b"edit");
+        let touched = [root.join("base/art/base.frm")];
+        let changes = registry.refresh_archives(&[0, 1], &touched).unwrap();
+        assert_eq!(changes.added, ["art/unseen.frm"]);
+        assert_eq!(changes.modified, ["art/base.frm", "art/tile.frm"]);
+        assert_eq!(archive_of(&registry, "art/tile.frm"), Some(1));
+        assert_eq!(registry.shadowed["art/tile.frm"].len(), 1);
+
+        registry.limits.max_path_len = Some(8);
+        let err = registry.refresh_archives(&[1], &[]).unwrap_err();
+        assert!(
+            matches!(err, DataInitError::GatherPaths(crawler::Error::PathTooLong(_, 8))),
+            "{:?}",
+            err
+        );
+        let err = registry.refresh_archives(&[0, 2], &[]).unwrap_err();
+        assert!(matches!(err, DataInitError::NoArchive(2)), "{:?}", err);
+    }
+}
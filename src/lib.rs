@@ -1,59 +1,208 @@
+mod budget;
+mod builder;
 //mod converter;
 mod converter;
+mod journal;
+mod metrics;
+mod resolve;
+mod service;
+mod snapshot;
+pub mod blend;
+pub mod compliance;
 pub mod crawler;
+pub mod critters;
+pub mod dat;
 pub mod datafiles;
+pub mod deps;
+pub mod export;
+pub mod files;
 pub mod fofrm;
+pub mod fomap;
 pub mod frm;
+pub mod hash;
+pub mod intrface;
+pub mod lint;
+pub mod localize;
+pub mod lst;
+pub mod manifest;
+pub mod mirror;
+pub mod msg;
+pub mod offsets;
 pub mod palette;
+pub mod passwords;
+pub mod paths;
+pub mod quota;
+pub mod references;
+pub mod rename;
 pub mod retriever;
+pub mod search;
+pub mod similar;
+pub mod sink;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod text;
+pub mod tiles;
+#[cfg(feature = "watch")]
+pub mod watch;
+pub mod xref;
 
 use std::{collections::BTreeMap, path::{Path, PathBuf}, sync::Arc};
 
 use serde::{Deserialize, Serialize};
 pub type PathMap<K, V> = BTreeMap<K, V>;
 pub type ChangeTime = std::time::SystemTime;
+#[cfg(feature = "cas-retriever")]
+pub use retriever::cas::CasRetriever;
+#[cfg(feature = "redb-retriever")]
+pub use retriever::redb::RedbRetriever;
 #[cfg(feature = "sled-retriever")]
-pub use retriever::sled::SledRetriever;
+pub use retriever::sled::{SledConfig, SledRetriever};
+#[cfg(feature = "watch")]
+pub use watch::{RegistryWatcher, WatchEvent};
 
 pub use crate::{
-    converter::{Converter, GetImageError, RawImage},
-    palette::Palette,
-    retriever::{fo::FoRetriever, Retriever},
+    budget::{BudgetUsage, Evict, MemoryBudget},
+    builder::{CacheMiss, CachePersistence, CacheWriteHandle, FoRegistryBuilder, InitReport},
+    converter::{
+        AlphaMask, Anchor, AnchorPolicy, Animation, AnimationDirection, AnimationLayout,
+        Background, Bounds, ConvertOptions, ConvertOptionsBuilder, ConvertScratch, Converter,
+        DirectionMap, DiskCache, Fingerprint, GetImageError, Placeholder, PreviewOptions,
+        PrimeStats, RawImage, RetrieveError,
+    },
+    journal::Changes,
+    metrics::{Metrics, Operation, OperationStats},
+    palette::{Palette, RgbaLut},
+    resolve::{Resolution, Source},
+    retriever::{
+        chain::{ChainRetriever, Shadowing},
+        fo::{FoRetriever, PrefetchMode},
+        memory::MemoryRetriever,
+        FileStream, Retriever, RetrieverExt,
+    },
+    service::{ConvertService, JobHandle, SubmitError},
+    snapshot::RegistrySnapshot,
+    text::TextEncoding,
 };
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum FileLocation {
-    Archive(u16),
-    Local,
+    /// File inside a zip, `entry` is its index in the central directory, so it's unambiguous
+    /// even if the zip has several entries with the same name.
+    Archive { archive: u32, entry: u32 },
+    /// File inside a local data folder, index points into the same list as archives.
+    Local(u32),
+    /// File inside a tarball, `offset` is a position of its data in the uncompressed tar stream.
+    Tar { archive: u32, offset: u64 },
+    /// File inside a Fallout DAT archive, `packed` data is a zlib stream in DAT2 and LZSS blocks
+    /// in DAT1.
+    Dat { archive: u32, offset: u64, packed: bool },
 }
-impl Default for FileLocation {
-    fn default() -> Self {
-        FileLocation::Local
+impl FileLocation {
+    pub fn archive_index(&self) -> u32 {
+        match *self {
+            FileLocation::Local(index) => index,
+            FileLocation::Archive { archive, .. }
+            | FileLocation::Tar { archive, .. }
+            | FileLocation::Dat { archive, .. } => archive,
+        }
     }
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FileInfo {
     location: FileLocation,
     original_path: String,
     compressed_size: u64,
+    uncompressed_size: u64,
+    /// Recognized once at crawl time, see [`FileInfo::file_type`].
+    file_type: FileType,
 }
 impl FileInfo {
     pub fn location<'a>(&self, data: &'a FoRegistry) -> Option<&'a std::path::PathBuf> {
-        match self.location {
-            FileLocation::Archive(index) => data
-                .archives
-                .get(index as usize)
-                .map(|archive| &archive.path),
-            _ => None,
-        }
+        data.archives
+            .get(self.location.archive_index() as usize)
+            .map(|archive| &archive.path)
+    }
+
+    /// Bytes the file takes in its archive, what players download.
+    pub fn compressed_size(&self) -> u64 {
+        self.compressed_size
+    }
+
+    /// Bytes of the file itself, as declared by the archive for zip entries.
+    pub fn uncompressed_size(&self) -> u64 {
+        self.uncompressed_size
+    }
+
+    /// Type of the file by its extension, same as [`retriever::recognize_type`]
+    /// of its path without redoing the string work.
+    pub fn file_type(&self) -> &FileType {
+        &self.file_type
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FoArchive {
     changed: ChangeTime,
     path: std::path::PathBuf,
+    /// Conventional folder the files of the archive are registered in, `None` for the root.
+    mount: Option<String>,
+    /// Detected on first use, telling DAT versions apart reads the archive header.
+    #[serde(skip)]
+    kind: once_cell::sync::OnceCell<ArchiveKind>,
+}
+impl FoArchive {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn mount(&self) -> Option<&str> {
+        self.mount.as_deref()
+    }
+
+    pub fn kind(&self) -> ArchiveKind {
+        *self.kind.get_or_init(|| self.detect_kind())
+    }
+
+    fn detect_kind(&self) -> ArchiveKind {
+        if self.path.is_dir() {
+            return ArchiveKind::Folder;
+        }
+        let name = self
+            .path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if name.ends_with(".tar") {
+            ArchiveKind::Tar
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            ArchiveKind::TarGz
+        } else if name.ends_with(".dat") {
+            let version = std::fs::File::open(&self.path)
+                .and_then(|mut file| dat::detect_version(&mut file));
+            match version {
+                Ok(dat::DatVersion::Dat1) => ArchiveKind::Dat1,
+                // unreadable archives fail later with a proper error
+                Ok(dat::DatVersion::Dat2) | Err(_) => ArchiveKind::Dat2,
+            }
+        } else {
+            ArchiveKind::Zip
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveKind {
+    Folder,
+    Zip,
+    Tar,
+    /// Can't be seeked, so it's unpacked into a temporary tar file on first access.
+    TarGz,
+    /// Fallout 1 `.dat`, told from DAT2 by its contents, see [`dat`].
+    Dat1,
+    /// Fallout 2 `.dat`.
+    Dat2,
 }
 
 pub struct FileData {
@@ -61,9 +210,13 @@ pub struct FileData {
     pub data: bytes::Bytes,
     pub dimensions: (u32, u32),
     pub offset: (i16, i16),
+    /// Changes only if sources or options of conversion change.
+    pub fingerprint: converter::Fingerprint,
+    /// Error of the requested image if a placeholder was returned instead.
+    pub replaced_error: Option<Box<GetImageError>>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum FileType {
     Png,
     Frm,
@@ -73,21 +226,43 @@ pub enum FileType {
     Unknown,
 }
 
-#[derive(Debug, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Hash)]
 pub enum DataType {
     Png,
     Rgba,
+    /// Lossless, usually smaller than png.
+    #[cfg(feature = "webp")]
+    WebP,
+    /// Lossy, with alpha, see [`ConvertOptionsBuilder::quality`].
+    #[cfg(feature = "avif")]
+    Avif,
 }
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum DataInitError {
+    #[error("can't load palette: {0:?}")]
     LoadPalette(palette::Error),
+    #[error("can't read client data files: {0:?}")]
     Datafiles(datafiles::Error),
+    #[error("can't crawl archives: {0}")]
     GatherPaths(crawler::Error),
+    #[error("can't write registry cache: {0}")]
     CacheSerialize(bincode::Error),
+    #[error("can't read registry cache: {0}")]
     CacheDeserialize(bincode::Error),
+    #[error("registry cache io error: {0}")]
     CacheIO(std::io::Error),
+    #[error("registry cache is stale")]
     CacheStale,
+    /// Cache was written by an incompatible version of the crate.
+    #[error("registry cache was written by version {0}")]
+    CacheVersion(u32),
+    /// Index passed to [`FoRegistry::refresh_archives`] is out of range.
+    #[error("registry has no archive #{0}")]
+    NoArchive(usize),
+    #[cfg(feature = "sled-retriever")]
+    #[error("can't open database: {0}")]
+    SledInit(retriever::sled::Error),
 }
 
 pub struct FoData<R = FoRetriever> {
@@ -100,15 +275,35 @@ impl FoData {
         palette_path: P2,
     ) -> Result<Self, DataInitError> {
         let registry = FoRegistry::init(client_root)?;
-        let retriever = registry.into_retriever();
+        Self::init_with_retriever(registry.into_retriever(), palette_path)
+    }
+}
+#[cfg(feature = "sled-retriever")]
+impl FoData<SledRetriever> {
+    pub fn init_sled<P: AsRef<Path>, P2: AsRef<Path>>(
+        db_path: P,
+        palette_path: P2,
+    ) -> Result<Self, DataInitError> {
+        let retriever = SledRetriever::init(db_path).map_err(DataInitError::SledInit)?;
+        Self::init_with_retriever(retriever, palette_path)
+    }
+}
+impl<R> FoData<R> {
+    /// `palette` is used as is, see [`FoData::init_with_retriever`] for loading game palette.
+    pub fn with_retriever(retriever: R, palette: Palette) -> Self {
+        Self { retriever, palette }
+    }
 
+    /// Loads game palette from `palette_path`, scaling its 6-bit colors to 8 bits.
+    pub fn init_with_retriever<P: AsRef<Path>>(
+        retriever: R,
+        palette_path: P,
+    ) -> Result<Self, DataInitError> {
         let palette = palette::load_palette(palette_path).map_err(DataInitError::LoadPalette)?;
         let palette = palette.colors_multiply(4);
-
-        Ok(Self { retriever, palette })
+        Ok(Self::with_retriever(retriever, palette))
     }
-}
-impl<R> FoData<R> {
+
     pub fn converter(&self) -> Converter<'_, '_, R> {
         Converter::new(&self.retriever, &self.palette)
     }
@@ -118,13 +313,45 @@ impl<R> FoData<R> {
 pub struct FoRegistry {
     changed: ChangeTime,
     archives: Vec<FoArchive>,
-    files: PathMap<String, FileInfo>,
-    dirs: Dirs,
+    /// Shared with snapshots, copied on first mutation.
+    files: Arc<PathMap<String, FileInfo>>,
+    dirs: Arc<Dirs>,
+    /// Files hidden by files with the same path in later archives, in archive order.
+    shadowed: Arc<PathMap<String, Vec<FileInfo>>>,
+    /// Stored in the cache header.
+    #[serde(skip)]
+    settings: CacheSettings,
+    #[serde(skip)]
+    passwords: passwords::Passwords,
+    /// Built on first lookup by hash.
+    #[serde(skip)]
+    by_hash: once_cell::sync::OnceCell<std::collections::HashMap<u32, String>>,
+    #[serde(skip)]
+    last_changes: Changes,
+    #[serde(skip)]
+    init_report: builder::InitReport,
+    /// Applied again on refresh.
+    #[serde(skip)]
+    entry_filter: crawler::EntryFilter,
+    /// Applied again on refresh.
+    #[serde(skip)]
+    limits: crawler::Limits,
     //cache: HashMap<(String, OutputType), FileData>,
     //palette: Palette,
 }
 
 const CACHE_PATH: &str = "fo_data.bin";
+/// Bumped on every change of the serialized registry layout.
+const CACHE_VERSION: u32 = 10;
+
+/// Registry settings that change its content, cache built with other settings is stale.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+struct CacheSettings {
+    path_rules: paths::PathRules,
+    hash_function: hash::HashFunction,
+    precedence: crawler::Precedence,
+}
+
 impl FoRegistry {
     pub fn stub() -> Self {
         FoRegistry {
@@ -132,30 +359,61 @@ impl FoRegistry {
             archives: Default::default(),
             files: Default::default(),
             dirs: Default::default(),
+            shadowed: Default::default(),
+            settings: Default::default(),
+            passwords: Default::default(),
+            by_hash: Default::default(),
+            last_changes: Default::default(),
+            init_report: Default::default(),
+            entry_filter: Default::default(),
+            limits: Default::default(),
             //palette: Default::default(),
         }
     }
 
-    fn recover_from_cache<P: AsRef<Path>>(client_root: P) -> Result<Self, DataInitError> {
+    /// Cache is stale if it was built from other `archives` or any of them changed since.
+    fn recover_from_cache(
+        archives: &[FoArchive],
+        settings: CacheSettings,
+    ) -> Result<Self, CacheMiss> {
         type Error = DataInitError;
-        let cache_file = std::fs::File::open(CACHE_PATH).map_err(Error::CacheIO)?;
+        let cache_file = match std::fs::File::open(CACHE_PATH) {
+            Ok(cache_file) => cache_file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Err(CacheMiss::Missing)
+            }
+            Err(err) => return Err(Error::CacheIO(err).into()),
+        };
         let cache_changed = cache_file
             .metadata()
             .map_err(Error::CacheIO)?
             .modified()
             .map_err(Error::CacheIO)?;
-        let reader = std::io::BufReader::new(cache_file);
-        let fo_data: FoRegistry =
+        let mut reader = std::io::BufReader::new(cache_file);
+        let version: u32 =
+            bincode::deserialize_from(&mut reader).map_err(Error::CacheDeserialize)?;
+        if version != CACHE_VERSION {
+            return Err(CacheMiss::Version(version));
+        }
+        let cache_settings: CacheSettings =
+            bincode::deserialize_from(&mut reader).map_err(Error::CacheDeserialize)?;
+        if cache_settings != settings {
+            return Err(CacheMiss::Settings);
+        }
+        let mut fo_data: FoRegistry =
             bincode::deserialize_from(reader).map_err(Error::CacheDeserialize)?;
-        let datafiles_changetime =
-            datafiles::datafiles_changetime(client_root).map_err(Error::Datafiles)?;
+        fo_data.settings = settings;
         let cache_changed = cache_changed.min(fo_data.changed);
-        if datafiles_changetime > cache_changed {
-            return Err(Error::CacheStale);
+        let same_archives = fo_data.archives.len() == archives.len()
+            && fo_data.archives.iter().zip(archives).all(|(cached, archive)| {
+                (&cached.path, &cached.mount) == (&archive.path, &archive.mount)
+            });
+        if !same_archives {
+            return Err(CacheMiss::Archives);
         }
-        for archive in &fo_data.archives {
+        for archive in archives {
             if archive.changed > cache_changed {
-                return Err(Error::CacheStale);
+                return Err(CacheMiss::Modified(archive.path.clone()));
             }
         }
         Ok(fo_data)
@@ -189,33 +447,25 @@ impl FoRegistry {
     */
 
     pub fn init(client_root: impl AsRef<Path>) -> Result<Self, DataInitError> {
-        type Error = DataInitError;
-        match Self::recover_from_cache(&client_root) {
-            Err(err) => println!("FoData recovery failed: {:?}", err),
-            ok => return ok,
-        }
+        Self::builder(client_root).build()
+    }
 
-        let archives = datafiles::parse_datafile(client_root).map_err(Error::Datafiles)?;
-        let files = crawler::gather_paths(&archives).map_err(Error::GatherPaths)?;
-        let mut dirs = Dirs::default();
-        for (path, _) in &files {
-            dirs.register(path, FoMetadata::File);
-        }
+    pub fn builder(client_root: impl AsRef<Path>) -> FoRegistryBuilder {
+        FoRegistryBuilder::new(client_root)
+    }
 
-        let changed = ChangeTime::now();
-        let fo_data = FoRegistry {
-            changed,
-            archives,
-            files,
-            dirs,
-            //palette,
-        };
-        {
-            let cache_file = std::fs::File::create(CACHE_PATH).map_err(Error::CacheIO)?;
-            let mut writer = std::io::BufWriter::new(cache_file);
-            bincode::serialize_into(&mut writer, &fo_data).map_err(Error::CacheSerialize)?;
-        }
-        Ok(fo_data)
+    /// Builder of a registry of server data only, see [`FoRegistryBuilder::server_root`].
+    pub fn server_builder(server_root: impl AsRef<Path>) -> FoRegistryBuilder {
+        FoRegistryBuilder::new(&server_root)
+            .without_client()
+            .server_root(server_root)
+    }
+
+    /// How the client at `client_root` lists its archives, see [`datafiles::detect_layout`].
+    pub fn detect_layout(
+        client_root: impl AsRef<Path>,
+    ) -> Result<datafiles::ClientLayout, DataInitError> {
+        datafiles::detect_layout(client_root).map_err(DataInitError::Datafiles)
     }
 
     pub fn count_archives(&self) -> usize {
@@ -230,14 +480,50 @@ impl FoRegistry {
         FoRetriever::new(Arc::new(self))
     }
 
+    /// Files in path order, the same on every run.
     pub fn files(&self) -> impl ExactSizeIterator<Item = (&str, &FileInfo)> {
         self.files.iter().map(|(path, info)| (path.as_str(), info))
     }
 
+    /// Files with their [`FoRegistry::hash_function`] name hashes in hash order, colliding
+    /// paths in path order. Matches the order of engine lookup tables and their dumps.
+    pub fn files_by_hash(&self) -> Vec<(u32, &str, &FileInfo)> {
+        by_hash_order(self.hash_function(), self.files())
+    }
+
+    /// Rules the registry was crawled with.
+    pub fn path_rules(&self) -> &paths::PathRules {
+        &self.settings.path_rules
+    }
+
+    /// Precedence of archives of the client layout, see [`crawler::Precedence`].
+    pub fn precedence(&self) -> crawler::Precedence {
+        self.settings.precedence
+    }
+
+    pub fn hash_function(&self) -> hash::HashFunction {
+        self.settings.hash_function
+    }
+
     pub fn file_info(&self, path: &str) -> Option<&FileInfo> {
         self.files.get(path)
     }
 
+    /// Path with the name hash of [`FoRegistry::hash_function`], on collision the first
+    /// path in order wins.
+    pub fn path_by_hash(&self, hash: u32) -> Option<&str> {
+        let by_hash = self.by_hash.get_or_init(|| {
+            let mut by_hash = std::collections::HashMap::with_capacity(self.files.len());
+            for path in self.files.keys() {
+                by_hash
+                    .entry(self.settings.hash_function.hash(path))
+                    .or_insert_with(|| path.clone());
+            }
+            by_hash
+        });
+        by_hash.get(&hash).map(String::as_str)
+    }
+
     fn is_dir(&self, path: &str) -> bool {
         path.is_empty() || self.dirs.map.get(path.trim_end_matches('/')).is_some()
     }
@@ -274,9 +560,90 @@ impl FoRegistry {
     pub fn ls_dir<'a>(&'a self, path: &'a str) -> Option<impl 'a + Iterator<Item = &'a str>> {
         Some(self.dirs.map.get(path.trim_end_matches('/'))?.iter().map(|(entry, _)| entry.as_str()))
     }
+
+    /// Entries of the directory filtered, sorted and paginated by `options`,
+    /// `None` if there's no such directory.
+    pub fn ls_dir_with<'a>(&'a self, path: &str, options: &LsOptions) -> Option<LsPage<'a>> {
+        let entries = self.dirs.map.get(path.trim_end_matches('/'))?;
+        let mut entries: Vec<LsEntry<'a>> = entries
+            .iter()
+            .filter_map(|(path, metadata)| {
+                let is_dir = matches!(metadata, FoMetadata::Dir);
+                let wanted = match options.filter {
+                    LsFilter::All => true,
+                    LsFilter::Files => !is_dir,
+                    LsFilter::Dirs => is_dir,
+                };
+                if !wanted {
+                    return None;
+                }
+                let info = if is_dir { None } else { self.files.get(path) };
+                Some(LsEntry {
+                    path,
+                    name: path.rsplit('/').next().unwrap_or(path),
+                    is_dir,
+                    size: info.map_or(0, FileInfo::uncompressed_size),
+                    file_type: info.map(FileInfo::file_type),
+                })
+            })
+            .collect();
+        let extension = |name: &'a str| name.rsplit_once('.').map_or("", |(_, ext)| ext);
+        match options.sort {
+            LsSort::Name => {}
+            LsSort::Size => entries.sort_by_key(|entry| std::cmp::Reverse(entry.size)),
+            LsSort::Type => entries.sort_by(|a, b| extension(a.name).cmp(extension(b.name))),
+        }
+        // Stable sorts keep name order for equal keys.
+        entries.sort_by_key(|entry| !entry.is_dir);
+        let total = entries.len();
+        let limit = options.limit.unwrap_or(usize::MAX);
+        let entries = entries.into_iter().skip(options.offset).take(limit).collect();
+        Some(LsPage { entries, total })
+    }
+
+    /// Entries completing a partially typed path, like shell tab-completion: the next
+    /// path component of every entry of the typed directory whose path starts with
+    /// `prefix`. Paths are conventional and sorted, directories end with `/`.
+    pub fn complete(&self, prefix: &str) -> Vec<String> {
+        let prefix = self.path_rules().normalize(prefix);
+        let prefix = prefix.trim_start_matches('/');
+        let dir = prefix.rsplit_once('/').map_or("", |(dir, _)| dir);
+        let entries = match self.dirs.map.get(dir) {
+            Some(entries) => entries,
+            None => return Vec::new(),
+        };
+        entries
+            .range::<str, _>((std::ops::Bound::Included(prefix), std::ops::Bound::Unbounded))
+            .take_while(|(path, _)| path.starts_with(prefix))
+            .map(|(path, metadata)| match metadata {
+                FoMetadata::Dir => format!("{}/", path),
+                FoMetadata::File => path.clone(),
+            })
+            .collect()
+    }
+
+    /// Adds or replaces a file, `path` must be conventional.
+    pub fn insert_file(&mut self, path: String, info: FileInfo) -> Option<FileInfo> {
+        Arc::make_mut(&mut self.dirs).register(&path, FoMetadata::File);
+        let old = Arc::make_mut(&mut self.files).insert(path, info);
+        self.mutated();
+        old
+    }
+
+    pub fn remove_file(&mut self, path: &str) -> Option<FileInfo> {
+        let old = Arc::make_mut(&mut self.files).remove(path)?;
+        Arc::make_mut(&mut self.dirs).unregister(path);
+        self.mutated();
+        Some(old)
+    }
+
+    fn mutated(&mut self) {
+        self.changed = ChangeTime::now();
+        self.by_hash = Default::default();
+    }
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 struct Dirs {
     map: PathMap<String, PathMap<String, FoMetadata>>,
 }
@@ -298,14 +665,114 @@ impl Dirs {
             self.register(parent, FoMetadata::Dir);
         }
     }
+    /// Removes the entry and parent directories left empty.
+    fn unregister(&mut self, path: &str) {
+        let parent = Self::parent(path);
+
+        let entries = match self.map.get_mut(parent) {
+            Some(entries) => entries,
+            None => return,
+        };
+        entries.remove(path);
+        if entries.is_empty() && !parent.is_empty() {
+            self.map.remove(parent);
+            self.unregister(parent);
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum FoMetadata {
     File,
     Dir,
 }
 
+/// Whether a tool that changes files does so. Both modes return the same report,
+/// a dry run lists exactly what applying would do. Tools only change files when asked to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    #[default]
+    DryRun,
+    Apply,
+}
+
+impl Mode {
+    pub fn is_dry_run(self) -> bool {
+        self == Mode::DryRun
+    }
+}
+
+/// Order of [`FoRegistry::ls_dir_with`] entries, directories always come first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LsSort {
+    Name,
+    /// Biggest files first, then by name.
+    Size,
+    /// By extension, then by name.
+    Type,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LsFilter {
+    All,
+    Files,
+    Dirs,
+}
+
+#[derive(Debug, Clone)]
+pub struct LsOptions {
+    pub sort: LsSort,
+    pub filter: LsFilter,
+    /// Entries to skip after filtering and sorting.
+    pub offset: usize,
+    /// Entries to return at most, all if `None`.
+    pub limit: Option<usize>,
+}
+
+impl Default for LsOptions {
+    fn default() -> Self {
+        Self {
+            sort: LsSort::Name,
+            filter: LsFilter::All,
+            offset: 0,
+            limit: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LsEntry<'a> {
+    /// Conventional path.
+    pub path: &'a str,
+    pub name: &'a str,
+    pub is_dir: bool,
+    /// Size of the file, 0 for directories.
+    pub size: u64,
+    /// `None` for directories.
+    pub file_type: Option<&'a FileType>,
+}
+
+/// A page of a directory listing.
+#[derive(Debug, Clone)]
+pub struct LsPage<'a> {
+    pub entries: Vec<LsEntry<'a>>,
+    /// Entries matching the filter before pagination.
+    pub total: usize,
+}
+
+/// Sorts files given in path order by name hash, the sort is stable so paths with the same
+/// hash stay in path order.
+fn by_hash_order<'a>(
+    hash_function: hash::HashFunction,
+    files: impl Iterator<Item = (&'a str, &'a FileInfo)>,
+) -> Vec<(u32, &'a str, &'a FileInfo)> {
+    let mut files: Vec<_> = files
+        .map(|(path, info)| (hash_function.hash(path), path, info))
+        .collect();
+    files.sort_by_key(|&(hash, _, _)| hash);
+    files
+}
+
 trait PathError<T, E>: Sized {
     fn path_err<E2>(self, path: &Path, fun: fn(PathBuf, E) -> E2) -> Result<T, E2>;
     fn paths_err<E2>(self, path1: &Path, path2: &Path, fun: fn(PathBuf, PathBuf, E) -> E2) -> Result<T, E2>;
@@ -357,11 +824,17 @@ mod test_stuff {
             .into_retriever()
     }
 
+    #[cfg(feature = "sled-retriever")]
+    pub fn test_data() -> crate::FoData<crate::SledRetriever> {
+        crate::FoData::init_sled(test_assets().join("db/assets"), palette_path()).unwrap()
+    }
+
     #[cfg(feature = "sled-retriever")]
     pub fn test_retriever() -> &'static crate::SledRetriever {
-        static RETRIEVER: once_cell::sync::Lazy<SledRetriever> = once_cell::sync::Lazy::new(|| {
-            crate::SledRetriever::init(test_assets().join("db/assets"), palette_path()).unwrap()
-        });
+        static RETRIEVER: once_cell::sync::Lazy<crate::SledRetriever> =
+            once_cell::sync::Lazy::new(|| {
+                crate::SledRetriever::init(test_assets().join("db/assets")).unwrap()
+            });
         &*RETRIEVER
     }
 }
@@ -446,6 +919,77 @@ mod tests {
         }
     }
 
+    #[test]
+    fn ls_dir_pages() {
+        let mut registry = FoRegistry::stub();
+        let files = [("art/b.png", 5), ("art/a.frm", 1), ("art/c.frm", 9), ("art/z/d.png", 2)];
+        for (path, size) in files {
+            let info = FileInfo {
+                location: FileLocation::Local(0),
+                original_path: path.to_owned(),
+                compressed_size: size,
+                uncompressed_size: size,
+                file_type: retriever::recognize_type(path),
+            };
+            registry.insert_file(path.to_owned(), info);
+        }
+        let names = |options: &LsOptions| -> Vec<&str> {
+            let page = registry.ls_dir_with("art/", options).unwrap();
+            page.entries.iter().map(|entry| entry.name).collect()
+        };
+        let options = LsOptions::default();
+        assert_eq!(names(&options), ["z", "a.frm", "b.png", "c.frm"]);
+        let by_size = LsOptions {
+            sort: LsSort::Size,
+            ..options.clone()
+        };
+        assert_eq!(names(&by_size), ["z", "c.frm", "b.png", "a.frm"]);
+        let by_type = LsOptions {
+            sort: LsSort::Type,
+            filter: LsFilter::Files,
+            ..options.clone()
+        };
+        assert_eq!(names(&by_type), ["a.frm", "c.frm", "b.png"]);
+        let dirs = LsOptions {
+            filter: LsFilter::Dirs,
+            ..options.clone()
+        };
+        assert_eq!(names(&dirs), ["z"]);
+
+        let paged = LsOptions {
+            offset: 1,
+            limit: Some(2),
+            ..options
+        };
+        let page = registry.ls_dir_with("art", &paged).unwrap();
+        assert_eq!(page.total, 4);
+        assert_eq!(page.entries[0].path, "art/a.frm");
+        assert_eq!(page.entries[1].file_type, Some(&FileType::Png));
+        assert_eq!(page.entries.len(), 2);
+        assert!(registry.ls_dir_with("sound", &paged).is_none());
+    }
+
+    #[test]
+    fn complete_paths() {
+        let mut registry = FoRegistry::stub();
+        for path in ["art/intrface/a.png", "art/items/b.frm", "art/inven.lst", "sound/c.acm"] {
+            let info = FileInfo {
+                location: FileLocation::Local(0),
+                original_path: path.to_owned(),
+                compressed_size: 0,
+                uncompressed_size: 0,
+                file_type: retriever::recognize_type(path),
+            };
+            registry.insert_file(path.to_owned(), info);
+        }
+        assert_eq!(registry.complete(""), ["art/", "sound/"]);
+        assert_eq!(registry.complete("Art\\I"), ["art/intrface/", "art/inven.lst", "art/items/"]);
+        assert_eq!(registry.complete("art/int"), ["art/intrface/"]);
+        assert_eq!(registry.complete("art/intrface/"), ["art/intrface/a.png"]);
+        assert!(registry.complete("art/x").is_empty());
+        assert!(registry.complete("music/").is_empty());
+    }
+
     #[test]
     fn print_frm_animation_info() {
         let retriever = test_retriever();
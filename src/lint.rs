@@ -0,0 +1,432 @@
+//! Health check of the whole registry: runs configured checks over every file
+//! and collects findings into a single report.
+//!
+//! Every check has a stable code, reports are serializable and can be filtered
+//! through a [`Baseline`] of known findings, so CI fails only on new problems.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{crawler, frm, references, FileType, FoRetriever};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+/// Serialized as its stable code, see [`Check::code`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Check {
+    /// Art referenced by fofrm, fomap, interface layouts or msg files is missing.
+    #[serde(rename = "FO001")]
+    BrokenRefs,
+    /// Files with the same content under different paths.
+    #[serde(rename = "FO002")]
+    DuplicateContent,
+    /// Files that can't be read or parsed as their format.
+    #[serde(rename = "FO003")]
+    Unparsable,
+    /// Files hidden by files with the same path in later archives.
+    #[serde(rename = "FO004")]
+    Shadowed,
+    /// Png images bigger than [`LintConfig::max_png_dimensions`].
+    #[serde(rename = "FO005")]
+    OversizedPng,
+    /// FRMs with their own `.pal` file next to them, converter always uses the main palette.
+    #[serde(rename = "FO006")]
+    WrongPalette,
+}
+
+impl Check {
+    pub const ALL: [Check; 6] = [
+        Check::BrokenRefs,
+        Check::DuplicateContent,
+        Check::Unparsable,
+        Check::Shadowed,
+        Check::OversizedPng,
+        Check::WrongPalette,
+    ];
+
+    /// Never changes for a check and is never reused by another one.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Check::BrokenRefs => "FO001",
+            Check::DuplicateContent => "FO002",
+            Check::Unparsable => "FO003",
+            Check::Shadowed => "FO004",
+            Check::OversizedPng => "FO005",
+            Check::WrongPalette => "FO006",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Check> {
+        Check::ALL
+            .iter()
+            .copied()
+            .find(|check| check.code() == code)
+    }
+
+    pub fn default_severity(&self) -> Severity {
+        match self {
+            Check::BrokenRefs | Check::Unparsable => Severity::Error,
+            Check::OversizedPng | Check::WrongPalette => Severity::Warning,
+            Check::DuplicateContent | Check::Shadowed => Severity::Info,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LintConfig {
+    pub checks: Vec<Check>,
+    pub severities: HashMap<Check, Severity>,
+    pub max_png_dimensions: (u32, u32),
+    /// Missing `.frm` is not reported if one of `.fr0`..`.fr5` exists, and vice versa.
+    pub split_frm_fallback: bool,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        Self {
+            checks: Check::ALL.to_vec(),
+            severities: HashMap::new(),
+            max_png_dimensions: (2048, 2048),
+            split_frm_fallback: true,
+        }
+    }
+}
+
+impl LintConfig {
+    fn enabled(&self, check: Check) -> bool {
+        self.checks.contains(&check)
+    }
+
+    fn severity(&self, check: Check) -> Severity {
+        self.severities
+            .get(&check)
+            .copied()
+            .unwrap_or_else(|| check.default_severity())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Finding {
+    pub check: Check,
+    pub severity: Severity,
+    /// Conventional path of the file with the problem.
+    pub path: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Report {
+    /// Sorted by path, then by check.
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    /// Findings that are not in the baseline.
+    pub fn new_findings(&self, baseline: &Baseline) -> Report {
+        Report {
+            findings: self
+                .findings
+                .iter()
+                .filter(|finding| !baseline.contains(finding))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.findings
+            .iter()
+            .filter(|finding| finding.severity == severity)
+            .count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.count(Severity::Error) > 0
+    }
+}
+
+/// Known findings that are not reported again, as a text file with `CODE path` lines.
+/// `*` in place of code or path matches anything, `#` starts a comment.
+/// Messages are not a part of the baseline, so it survives wording changes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Baseline {
+    entries: BTreeSet<(String, String)>,
+}
+
+impl Baseline {
+    pub fn from_report(report: &Report) -> Self {
+        let entries = report
+            .findings
+            .iter()
+            .map(|finding| (finding.check.code().to_owned(), finding.path.clone()))
+            .collect();
+        Self { entries }
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let entries = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default().trim())
+            .filter(|line| !line.is_empty())
+            .map(|line| match line.split_once(char::is_whitespace) {
+                Some((code, path)) => (code.to_owned(), path.trim().to_owned()),
+                None => (line.to_owned(), "*".to_owned()),
+            })
+            .collect();
+        Self { entries }
+    }
+
+    /// Suppresses all findings of `code` at `path`, both can be `*`.
+    pub fn suppress(&mut self, code: &str, path: &str) {
+        self.entries.insert((code.to_owned(), path.to_owned()));
+    }
+
+    pub fn contains(&self, finding: &Finding) -> bool {
+        let code = finding.check.code();
+        [
+            (code, finding.path.as_str()),
+            (code, "*"),
+            ("*", finding.path.as_str()),
+        ]
+        .iter()
+        .any(|&(code, path)| self.entries.contains(&(code.to_owned(), path.to_owned())))
+    }
+
+    pub fn to_text(&self) -> String {
+        self.entries
+            .iter()
+            .map(|(code, path)| format!("{} {}\n", code, path))
+            .collect()
+    }
+}
+
+struct Collector<'a> {
+    config: &'a LintConfig,
+    findings: Vec<Finding>,
+}
+
+impl<'a> Collector<'a> {
+    fn push(&mut self, check: Check, path: &str, message: String) {
+        if self.config.enabled(check) {
+            self.findings.push(Finding {
+                check,
+                severity: self.config.severity(check),
+                path: path.to_owned(),
+                message,
+            });
+        }
+    }
+}
+
+pub fn lint(retriever: &FoRetriever, config: &LintConfig) -> Report {
+    use std::hash::{Hash, Hasher};
+
+    let registry = retriever.registry();
+    let mut collector = Collector {
+        config,
+        findings: Vec::new(),
+    };
+    let mut by_content: BTreeMap<(usize, u64), Vec<&str>> = BTreeMap::new();
+
+    for (path, info) in registry.files() {
+        let data = match retriever.file_by_info(info) {
+            Ok(data) => data,
+            Err(err) => {
+                collector.push(Check::Unparsable, path, format!("can't read: {}", err));
+                continue;
+            }
+        };
+        if config.enabled(Check::DuplicateContent) {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            data.hash(&mut hasher);
+            by_content
+                .entry((data.len(), hasher.finish()))
+                .or_default()
+                .push(path);
+        }
+
+        match info.file_type() {
+            FileType::Frm => {
+                if let Err(err) = frm::frm(&data) {
+                    collector.push(Check::Unparsable, path, format!("invalid frm: {:?}", err));
+                }
+                let own_palette = path
+                    .rsplit_once('.')
+                    .map(|(stem, _)| format!("{}.pal", stem))
+                    .filter(|palette| registry.file_info(palette).is_some());
+                if let Some(palette) = own_palette {
+                    collector.push(
+                        Check::WrongPalette,
+                        path,
+                        format!("has own palette {:?}", palette),
+                    );
+                }
+            }
+            FileType::Png => {
+                let dimensions = image::io::Reader::with_format(
+                    std::io::Cursor::new(&data),
+                    image::ImageFormat::Png,
+                )
+                .into_dimensions();
+                match dimensions {
+                    Err(err) => {
+                        collector.push(Check::Unparsable, path, format!("invalid png: {}", err))
+                    }
+                    Ok((width, height)) => {
+                        let (max_width, max_height) = config.max_png_dimensions;
+                        if width > max_width || height > max_height {
+                            collector.push(
+                                Check::OversizedPng,
+                                path,
+                                format!(
+                                    "{}x{} is bigger than {}x{}",
+                                    width, height, max_width, max_height
+                                ),
+                            );
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        if references::can_reference(path) {
+            let text = crate::text::decode_text(&data, crate::TextEncoding::Auto);
+            match references::art_references_of(path, &text) {
+                Err(err) => collector.push(Check::Unparsable, path, err),
+                Ok(deps) => {
+                    let exists = |path: &str| registry.file_info(path).is_some();
+                    for dep in deps {
+                        let found = if config.split_frm_fallback {
+                            references::exists_with_counterparts(&dep, exists)
+                        } else {
+                            exists(&dep)
+                        };
+                        if found {
+                            continue;
+                        }
+                        collector.push(Check::BrokenRefs, path, format!("missing {:?}", dep));
+                    }
+                }
+            }
+        }
+    }
+
+    for paths in by_content.values().filter(|paths| paths.len() > 1) {
+        for &path in paths {
+            let others: Vec<_> = paths.iter().filter(|&&other| other != path).collect();
+            collector.push(
+                Check::DuplicateContent,
+                path,
+                format!("same content as {:?}", others),
+            );
+        }
+    }
+
+    if config.enabled(Check::Shadowed) {
+        match crawler::shadowed_files(&registry.archives) {
+            Ok(shadowed) => {
+                for (original_path, _size, old, new) in shadowed {
+                    let path = nom_prelude::make_path_conventional(&original_path);
+                    let message = format!("{:?} is shadowed by {:?}", old, new);
+                    collector.push(Check::Shadowed, &path, message);
+                }
+            }
+            Err(err) => collector.push(
+                Check::Unparsable,
+                "",
+                format!("can't crawl archives: {}", err),
+            ),
+        }
+    }
+
+    let mut findings = collector.findings;
+    findings.sort_by(|a, b| (&a.path, a.check).cmp(&(&b.path, b.check)));
+    Report { findings }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempData;
+
+    #[test]
+    fn stable_codes() {
+        for check in Check::ALL.iter() {
+            assert_eq!(Check::from_code(check.code()), Some(*check));
+            let serialized = bincode::serialize(check).unwrap();
+            assert_eq!(bincode::deserialize::<Check>(&serialized).unwrap(), *check);
+        }
+        assert_eq!(Check::BrokenRefs.code(), "FO001");
+        assert_eq!(Check::from_code("FO999"), None);
+    }
+
+    #[test]
+    fn lint_folder() {
+        let mut png = std::io::Cursor::new(Vec::new());
+        image::DynamicImage::ImageRgba8(image::RgbaImage::new(8, 2))
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+        let png = png.into_inner();
+
+        let data = TempData::new(&[
+            ("art/anim.fofrm", b"frm_0=a.png\nfrm_1=missing.png\n"),
+            ("art/a.png", &png),
+            ("art/copy.png", &png),
+            ("art/broken.frm", b"frm"),
+            ("art/broken.pal", b""),
+        ]);
+        let retriever = data.retriever();
+        let config = LintConfig {
+            max_png_dimensions: (4, 4),
+            ..LintConfig::default()
+        };
+        let report = lint(&retriever, &config);
+        let findings: Vec<_> = report
+            .findings
+            .iter()
+            .map(|finding| (finding.path.as_str(), finding.check, finding.severity))
+            .collect();
+        assert_eq!(
+            findings,
+            [
+                ("art/a.png", Check::DuplicateContent, Severity::Info),
+                ("art/a.png", Check::OversizedPng, Severity::Warning),
+                ("art/anim.fofrm", Check::BrokenRefs, Severity::Error),
+                ("art/broken.frm", Check::Unparsable, Severity::Error),
+                ("art/broken.frm", Check::WrongPalette, Severity::Warning),
+                ("art/copy.png", Check::DuplicateContent, Severity::Info),
+                ("art/copy.png", Check::OversizedPng, Severity::Warning),
+            ]
+        );
+        assert!(report.has_errors());
+        assert_eq!(report.count(Severity::Info), 2);
+
+        let config = LintConfig {
+            checks: vec![Check::BrokenRefs],
+            severities: vec![(Check::BrokenRefs, Severity::Warning)]
+                .into_iter()
+                .collect(),
+            ..LintConfig::default()
+        };
+        let report = lint(&retriever, &config);
+        assert_eq!(report.findings.len(), 1);
+        assert!(!report.has_errors());
+
+        let report = lint(&retriever, &LintConfig::default());
+        let baseline = Baseline::parse(&Baseline::from_report(&report).to_text());
+        assert!(report.new_findings(&baseline).findings.is_empty());
+        let mut baseline =
+            Baseline::parse("# known\nFO002 *\n* art/broken.frm\nFO001 art/anim.fofrm\n");
+        assert!(report.new_findings(&baseline).findings.is_empty());
+        baseline = Baseline::parse("FO001 art/other.fofrm");
+        baseline.suppress("*", "art/broken.frm");
+        assert_eq!(report.new_findings(&baseline).findings.len(), 3);
+    }
+}
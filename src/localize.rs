@@ -0,0 +1,446 @@
+//! Localizable error messages for tool frontends.
+//!
+//! Errors implementing [`Localize`] expose a stable message key and named parameters
+//! instead of a finished sentence. A [`Catalog`] maps keys to templates in the language
+//! of the UI, templates reference parameters as `{name}` and the message of the error
+//! that caused this one as `{cause}`. Keys missing from a catalog fall back to English.
+//!
+//! Catalog files have a `key = template` line per message, empty lines and lines starting
+//! with `#` are skipped. [`Catalog::english`] lists every key and is a starting point
+//! for translations.
+
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use crate::{
+    converter::GetImageError, crawler, manifest::ManifestError, retriever::fo, DataInitError,
+};
+
+/// Named parameters of a message, in the order they appear in the English template.
+pub type Params = Vec<(&'static str, String)>;
+
+pub trait Localize {
+    /// Stable key like `retriever.not_found`, never changes once released.
+    fn message_key(&self) -> &'static str;
+
+    fn message_params(&self) -> Params {
+        Vec::new()
+    }
+
+    /// Localizable error that caused this one.
+    fn cause(&self) -> Option<&dyn Localize> {
+        None
+    }
+}
+
+const ENGLISH: &[(&str, &str)] = &[
+    ("retriever.not_found", "path not found"),
+    ("retriever.invalid_archive_index", "invalid archive index"),
+    ("retriever.open_archive", "can't open archive {path}: {error}"),
+    ("retriever.zip", "zip error: {error}"),
+    ("retriever.archive_read", "archive io error: {error}"),
+    ("retriever.local_read", "can't read local file {path}: {error}"),
+    ("retriever.archive_kind_mismatch", "archive kind doesn't match file location"),
+    ("retriever.unpack_tar", "can't unpack tarball {path}: {error}"),
+    ("retriever.invalid_password", "invalid archive password"),
+    ("retriever.file_too_large", "file is too large: {size} bytes, limit is {limit} bytes"),
+    ("crawler.walk", "can't walk data folder {path}: {error}"),
+    ("crawler.tar", "can't read tar archive {path}: {error}"),
+    ("crawler.dat", "can't read dat archive {path}: {error}"),
+    ("crawler.zip", "can't read zip archive {path}: {error}"),
+    ("crawler.non_utf8_path", "path is not valid utf-8: {path}"),
+    ("crawler.too_many_files", "more than {limit} files, is data root correct?"),
+    ("crawler.too_large_total_size", "more than {limit} bytes of files, is data root correct?"),
+    ("crawler.path_too_long", "path {path} is longer than {limit} bytes"),
+    ("image.file_type", "unsupported file type {type}"),
+    ("image.utf8", "text is not valid utf-8: {error}"),
+    ("image.frm_parse", "can't parse frm: {error}"),
+    ("image.fofrm_parse", "can't parse fofrm: {error}"),
+    ("image.no_parent_folder", "image has no parent folder"),
+    ("image.invalid_relative_path", "invalid path {path} relative to {base}"),
+    ("image.no_direction", "no such direction"),
+    ("image.no_frame", "no such frame"),
+    ("image.from_raw", "can't build image from raw pixels"),
+    ("image.write", "can't encode image: {error}"),
+    ("image.png_decode", "can't decode png: {error}"),
+    ("image.gif_decode", "can't decode gif: {error}"),
+    ("image.recursion", "referenced image, {depth} levels deep: {cause}"),
+    ("image.recursion_limit", "too many nested references"),
+    ("image.no_palette", "no palette"),
+    ("image.retrieve", "can't retrieve file: {cause}"),
+    ("image.unknown_hash", "no path with hash {hash}"),
+    ("image.service_stopped", "conversion service stopped"),
+    ("init.load_palette", "can't load palette: {error}"),
+    ("init.datafiles", "can't read client data files: {error}"),
+    ("init.gather_paths", "can't crawl archives: {cause}"),
+    ("init.cache_serialize", "can't write registry cache: {error}"),
+    ("init.cache_deserialize", "can't read registry cache: {error}"),
+    ("init.cache_io", "registry cache io error: {error}"),
+    ("init.cache_stale", "registry cache is stale"),
+    ("init.cache_version", "registry cache was written by version {version}"),
+    ("init.no_archive", "registry has no archive #{index}"),
+    ("init.database", "can't open database: {error}"),
+    ("manifest.syntax", "line {line}: expected `name size crc`"),
+    ("manifest.size", "line {line}: invalid size {value}"),
+    ("manifest.crc", "line {line}: invalid crc {value}"),
+    ("manifest.retrieve", "can't retrieve {path}: {cause}"),
+];
+
+#[derive(Debug, Error)]
+pub enum CatalogError {
+    #[error("line {0}: expected `key = template`")]
+    Syntax(usize),
+}
+
+/// Message templates of one language by key.
+#[derive(Debug, Clone, Default)]
+pub struct Catalog {
+    templates: HashMap<String, String>,
+}
+
+impl Catalog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Templates of every key in English, the fallback of every catalog.
+    pub fn english() -> Self {
+        let templates = ENGLISH
+            .iter()
+            .map(|&(key, template)| (key.to_owned(), template.to_owned()))
+            .collect();
+        Self { templates }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, CatalogError> {
+        let mut catalog = Self::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, template) = line.split_once('=').ok_or(CatalogError::Syntax(index + 1))?;
+            catalog.insert(key.trim(), template.trim());
+        }
+        Ok(catalog)
+    }
+
+    /// Catalog file, sorted by key.
+    pub fn to_text(&self) -> String {
+        let mut entries: Vec<_> = self.templates.iter().collect();
+        entries.sort();
+        entries
+            .into_iter()
+            .map(|(key, template)| format!("{} = {}\n", key, template))
+            .collect()
+    }
+
+    pub fn insert(&mut self, key: &str, template: &str) -> Option<String> {
+        self.templates.insert(key.to_owned(), template.to_owned())
+    }
+
+    pub fn template(&self, key: &str) -> Option<&str> {
+        self.templates.get(key).map(String::as_str)
+    }
+
+    /// Message of `error` with parameters and causes filled in, unknown keys render
+    /// as the key itself.
+    pub fn render(&self, error: &dyn Localize) -> String {
+        let key = error.message_key();
+        let template = self
+            .template(key)
+            .or_else(|| ENGLISH.iter().find(|(english, _)| *english == key).map(|(_, t)| *t))
+            .unwrap_or(key);
+        let mut params = error.message_params();
+        if let Some(cause) = error.cause() {
+            params.push(("cause", self.render(cause)));
+        }
+        fill(template, &params)
+    }
+}
+
+/// Replaces `{name}` with parameters, unknown names are kept as is.
+fn fill(template: &str, params: &[(&'static str, String)]) -> String {
+    let mut message = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        message.push_str(&rest[..open]);
+        rest = &rest[open..];
+        let value = rest.find('}').and_then(|close| {
+            let (_, value) = params.iter().find(|(name, _)| *name == &rest[1..close])?;
+            Some((value, close))
+        });
+        match value {
+            Some((value, close)) => {
+                message.push_str(value);
+                rest = &rest[close + 1..];
+            }
+            None => {
+                message.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    message.push_str(rest);
+    message
+}
+
+fn path(path: &std::path::Path) -> String {
+    path.display().to_string()
+}
+
+impl Localize for fo::Error {
+    fn message_key(&self) -> &'static str {
+        use fo::Error::*;
+        match self {
+            NotFound => "retriever.not_found",
+            InvalidArchiveIndex => "retriever.invalid_archive_index",
+            OpenArchive(..) => "retriever.open_archive",
+            Zip(_) => "retriever.zip",
+            ArchiveRead(_) => "retriever.archive_read",
+            LocalRead(..) => "retriever.local_read",
+            ArchiveKindMismatch => "retriever.archive_kind_mismatch",
+            UnpackTar(..) => "retriever.unpack_tar",
+            InvalidPassword => "retriever.invalid_password",
+            FileTooLarge(..) => "retriever.file_too_large",
+        }
+    }
+
+    fn message_params(&self) -> Params {
+        use fo::Error::*;
+        match self {
+            OpenArchive(file, err) | LocalRead(file, err) | UnpackTar(file, err) => {
+                vec![("path", path(file)), ("error", err.to_string())]
+            }
+            Zip(err) => vec![("error", err.to_string())],
+            ArchiveRead(err) => vec![("error", err.to_string())],
+            FileTooLarge(size, limit) => {
+                vec![("size", size.to_string()), ("limit", limit.to_string())]
+            }
+            NotFound | InvalidArchiveIndex | ArchiveKindMismatch | InvalidPassword => Vec::new(),
+        }
+    }
+}
+
+impl Localize for crawler::Error {
+    fn message_key(&self) -> &'static str {
+        use crawler::Error::*;
+        match self {
+            Walk(..) => "crawler.walk",
+            Tar(..) => "crawler.tar",
+            Dat(..) => "crawler.dat",
+            Zip(..) => "crawler.zip",
+            NonUtf8Path(_) => "crawler.non_utf8_path",
+            TooManyFiles(_) => "crawler.too_many_files",
+            TooLargeTotalSize(_) => "crawler.too_large_total_size",
+            PathTooLong(..) => "crawler.path_too_long",
+        }
+    }
+
+    fn message_params(&self) -> Params {
+        use crawler::Error::*;
+        match self {
+            Walk(folder, err) => vec![("path", path(folder)), ("error", err.to_string())],
+            Tar(file, err) | Dat(file, err) => {
+                vec![("path", path(file)), ("error", err.to_string())]
+            }
+            Zip(file, err) => vec![("path", path(file)), ("error", err.to_string())],
+            NonUtf8Path(file) => vec![("path", path(file))],
+            TooManyFiles(limit) => vec![("limit", limit.to_string())],
+            TooLargeTotalSize(limit) => vec![("limit", limit.to_string())],
+            PathTooLong(file, limit) => vec![("path", file.clone()), ("limit", limit.to_string())],
+        }
+    }
+}
+
+impl Localize for GetImageError {
+    fn message_key(&self) -> &'static str {
+        use GetImageError::*;
+        match self {
+            FileType(_) => "image.file_type",
+            Utf8(_) => "image.utf8",
+            FrmParse(_) => "image.frm_parse",
+            FoFrmParse(_) => "image.fofrm_parse",
+            NoParentFolder => "image.no_parent_folder",
+            InvalidRelativePath(..) => "image.invalid_relative_path",
+            NoDirection => "image.no_direction",
+            NoFrame => "image.no_frame",
+            ImageFromRaw => "image.from_raw",
+            ImageWrite(_) => "image.write",
+            PngDecode(_) => "image.png_decode",
+            GifDecode(_) => "image.gif_decode",
+            Recursion(..) => "image.recursion",
+            RecursionLimit => "image.recursion_limit",
+            NoPallete => "image.no_palette",
+            Retrieve(_) => "image.retrieve",
+            UnknownHash(_) => "image.unknown_hash",
+            ServiceStopped => "image.service_stopped",
+        }
+    }
+
+    fn message_params(&self) -> Params {
+        use GetImageError::*;
+        match self {
+            FileType(file_type) => vec![("type", format!("{:?}", file_type))],
+            Utf8(err) => vec![("error", err.to_string())],
+            FrmParse(err) => vec![("error", format!("{:?}", err))],
+            FoFrmParse(err) => vec![("error", format!("{:?}", err))],
+            InvalidRelativePath(base, relative) => {
+                vec![("path", relative.clone()), ("base", base.clone())]
+            }
+            ImageWrite(err) | PngDecode(err) | GifDecode(err) => vec![("error", err.to_string())],
+            Recursion(depth, _) => vec![("depth", (depth + 1).to_string())],
+            // third-party retrievers only have an English message
+            Retrieve(err) if err.downcast_ref::<fo::Error>().is_none() => {
+                vec![("cause", err.to_string())]
+            }
+            UnknownHash(hash) => vec![("hash", format!("{:08x}", hash))],
+            _ => Vec::new(),
+        }
+    }
+
+    fn cause(&self) -> Option<&dyn Localize> {
+        match self {
+            GetImageError::Recursion(_, origin) => Some(&**origin),
+            GetImageError::Retrieve(err) => Some(err.downcast_ref::<fo::Error>()?),
+            _ => None,
+        }
+    }
+}
+
+impl Localize for DataInitError {
+    fn message_key(&self) -> &'static str {
+        use DataInitError::*;
+        match self {
+            LoadPalette(_) => "init.load_palette",
+            Datafiles(_) => "init.datafiles",
+            GatherPaths(_) => "init.gather_paths",
+            CacheSerialize(_) => "init.cache_serialize",
+            CacheDeserialize(_) => "init.cache_deserialize",
+            CacheIO(_) => "init.cache_io",
+            CacheStale => "init.cache_stale",
+            CacheVersion(_) => "init.cache_version",
+            NoArchive(_) => "init.no_archive",
+            #[cfg(feature = "sled-retriever")]
+            SledInit(_) => "init.database",
+        }
+    }
+
+    fn message_params(&self) -> Params {
+        use DataInitError::*;
+        match self {
+            LoadPalette(err) => vec![("error", format!("{:?}", err))],
+            Datafiles(err) => vec![("error", format!("{:?}", err))],
+            CacheSerialize(err) | CacheDeserialize(err) => vec![("error", err.to_string())],
+            CacheIO(err) => vec![("error", err.to_string())],
+            CacheVersion(version) => vec![("version", version.to_string())],
+            NoArchive(index) => vec![("index", index.to_string())],
+            #[cfg(feature = "sled-retriever")]
+            SledInit(err) => vec![("error", err.to_string())],
+            GatherPaths(_) | CacheStale => Vec::new(),
+        }
+    }
+
+    fn cause(&self) -> Option<&dyn Localize> {
+        match self {
+            DataInitError::GatherPaths(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl Localize for ManifestError {
+    fn message_key(&self) -> &'static str {
+        match self {
+            ManifestError::Syntax(_) => "manifest.syntax",
+            ManifestError::Size(..) => "manifest.size",
+            ManifestError::Crc(..) => "manifest.crc",
+            ManifestError::Retrieve(..) => "manifest.retrieve",
+        }
+    }
+
+    fn message_params(&self) -> Params {
+        match self {
+            ManifestError::Syntax(line) => vec![("line", line.to_string())],
+            ManifestError::Size(line, value) | ManifestError::Crc(line, value) => {
+                vec![("line", line.to_string()), ("value", value.clone())]
+            }
+            ManifestError::Retrieve(name, _) => vec![("path", name.clone())],
+        }
+    }
+
+    fn cause(&self) -> Option<&dyn Localize> {
+        match self {
+            ManifestError::Retrieve(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn localized_messages() {
+        let english = Catalog::english();
+        let keys: std::collections::BTreeSet<_> = ENGLISH.iter().map(|(key, _)| key).collect();
+        assert_eq!(keys.len(), ENGLISH.len(), "keys are unique");
+
+        let too_large = fo::Error::FileTooLarge(10, 4);
+        assert_eq!(english.render(&too_large), too_large.to_string());
+        let init = DataInitError::CacheVersion(7);
+        assert_eq!(english.render(&init), init.to_string());
+        let missing = DataInitError::NoArchive(3);
+        assert_eq!(english.render(&missing), missing.to_string());
+        let nested = ManifestError::Retrieve("art/a.frm".into(), too_large);
+        assert_eq!(
+            english.render(&nested),
+            "can't retrieve art/a.frm: file is too large: 10 bytes, limit is 4 bytes"
+        );
+
+        let russian = Catalog::parse(
+            "# русский\n\
+             retriever.file_too_large = файл слишком большой: {size} байт, предел {limit} байт\n\
+             manifest.retrieve = не удалось прочитать {path}: {cause} {unknown}\n",
+        )
+        .unwrap();
+        assert_eq!(
+            russian.render(&nested),
+            "не удалось прочитать art/a.frm: файл слишком большой: 10 байт, предел 4 байт {unknown}"
+        );
+        assert_eq!(russian.render(&fo::Error::NotFound), "path not found");
+        let image = GetImageError::Recursion(0, Box::new(GetImageError::NoFrame));
+        assert_eq!(english.render(&image), "referenced image, 1 levels deep: no such frame");
+        assert!(matches!(Catalog::parse("key"), Err(CatalogError::Syntax(1))));
+        let text = russian.to_text();
+        assert!(text.starts_with("manifest.retrieve = "));
+        assert_eq!(Catalog::parse(&text).unwrap().to_text(), text);
+    }
+
+    #[test]
+    fn malformed_catalogs_and_templates() {
+        assert!(Catalog::parse("").unwrap().to_text().is_empty());
+        assert!(Catalog::parse("# comment\n\n   \n").unwrap().to_text().is_empty());
+        assert!(matches!(Catalog::parse("a = b\n\nno separator"), Err(CatalogError::Syntax(3))));
+        let catalog = Catalog::parse("empty =\nequals = a = b\n").unwrap();
+        assert_eq!(catalog.template("empty"), Some(""));
+        assert_eq!(catalog.template("equals"), Some("a = b"));
+
+        let params = [("size", "10".to_owned())];
+        assert_eq!(fill("", &params), "");
+        assert_eq!(fill("{size", &params), "{size");
+        assert_eq!(fill("}{}{size}{", &params), "}{}10{");
+        assert_eq!(fill("{a{size}}", &params), "{a10}");
+        assert_eq!(fill("{ключ} {size}", &params), "{ключ} 10");
+
+        struct Unknown;
+        impl Localize for Unknown {
+            fn message_key(&self) -> &'static str {
+                "custom.unknown"
+            }
+        }
+        assert_eq!(Catalog::new().render(&Unknown), "custom.unknown");
+    }
+}
@@ -0,0 +1,110 @@
+//! Fallout `.lst` files: asset names addressed by line index.
+//!
+//! Every line is an entry, the name is the first token and anything after
+//! `,`, `;`, space or tab is kept as extra data (e.g. `hmwarr,11,1` in critters.lst).
+//! Names are relative to the folder of the list file.
+
+use crate::FoRegistry;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LstEntry<'a> {
+    pub index: usize,
+    /// Empty for blank lines, they still occupy an index.
+    pub name: &'a str,
+    pub extra: Option<&'a str>,
+}
+
+pub fn parse_lst(text: &str) -> Vec<LstEntry<'_>> {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| {
+            let line = line.trim();
+            let is_separator = |c: char| matches!(c, ',' | ';' | ' ' | '\t');
+            let (name, extra) = match line.find(is_separator) {
+                Some(pos) => (&line[..pos], Some(line[pos..].trim_start_matches(is_separator))),
+                None => (line, None),
+            };
+            LstEntry {
+                index,
+                name,
+                extra: extra.filter(|extra| !extra.is_empty()),
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct Lst<'a> {
+    folder: String,
+    entries: Vec<LstEntry<'a>>,
+}
+
+impl<'a> Lst<'a> {
+    /// `lst_path` is a conventional path of the list file itself.
+    pub fn parse(lst_path: &str, text: &'a str) -> Self {
+        let folder = lst_path.rsplit_once('/').map_or("", |(folder, _)| folder);
+        Self {
+            folder: folder.to_owned(),
+            entries: parse_lst(text),
+        }
+    }
+
+    pub fn entries(&self) -> &[LstEntry<'a>] {
+        &self.entries
+    }
+
+    pub fn get(&self, index: usize) -> Option<&LstEntry<'a>> {
+        self.entries.get(index)
+    }
+
+    /// Conventional path of the entry, `None` for blank lines.
+    pub fn path(&self, index: usize) -> Option<String> {
+        let entry = self.get(index).filter(|entry| !entry.name.is_empty())?;
+        let path = if self.folder.is_empty() {
+            entry.name.to_owned()
+        } else {
+            format!("{}/{}", self.folder, entry.name)
+        };
+        Some(nom_prelude::make_path_conventional(&path))
+    }
+
+    /// Path of the entry if it exists in the registry.
+    pub fn resolve(&self, index: usize, registry: &FoRegistry) -> Option<String> {
+        self.path(index)
+            .filter(|path| registry.file_info(path).is_some())
+    }
+
+    /// Non-blank entries without files in the registry.
+    pub fn missing<'s>(&'s self, registry: &'s FoRegistry) -> impl 's + Iterator<Item = &'s LstEntry<'a>> {
+        self.entries.iter().filter(move |entry| {
+            !entry.name.is_empty() && self.resolve(entry.index, registry).is_none()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_resolve_paths() {
+        let text = "HMWARR,11,1\r\n\r\nGRID000.FRM ; comment\nreserve.frm\n";
+        let lst = Lst::parse("art/critters/critters.lst", text);
+        assert_eq!(lst.entries().len(), 4);
+        assert_eq!(
+            lst.get(0),
+            Some(&LstEntry {
+                index: 0,
+                name: "HMWARR",
+                extra: Some("11,1")
+            })
+        );
+        assert_eq!(lst.get(2).unwrap().extra, Some("comment"));
+        assert_eq!(lst.path(1), None);
+        assert_eq!(lst.path(2).as_deref(), Some("art/critters/grid000.frm"));
+
+        let registry = FoRegistry::stub();
+        assert_eq!(lst.resolve(2, &registry), None);
+        assert_eq!(lst.missing(&registry).count(), 3);
+    }
+}
@@ -0,0 +1,216 @@
+//! Checksum manifests for FOnline auto-updaters, generated from the registry.
+//!
+//! A manifest lists every file as a `name size crc` line: the conventional path, the
+//! uncompressed size in bytes and the CRC-32 (IEEE) of the contents as 8 hex digits.
+//! Names may contain spaces, size and crc are always the last two fields.
+//! Empty lines and lines starting with `#` are skipped.
+
+use std::fmt::Write;
+
+use thiserror::Error;
+
+use crate::{retriever::fo, FoRetriever};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub name: String,
+    pub size: u64,
+    pub crc: u32,
+}
+
+#[derive(Debug, Error)]
+pub enum ManifestError {
+    #[error("line {0}: expected `name size crc`")]
+    Syntax(usize),
+    #[error("line {0}: invalid size {1:?}")]
+    Size(usize, String),
+    #[error("line {0}: invalid crc {1:?}")]
+    Crc(usize, String),
+    #[error("can't retrieve {0:?}: {1}")]
+    Retrieve(String, fo::Error),
+}
+
+/// Manifest of every file of the registry, sorted by name.
+pub fn build_manifest(retriever: &FoRetriever) -> Result<Vec<ManifestEntry>, ManifestError> {
+    retriever
+        .registry()
+        .files()
+        .map(|(path, info)| {
+            let data = retriever
+                .file_by_info(info)
+                .map_err(|err| ManifestError::Retrieve(path.to_owned(), err))?;
+            Ok(ManifestEntry {
+                name: path.to_owned(),
+                size: data.len() as u64,
+                crc: crc32fast::hash(&data),
+            })
+        })
+        .collect()
+}
+
+pub fn to_text(entries: &[ManifestEntry]) -> String {
+    let mut text = String::new();
+    for entry in entries {
+        let _ = writeln!(text, "{} {} {:08X}", entry.name, entry.size, entry.crc);
+    }
+    text
+}
+
+pub fn parse_manifest(text: &str) -> Result<Vec<ManifestEntry>, ManifestError> {
+    let mut entries = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.rsplitn(3, char::is_whitespace);
+        let (crc, size, name) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(crc), Some(size), Some(name)) if !name.trim().is_empty() => (crc, size, name),
+            _ => return Err(ManifestError::Syntax(line_number)),
+        };
+        let size = size
+            .parse()
+            .map_err(|_| ManifestError::Size(line_number, size.to_owned()))?;
+        let crc = u32::from_str_radix(crc, 16)
+            .map_err(|_| ManifestError::Crc(line_number, crc.to_owned()))?;
+        entries.push(ManifestEntry {
+            name: name.trim().to_owned(),
+            size,
+            crc,
+        });
+    }
+    Ok(entries)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    /// Listed by the manifest, but not in the registry.
+    Missing(String),
+    /// In the registry, but not listed by the manifest.
+    Unlisted(String),
+    Size { name: String, listed: u64, actual: u64 },
+    Crc { name: String, listed: u32, actual: u32 },
+}
+
+/// Differences between `entries` and the registry, an up to date manifest has none.
+/// Names are compared as conventional paths, contents are only read when sizes match.
+pub fn verify_manifest(
+    retriever: &FoRetriever,
+    entries: &[ManifestEntry],
+) -> Result<Vec<Mismatch>, ManifestError> {
+    let registry = retriever.registry();
+    let rules = registry.path_rules();
+    let mut listed = std::collections::BTreeSet::new();
+    let mut mismatches = Vec::new();
+    for entry in entries {
+        let name = rules.normalize(&entry.name);
+        let info = match registry.file_info(&name) {
+            Some(info) => info,
+            None => {
+                mismatches.push(Mismatch::Missing(entry.name.clone()));
+                continue;
+            }
+        };
+        listed.insert(name);
+        if info.uncompressed_size() != entry.size {
+            mismatches.push(Mismatch::Size {
+                name: entry.name.clone(),
+                listed: entry.size,
+                actual: info.uncompressed_size(),
+            });
+            continue;
+        }
+        let data = retriever
+            .file_by_info(info)
+            .map_err(|err| ManifestError::Retrieve(entry.name.clone(), err))?;
+        let crc = crc32fast::hash(&data);
+        if crc != entry.crc {
+            mismatches.push(Mismatch::Crc {
+                name: entry.name.clone(),
+                listed: entry.crc,
+                actual: crc,
+            });
+        }
+    }
+    for (path, _) in registry.files() {
+        if !listed.contains(path) {
+            mismatches.push(Mismatch::Unlisted(path.to_owned()));
+        }
+    }
+    Ok(mismatches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempData;
+
+    #[test]
+    fn updater_manifest() {
+        let data = TempData::new(&[("art/my tiles/a.frm", b"frm"), ("text.msg", b"{1}{}{hi}")]);
+        let retriever = data.retriever();
+
+        let manifest = build_manifest(&retriever).unwrap();
+        let text = to_text(&manifest);
+        assert_eq!(
+            text.lines().next(),
+            Some(format!("art/my tiles/a.frm 3 {:08X}", crc32fast::hash(b"frm")).as_str())
+        );
+        assert_eq!(parse_manifest(&format!("# list\n\n{}", text)).unwrap(), manifest);
+        assert!(verify_manifest(&retriever, &manifest).unwrap().is_empty());
+
+        let mut stale = manifest.clone();
+        stale[0].crc ^= 1;
+        stale[1].size = 1;
+        stale[1].name = "TEXT.MSG".into();
+        stale.push(ManifestEntry {
+            name: "gone.frm".into(),
+            ..manifest[0].clone()
+        });
+        let mismatches = verify_manifest(&retriever, &stale).unwrap();
+        assert_eq!(mismatches.len(), 3);
+        let crc_of = |mismatch: &Mismatch| match mismatch {
+            Mismatch::Crc { name, .. } => Some(name.clone()),
+            _ => None,
+        };
+        assert_eq!(crc_of(&mismatches[0]).as_deref(), Some("art/my tiles/a.frm"));
+        assert!(matches!(&mismatches[1], Mismatch::Size { actual: 9, .. }));
+        assert_eq!(mismatches[2], Mismatch::Missing("gone.frm".into()));
+        let unlisted = verify_manifest(&retriever, &manifest[..1]).unwrap();
+        assert_eq!(unlisted, [Mismatch::Unlisted("text.msg".into())]);
+
+        assert!(matches!(parse_manifest("a.frm 3"), Err(ManifestError::Syntax(1))));
+        assert!(matches!(parse_manifest("a.frm x 0"), Err(ManifestError::Size(1, _))));
+        assert!(matches!(parse_manifest("a.frm 3 zz"), Err(ManifestError::Crc(1, _))));
+    }
+
+    #[test]
+    fn empty_and_unreadable() {
+        let empty = crate::FoRegistry::stub().into_retriever();
+        assert!(build_manifest(&empty).unwrap().is_empty());
+        assert!(to_text(&[]).is_empty());
+        assert!(parse_manifest("").unwrap().is_empty());
+        assert!(parse_manifest("# only\n  \n").unwrap().is_empty());
+        assert!(verify_manifest(&empty, &[]).unwrap().is_empty());
+
+        let parsed = parse_manifest("a b.frm\t3\t0000000A\n").unwrap();
+        assert_eq!((parsed[0].name.as_str(), parsed[0].size, parsed[0].crc), ("a b.frm", 3, 10));
+        assert!(matches!(parse_manifest("\n 3 0"), Err(ManifestError::Syntax(2))));
+        assert!(matches!(parse_manifest("a.frm -1 0"), Err(ManifestError::Size(1, _))));
+        assert!(matches!(parse_manifest("a.frm 1 1FFFFFFFF"), Err(ManifestError::Crc(1, _))));
+
+        let data = TempData::new(&[("a.frm", b"frm")]);
+        let retriever = data.retriever();
+        let unlisted = verify_manifest(&retriever, &[]).unwrap();
+        assert_eq!(unlisted, [Mismatch::Unlisted("a.frm".into())]);
+        let manifest = build_manifest(&retriever).unwrap();
+        std::fs::remove_file(data.root().join("a.frm")).unwrap();
+        let err = build_manifest(&retriever).unwrap_err();
+        assert!(matches!(&err, ManifestError::Retrieve(name, _) if name == "a.frm"), "{}", err);
+        assert!(matches!(
+            verify_manifest(&retriever, &manifest),
+            Err(ManifestError::Retrieve(..))
+        ));
+    }
+}
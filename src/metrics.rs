@@ -0,0 +1,201 @@
+//! Optional runtime counters of retrievers and converters, to find hot spots and regressions.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// Measured kind of work.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    /// Opening of an archive, once per archive and retriever.
+    ArchiveOpen,
+    /// File read from an archive or a folder, bytes are decompressed size.
+    FileRead,
+    /// File taken from prefetched ones instead of being read.
+    PrefetchHit,
+    /// Parsing of FRM headers and locating of the frame.
+    FrmDecode,
+    /// Png encoding, bytes are encoded size.
+    PngEncode,
+    /// Whole conversion through [`Converter::get_with`](crate::Converter::get_with),
+    /// bytes are output size.
+    Convert,
+}
+
+impl Operation {
+    pub const ALL: [Operation; 6] = [
+        Operation::ArchiveOpen,
+        Operation::FileRead,
+        Operation::PrefetchHit,
+        Operation::FrmDecode,
+        Operation::PngEncode,
+        Operation::Convert,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Operation::ArchiveOpen => "archive_open",
+            Operation::FileRead => "file_read",
+            Operation::PrefetchHit => "prefetch_hit",
+            Operation::FrmDecode => "frm_decode",
+            Operation::PngEncode => "png_encode",
+            Operation::Convert => "convert",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OperationStats {
+    pub count: u64,
+    pub bytes: u64,
+    /// Summed over all threads, may exceed wall time.
+    pub time: Duration,
+}
+
+impl OperationStats {
+    /// `None` if nothing was measured.
+    pub fn mean_time(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(self.time / self.count.min(u32::MAX as u64) as u32)
+    }
+}
+
+#[derive(Default)]
+struct Counter {
+    count: AtomicU64,
+    bytes: AtomicU64,
+    nanos: AtomicU64,
+}
+
+/// Cheap to clone, clones share counters. Attach the same metrics to several
+/// retrievers and converters to sum them up.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    counters: Arc<[Counter; Operation::ALL.len()]>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn counter(&self, operation: Operation) -> &Counter {
+        &self.counters[operation as usize]
+    }
+
+    pub fn record(&self, operation: Operation, bytes: u64, time: Duration) {
+        let counter = self.counter(operation);
+        counter.count.fetch_add(1, Ordering::Relaxed);
+        counter.bytes.fetch_add(bytes, Ordering::Relaxed);
+        let nanos = time.as_nanos().min(u64::MAX as u128) as u64;
+        // saturates instead of wrapping around after about 584 years of summed time
+        let _ = counter.nanos.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |total| {
+            Some(total.saturating_add(nanos))
+        });
+    }
+
+    pub fn get(&self, operation: Operation) -> OperationStats {
+        let counter = self.counter(operation);
+        OperationStats {
+            count: counter.count.load(Ordering::Relaxed),
+            bytes: counter.bytes.load(Ordering::Relaxed),
+            time: Duration::from_nanos(counter.nanos.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Stats of every operation, in the order of [`Operation::ALL`].
+    pub fn snapshot(&self) -> Vec<(Operation, OperationStats)> {
+        Operation::ALL
+            .iter()
+            .map(|&operation| (operation, self.get(operation)))
+            .collect()
+    }
+
+    pub fn reset(&self) {
+        for counter in self.counters.iter() {
+            counter.count.store(0, Ordering::Relaxed);
+            counter.bytes.store(0, Ordering::Relaxed);
+            counter.nanos.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+impl std::fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map()
+            .entries(self.snapshot().iter().map(|(operation, stats)| (operation.name(), stats)))
+            .finish()
+    }
+}
+
+/// Runs `work`, recording it if `metrics` are attached. Errors are not recorded.
+pub(crate) fn measure<T, E>(
+    metrics: Option<&Metrics>,
+    operation: Operation,
+    work: impl FnOnce() -> Result<T, E>,
+    bytes: impl FnOnce(&T) -> usize,
+) -> Result<T, E> {
+    let metrics = match metrics {
+        Some(metrics) => metrics,
+        None => return work(),
+    };
+    let start = Instant::now();
+    let result = work();
+    if let Ok(value) = &result {
+        metrics.record(operation, bytes(value) as u64, start.elapsed());
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_counters() {
+        let metrics = Metrics::new();
+        let clone = metrics.clone();
+        clone.record(Operation::FileRead, 10, Duration::from_millis(2));
+        let read = || Ok::<_, ()>(vec![0; 5]);
+        assert_eq!(
+            measure(Some(&metrics), Operation::FileRead, read, Vec::len),
+            Ok(vec![0; 5])
+        );
+        let failed = || Err::<Vec<u8>, _>(());
+        assert!(measure(Some(&metrics), Operation::FileRead, failed, Vec::len).is_err());
+
+        let stats = metrics.get(Operation::FileRead);
+        assert_eq!((stats.count, stats.bytes), (2, 15));
+        assert!(stats.time >= Duration::from_millis(2));
+        assert!(stats.mean_time().unwrap() >= Duration::from_millis(1));
+        assert_eq!(metrics.get(Operation::Convert), OperationStats::default());
+        assert_eq!(metrics.snapshot().len(), Operation::ALL.len());
+
+        metrics.reset();
+        assert_eq!(clone.get(Operation::FileRead).count, 0);
+    }
+
+    #[test]
+    fn empty_and_saturated() {
+        for (index, operation) in Operation::ALL.iter().enumerate() {
+            assert_eq!(*operation as usize, index);
+        }
+        let metrics = Metrics::new();
+        assert_eq!(metrics.get(Operation::Convert).mean_time(), None);
+        let read = || Ok::<_, ()>(vec![0; 5]);
+        assert!(measure(None, Operation::FileRead, read, Vec::len).is_ok());
+        assert_eq!(metrics.get(Operation::FileRead).count, 0);
+
+        metrics.record(Operation::Convert, 0, Duration::MAX);
+        metrics.record(Operation::Convert, 0, Duration::from_secs(1));
+        let stats = metrics.get(Operation::Convert);
+        assert_eq!(stats.time, Duration::from_nanos(u64::MAX));
+        assert_eq!(stats.mean_time(), Some(Duration::from_nanos(u64::MAX / 2)));
+    }
+}
@@ -0,0 +1,298 @@
+//! Synchronization of registry contents to a destination, the core of an updater:
+//! new and changed files are copied, removed ones are deleted, written ones are verified.
+
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::{retriever::fo, FoRetriever, Mode};
+
+/// Where [`mirror`] copies files to, implement it for remote storages.
+/// Paths are conventional paths of the registry.
+pub trait MirrorTarget {
+    /// Every file of the target with its size.
+    fn list(&self) -> io::Result<BTreeMap<String, u64>>;
+    fn read(&self, path: &str) -> io::Result<Vec<u8>>;
+    /// Replaces the file, creating parent folders if needed.
+    fn write(&self, path: &str, data: &[u8]) -> io::Result<()>;
+    fn remove(&self, path: &str) -> io::Result<()>;
+}
+
+/// Local folder, files are written next to their final path and then renamed,
+/// so an interrupted run never leaves a half-written file under a registry path.
+#[derive(Debug, Clone)]
+pub struct DirTarget {
+    root: PathBuf,
+}
+
+impl DirTarget {
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        Self {
+            root: root.as_ref().to_owned(),
+        }
+    }
+
+    fn list_folder(&self, folder: &Path, files: &mut BTreeMap<String, u64>) -> io::Result<()> {
+        for entry in std::fs::read_dir(folder)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                self.list_folder(&entry.path(), files)?;
+            } else if file_type.is_file() {
+                let path = entry.path();
+                let relative = path
+                    .strip_prefix(&self.root)
+                    .expect("Listed paths are inside of the root");
+                let relative = relative.to_str().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "non-utf8 path")
+                })?;
+                files.insert(relative.replace('\\', "/"), entry.metadata()?.len());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl MirrorTarget for DirTarget {
+    fn list(&self) -> io::Result<BTreeMap<String, u64>> {
+        let mut files = BTreeMap::new();
+        if self.root.is_dir() {
+            self.list_folder(&self.root, &mut files)?;
+        }
+        Ok(files)
+    }
+
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        std::fs::read(self.root.join(path))
+    }
+
+    fn write(&self, path: &str, data: &[u8]) -> io::Result<()> {
+        let path = self.root.join(path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut temp = path.clone().into_os_string();
+        temp.push(".partial");
+        std::fs::write(&temp, data)?;
+        std::fs::rename(&temp, &path)
+    }
+
+    fn remove(&self, path: &str) -> io::Result<()> {
+        std::fs::remove_file(self.root.join(path))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MirrorOptions {
+    /// A dry run leaves the target untouched and doesn't verify.
+    pub mode: Mode,
+    /// Removes files of the target that are not in the registry.
+    pub delete_removed: bool,
+    /// Reads every written file back and compares it with the registry.
+    pub verify: bool,
+}
+
+impl Default for MirrorOptions {
+    fn default() -> Self {
+        Self {
+            mode: Mode::default(),
+            delete_removed: true,
+            verify: true,
+        }
+    }
+}
+
+/// Paths are sorted. In a dry run lists what would be done.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MirrorReport {
+    /// Files missing from the target.
+    pub copied: Vec<String>,
+    /// Files with different contents in the target.
+    pub updated: Vec<String>,
+    /// Files of the target that are not in the registry.
+    pub deleted: Vec<String>,
+    pub unchanged: usize,
+    /// Bytes written or to be written to the target.
+    pub bytes: u64,
+}
+
+#[derive(Debug, Error)]
+pub enum MirrorError {
+    #[error("can't list target: {0}")]
+    List(io::Error),
+    #[error("can't retrieve {0:?}: {1}")]
+    Retrieve(String, fo::Error),
+    #[error("can't access {0:?} in target: {1}")]
+    Target(String, io::Error),
+    #[error("{0:?} in target doesn't match the registry after writing")]
+    Verify(String),
+}
+
+/// Makes `target` contain exactly the files of the registry.
+///
+/// `progress` is called with the number of processed paths and the total after every path.
+/// Stops at the first error, files synchronized before it stay in place, so a rerun
+/// continues where it stopped.
+pub fn mirror(
+    retriever: &FoRetriever,
+    target: &impl MirrorTarget,
+    options: &MirrorOptions,
+    mut progress: impl FnMut(usize, usize),
+) -> Result<MirrorReport, MirrorError> {
+    let registry = retriever.registry();
+    let mut existing = target.list().map_err(MirrorError::List)?;
+    let target_err = |path: &str| {
+        let path = path.to_owned();
+        move |err| MirrorError::Target(path, err)
+    };
+
+    let removed: Vec<String> = existing
+        .keys()
+        .filter(|path| registry.file_info(path).is_none())
+        .cloned()
+        .collect();
+    let to_delete = if options.delete_removed { removed.len() } else { 0 };
+    let total = registry.files().len() + to_delete;
+    let mut done = 0;
+    let mut report = MirrorReport::default();
+
+    for (path, info) in registry.files() {
+        let data = retriever
+            .file_by_info(info)
+            .map_err(|err| MirrorError::Retrieve(path.to_owned(), err))?;
+        let changed = match existing.remove(path) {
+            None => {
+                report.copied.push(path.to_owned());
+                true
+            }
+            Some(size) => {
+                let same = size == data.len() as u64
+                    && target.read(path).map_err(target_err(path))? == data;
+                if !same {
+                    report.updated.push(path.to_owned());
+                }
+                !same
+            }
+        };
+        if changed {
+            report.bytes += data.len() as u64;
+            if !options.mode.is_dry_run() {
+                target.write(path, &data).map_err(target_err(path))?;
+                if options.verify {
+                    let written = target.read(path).map_err(target_err(path))?;
+                    if written != data {
+                        return Err(MirrorError::Verify(path.to_owned()));
+                    }
+                }
+            }
+        } else {
+            report.unchanged += 1;
+        }
+        done += 1;
+        progress(done, total);
+    }
+
+    if options.delete_removed {
+        for path in removed {
+            if !options.mode.is_dry_run() {
+                target.remove(&path).map_err(target_err(&path))?;
+            }
+            report.deleted.push(path);
+            done += 1;
+            progress(done, total);
+        }
+    }
+    report.copied.sort();
+    report.updated.sort();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempData;
+
+    #[test]
+    fn mirror_folder() {
+        let source = TempData::new(&[
+            ("art/a.png", b"new"),
+            ("art/b.png", b"same"),
+            ("art/c.png", b"longer"),
+        ]);
+        let destination = TempData::new(&[
+            ("art/b.png", b"same"),
+            ("art/c.png", b"short"),
+            ("art/old.png", b"old"),
+        ]);
+        let retriever = source.retriever();
+        let target = DirTarget::new(destination.root());
+
+        let planned = mirror(&retriever, &target, &MirrorOptions::default(), |_, _| {}).unwrap();
+        assert_eq!(planned.copied, ["art/a.png"]);
+        assert_eq!(planned.updated, ["art/c.png"]);
+        assert_eq!(planned.deleted, ["art/old.png"]);
+        assert_eq!((planned.unchanged, planned.bytes), (1, 9));
+        assert_eq!(target.list().unwrap().len(), 3);
+
+        let options = MirrorOptions {
+            mode: Mode::Apply,
+            ..MirrorOptions::default()
+        };
+        let mut calls = Vec::new();
+        let report =
+            mirror(&retriever, &target, &options, |done, total| calls.push((done, total))).unwrap();
+        assert_eq!(report, planned);
+        assert_eq!(calls.last(), Some(&(4, 4)));
+        let synced = target.list().unwrap();
+        assert_eq!(synced.keys().collect::<Vec<_>>(), ["art/a.png", "art/b.png", "art/c.png"]);
+        assert_eq!(target.read("art/c.png").unwrap(), b"longer");
+
+        let report = mirror(&retriever, &target, &options, |_, _| {}).unwrap();
+        assert_eq!(report.unchanged, 3);
+        assert_eq!(report.bytes, 0);
+    }
+
+    struct BrokenTarget;
+
+    impl MirrorTarget for BrokenTarget {
+        fn list(&self) -> io::Result<BTreeMap<String, u64>> {
+            Err(io::ErrorKind::PermissionDenied.into())
+        }
+        fn read(&self, _: &str) -> io::Result<Vec<u8>> {
+            unreachable!()
+        }
+        fn write(&self, _: &str, _: &[u8]) -> io::Result<()> {
+            unreachable!()
+        }
+        fn remove(&self, _: &str) -> io::Result<()> {
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn missing_source_and_broken_target() {
+        let source = TempData::new(&[("art/gone.png", b"gone")]);
+        let retriever = source.retriever();
+        let options = MirrorOptions::default();
+        assert!(matches!(
+            mirror(&retriever, &BrokenTarget, &options, |_, _| {}),
+            Err(MirrorError::List(_))
+        ));
+
+        std::fs::remove_file(source.root().join("art/gone.png")).unwrap();
+        let target = DirTarget::new(source.root().join("missing"));
+        assert!(matches!(
+            mirror(&retriever, &target, &options, |_, _| {}),
+            Err(MirrorError::Retrieve(path, _)) if path == "art/gone.png"
+        ));
+
+        let empty = crate::FoRegistry::stub().into_retriever();
+        let mut calls = 0;
+        let report = mirror(&empty, &target, &options, |_, _| calls += 1).unwrap();
+        assert_eq!((report, calls), (MirrorReport::default(), 0));
+    }
+}
@@ -0,0 +1,167 @@
+//! Fallout `.msg` files: `{number}{audio}{text}` records, anything outside of braces is a comment.
+//! Text may span multiple lines, numbers may repeat for random lines.
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MsgEntry<'a> {
+    pub number: u32,
+    /// Sound name without extension, empty for most records.
+    pub audio: &'a str,
+    pub text: &'a str,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MsgError {
+    /// Byte offset of the opening brace.
+    UnclosedBrace(usize),
+    /// Byte offset of the record and a field that should follow.
+    MissingField(usize, &'static str),
+    InvalidNumber(usize, String),
+}
+
+impl fmt::Display for MsgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MsgError::UnclosedBrace(offset) => write!(f, "unclosed brace at {}", offset),
+            MsgError::MissingField(offset, field) => {
+                write!(f, "record at {} has no {} field", offset, field)
+            }
+            MsgError::InvalidNumber(offset, number) => {
+                write!(f, "invalid record number {:?} at {}", number, offset)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MsgError {}
+
+fn field(text: &str, offset: usize) -> Result<(&str, usize), MsgError> {
+    let end = text[offset + 1..]
+        .find('}')
+        .ok_or(MsgError::UnclosedBrace(offset))?;
+    Ok((&text[offset + 1..offset + 1 + end], offset + end + 2))
+}
+
+fn next_field<'a>(
+    text: &'a str,
+    offset: usize,
+    record: usize,
+    name: &'static str,
+) -> Result<(&'a str, usize), MsgError> {
+    let skipped = text[offset..].len() - text[offset..].trim_start().len();
+    let offset = offset + skipped;
+    if !text[offset..].starts_with('{') {
+        return Err(MsgError::MissingField(record, name));
+    }
+    field(text, offset)
+}
+
+pub fn parse_msg(text: &str) -> Result<Vec<MsgEntry<'_>>, MsgError> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while let Some(start) = text[offset..].find('{') {
+        let record = offset + start;
+        let (number, rest) = field(text, record)?;
+        let (audio, rest) = next_field(text, rest, record, "audio")?;
+        let (message, rest) = next_field(text, rest, record, "text")?;
+        let number = number
+            .trim()
+            .parse()
+            .map_err(|_| MsgError::InvalidNumber(record, number.to_owned()))?;
+        entries.push(MsgEntry {
+            number,
+            audio: audio.trim(),
+            text: message,
+        });
+        offset = rest;
+    }
+    Ok(entries)
+}
+
+#[derive(Debug, Clone)]
+pub struct Msg<'a> {
+    entries: Vec<MsgEntry<'a>>,
+}
+
+impl<'a> Msg<'a> {
+    pub fn parse(text: &'a str) -> Result<Self, MsgError> {
+        Ok(Self {
+            entries: parse_msg(text)?,
+        })
+    }
+
+    pub fn entries(&self) -> &[MsgEntry<'a>] {
+        &self.entries
+    }
+
+    /// First text with the number.
+    pub fn get(&self, number: u32) -> Option<&'a str> {
+        self.get_all(number).next()
+    }
+
+    pub fn get_all(&self, number: u32) -> impl '_ + Iterator<Item = &'a str> {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.number == number)
+            .map(|entry| entry.text)
+    }
+
+    /// Names of sounds referenced by records, deduplicated.
+    pub fn audio_references(&self) -> Vec<&'a str> {
+        let mut names: Vec<_> = self
+            .entries
+            .iter()
+            .map(|entry| entry.audio)
+            .filter(|audio| !audio.is_empty())
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    /// Texts that are art file names as a whole, as in picture lists of dialogs.
+    pub fn art_names(&self) -> Vec<&'a str> {
+        let mut names: Vec<_> = self
+            .entries
+            .iter()
+            .map(|entry| entry.text.trim())
+            .filter(|text| {
+                !text.contains(char::is_whitespace) && crate::intrface::is_art_name(text)
+            })
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_records() {
+        let text = "# comment\n{100}{}{Hello}\n{101}{ SND01 }{Multi\nline}\n{101}{}{Other}\n{102}{}{art/misc/pic.png}";
+        let msg = Msg::parse(text).unwrap();
+        assert_eq!(msg.entries().len(), 4);
+        assert_eq!(msg.get(100), Some("Hello"));
+        assert_eq!(msg.get(101), Some("Multi\nline"));
+        assert_eq!(msg.get_all(101).count(), 2);
+        assert_eq!(msg.audio_references(), ["SND01"]);
+        assert_eq!(msg.art_names(), ["art/misc/pic.png"]);
+    }
+
+    #[test]
+    fn report_errors() {
+        assert_eq!(parse_msg("{100}{}{text"), Err(MsgError::UnclosedBrace(7)));
+        assert_eq!(
+            parse_msg("{100} x {}{}"),
+            Err(MsgError::MissingField(0, "audio"))
+        );
+        assert_eq!(
+            parse_msg("{abc}{}{}"),
+            Err(MsgError::InvalidNumber(0, "abc".into()))
+        );
+    }
+}
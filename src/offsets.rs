@@ -0,0 +1,386 @@
+//! Table of FRM and FOFRM offsets for bulk alignment passes: exported as CSV, edited in a
+//! spreadsheet or by a script and written back into the files of data folders.
+//!
+//! Rows of a file are its whole-file offset (FOFRM only), shifts of directions and next
+//! offsets of frames. Offsets a FOFRM doesn't set are exported as 0.
+
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    fofrm, frm,
+    rename::local_file,
+    retriever::{fo, recognize_type},
+    FileType, FoRegistry, FoRetriever, Mode,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OffsetRow {
+    pub path: String,
+    /// `None` for the offset of the whole FOFRM.
+    pub direction: Option<u8>,
+    /// `None` for the shift of the direction, otherwise the next offset of the frame.
+    pub frame: Option<u16>,
+    pub x: i16,
+    pub y: i16,
+}
+
+pub const CSV_HEADER: &str = "path,direction,frame,x,y";
+
+#[derive(Debug, Error)]
+pub enum OffsetsError {
+    #[error("line {0}: expected `{}`", CSV_HEADER)]
+    Csv(usize),
+    #[error("{0:?} is not in the registry")]
+    NotFound(String),
+    #[error("{0:?} is inside an archive, only files of data folders can be patched")]
+    NotLocal(String),
+    #[error("{0:?} is neither frm nor fofrm")]
+    Unsupported(String),
+    #[error("can't retrieve {0:?}: {1}")]
+    Retrieve(String, fo::Error),
+    #[error("can't parse {0:?}: {1}")]
+    Parse(String, String),
+    #[error("{path:?} has no offset of direction {direction:?} frame {frame:?}")]
+    NoSuchOffset {
+        path: String,
+        direction: Option<u8>,
+        frame: Option<u16>,
+    },
+    #[error("can't access {0:?}: {1}")]
+    Io(PathBuf, io::Error),
+}
+
+fn parse_err<E: std::fmt::Debug>(path: &str) -> impl FnOnce(E) -> OffsetsError + '_ {
+    move |err| OffsetsError::Parse(path.to_owned(), format!("{:?}", err))
+}
+
+/// Offsets of an FRM or FOFRM file, in file order.
+pub fn rows_of(path: &str, data: &[u8]) -> Result<Vec<OffsetRow>, OffsetsError> {
+    let row = |direction, frame, x, y| OffsetRow {
+        path: path.to_owned(),
+        direction,
+        frame,
+        x,
+        y,
+    };
+    let mut rows = Vec::new();
+    match recognize_type(path) {
+        FileType::Frm => {
+            let header = frm::parse_header(data).map_err(parse_err(path))?;
+            for (dir, direction) in header.directions.iter().enumerate() {
+                let dir = Some(dir as u8);
+                rows.push(row(dir, None, direction.shift_x, direction.shift_y));
+                for (index, frame) in direction.frames.iter().enumerate() {
+                    rows.push(row(dir, Some(index as u16), frame.offset_x, frame.offset_y));
+                }
+            }
+        }
+        FileType::FoFrm => {
+            let text = std::str::from_utf8(data).map_err(parse_err(path))?;
+            let fofrm =
+                fofrm::parse_verbose(text).map_err(parse_err(path))?;
+            let or_zero = |value: Option<i16>| value.unwrap_or(0);
+            rows.push(row(None, None, or_zero(fofrm.offset_x), or_zero(fofrm.offset_y)));
+            for (dir, direction) in fofrm.directions.iter().enumerate() {
+                let dir = Some(dir as u8);
+                let (x, y) = (or_zero(direction.offset_x), or_zero(direction.offset_y));
+                rows.push(row(dir, None, x, y));
+                for (index, frame) in direction.frames.iter().enumerate() {
+                    let (x, y) = (or_zero(frame.next_x), or_zero(frame.next_y));
+                    rows.push(row(dir, Some(index as u16), x, y));
+                }
+            }
+        }
+        _ => return Err(OffsetsError::Unsupported(path.to_owned())),
+    }
+    Ok(rows)
+}
+
+/// Offsets of every FRM and FOFRM of the registry sorted by path, and files that can't
+/// be read or parsed.
+pub fn export_offsets(retriever: &FoRetriever) -> (Vec<OffsetRow>, Vec<(String, OffsetsError)>) {
+    let mut paths: Vec<_> = retriever
+        .registry()
+        .files()
+        .filter(|(_, info)| matches!(info.file_type(), FileType::Frm | FileType::FoFrm))
+        .collect();
+    paths.sort_unstable_by_key(|(path, _)| *path);
+    let mut rows = Vec::new();
+    let mut failed = Vec::new();
+    for (path, info) in paths {
+        let file_rows = retriever
+            .file_by_info(info)
+            .map_err(|err| OffsetsError::Retrieve(path.to_owned(), err))
+            .and_then(|data| rows_of(path, &data));
+        match file_rows {
+            Ok(file_rows) => rows.extend(file_rows),
+            Err(err) => failed.push((path.to_owned(), err)),
+        }
+    }
+    (rows, failed)
+}
+
+pub fn to_csv(rows: &[OffsetRow]) -> String {
+    use std::fmt::Write;
+
+    let mut csv = format!("{}\n", CSV_HEADER);
+    let optional = |value: Option<u16>| value.map(|value| value.to_string()).unwrap_or_default();
+    for row in rows {
+        let direction = optional(row.direction.map(u16::from));
+        let frame = optional(row.frame);
+        writeln!(csv, "{},{},{},{},{}", row.path, direction, frame, row.x, row.y).unwrap();
+    }
+    csv
+}
+
+/// Reads rows written by [`to_csv`], the header line and empty lines are skipped.
+pub fn parse_csv(text: &str) -> Result<Vec<OffsetRow>, OffsetsError> {
+    let mut rows = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || (index == 0 && line == CSV_HEADER) {
+            continue;
+        }
+        let row = move || -> Option<OffsetRow> {
+            // Paths may contain commas, numbers can't.
+            let mut fields = line.rsplitn(5, ',');
+            let (y, x) = (fields.next()?.trim(), fields.next()?.trim());
+            let (frame, direction) = (fields.next()?.trim(), fields.next()?.trim());
+            let path = fields.next()?.trim();
+            fn optional<T: std::str::FromStr>(field: &str) -> Option<Option<T>> {
+                match field {
+                    "" => Some(None),
+                    field => field.parse().ok().map(Some),
+                }
+            }
+            Some(OffsetRow {
+                path: path.to_owned(),
+                direction: optional(direction)?,
+                frame: optional(frame)?,
+                x: x.parse().ok()?,
+                y: y.parse().ok()?,
+            })
+        };
+        rows.push(row().ok_or(OffsetsError::Csv(index + 1))?);
+    }
+    Ok(rows)
+}
+
+fn patch_frm(path: &str, data: &[u8], rows: &[&OffsetRow]) -> Result<Vec<u8>, OffsetsError> {
+    let mut header =
+        frm::parse_header(data).map_err(parse_err(path))?;
+    for row in rows {
+        let direction = row
+            .direction
+            .and_then(|dir| header.directions.get_mut(dir as usize));
+        let offset = direction.and_then(|direction| match row.frame {
+            None => Some((&mut direction.shift_x, &mut direction.shift_y)),
+            Some(frame) => direction
+                .frames
+                .get_mut(frame as usize)
+                .map(|frame| (&mut frame.offset_x, &mut frame.offset_y)),
+        });
+        let (x, y) = offset.ok_or_else(|| no_such_offset(row))?;
+        *x = row.x;
+        *y = row.y;
+    }
+    let mut patched = data.to_vec();
+    frm::patch_offsets(&mut patched, &header).map_err(parse_err(path))?;
+    Ok(patched)
+}
+
+fn patch_fofrm(path: &str, data: &[u8], rows: &[&OffsetRow]) -> Result<Vec<u8>, OffsetsError> {
+    let text = std::str::from_utf8(data).map_err(parse_err(path))?;
+    let mut fofrm =
+        fofrm::parse_verbose(text).map_err(parse_err(path))?;
+    let mut changed = false;
+    for row in rows {
+        let offset = match (row.direction, row.frame) {
+            (None, None) => Some((&mut fofrm.offset_x, &mut fofrm.offset_y)),
+            (None, Some(_)) => None,
+            (Some(dir), frame) => fofrm.directions.get_mut(dir as usize).and_then(|direction| {
+                match frame {
+                    None => Some((&mut direction.offset_x, &mut direction.offset_y)),
+                    Some(frame) => direction
+                        .frames
+                        .get_mut(frame as usize)
+                        .map(|frame| (&mut frame.next_x, &mut frame.next_y)),
+                }
+            }),
+        };
+        let (x, y) = offset.ok_or_else(|| no_such_offset(row))?;
+        // Unset offsets are kept unset unless they're changed.
+        for (offset, value) in [(x, row.x), (y, row.y)] {
+            if offset.unwrap_or(0) != value {
+                *offset = Some(value);
+                changed = true;
+            }
+        }
+    }
+    Ok(match changed {
+        true => fofrm.to_text().into_bytes(),
+        false => data.to_vec(),
+    })
+}
+
+fn no_such_offset(row: &OffsetRow) -> OffsetsError {
+    OffsetsError::NoSuchOffset {
+        path: row.path.clone(),
+        direction: row.direction,
+        frame: row.frame,
+    }
+}
+
+/// Writes offsets of `rows` into files of data folders and returns paths of changed files,
+/// sorted. Rows may cover only some offsets of a file, the rest is kept. All files are
+/// read and patched before anything is written, so a bad row changes nothing.
+/// A dry run writes nothing and returns the same paths.
+pub fn apply_offsets(
+    registry: &FoRegistry,
+    rows: &[OffsetRow],
+    mode: Mode,
+) -> Result<Vec<String>, OffsetsError> {
+    let rules = registry.path_rules();
+    let mut by_path: BTreeMap<String, Vec<&OffsetRow>> = BTreeMap::new();
+    for row in rows {
+        by_path.entry(rules.normalize(&row.path)).or_default().push(row);
+    }
+    let io_err = |file: &Path| {
+        let file = file.to_owned();
+        move |err| OffsetsError::Io(file, err)
+    };
+    let mut writes = Vec::new();
+    for (path, rows) in &by_path {
+        let info = registry
+            .file_info(path)
+            .ok_or_else(|| OffsetsError::NotFound(path.clone()))?;
+        let (_, file) =
+            local_file(registry, info).ok_or_else(|| OffsetsError::NotLocal(path.clone()))?;
+        let data = std::fs::read(&file).map_err(io_err(&file))?;
+        let patched = match info.file_type() {
+            FileType::Frm => patch_frm(path, &data, rows)?,
+            FileType::FoFrm => patch_fofrm(path, &data, rows)?,
+            _ => return Err(OffsetsError::Unsupported(path.clone())),
+        };
+        if patched != data {
+            writes.push((path.clone(), file, patched));
+        }
+    }
+    if !mode.is_dry_run() {
+        for (_, file, data) in &writes {
+            std::fs::write(file, data).map_err(io_err(file))?;
+        }
+    }
+    Ok(writes.into_iter().map(|(path, _, _)| path).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{frm::FrameHeader, testing::FoFrmFixture};
+
+    #[test]
+    fn offsets_roundtrip() {
+        let frame = FrameHeader {
+            width: 1,
+            height: 1,
+            offset_x: 1,
+            offset_y: 2,
+        };
+        let frm = crate::testing::frm_fixture(10, (3, 4), &[frame, frame], 1);
+        let fofrm = FoFrmFixture::new()
+            .fps(5)
+            .frame("a.frm", (1, 2))
+            .direction()
+            .frame("a.frm", (0, 0))
+            .build();
+        let data = crate::testing::TempData::new(&[
+            ("art/a.frm", &frm),
+            ("art/b.fofrm", fofrm.as_bytes()),
+            ("art/broken.fofrm", b"offs_x=a"),
+        ]);
+        let root = data.root();
+        let retriever = data.retriever();
+        let (rows, failed) = export_offsets(&retriever);
+        assert_eq!(failed.len(), 1);
+        let csv = to_csv(&rows);
+        assert_eq!(
+            csv,
+            "path,direction,frame,x,y\n\
+             art/a.frm,0,,3,4\n\
+             art/a.frm,0,0,1,2\n\
+             art/a.frm,0,1,1,2\n\
+             art/b.fofrm,,,0,0\n\
+             art/b.fofrm,0,,0,0\n\
+             art/b.fofrm,0,0,1,2\n\
+             art/b.fofrm,1,,0,0\n\
+             art/b.fofrm,1,0,0,0\n"
+        );
+        assert_eq!(parse_csv(&csv).unwrap(), rows);
+        assert!(matches!(parse_csv("art/a.frm,0,x,1,2"), Err(OffsetsError::Csv(1))));
+
+        let registry = retriever.registry();
+        assert_eq!(apply_offsets(registry, &rows, Mode::Apply).unwrap(), Vec::<String>::new());
+        let edits = parse_csv("Art/A.frm,0,1,-5,6\nart/b.fofrm,1,,7,0\n").unwrap();
+        let planned = apply_offsets(registry, &edits, Mode::DryRun).unwrap();
+        assert_eq!(planned, ["art/a.frm", "art/b.fofrm"]);
+        assert_eq!(std::fs::read(root.join("art/a.frm")).unwrap(), frm);
+        assert_eq!(apply_offsets(registry, &edits, Mode::Apply).unwrap(), planned);
+        let frm = std::fs::read(root.join("art/a.frm")).unwrap();
+        assert_eq!(rows_of("art/a.frm", &frm).unwrap()[2].x, -5);
+        let fofrm = std::fs::read(root.join("art/b.fofrm")).unwrap();
+        let text = std::str::from_utf8(&fofrm).unwrap();
+        assert_eq!(
+            text,
+            "fps=5\nfrm_0=a.frm\nnext_x_0=1\nnext_y_0=2\n\
+             [dir_1]\nfrm_0=a.frm\nnext_x_0=0\nnext_y_0=0\noffs_x=7\n"
+        );
+        let bad = parse_csv("art/a.frm,1,,0,0").unwrap();
+        assert!(matches!(
+            apply_offsets(registry, &bad, Mode::Apply),
+            Err(OffsetsError::NoSuchOffset { .. })
+        ));
+    }
+
+    #[test]
+    fn unsupported_and_missing_files() {
+        assert!(parse_csv("").unwrap().is_empty());
+        assert!(parse_csv(&format!("{}\n\n", CSV_HEADER)).unwrap().is_empty());
+        assert!(matches!(parse_csv("\nart/a.frm,0,0,1"), Err(OffsetsError::Csv(2))));
+        assert!(matches!(rows_of("art/a.png", b""), Err(OffsetsError::Unsupported(_))));
+        assert!(matches!(rows_of("art/a.frm", &[0; 10]), Err(OffsetsError::Parse(..))));
+        assert!(matches!(rows_of("art/a.fofrm", &[0xff]), Err(OffsetsError::Parse(..))));
+
+        let mut registry = FoRegistry::stub();
+        let path = "art/packed.frm";
+        let info = crate::FileInfo {
+            location: crate::FileLocation::Archive {
+                archive: 0,
+                entry: 0,
+            },
+            original_path: path.to_owned(),
+            compressed_size: 0,
+            uncompressed_size: 0,
+            file_type: FileType::Frm,
+        };
+        registry.insert_file(path.to_owned(), info);
+        let rows = parse_csv("art/missing.frm,0,,1,1").unwrap();
+        assert!(matches!(
+            apply_offsets(&registry, &rows, Mode::Apply),
+            Err(OffsetsError::NotFound(path)) if path == "art/missing.frm"
+        ));
+        let rows = parse_csv("art/packed.frm,0,,1,1").unwrap();
+        assert!(matches!(
+            apply_offsets(&registry, &rows, Mode::DryRun),
+            Err(OffsetsError::NotLocal(_))
+        ));
+        assert!(apply_offsets(&registry, &[], Mode::Apply).unwrap().is_empty());
+    }
+}
@@ -2,7 +2,7 @@ use nom::number::complete::be_u8;
 use nom_prelude::*;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Palette {
     pub colors: Vec<Color>,
 }
@@ -32,6 +32,68 @@ impl Palette {
     pub fn colors_multiply_f32(&self, val: f32) -> Palette {
         self.map_colors(|color| (color as f32 * val).round().clamp(0.0, 255.0) as u8)
     }
+
+    pub fn rgba_lut(&self) -> RgbaLut {
+        self.rgba_lut_multiplied(1)
+    }
+
+    /// Lookup table of colors multiplied by `val`, as [`Palette::colors_multiply`] without cloning palette.
+    pub fn rgba_lut_multiplied(&self, val: u8) -> RgbaLut {
+        let mut lut = [[0, 0, 0, 255]; 256];
+        for (entry, color) in lut.iter_mut().zip(&self.colors) {
+            *entry = [
+                color.red.saturating_mul(val),
+                color.green.saturating_mul(val),
+                color.blue.saturating_mul(val),
+                255,
+            ];
+        }
+        lut[0] = [0, 0, 0, 0];
+        RgbaLut(lut)
+    }
+}
+
+/// Palette expanded to RGBA, index 0 is transparent.
+#[derive(Debug, Clone)]
+pub struct RgbaLut([[u8; 4]; 256]);
+
+impl RgbaLut {
+    pub fn get(&self, index: u8) -> [u8; 4] {
+        self.0[index as usize]
+    }
+
+    /// Appends RGBA pixels of palette indices to `out`.
+    pub fn expand_into(&self, indices: &[u8], out: &mut Vec<u8>) {
+        out.reserve(indices.len() * 4);
+        for &index in indices {
+            out.extend_from_slice(&self.0[index as usize]);
+        }
+    }
+
+    /// Opaque color closest to `rgb` and squared distance to it, index 0 is never returned.
+    pub fn nearest(&self, [red, green, blue]: [u8; 3]) -> (u8, u32) {
+        let distance = |[r, g, b, _]: [u8; 4]| {
+            let channel = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+            channel(r, red) + channel(g, green) + channel(b, blue)
+        };
+        (1..=255)
+            .map(|index| (index, distance(self.0[index as usize])))
+            .min_by_key(|&(_, distance)| distance)
+            .unwrap_or((1, u32::MAX))
+    }
+
+    /// `None` if there are less than `width * height` indices.
+    pub fn expand_image(
+        &self,
+        width: u32,
+        height: u32,
+        indices: &[u8],
+    ) -> Option<image::RgbaImage> {
+        let indices = indices.get(..width as usize * height as usize)?;
+        let mut data = Vec::new();
+        self.expand_into(indices, &mut data);
+        image::RgbaImage::from_raw(width, height, data)
+    }
 }
 
 #[derive(Debug)]
@@ -69,7 +131,7 @@ fn parse_palette<'a, Error: ParseError<&'a [u8]>>(
 }
 
 #[repr(C)]
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Color {
     pub red: u8,
     pub green: u8,
@@ -126,4 +188,32 @@ mod tests {
         let tuples = colors.colors_tuples();
         assert_eq!(tuples, &[(10, 20, 30), (40, 50, 60), (70, 80, 90),]);
     }
+
+    #[test]
+    fn test_rgba_lut() {
+        let palette = Palette {
+            colors: vec![
+                Color {
+                    red: 1,
+                    green: 2,
+                    blue: 3,
+                },
+                Color {
+                    red: 4,
+                    green: 5,
+                    blue: 70,
+                },
+            ],
+        };
+        let lut = palette.rgba_lut_multiplied(4);
+        assert_eq!(lut.get(0), [0, 0, 0, 0]);
+        assert_eq!(lut.get(1), [16, 20, 255, 255]);
+        assert_eq!(lut.get(2), [0, 0, 0, 255]);
+
+        let image = palette.rgba_lut().expand_image(2, 1, &[1, 0, 1]).unwrap();
+        assert_eq!(image.into_raw(), [4, 5, 70, 255, 0, 0, 0, 0]);
+        assert!(palette.rgba_lut().expand_image(2, 2, &[1, 0, 1]).is_none());
+        assert_eq!(palette.rgba_lut().nearest([4, 5, 69]), (1, 1));
+        assert_eq!(palette.rgba_lut().nearest([0, 0, 0]), (2, 0));
+    }
 }
@@ -0,0 +1,42 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+pub type PasswordCallback = dyn Fn(&Path) -> Option<Vec<u8>> + Send + Sync;
+
+/// Passwords of encrypted (ZipCrypto or AES) archives.
+/// Explicit per-archive passwords take precedence over the callback.
+#[derive(Clone, Default)]
+pub struct Passwords {
+    by_archive: BTreeMap<PathBuf, Vec<u8>>,
+    callback: Option<Arc<PasswordCallback>>,
+}
+
+impl Passwords {
+    /// `archive` should be canonical, as archive paths from `DataFiles.cfg` are.
+    pub fn insert(&mut self, archive: PathBuf, password: Vec<u8>) {
+        self.by_archive.insert(archive, password);
+    }
+
+    pub fn set_callback(&mut self, callback: Arc<PasswordCallback>) {
+        self.callback = Some(callback);
+    }
+
+    pub fn get(&self, archive: &Path) -> Option<Vec<u8>> {
+        match self.by_archive.get(archive) {
+            Some(password) => Some(password.clone()),
+            None => self.callback.as_ref().and_then(|callback| callback(archive)),
+        }
+    }
+}
+
+impl std::fmt::Debug for Passwords {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Passwords")
+            .field("archives", &self.by_archive.keys().collect::<Vec<_>>())
+            .field("callback", &self.callback.is_some())
+            .finish()
+    }
+}
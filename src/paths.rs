@@ -0,0 +1,137 @@
+//! Conventional paths: the form paths are stored in the registry and looked up with.
+//!
+//! Default rules match `fformat_utils`, engine forks with other rules can set
+//! their own with [`FoRegistryBuilder::path_rules`](crate::FoRegistryBuilder::path_rules).
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CaseRule {
+    Lowercase,
+    Preserve,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UnicodeRule {
+    /// Only ASCII letters change case, other characters are kept as is.
+    Ascii,
+    /// Full Unicode lowercase mapping, can change length of the path.
+    Full,
+}
+
+/// Separator of conventional paths is always `/`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathRules {
+    /// Treat `\` as a separator too.
+    pub backslash_separator: bool,
+    pub case: CaseRule,
+    pub unicode: UnicodeRule,
+}
+
+impl PathRules {
+    /// Rules of the FOnline engine and `fformat_utils`.
+    pub const FONLINE: PathRules = PathRules {
+        backslash_separator: true,
+        case: CaseRule::Lowercase,
+        unicode: UnicodeRule::Ascii,
+    };
+
+    pub fn normalize(&self, path: &str) -> String {
+        let path = if self.backslash_separator {
+            path.replace('\\', "/")
+        } else {
+            path.to_owned()
+        };
+        match (self.case, self.unicode) {
+            (CaseRule::Preserve, _) => path,
+            (CaseRule::Lowercase, UnicodeRule::Ascii) => path.to_ascii_lowercase(),
+            (CaseRule::Lowercase, UnicodeRule::Full) => path.to_lowercase(),
+        }
+    }
+
+    /// Conventional path of `reference` made by a file at conventional path `base`.
+    ///
+    /// The reference is relative to the folder of `base`, or to the data root if it starts
+    /// with a separator. `.` segments are skipped and `..` go one folder up,
+    /// `None` if the reference escapes data root.
+    pub fn resolve_relative(&self, base: &str, reference: &str) -> Option<String> {
+        let is_separator = |c| c == '/' || (self.backslash_separator && c == '\\');
+        let mut segments: Vec<_> = if reference.starts_with(is_separator) {
+            Vec::new()
+        } else {
+            base.split('/').collect()
+        };
+        segments.pop();
+        for segment in reference.split(is_separator) {
+            match segment {
+                "" | "." => {}
+                ".." => {
+                    segments.pop()?;
+                }
+                segment => segments.push(segment),
+            }
+        }
+        Some(self.normalize(&segments.join("/")))
+    }
+}
+
+impl Default for PathRules {
+    fn default() -> Self {
+        Self::FONLINE
+    }
+}
+
+/// Conventional path with default rules, same as `fformat_utils::make_path_conventional`.
+pub fn make_path_conventional(path: &str) -> String {
+    PathRules::FONLINE.normalize(path)
+}
+
+/// Reference resolved with default rules, see [`PathRules::resolve_relative`].
+pub fn resolve_relative(base: &str, reference: &str) -> Option<String> {
+    PathRules::FONLINE.resolve_relative(base, reference)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configurable_rules() {
+        let path = "Art\\Critters/ÄHMJMPS.FRM";
+        assert_eq!(make_path_conventional(path), nom_prelude::make_path_conventional(path));
+        assert_eq!(make_path_conventional(path), "art/critters/Ähmjmps.frm");
+
+        let full = PathRules {
+            unicode: UnicodeRule::Full,
+            ..PathRules::FONLINE
+        };
+        assert_eq!(full.normalize(path), "art/critters/ähmjmps.frm");
+
+        let unix = PathRules {
+            backslash_separator: false,
+            case: CaseRule::Preserve,
+            ..PathRules::FONLINE
+        };
+        assert_eq!(unix.normalize(path), path);
+    }
+
+    #[test]
+    fn relative_references() {
+        let base = "art/critters/hmjmps.fofrm";
+        assert_eq!(resolve_relative(base, "HMJMPSAA.frm").unwrap(), "art/critters/hmjmpsaa.frm");
+        assert_eq!(resolve_relative(base, "./a/../b.png").unwrap(), "art/critters/b.png");
+        assert_eq!(resolve_relative(base, "..\\misc//b.png").unwrap(), "art/misc/b.png");
+        assert_eq!(resolve_relative(base, "/art/b.png").unwrap(), "art/b.png");
+        assert_eq!(resolve_relative(base, "\\b.png").unwrap(), "b.png");
+        assert_eq!(resolve_relative("b.fofrm", "c.png").unwrap(), "c.png");
+        assert_eq!(resolve_relative(base, "../../../b.png"), None);
+        assert_eq!(resolve_relative(base, "/../b.png"), None);
+
+        let unix = PathRules {
+            backslash_separator: false,
+            case: CaseRule::Preserve,
+            ..PathRules::FONLINE
+        };
+        assert_eq!(unix.resolve_relative(base, "..\\B.png").unwrap(), "art/critters/..\\B.png");
+    }
+}
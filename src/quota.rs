@@ -0,0 +1,166 @@
+//! Size budgets of folders, checked by content CI so the download doesn't grow unnoticed.
+//!
+//! Budgets are declared in a text file with `folder size` lines, e.g. `art/intrface 50MB`.
+//! Sizes are bytes or have a `KB`, `MB` or `GB` suffix, multiples of 1024.
+//! `#` starts a comment, `/` as a folder means the whole registry.
+
+use thiserror::Error;
+
+use crate::FoRegistry;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quota {
+    /// Conventional path of the folder, empty for the whole registry.
+    pub folder: String,
+    pub limit: u64,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum QuotaError {
+    #[error("line {0}: expected `folder size`")]
+    Syntax(usize),
+    #[error("line {0}: invalid size {1:?}")]
+    Size(usize, String),
+}
+
+/// Bytes of `42`, `42B`, `1.5MB` and alike, case insensitive.
+pub fn parse_size(text: &str) -> Option<u64> {
+    let text = text.trim().to_ascii_uppercase();
+    let (number, multiplier) = [("GB", 1 << 30), ("MB", 1 << 20), ("KB", 1 << 10), ("B", 1)]
+        .iter()
+        .find_map(|&(suffix, multiplier)| Some((text.strip_suffix(suffix)?, multiplier)))
+        .unwrap_or((&text, 1));
+    let number: f64 = number.trim().parse().ok()?;
+    if !number.is_finite() || number < 0.0 {
+        return None;
+    }
+    Some((number * multiplier as f64) as u64)
+}
+
+pub fn parse_quotas(text: &str) -> Result<Vec<Quota>, QuotaError> {
+    let mut quotas = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (folder, size) = line
+            .rsplit_once(char::is_whitespace)
+            .ok_or(QuotaError::Syntax(line_number))?;
+        let limit =
+            parse_size(size).ok_or_else(|| QuotaError::Size(line_number, size.to_owned()))?;
+        quotas.push(Quota {
+            folder: folder.trim().trim_matches(&['/', '\\'][..]).to_owned(),
+            limit,
+        });
+    }
+    Ok(quotas)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub folder: String,
+    pub limit: u64,
+    pub used: u64,
+    /// Biggest files of the folder with their sizes, biggest first.
+    pub offenders: Vec<(String, u64)>,
+}
+
+/// Folders over their budget, in the order of `quotas`. Sizes are archive bytes, what
+/// players download. Up to `offenders` biggest files are listed for every violation.
+pub fn check_quotas(registry: &FoRegistry, quotas: &[Quota], offenders: usize) -> Vec<Violation> {
+    let rules = registry.path_rules();
+    let mut violations = Vec::new();
+    for quota in quotas {
+        let folder = rules.normalize(&quota.folder);
+        let inside = |path: &str| match path.strip_prefix(folder.as_str()) {
+            Some(rest) => folder.is_empty() || rest.starts_with('/'),
+            None => false,
+        };
+        let mut files: Vec<_> = registry
+            .files()
+            .filter(|(path, _)| inside(path))
+            .map(|(path, info)| (path, info.compressed_size()))
+            .collect();
+        let used = files.iter().map(|(_, size)| size).sum();
+        if used <= quota.limit {
+            continue;
+        }
+        files.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        violations.push(Violation {
+            folder: quota.folder.clone(),
+            limit: quota.limit,
+            used,
+            offenders: files
+                .into_iter()
+                .take(offenders)
+                .map(|(path, size)| (path.to_owned(), size))
+                .collect(),
+        });
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotas() {
+        assert_eq!(parse_size("42"), Some(42));
+        assert_eq!(parse_size("1.5kb"), Some(1536));
+        assert_eq!(parse_size("50 MB"), Some(50 << 20));
+        assert_eq!(parse_size("-1"), None);
+        assert_eq!(parse_size("MB"), None);
+        assert_eq!(
+            parse_quotas("# budgets\nart/intrface/ 4B\n/ 1KB # total\n").unwrap(),
+            [
+                Quota {
+                    folder: "art/intrface".into(),
+                    limit: 4
+                },
+                Quota {
+                    folder: "".into(),
+                    limit: 1024
+                },
+            ]
+        );
+        assert_eq!(parse_quotas("art\n"), Err(QuotaError::Syntax(1)));
+        assert_eq!(parse_quotas("\nart 5XB"), Err(QuotaError::Size(2, "5XB".into())));
+
+        let data = crate::testing::TempData::new(&[
+            ("art/intrface/big.png", b"xxxx"),
+            ("art/intrface/small.png", b"x"),
+            ("art/intrface.png", b"xxxxxxxx"),
+        ]);
+        let registry = data.registry();
+        let quotas = parse_quotas("Art/Intrface 4\nart 1KB").unwrap();
+        let violations = check_quotas(&registry, &quotas, 1);
+        assert_eq!(
+            violations,
+            [Violation {
+                folder: "Art/Intrface".into(),
+                limit: 4,
+                used: 5,
+                offenders: vec![("art/intrface/big.png".into(), 4)],
+            }]
+        );
+    }
+
+    #[test]
+    fn empty_budgets_and_registry() {
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("inf"), None);
+        assert_eq!(parse_size("NaN MB"), None);
+        assert_eq!(parse_size("0b"), Some(0));
+        assert_eq!(parse_quotas("").unwrap(), []);
+        assert_eq!(parse_quotas("# nothing\n   \n").unwrap(), []);
+        assert_eq!(parse_quotas("art 1 # 2MB\n").unwrap()[0].limit, 1);
+
+        let registry = FoRegistry::stub();
+        let quotas = parse_quotas("/ 0\nart 0").unwrap();
+        assert_eq!(check_quotas(&registry, &quotas, 10), []);
+        assert_eq!(check_quotas(&registry, &[], 10), []);
+    }
+}
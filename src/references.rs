@@ -0,0 +1,273 @@
+//! Art referenced by data files of different formats, as conventional paths.
+
+use thiserror::Error;
+
+use crate::{fofrm, fomap, intrface, msg, paths::PathRules};
+
+pub trait ReferencesArt {
+    /// Conventional paths of referenced art, sorted and deduplicated.
+    /// `path` is the conventional path of the referencing file itself, for relative references.
+    fn art_references(&self, path: &str) -> Vec<String>;
+}
+
+#[derive(Debug, Error)]
+pub enum DepsError<E> {
+    #[error("can't retrieve file: {0}")]
+    Retrieve(E),
+    #[error("can't parse {0:?}: {1}")]
+    Parse(String, String),
+}
+
+/// Same as [`paths::resolve_relative`](crate::paths::resolve_relative).
+pub fn resolve_relative(base: &str, relative: &str) -> Option<String> {
+    crate::paths::resolve_relative(base, relative)
+}
+
+/// Same as [`PathRules::resolve_relative`].
+pub fn resolve_relative_with(base: &str, relative: &str, rules: &PathRules) -> Option<String> {
+    rules.resolve_relative(base, relative)
+}
+
+/// File the engine loads when `path` is missing: `.fr<direction>` for `.frm`, or `.frm` for
+/// `.frN` if `direction` is 0. Returned with the direction inside of that file.
+pub fn split_frm_counterpart(path: &str, direction: usize) -> Option<(String, usize)> {
+    let (stem, _) = path.rsplit_once('.')?;
+    match extension(path).as_str() {
+        "frm" if direction < crate::critters::DIRECTIONS as usize => {
+            Some((format!("{}.fr{}", stem, direction), 0))
+        }
+        ext => {
+            let split = ext.strip_prefix("fr")?.parse::<usize>().ok()?;
+            if split < crate::critters::DIRECTIONS as usize && direction == 0 {
+                Some((format!("{}.frm", stem), split))
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// Rule a reference was resolved by, for diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReferenceRule {
+    /// Relative to the folder of the referencing file.
+    ParentRelative,
+    /// Relative to the data root.
+    RootRelative,
+}
+
+/// Paths `relative` may refer to from `base` in lookup order: relative to the folder of `base`,
+/// then to the data root. References with a leading slash are only root-relative.
+pub fn reference_candidates(
+    base: &str,
+    relative: &str,
+    rules: &PathRules,
+) -> Vec<(String, ReferenceRule)> {
+    let rooted = relative.starts_with(&['/', '\\'][..]);
+    let parent = Some(base)
+        .filter(|_| !rooted)
+        .and_then(|base| rules.resolve_relative(base, relative))
+        .map(|path| (path, ReferenceRule::ParentRelative));
+    let root = rules
+        .resolve_relative("", relative)
+        .filter(|root| parent.as_ref().map(|(parent, _)| parent) != Some(root))
+        .map(|path| (path, ReferenceRule::RootRelative));
+    parent.into_iter().chain(root).collect()
+}
+
+fn sorted(mut paths: Vec<String>) -> Vec<String> {
+    paths.sort_unstable();
+    paths.dedup();
+    paths
+}
+
+impl ReferencesArt for fofrm::FoFrmRaw<'_> {
+    fn art_references(&self, path: &str) -> Vec<String> {
+        let frames = self
+            .directions
+            .iter()
+            .flat_map(|direction| &direction.frames);
+        sorted(
+            frames
+                .filter_map(|frame| resolve_relative(path, frame.frm?))
+                .collect(),
+        )
+    }
+}
+
+impl ReferencesArt for fomap::Fomap<'_> {
+    fn art_references(&self, _path: &str) -> Vec<String> {
+        /// Object keys that override art of the proto.
+        const ART_KEYS: &[&str] = &["PicMapName", "PicInvName"];
+
+        let tiles = self.tiles.iter().map(|tile| tile.name);
+        let objects = self.objects.iter().flat_map(|object| {
+            object
+                .extra
+                .iter()
+                .filter(|(key, _)| ART_KEYS.contains(key))
+                .map(|(_, value)| *value)
+        });
+        sorted(
+            tiles
+                .chain(objects)
+                .map(nom_prelude::make_path_conventional)
+                .collect(),
+        )
+    }
+}
+
+impl ReferencesArt for [intrface::IniReference<'_>] {
+    fn art_references(&self, _path: &str) -> Vec<String> {
+        sorted(self.iter().map(intrface::IniReference::path).collect())
+    }
+}
+
+impl ReferencesArt for msg::Msg<'_> {
+    fn art_references(&self, _path: &str) -> Vec<String> {
+        sorted(
+            self.art_names()
+                .into_iter()
+                .map(nom_prelude::make_path_conventional)
+                .collect(),
+        )
+    }
+}
+
+/// Art of items, critters and scenery in `key=value` lines of a server proto file.
+fn proto_art_references(text: &str) -> Vec<String> {
+    const ART_KEYS: &[&str] = &["PicMap", "PicInv"];
+
+    sorted(
+        text.lines()
+            .filter_map(|line| line.split_once('='))
+            .filter(|(key, _)| ART_KEYS.contains(&key.trim()))
+            .map(|(_, value)| value.trim())
+            .filter(|value| !value.is_empty())
+            .map(nom_prelude::make_path_conventional)
+            .collect(),
+    )
+}
+
+/// Whether `path` or one of its split counterparts exists, as the engine falls back to them,
+/// see [`split_frm_counterpart`].
+pub fn exists_with_counterparts(path: &str, exists: impl Fn(&str) -> bool) -> bool {
+    exists(path)
+        || (0..crate::critters::DIRECTIONS as usize).any(|direction| {
+            matches!(
+                split_frm_counterpart(path, direction),
+                Some((counterpart, _)) if exists(&counterpart)
+            )
+        })
+}
+
+const REFERENCING_EXTENSIONS: &[&str] = &["fofrm", "fomap", "fopro", "ini", "msg"];
+
+fn extension(path: &str) -> String {
+    path.rsplit_once('.')
+        .map_or("", |(_, ext)| ext)
+        .to_ascii_lowercase()
+}
+
+/// Whether files of this format may reference art.
+pub fn can_reference(path: &str) -> bool {
+    REFERENCING_EXTENSIONS.contains(&extension(path).as_str())
+}
+
+/// Parses text of a referencing file by its extension, files of other formats have no references.
+pub fn art_references_of(path: &str, text: &str) -> Result<Vec<String>, String> {
+    Ok(match extension(path).as_str() {
+        "fofrm" => fofrm::parse_verbose(text)
+            .map_err(|err| format!("{:?}", err))?
+            .art_references(path),
+        "fomap" => fomap::parse_fomap(text)
+            .map_err(|err| err.to_string())?
+            .art_references(path),
+        "fopro" => proto_art_references(text),
+        "ini" => intrface::parse_references(text).art_references(path),
+        "msg" => msg::Msg::parse(text)
+            .map_err(|err| err.to_string())?
+            .art_references(path),
+        _ => Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_paths() {
+        assert_eq!(
+            resolve_relative("art/critters/hmjmps.fofrm", "HMJMPSAA.frm").as_deref(),
+            Some("art/critters/hmjmpsaa.frm")
+        );
+        assert_eq!(
+            resolve_relative("art/critters/a.fofrm", "..\\misc/./b.png").as_deref(),
+            Some("art/misc/b.png")
+        );
+        assert_eq!(resolve_relative("art/a.fofrm", "../../b.png"), None);
+    }
+
+    #[test]
+    fn candidates_of_references() {
+        let rules = PathRules::FONLINE;
+        let candidates = |relative| reference_candidates("art/critters/a.fofrm", relative, &rules);
+        assert_eq!(
+            candidates("art/items/B.png"),
+            [
+                ("art/critters/art/items/b.png".into(), ReferenceRule::ParentRelative),
+                ("art/items/b.png".into(), ReferenceRule::RootRelative),
+            ]
+        );
+        assert_eq!(
+            candidates("\\art\\items\\b.png"),
+            [("art/items/b.png".into(), ReferenceRule::RootRelative)]
+        );
+        assert_eq!(
+            candidates("../b.png"),
+            [("art/b.png".into(), ReferenceRule::ParentRelative)]
+        );
+    }
+
+    #[test]
+    fn split_frm_counterparts() {
+        let counterpart = |path, direction| split_frm_counterpart(path, direction);
+        assert_eq!(counterpart("art/hmjmpsaa.frm", 2), Some(("art/hmjmpsaa.fr2".into(), 0)));
+        assert_eq!(counterpart("art/hmjmpsaa.fr4", 0), Some(("art/hmjmpsaa.frm".into(), 4)));
+        assert_eq!(counterpart("art/hmjmpsaa.fr4", 1), None);
+        assert_eq!(counterpart("art/hmjmpsaa.frm", 6), None);
+        assert_eq!(counterpart("art/hmjmpsaa.fr6", 0), None);
+        assert_eq!(counterpart("art/hmjmpsaa.png", 0), None);
+    }
+
+    #[test]
+    fn references_by_extension() {
+        let fofrm = "fps=10\ncount=2\n[dir_0]\nfrm_0=a.png\nfrm_1=../b.png\n";
+        assert_eq!(
+            art_references_of("art/scenery/x.fofrm", fofrm).unwrap(),
+            ["art/b.png", "art/scenery/a.png"]
+        );
+        assert_eq!(
+            art_references_of("data/game.msg", "{1}{}{art/Splash/x.png}\n{2}{}{text}").unwrap(),
+            ["art/splash/x.png"]
+        );
+        assert_eq!(
+            art_references_of("default.ini", "[Inv]\nMain = inv.frm\n").unwrap(),
+            ["art/intrface/inv.frm"]
+        );
+        assert_eq!(
+            art_references_of("proto/items/a.fopro", "PicMap=art\\items\\A.frm\nPicInv=\n")
+                .unwrap(),
+            ["art/items/a.frm"]
+        );
+        assert!(art_references_of("data/bad.msg", "{1}{}").is_err());
+        assert!(
+            art_references_of("data/readme.txt", "art/x.png")
+                .unwrap()
+                .is_empty()
+        );
+        assert!(can_reference("Art/X.FOFRM"));
+        assert!(!can_reference("art/x.frm"));
+    }
+}
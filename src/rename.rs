@@ -0,0 +1,382 @@
+//! Renaming of files in data folders, with textual references to them rewritten across
+//! fofrm frames, fomap tiles and objects, protos, interface layouts and msg files.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::{intrface::INTRFACE_DIR, paths::PathRules, FileInfo, FileLocation, FoRegistry, Mode};
+
+/// How references of a format are resolved.
+#[derive(Debug, Clone, Copy)]
+enum Style {
+    /// Relative to the folder of the referencing file.
+    Parent,
+    /// Relative to the data root.
+    Root,
+    /// Relative to [`INTRFACE_DIR`].
+    Intrface,
+}
+
+impl Style {
+    fn of(path: &str) -> Option<Style> {
+        let (_, ext) = path.rsplit_once('.')?;
+        Some(match ext.to_ascii_lowercase().as_str() {
+            "fofrm" => Style::Parent,
+            "fomap" | "fopro" | "msg" => Style::Root,
+            "ini" => Style::Intrface,
+            _ => return None,
+        })
+    }
+
+    fn resolve(self, base: &str, token: &str, rules: &PathRules) -> Option<String> {
+        match self {
+            Style::Parent => rules.resolve_relative(base, token),
+            Style::Root => Some(rules.normalize(token)),
+            Style::Intrface => Some(rules.normalize(&format!("{}/{}", INTRFACE_DIR, token))),
+        }
+    }
+
+    /// Reference to `target` from `base`, with separators of the replaced `token`.
+    /// `None` if the format can't refer to `target`.
+    fn reference(self, base: &str, target: &str, token: &str) -> Option<String> {
+        let reference = match self {
+            Style::Parent => relative_path(base, target),
+            Style::Root => target.to_owned(),
+            Style::Intrface => target.strip_prefix(INTRFACE_DIR)?.strip_prefix('/')?.to_owned(),
+        };
+        Some(if token.contains('\\') {
+            reference.replace('/', "\\")
+        } else {
+            reference
+        })
+    }
+}
+
+/// Path of `target` relative to the folder of `base`.
+fn relative_path(base: &str, target: &str) -> String {
+    let mut folder: Vec<_> = base.split('/').collect();
+    folder.pop();
+    let target: Vec<_> = target.split('/').collect();
+    let common = folder
+        .iter()
+        .zip(&target[..target.len() - 1])
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut parts = vec![".."; folder.len() - common];
+    parts.extend(&target[common..]);
+    parts.join("/")
+}
+
+fn is_delimiter(byte: u8) -> bool {
+    byte.is_ascii_whitespace() || b"={},;\"'".contains(&byte)
+}
+
+/// Replaces tokens for which `replace` returns a new value, it's called with 1-based line
+/// number of the token. Works on bytes, so text in any encoding is kept as is.
+fn rewrite_tokens(
+    data: &[u8],
+    mut replace: impl FnMut(usize, &str) -> Option<String>,
+) -> (Vec<u8>, Vec<(usize, String, String)>) {
+    let mut rewritten = Vec::with_capacity(data.len());
+    let mut changes = Vec::new();
+    let mut line = 1;
+    let mut pos = 0;
+    while pos < data.len() {
+        if is_delimiter(data[pos]) {
+            if data[pos] == b'\n' {
+                line += 1;
+            }
+            rewritten.push(data[pos]);
+            pos += 1;
+            continue;
+        }
+        let end = data[pos..]
+            .iter()
+            .position(|&byte| is_delimiter(byte))
+            .map_or(data.len(), |len| pos + len);
+        let token = &data[pos..end];
+        let new = std::str::from_utf8(token)
+            .ok()
+            .filter(|token| token.contains('.'))
+            .and_then(|token| Some((token, replace(line, token)?)));
+        match new {
+            Some((old, new)) => {
+                rewritten.extend_from_slice(new.as_bytes());
+                changes.push((line, old.to_owned(), new));
+            }
+            None => rewritten.extend_from_slice(token),
+        }
+        pos = end;
+    }
+    (rewritten, changes)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RenameOptions {
+    /// A dry run leaves files and the registry untouched.
+    pub mode: Mode,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rewrite {
+    /// Conventional path of the referencing file, after the rename.
+    pub path: String,
+    /// 1-based.
+    pub line: usize,
+    pub old: String,
+    pub new: String,
+}
+
+/// Sorted by path. In a dry run lists what would be done.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RenameReport {
+    pub rewrites: Vec<Rewrite>,
+    /// References that the format of the file can't express at the new path, left as is,
+    /// `new` is empty.
+    pub unrewritable: Vec<Rewrite>,
+    /// Referencing files inside archives, they're neither checked nor rewritten.
+    pub unchecked: Vec<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum RenameError {
+    #[error("{0:?} is not in the registry")]
+    NotFound(String),
+    #[error("{0:?} is already in the registry")]
+    Exists(String),
+    #[error("{0:?} is inside an archive, only files of data folders can be renamed")]
+    NotLocal(String),
+    #[error("{0:?} is outside of the folder the data folder is mounted in")]
+    OutsideMount(String),
+    #[error("can't access {0:?}: {1}")]
+    Io(PathBuf, io::Error),
+}
+
+/// Data folder of a file and the file inside of it.
+pub(crate) fn local_file(registry: &FoRegistry, info: &FileInfo) -> Option<(PathBuf, PathBuf)> {
+    match info.location {
+        FileLocation::Local(index) => {
+            let folder = registry.archives.get(index as usize)?.path.clone();
+            let file = folder.join(&info.original_path);
+            Some((folder, file))
+        }
+        FileLocation::Archive { .. } | FileLocation::Tar { .. } | FileLocation::Dat { .. } => None,
+    }
+}
+
+fn io_err(path: &Path) -> impl FnOnce(io::Error) -> RenameError + '_ {
+    move |err| RenameError::Io(path.to_owned(), err)
+}
+
+/// Renames `from` to `to` in its data folder and rewrites references to it in every
+/// referencing file of data folders. References of the renamed file itself are rewritten
+/// too if they're relative to its folder.
+///
+/// All files are read before anything is written, so errors of reading change nothing.
+pub fn rename(
+    registry: &mut FoRegistry,
+    from: &str,
+    to: &str,
+    options: &RenameOptions,
+) -> Result<RenameReport, RenameError> {
+    let rules = *registry.path_rules();
+    let (from, to) = (rules.normalize(from), rules.normalize(to));
+    let info = registry
+        .file_info(&from)
+        .ok_or_else(|| RenameError::NotFound(from.clone()))?
+        .clone();
+    if registry.file_info(&to).is_some() {
+        return Err(RenameError::Exists(to));
+    }
+    let (folder, old_file) =
+        local_file(registry, &info).ok_or_else(|| RenameError::NotLocal(from.clone()))?;
+    let mount = registry.archives[info.location.archive_index() as usize].mount();
+    let original_path = match mount {
+        Some(mount) => to
+            .strip_prefix(mount)
+            .and_then(|path| path.strip_prefix('/'))
+            .ok_or_else(|| RenameError::OutsideMount(to.clone()))?,
+        None => to.as_str(),
+    }
+    .to_owned();
+    let new_file = folder.join(&original_path);
+
+    let mut report = RenameReport::default();
+    let mut writes = Vec::new();
+    for (path, file_info) in registry.files() {
+        let style = match Style::of(path) {
+            Some(style) => style,
+            None => continue,
+        };
+        let file = match local_file(registry, file_info) {
+            Some((_, file)) => file,
+            None => {
+                report.unchecked.push(path.to_owned());
+                continue;
+            }
+        };
+        let moved = path == from;
+        let new_path = if moved { to.as_str() } else { path };
+        let data = std::fs::read(&file).map_err(io_err(&file))?;
+        let mut unrewritable = Vec::new();
+        let (rewritten, changes) = rewrite_tokens(&data, |line, token| {
+            let target = style.resolve(path, token, &rules)?;
+            let new_target = if target == from {
+                to.clone()
+            } else if moved && style.resolve(new_path, token, &rules).as_ref() != Some(&target) {
+                registry.file_info(&target)?;
+                target
+            } else {
+                return None;
+            };
+            let reference = style.reference(new_path, &new_target, token);
+            if reference.is_none() {
+                unrewritable.push((line, token.to_owned(), String::new()));
+            }
+            reference
+        });
+        let rewrite = |(line, old, new)| Rewrite {
+            path: new_path.to_owned(),
+            line,
+            old,
+            new,
+        };
+        report.rewrites.extend(changes.into_iter().map(rewrite));
+        report
+            .unrewritable
+            .extend(unrewritable.into_iter().map(rewrite));
+        if rewritten != data {
+            writes.push((if moved { new_file.clone() } else { file }, rewritten));
+        }
+    }
+    report.rewrites.sort_by(|a, b| (&a.path, a.line).cmp(&(&b.path, b.line)));
+    report
+        .unrewritable
+        .sort_by(|a, b| (&a.path, a.line).cmp(&(&b.path, b.line)));
+    if options.mode.is_dry_run() {
+        return Ok(report);
+    }
+
+    if let Some(parent) = new_file.parent() {
+        std::fs::create_dir_all(parent).map_err(io_err(parent))?;
+    }
+    std::fs::rename(&old_file, &new_file).map_err(io_err(&old_file))?;
+    registry.remove_file(&from);
+    registry.insert_file(
+        to,
+        FileInfo {
+            file_type: crate::retriever::recognize_type(&original_path),
+            original_path,
+            ..info
+        },
+    );
+    for (file, data) in writes {
+        std::fs::write(&file, data).map_err(io_err(&file))?;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempData;
+
+    #[test]
+    fn rename_and_rewrite() {
+        let contents: &[(&str, &[u8])] = &[
+            ("art/items/a.frm", b"frm"),
+            ("art/items/anim.fofrm", b"fps=10\r\nfrm_0=a.frm\r\nfrm_1=..\\misc\\b.png\r\n"),
+            ("art/misc/b.png", b"png"),
+            ("art/intrface/default.ini", b"[Inv]\nMain = inv.frm\n"),
+            ("maps/m.fomap", b"[Tiles]\ntile 1 2 art\\items\\A.FRM\n"),
+            ("text/game.msg", b"{1}{}{art/items/a.frm}\n"),
+        ];
+        let data = TempData::new(contents);
+        let root = data.root();
+        let mut registry = data.registry();
+        let read = |path: &str| std::fs::read_to_string(root.join(path)).unwrap();
+
+        let dry_run = RenameOptions::default();
+        let planned = rename(&mut registry, "art/items/a.frm", "art/scenery/x.frm", &dry_run);
+        let planned = planned.unwrap();
+        let rewrites: Vec<_> = planned
+            .rewrites
+            .iter()
+            .map(|rewrite| (rewrite.path.as_str(), rewrite.line, rewrite.new.as_str()))
+            .collect();
+        assert_eq!(
+            rewrites,
+            [
+                ("art/items/anim.fofrm", 2, "../scenery/x.frm"),
+                ("maps/m.fomap", 2, "art\\scenery\\x.frm"),
+                ("text/game.msg", 1, "art/scenery/x.frm"),
+            ]
+        );
+        assert!(registry.file_info("art/items/a.frm").is_some());
+        assert_eq!(read("maps/m.fomap").as_bytes(), contents[4].1);
+
+        let options = RenameOptions { mode: Mode::Apply };
+        let report = rename(&mut registry, "art/items/a.frm", "art/scenery/x.frm", &options);
+        assert_eq!(report.unwrap(), planned);
+        assert!(registry.file_info("art/items/a.frm").is_none());
+        assert_eq!(read("art/scenery/x.frm"), "frm");
+        assert_eq!(read("maps/m.fomap"), "[Tiles]\ntile 1 2 art\\scenery\\x.frm\n");
+
+        let report = rename(&mut registry, "art/items/anim.fofrm", "art/anim.fofrm", &options);
+        assert_eq!(report.unwrap().rewrites.len(), 2);
+        assert_eq!(
+            read("art/anim.fofrm"),
+            "fps=10\r\nfrm_0=scenery/x.frm\r\nfrm_1=misc\\b.png\r\n"
+        );
+
+        let report = rename(&mut registry, "art/misc/b.png", "art/b.png", &options).unwrap();
+        assert_eq!(report.rewrites[0].new, "b.png");
+        assert!(matches!(
+            rename(&mut registry, "art/missing.png", "art/c.png", &options),
+            Err(RenameError::NotFound(_))
+        ));
+        assert!(matches!(
+            rename(&mut registry, "art/b.png", "art/anim.fofrm", &options),
+            Err(RenameError::Exists(_))
+        ));
+    }
+
+    #[test]
+    fn mounted_packed_and_missing_files() {
+        let data = TempData::new(&[("items/a.frm", b"frm")]);
+        let archive = crate::testing::archive(data.root(), Some("art"));
+        let mut registry = crate::testing::registry(vec![archive]);
+        let packed = FileInfo {
+            location: FileLocation::Archive {
+                archive: 0,
+                entry: 0,
+            },
+            original_path: "art/packed.frm".into(),
+            compressed_size: 0,
+            uncompressed_size: 0,
+            file_type: crate::FileType::Frm,
+        };
+        registry.insert_file("art/packed.frm".into(), packed);
+        let options = RenameOptions { mode: Mode::Apply };
+
+        assert!(matches!(
+            rename(&mut registry, "art/items/a.frm", "maps/a.frm", &options),
+            Err(RenameError::OutsideMount(path)) if path == "maps/a.frm"
+        ));
+        assert!(matches!(
+            rename(&mut registry, "art/packed.frm", "art/b.frm", &options),
+            Err(RenameError::NotLocal(_))
+        ));
+        std::fs::remove_file(data.root().join("items/a.frm")).unwrap();
+        assert!(matches!(
+            rename(&mut registry, "art/items/a.frm", "art/b.frm", &options),
+            Err(RenameError::Io(..))
+        ));
+        assert!(registry.file_info("art/items/a.frm").is_some());
+        assert!(registry.file_info("art/b.frm").is_none());
+    }
+}
@@ -0,0 +1,120 @@
+//! Which archive supplies a file and which ones it hides, for mod managers
+//! explaining the load order to users.
+
+use std::path::Path;
+
+use crate::{FileInfo, FoRegistry};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source<'a> {
+    /// Archive or data folder, `None` if the registry has no archive with this index.
+    pub archive: Option<&'a Path>,
+    /// Path as stored in the archive, before making it conventional.
+    pub original_path: &'a str,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+}
+
+impl<'a> Source<'a> {
+    fn new(registry: &'a FoRegistry, info: &'a FileInfo) -> Self {
+        Source {
+            archive: info.location(registry).map(AsRef::as_ref),
+            original_path: &info.original_path,
+            compressed_size: info.compressed_size,
+            uncompressed_size: info.uncompressed_size,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resolution<'a> {
+    pub winner: Source<'a>,
+    /// Hidden sources, the next in priority goes first.
+    pub shadowed: Vec<Source<'a>>,
+}
+
+impl FoRegistry {
+    /// Source of the file and sources it shadows, `path` must be conventional.
+    pub fn resolve(&self, path: &str) -> Option<Resolution<'_>> {
+        let winner = Source::new(self, self.file_info(path)?);
+        let shadowed = self
+            .shadowed
+            .get(path)
+            .into_iter()
+            .flatten()
+            .rev()
+            .map(|info| Source::new(self, info))
+            .collect();
+        Some(Resolution { winner, shadowed })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        crawler::{self, Limits, Precedence},
+        paths::PathRules,
+        testing::{self, TempData},
+    };
+
+    #[test]
+    fn resolve_load_order() {
+        let data = TempData::new(&[
+            ("base/art/Tile.frm", b"base"),
+            ("base/art/only.frm", b"base"),
+            ("mod1/art/Tile.frm", b"mod1"),
+            ("mod2/art/Tile.frm", b"mod2"),
+        ]);
+        let root = data.root();
+        let archives: Vec<_> = ["base", "mod1", "mod2"]
+            .iter()
+            .map(|folder| testing::archive(root.join(folder), None))
+            .collect();
+        let (files, report) =
+            crawler::gather_paths_reported(&archives, &Limits::unlimited(), &PathRules::FONLINE)
+                .unwrap();
+        let registry = FoRegistry {
+            archives,
+            files: Arc::new(files),
+            shadowed: Arc::new(report.shadowed),
+            ..FoRegistry::stub()
+        };
+
+        let resolution = registry.resolve("art/tile.frm").unwrap();
+        assert_eq!(resolution.winner.archive, Some(root.join("mod2").as_path()));
+        assert_eq!(resolution.winner.original_path, "art/Tile.frm");
+        let shadowed: Vec<_> = resolution
+            .shadowed
+            .iter()
+            .map(|source| source.archive.unwrap().to_owned())
+            .collect();
+        assert_eq!(shadowed, [root.join("mod1"), root.join("base")]);
+
+        assert!(registry.resolve("art/only.frm").unwrap().shadowed.is_empty());
+        assert!(registry.resolve("art/missing.frm").is_none());
+
+        let (files, report) = crawler::gather_paths_with_precedence(
+            &registry.archives,
+            &Limits::unlimited(),
+            &PathRules::FONLINE,
+            Precedence::FirstWins,
+        )
+        .unwrap();
+        let registry = FoRegistry {
+            files: Arc::new(files),
+            shadowed: Arc::new(report.shadowed),
+            ..registry
+        };
+        let resolution = registry.resolve("art/tile.frm").unwrap();
+        assert_eq!(resolution.winner.archive, Some(root.join("base").as_path()));
+        let shadowed: Vec<_> = resolution
+            .shadowed
+            .iter()
+            .map(|source| source.archive.unwrap().to_owned())
+            .collect();
+        assert_eq!(shadowed, [root.join("mod1"), root.join("mod2")]);
+    }
+}
@@ -1,22 +1,126 @@
+#[cfg(feature = "cas-retriever")]
+pub mod cas;
+pub mod chain;
 pub mod fo;
+#[cfg(any(feature = "sled-retriever", feature = "redb-retriever"))]
+pub mod kv;
+pub mod memory;
+#[cfg(feature = "redb-retriever")]
+pub mod redb;
 #[cfg(feature = "sled-retriever")]
 pub mod sled;
 
-use std::path::Path;
+use std::{
+    io::{Read, Seek},
+    path::Path,
+};
 
-use crate::FileType;
+use bytes::Bytes;
+
+use crate::{paths::PathRules, references::DepsError, text::TextEncoding, FileType};
 
 pub trait Retriever {
     type Error;
     fn file_by_path(&self, path: &str) -> Result<Vec<u8>, Self::Error>;
+
+    /// Contents as shared bytes, the converter and servers keep slices of them cheaply.
+    /// The default wraps [`Retriever::file_by_path`] without copying, retrievers that
+    /// already hold files in memory hand out their buffers.
+    fn file_bytes_by_path(&self, path: &str) -> Result<Bytes, Self::Error> {
+        self.file_by_path(path).map(Bytes::from)
+    }
+
+    /// Reader of the file, for sending big sounds and videos without buffering them.
+    /// The default reads the whole file into memory, retrievers that can stream override it.
+    fn stream_by_path(&self, path: &str) -> Result<FileStream<'_>, Self::Error> {
+        Ok(self.file_by_path(path)?.into())
+    }
+
+    /// Path with the [`name_hash`](crate::hash::name_hash), `None` if it's unknown
+    /// or retriever doesn't index hashes.
+    fn path_by_hash(&self, _hash: u32) -> Option<&str> {
+        None
+    }
+
+    /// Rules of conventional paths this retriever expects.
+    fn path_rules(&self) -> &PathRules {
+        &PathRules::FONLINE
+    }
+
+    /// Cheap value that changes whenever contents of the file may have changed,
+    /// stable between runs. `None` if the retriever can't tell, then nothing is cached.
+    fn source_stamp(&self, _path: &str) -> Option<u64> {
+        None
+    }
 }
 
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek + ?Sized> ReadSeek for T {}
+
+/// Contents of a file returned by [`Retriever::stream_by_path`].
+pub enum FileStream<'a> {
+    /// Local files and files already in memory.
+    Seekable(Box<dyn ReadSeek + Send + 'a>),
+    /// Compressed archive entries, readable only front to back.
+    Sequential(Box<dyn Read + Send + 'a>),
+}
+
+impl<'a> FileStream<'a> {
+    pub fn seekable(&mut self) -> Option<&mut (dyn ReadSeek + Send + 'a)> {
+        match self {
+            FileStream::Seekable(reader) => Some(&mut **reader),
+            FileStream::Sequential(_) => None,
+        }
+    }
+}
+
+impl Read for FileStream<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            FileStream::Seekable(reader) => reader.read(buf),
+            FileStream::Sequential(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl From<Vec<u8>> for FileStream<'_> {
+    fn from(data: Vec<u8>) -> Self {
+        FileStream::Seekable(Box::new(std::io::Cursor::new(data)))
+    }
+}
+
+/// Helpers available for every [`Retriever`].
+pub trait RetrieverExt: Retriever {
+    /// Text file with detected encoding, see [`TextEncoding::Auto`].
+    fn text_by_path(&self, path: &str) -> Result<String, Self::Error> {
+        self.text_by_path_with(path, TextEncoding::Auto)
+    }
+
+    fn text_by_path_with(&self, path: &str, encoding: TextEncoding) -> Result<String, Self::Error> {
+        let bytes = self.file_by_path(path)?;
+        Ok(crate::text::decode_text(&bytes, encoding))
+    }
+
+    /// Conventional paths of art referenced by the file, see [`crate::references::ReferencesArt`].
+    fn get_deps(&self, path: &str) -> Result<Vec<String>, DepsError<Self::Error>> {
+        if !crate::references::can_reference(path) {
+            return Ok(Vec::new());
+        }
+        let text = self.text_by_path(path).map_err(DepsError::Retrieve)?;
+        crate::references::art_references_of(path, &text)
+            .map_err(|err| DepsError::Parse(path.to_owned(), err))
+    }
+}
+
+impl<R: Retriever + ?Sized> RetrieverExt for R {}
+
 pub fn recognize_type(path: &str) -> FileType {
     move || -> Option<_> {
         let ext = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
         Some(match ext.as_str() {
             "png" => FileType::Png,
-            "frm" => FileType::Frm,
+            "frm" | "fr0" | "fr1" | "fr2" | "fr3" | "fr4" | "fr5" => FileType::Frm,
             "gif" => FileType::Gif,
             "fofrm" => FileType::FoFrm,
             _ => FileType::Unsupported(ext),
@@ -0,0 +1,187 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::{PathError, PathMap};
+
+pub type Hash = [u8; 32];
+
+const INDEX_FILE: &str = "index.bin";
+const OBJECTS_DIR: &str = "objects";
+
+/// Content-addressed storage: every file is stored once under its blake3 hash,
+/// paths are mapped to hashes by an index.
+///
+/// Layout: `<root>/index.bin` and `<root>/objects/<first 2 hex digits>/<rest of hex digits>`.
+pub struct CasRetriever {
+    root: PathBuf,
+    index: PathMap<String, Hash>,
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("path not found")]
+    NotFound,
+    #[error("can't read index {0:?}: {1}")]
+    ReadIndex(PathBuf, std::io::Error),
+    #[error("can't write index {0:?}: {1}")]
+    WriteIndex(PathBuf, std::io::Error),
+    #[error("index serialization error: {0}")]
+    Index(bincode::Error),
+    #[error("can't read object {0:?}: {1}")]
+    ReadObject(PathBuf, std::io::Error),
+    #[error("can't write object {0:?}: {1}")]
+    WriteObject(PathBuf, std::io::Error),
+    #[error("object {0:?} is corrupted")]
+    Corrupted(PathBuf),
+    #[error("source retriever error: {0}")]
+    Source(crate::retriever::fo::Error),
+}
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+fn object_path(root: &Path, hash: &Hash) -> PathBuf {
+    let hex = blake3::Hash::from(*hash).to_hex();
+    root.join(OBJECTS_DIR).join(&hex[..2]).join(&hex[2..])
+}
+
+impl CasRetriever {
+    pub fn open(root: impl AsRef<Path>) -> Result<Self> {
+        let root = root.as_ref().to_owned();
+        let index_path = root.join(INDEX_FILE);
+        let file = std::fs::File::open(&index_path).path_err(&index_path, Error::ReadIndex)?;
+        let index = bincode::deserialize_from(std::io::BufReader::new(file)).map_err(Error::Index)?;
+        Ok(Self { root, index })
+    }
+
+    pub fn hash_by_path(&self, path: &str) -> Option<&Hash> {
+        self.index.get(path)
+    }
+
+    pub fn file_by_hash(&self, hash: &Hash) -> Result<Vec<u8>> {
+        let path = object_path(&self.root, hash);
+        let data = std::fs::read(&path).path_err(&path, Error::ReadObject)?;
+        if blake3::hash(&data).as_bytes() != hash {
+            return Err(Error::Corrupted(path));
+        }
+        Ok(data)
+    }
+
+    pub fn paths(&self) -> impl ExactSizeIterator<Item = (&str, &Hash)> {
+        self.index.iter().map(|(path, hash)| (path.as_str(), hash))
+    }
+}
+
+impl super::Retriever for CasRetriever {
+    type Error = Error;
+
+    fn file_by_path(&self, path: &str) -> Result<Vec<u8>, Self::Error> {
+        let hash = self.index.get(path).ok_or(Error::NotFound)?;
+        self.file_by_hash(hash)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct IngestStats {
+    pub files: usize,
+    /// Objects written by this ingest, files with already stored content are not counted.
+    pub new_objects: usize,
+    pub new_bytes: u64,
+}
+
+/// Copies every file of the registry behind `source` into the storage at `root`,
+/// merging with an existing index if there is one.
+pub fn ingest(source: &super::fo::FoRetriever, root: impl AsRef<Path>) -> Result<IngestStats> {
+    let root = root.as_ref();
+    let mut index = match CasRetriever::open(root) {
+        Ok(existing) => existing.index,
+        Err(Error::ReadIndex(_, err)) if err.kind() == std::io::ErrorKind::NotFound => {
+            PathMap::new()
+        }
+        Err(err) => return Err(err),
+    };
+
+    let mut stats = IngestStats::default();
+    for (path, file_info) in source.registry().files() {
+        let data = source.file_by_info(file_info).map_err(Error::Source)?;
+        let hash = *blake3::hash(&data).as_bytes();
+        let object = object_path(root, &hash);
+        if !object.exists() {
+            write_atomically(&object, &data).path_err(&object, Error::WriteObject)?;
+            stats.new_objects += 1;
+            stats.new_bytes += data.len() as u64;
+        }
+        index.insert(path.to_owned(), hash);
+        stats.files += 1;
+    }
+
+    let index_path = root.join(INDEX_FILE);
+    let index = bincode::serialize(&index).map_err(Error::Index)?;
+    write_atomically(&index_path, &index).path_err(&index_path, Error::WriteIndex)?;
+    Ok(stats)
+}
+
+fn write_atomically(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temp = path.with_extension("tmp");
+    let mut file = std::fs::File::create(&temp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    std::fs::rename(temp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::TempData, Retriever};
+
+    #[test]
+    fn read_and_verify_objects() {
+        let root = std::env::temp_dir().join("fo_data_test_cas");
+        let _ = std::fs::remove_dir_all(&root);
+        let data = b"same content";
+        let hash = *blake3::hash(data).as_bytes();
+        write_atomically(&object_path(&root, &hash), data).unwrap();
+        let mut index = PathMap::new();
+        index.insert("art/a.frm".to_owned(), hash);
+        index.insert("art/b.frm".to_owned(), hash);
+        write_atomically(&root.join(INDEX_FILE), &bincode::serialize(&index).unwrap()).unwrap();
+
+        let retriever = CasRetriever::open(&root).unwrap();
+        assert_eq!(retriever.file_by_path("art/a.frm").unwrap(), data);
+        assert_eq!(retriever.file_by_path("art/b.frm").unwrap(), data);
+        assert!(matches!(retriever.file_by_path("art/c.frm"), Err(Error::NotFound)));
+
+        std::fs::write(object_path(&root, &hash), b"other content").unwrap();
+        assert!(matches!(retriever.file_by_path("art/a.frm"), Err(Error::Corrupted(_))));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn ingest_deduplicates() {
+        let data = TempData::new(&[("art/a.frm", b"same content"), ("art/b.frm", b"same content")]);
+        let storage = TempData::new(&[]);
+        let source = data.retriever();
+        let stats = ingest(&source, storage.root()).unwrap();
+        assert_eq!((stats.files, stats.new_objects, stats.new_bytes), (2, 1, 12));
+
+        let objects: Vec<_> = std::fs::read_dir(storage.root().join(OBJECTS_DIR))
+            .unwrap()
+            .flat_map(|dir| std::fs::read_dir(dir.unwrap().path()).unwrap())
+            .collect();
+        assert_eq!(objects.len(), 1);
+        let retriever = CasRetriever::open(storage.root()).unwrap();
+        assert_eq!(retriever.paths().len(), 2);
+        let hash = retriever.hash_by_path("art/a.frm").unwrap();
+        assert_eq!(retriever.hash_by_path("art/b.frm"), Some(hash));
+        assert_eq!(retriever.file_by_path("art/b.frm").unwrap(), b"same content");
+
+        // content already in the storage isn't written again
+        let stats = ingest(&source, storage.root()).unwrap();
+        assert_eq!((stats.files, stats.new_objects, stats.new_bytes), (2, 0, 0));
+    }
+}
@@ -0,0 +1,190 @@
+//! Layering of two retrievers, e.g. a local mod folder over the client registry.
+//!
+//! Any error of the queried retriever falls through to the other one, so both must
+//! expect the same conventional paths. The chain reports an error only if neither
+//! has the file, then it has the errors of both.
+
+use bytes::Bytes;
+use thiserror::Error;
+
+use super::{FileStream, Retriever};
+use crate::paths::PathRules;
+
+/// Which retriever of a [`ChainRetriever`] is queried first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Shadowing {
+    /// Files of the top retriever hide files of the base one, as mods override the client.
+    #[default]
+    TopFirst,
+    /// The top retriever only adds files the base one doesn't have.
+    BaseFirst,
+}
+
+#[derive(Debug, Error)]
+#[error("not in top retriever: {top}; not in base retriever: {base}")]
+pub struct ChainError<A, B> {
+    pub top: A,
+    pub base: B,
+}
+
+pub struct ChainRetriever<A, B> {
+    top: A,
+    base: B,
+    shadowing: Shadowing,
+}
+
+impl<A: Retriever, B: Retriever> ChainRetriever<A, B> {
+    /// `top` shadows `base`, chains nest to layer more retrievers.
+    pub fn new(top: A, base: B) -> Self {
+        Self {
+            top,
+            base,
+            shadowing: Default::default(),
+        }
+    }
+
+    pub fn with_shadowing(mut self, shadowing: Shadowing) -> Self {
+        self.shadowing = shadowing;
+        self
+    }
+
+    pub fn top(&self) -> &A {
+        &self.top
+    }
+
+    pub fn base(&self) -> &B {
+        &self.base
+    }
+
+    fn query<'s, T>(
+        &'s self,
+        top: impl FnOnce(&'s A) -> Result<T, A::Error>,
+        base: impl FnOnce(&'s B) -> Result<T, B::Error>,
+    ) -> Result<T, ChainError<A::Error, B::Error>> {
+        match self.shadowing {
+            Shadowing::TopFirst => top(&self.top)
+                .or_else(|top| base(&self.base).map_err(|base| ChainError { top, base })),
+            Shadowing::BaseFirst => base(&self.base)
+                .or_else(|base| top(&self.top).map_err(|top| ChainError { top, base })),
+        }
+    }
+}
+
+impl<A: Retriever, B: Retriever> Retriever for ChainRetriever<A, B> {
+    type Error = ChainError<A::Error, B::Error>;
+
+    fn file_by_path(&self, path: &str) -> Result<Vec<u8>, Self::Error> {
+        self.query(|top| top.file_by_path(path), |base| base.file_by_path(path))
+    }
+
+    fn file_bytes_by_path(&self, path: &str) -> Result<Bytes, Self::Error> {
+        self.query(
+            |top| top.file_bytes_by_path(path),
+            |base| base.file_bytes_by_path(path),
+        )
+    }
+
+    fn stream_by_path(&self, path: &str) -> Result<FileStream<'_>, Self::Error> {
+        self.query(
+            |top| top.stream_by_path(path),
+            |base| base.stream_by_path(path),
+        )
+    }
+
+    fn path_by_hash(&self, hash: u32) -> Option<&str> {
+        match self.shadowing {
+            Shadowing::TopFirst => self
+                .top
+                .path_by_hash(hash)
+                .or_else(|| self.base.path_by_hash(hash)),
+            Shadowing::BaseFirst => self
+                .base
+                .path_by_hash(hash)
+                .or_else(|| self.top.path_by_hash(hash)),
+        }
+    }
+
+    /// Rules of the base retriever.
+    fn path_rules(&self) -> &PathRules {
+        self.base.path_rules()
+    }
+
+    /// Stamp of the retriever queried first. Without it the chain can't tell which layer
+    /// serves the file, so files missing from that retriever have no stamp either.
+    fn source_stamp(&self, path: &str) -> Option<u64> {
+        match self.shadowing {
+            Shadowing::TopFirst => self.top.source_stamp(path),
+            Shadowing::BaseFirst => self.base.source_stamp(path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryRetriever;
+
+    #[test]
+    fn layered_files() {
+        let client = MemoryRetriever::new()
+            .with_file("art/a.frm", &b"client"[..])
+            .with_file("art/b.frm", &b"client"[..]);
+        let mod_folder = MemoryRetriever::new()
+            .with_file("Art\\A.frm", &b"mod"[..])
+            .with_file("art/c.frm", &b"mod"[..]);
+        let chain = ChainRetriever::new(mod_folder, client);
+        assert_eq!(chain.file_by_path("art/a.frm").unwrap(), b"mod");
+        assert_eq!(chain.file_by_path("art/b.frm").unwrap(), b"client");
+        assert_eq!(chain.file_bytes_by_path("art/c.frm").unwrap(), &b"mod"[..]);
+        let hash = crate::hash::name_hash("art/b.frm");
+        assert_eq!(chain.path_by_hash(hash), Some("art/b.frm"));
+        assert_eq!(
+            chain.source_stamp("art/a.frm"),
+            chain.top().source_stamp("art/a.frm")
+        );
+        let err = chain.file_by_path("art/d.frm").unwrap_err();
+        assert!(err.to_string().contains("not in base retriever"));
+
+        let chain = chain.with_shadowing(Shadowing::BaseFirst);
+        assert_eq!(chain.file_by_path("art/a.frm").unwrap(), b"client");
+        assert_eq!(chain.file_by_path("art/c.frm").unwrap(), b"mod");
+        let mut stream = chain.stream_by_path("art/b.frm").unwrap();
+        let mut data = Vec::new();
+        std::io::Read::read_to_end(&mut stream, &mut data).unwrap();
+        assert_eq!(data, b"client");
+    }
+
+    /// Retriever with the default stamp, like CAS, sled and redb ones.
+    struct Unstamped(MemoryRetriever);
+
+    impl Retriever for Unstamped {
+        type Error = <MemoryRetriever as Retriever>::Error;
+
+        fn file_by_path(&self, path: &str) -> Result<Vec<u8>, Self::Error> {
+            self.0.file_by_path(path)
+        }
+
+        fn path_by_hash(&self, hash: u32) -> Option<&str> {
+            self.0.path_by_hash(hash)
+        }
+    }
+
+    #[test]
+    fn unstamped_layer() {
+        let client = MemoryRetriever::new()
+            .with_file("art/a.frm", &b"client"[..])
+            .with_file("art/b.frm", &b"client"[..]);
+        let top = Unstamped(MemoryRetriever::new().with_file("art/a.frm", &b"mod"[..]));
+        let chain = ChainRetriever::new(top, client);
+        // served from the top layer, the base stamp would survive changes of the mod file
+        assert!(chain.base().source_stamp("art/a.frm").is_some());
+        assert_eq!(chain.source_stamp("art/a.frm"), None);
+        assert_eq!(chain.source_stamp("art/b.frm"), None);
+
+        let chain = chain.with_shadowing(Shadowing::BaseFirst);
+        assert_eq!(
+            chain.source_stamp("art/b.frm"),
+            chain.base().source_stamp("art/b.frm")
+        );
+    }
+}
@@ -1,9 +1,17 @@
-use std::{sync::Arc, path::PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc},
+};
 
 use parking_lot::{MappedMutexGuard as Guard, Mutex, MutexGuard};
 use thiserror::Error;
 
-use crate::{FileLocation, FoRegistry, PathError};
+use super::FileStream;
+use crate::{
+    budget::Evict, dat::DatVersion, metrics::measure, ArchiveKind, FileLocation, FoRegistry,
+    MemoryBudget, Metrics, Operation, PathError,
+};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -15,27 +23,411 @@ pub enum Error {
     OpenArchive(PathBuf, std::io::Error),
     #[error("zip err: {0}")]
     Zip(zip::result::ZipError),
-    #[error("unsupporte file location")]
-    UnsupportedFileLocation,
     #[error("archive io error: {0}")]
     ArchiveRead(std::io::Error),
+    #[error("can't read local file {0:?}: {1}")]
+    LocalRead(PathBuf, std::io::Error),
+    #[error("archive kind doesn't match file location")]
+    ArchiveKindMismatch,
+    #[error("can't unpack tarball {0:?}: {1}")]
+    UnpackTar(PathBuf, std::io::Error),
+    #[error("invalid archive password")]
+    InvalidPassword,
+    #[error("file is too large: {0} bytes, limit is {1} bytes")]
+    FileTooLarge(u64, u64),
 }
 
+/// Default limit for files read into memory, bigger files are still available through
+/// [`FoRetriever::write_file_by_info`].
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 256 * 1024 * 1024;
+/// Declared sizes from archive metadata are not trusted for preallocation beyond this.
+pub(crate) const MAX_PREALLOCATION: u64 = 16 * 1024 * 1024;
+/// Default limit for decompressed files waiting to be read after [`FoRetriever::prefetch`].
+pub const DEFAULT_MAX_PREFETCHED_SIZE: u64 = 64 * 1024 * 1024;
+
 type Archive = zip::ZipArchive<std::io::BufReader<std::fs::File>>;
 
+/// How entry data is stored in an archive, for entries streamed straight from the file.
+enum Packing {
+    Stored,
+    Deflate,
+    Zlib,
+}
+
+enum OpenArchive {
+    Zip {
+        zip: Archive,
+        password: Option<Vec<u8>>,
+    },
+    Tar {
+        file: std::fs::File,
+        _unpacked: Option<UnpackedTar>,
+    },
+    Dat {
+        file: std::fs::File,
+        version: DatVersion,
+    },
+}
+
+impl OpenArchive {
+    fn zip_entry(&mut self, index: u32) -> Result<zip::read::ZipFile<'_>, Error> {
+        match self {
+            OpenArchive::Zip {
+                zip,
+                password: Some(password),
+            } => zip
+                .by_index_decrypt(index as usize, password)
+                .map_err(Error::Zip)?
+                .map_err(|_| Error::InvalidPassword),
+            OpenArchive::Zip {
+                zip,
+                password: None,
+            } => zip.by_index(index as usize).map_err(Error::Zip),
+            _ => Err(Error::ArchiveKindMismatch),
+        }
+    }
+
+    /// Position, stored size and packing of the data of a zip entry, `None` if it's
+    /// encrypted or compressed with something other than deflate.
+    fn zip_range(&mut self, index: u32) -> Result<Option<(u64, u64, Packing)>, Error> {
+        match self {
+            OpenArchive::Zip {
+                password: Some(_), ..
+            } => Ok(None),
+            OpenArchive::Zip {
+                zip,
+                password: None,
+            } => {
+                let file = match zip.by_index(index as usize) {
+                    Ok(file) => file,
+                    Err(zip::result::ZipError::UnsupportedArchive(_)) => return Ok(None),
+                    Err(err) => return Err(Error::Zip(err)),
+                };
+                let packing = match file.compression() {
+                    zip::CompressionMethod::Stored => Packing::Stored,
+                    zip::CompressionMethod::Deflated => Packing::Deflate,
+                    _ => return Ok(None),
+                };
+                Ok(Some((file.data_start(), file.compressed_size(), packing)))
+            }
+            _ => Err(Error::ArchiveKindMismatch),
+        }
+    }
+
+    fn tar_entry(&mut self, offset: u64, size: u64) -> Result<std::io::Take<&mut std::fs::File>, Error> {
+        use std::io::{Read, Seek, SeekFrom};
+
+        match self {
+            OpenArchive::Tar { file, .. } => {
+                file.seek(SeekFrom::Start(offset))
+                    .map_err(Error::ArchiveRead)?;
+                Ok(file.take(size))
+            }
+            _ => Err(Error::ArchiveKindMismatch),
+        }
+    }
+
+    /// Reader of the uncompressed data of a DAT entry, `stored_size` is the size stored in
+    /// the archive. LZSS entries of DAT1 are decompressed into memory right away.
+    fn dat_entry(
+        &mut self,
+        offset: u64,
+        stored_size: u64,
+        size: u64,
+        packed: bool,
+    ) -> Result<Box<dyn std::io::Read + '_>, Error> {
+        use std::io::{Read, Seek, SeekFrom};
+
+        match self {
+            OpenArchive::Dat { file, version } => {
+                file.seek(SeekFrom::Start(offset))
+                    .map_err(Error::ArchiveRead)?;
+                let mut stored = std::io::BufReader::new(file.take(stored_size));
+                Ok(match (packed, *version) {
+                    (false, _) => Box::new(stored),
+                    (true, DatVersion::Dat2) => Box::new(flate2::read::ZlibDecoder::new(stored)),
+                    (true, DatVersion::Dat1) => {
+                        let mut packed = Vec::new();
+                        stored.read_to_end(&mut packed).map_err(Error::ArchiveRead)?;
+                        let data = crate::dat::decompress_lzss(&packed, size as usize)
+                            .map_err(Error::ArchiveRead)?;
+                        Box::new(std::io::Cursor::new(data))
+                    }
+                })
+            }
+            _ => Err(Error::ArchiveKindMismatch),
+        }
+    }
+}
+
+/// Temporary uncompressed copy of a tarball, removed on drop.
+struct UnpackedTar {
+    path: PathBuf,
+}
+
+impl UnpackedTar {
+    fn unpack(tar_gz: &Path) -> Result<(Self, std::fs::File), Error> {
+        use std::{
+            io::{BufReader, Seek, SeekFrom},
+            sync::atomic::{AtomicUsize, Ordering},
+        };
+
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "fo_data_{}_{}.tar",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let unpacked = UnpackedTar { path };
+
+        let source = std::fs::File::open(tar_gz).path_err(tar_gz, Error::OpenArchive)?;
+        let mut decoder = flate2::read::GzDecoder::new(BufReader::new(source));
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&unpacked.path)
+            .path_err(&unpacked.path, Error::UnpackTar)?;
+        std::io::copy(&mut decoder, &mut file).path_err(tar_gz, Error::UnpackTar)?;
+        file.seek(SeekFrom::Start(0))
+            .path_err(&unpacked.path, Error::UnpackTar)?;
+        Ok((unpacked, file))
+    }
+}
+
+impl Drop for UnpackedTar {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrefetchMode {
+    /// Only open archives of the files, reading central directories and unpacking tarballs.
+    OpenArchives,
+    /// Also read files into memory, each is handed out once by the next read of its path.
+    Decompress,
+}
+
+/// Completion of a background [`FoRetriever::prefetch`].
+pub struct PrefetchHandle {
+    done: mpsc::Receiver<usize>,
+}
+
+impl PrefetchHandle {
+    /// Blocks until prefetch is finished, returns the number of files it warmed up.
+    pub fn wait(self) -> usize {
+        self.done.recv().unwrap_or(0)
+    }
+}
+
+#[derive(Default)]
+struct Prefetched {
+    files: HashMap<String, Vec<u8>>,
+    size: u64,
+}
+
+impl Evict for Mutex<Prefetched> {
+    fn name(&self) -> &str {
+        "prefetched files"
+    }
+
+    fn usage(&self) -> u64 {
+        self.lock().size
+    }
+
+    fn evict(&self, bytes: u64) -> u64 {
+        let mut prefetched = self.lock();
+        let mut freed = 0;
+        while freed < bytes {
+            let path = match prefetched.files.keys().next() {
+                Some(path) => path.clone(),
+                None => break,
+            };
+            let data = prefetched.files.remove(&path).expect("Key was just found");
+            freed += data.len() as u64;
+        }
+        prefetched.size -= freed;
+        freed
+    }
+}
+
+/// Cheap to clone, clones share open archives and prefetched files.
+#[derive(Clone)]
 pub struct FoRetriever {
-    archives: Vec<Mutex<Option<Box<Archive>>>>,
+    archives: Arc<Vec<Mutex<Option<Box<OpenArchive>>>>>,
     data: Arc<FoRegistry>,
+    max_file_size: Option<u64>,
+    prefetched: Arc<Mutex<Prefetched>>,
+    max_prefetched_size: u64,
+    budget: Option<MemoryBudget>,
+    metrics: Option<Metrics>,
+    #[cfg(feature = "tracing")]
+    slow_threshold: Option<std::time::Duration>,
 }
 
 impl FoRetriever {
     pub fn new(data: Arc<FoRegistry>) -> Self {
         let mut archives = Vec::new();
         archives.resize_with(data.archives.len(), Default::default);
-        Self { archives, data }
+        Self {
+            archives: Arc::new(archives),
+            data,
+            max_file_size: Some(DEFAULT_MAX_FILE_SIZE),
+            prefetched: Default::default(),
+            max_prefetched_size: DEFAULT_MAX_PREFETCHED_SIZE,
+            budget: None,
+            metrics: None,
+            #[cfg(feature = "tracing")]
+            slow_threshold: None,
+        }
+    }
+
+    /// Limit for files read into memory, `None` disables the check.
+    pub fn with_max_file_size(mut self, max_file_size: Option<u64>) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    /// Limit for decompressed files waiting to be read, files over it are not prefetched.
+    pub fn with_max_prefetched_size(mut self, max_prefetched_size: u64) -> Self {
+        self.max_prefetched_size = max_prefetched_size;
+        self
+    }
+
+    /// Accounts prefetched files in the shared budget, they are evicted when it's exceeded.
+    pub fn with_memory_budget(mut self, budget: &MemoryBudget) -> Self {
+        let cache: Arc<dyn Evict> = self.prefetched.clone();
+        budget.register(&cache);
+        self.budget = Some(budget.clone());
+        self
+    }
+
+    /// Counts archive opens, file reads and prefetch hits in `metrics`.
+    pub fn with_metrics(mut self, metrics: &Metrics) -> Self {
+        self.metrics = Some(metrics.clone());
+        self
+    }
+
+    /// Reads taking at least `threshold` are logged as `tracing` warnings,
+    /// with the path, archive and sizes of the file.
+    #[cfg(feature = "tracing")]
+    pub fn with_slow_threshold(mut self, threshold: std::time::Duration) -> Self {
+        self.slow_threshold = Some(threshold);
+        self
+    }
+
+    /// Hints that files are going to be read soon, warms them up on the rayon pool.
+    ///
+    /// Errors are ignored here, they are reported by the actual reads.
+    pub fn prefetch<I>(&self, paths: I, mode: PrefetchMode) -> PrefetchHandle
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        use rayon::prelude::*;
+
+        let paths: Vec<String> = paths.into_iter().map(Into::into).collect();
+        let retriever = self.clone();
+        let (sender, done) = mpsc::channel();
+        rayon::spawn(move || {
+            let warmed = paths
+                .par_iter()
+                .filter(|path| retriever.prefetch_one(path, mode))
+                .count();
+            let _ = sender.send(warmed);
+        });
+        PrefetchHandle { done }
     }
 
-    fn get_archive(&self, archive_index: usize) -> Result<Guard<Archive>, Error> {
+    fn prefetch_one(&self, path: &str, mode: PrefetchMode) -> bool {
+        let file_info = match self.data.file_info(path) {
+            Some(file_info) => file_info,
+            None => return false,
+        };
+        match file_info.location {
+            FileLocation::Archive { archive: index, .. }
+            | FileLocation::Tar { archive: index, .. }
+            | FileLocation::Dat { archive: index, .. } => {
+                if self.get_archive(index as usize).is_err() {
+                    return false;
+                }
+            }
+            FileLocation::Local(_) => {}
+        }
+        if mode == PrefetchMode::OpenArchives {
+            return true;
+        }
+        if self.prefetched.lock().files.contains_key(path) {
+            return true;
+        }
+        let data = match self.file_by_info(file_info) {
+            Ok(data) => data,
+            Err(_) => return false,
+        };
+        let size = data.len() as u64;
+        if let Some(budget) = &self.budget {
+            if !budget.make_room(size) {
+                return false;
+            }
+        }
+        let mut prefetched = self.prefetched.lock();
+        if prefetched.size + size > self.max_prefetched_size {
+            return false;
+        }
+        if prefetched.files.insert(path.to_owned(), data).is_none() {
+            prefetched.size += size;
+        }
+        true
+    }
+
+    fn take_prefetched(&self, path: &str) -> Option<Vec<u8>> {
+        let mut prefetched = self.prefetched.lock();
+        let data = prefetched.files.remove(path)?;
+        prefetched.size -= data.len() as u64;
+        if let Some(metrics) = &self.metrics {
+            metrics.record(Operation::PrefetchHit, data.len() as u64, Default::default());
+        }
+        Some(data)
+    }
+
+    fn check_size(&self, declared_size: u64) -> Result<(), Error> {
+        match self.max_file_size {
+            Some(max) if declared_size > max => Err(Error::FileTooLarge(declared_size, max)),
+            _ => Ok(()),
+        }
+    }
+
+    fn read_limited(
+        &self,
+        reader: impl std::io::Read,
+        declared_size: u64,
+        map_err: impl FnOnce(std::io::Error) -> Error,
+    ) -> Result<Vec<u8>, Error> {
+        use std::io::Read;
+
+        self.check_size(declared_size)?;
+        let mut buffer = Vec::with_capacity(declared_size.min(MAX_PREALLOCATION) as usize);
+        match self.max_file_size {
+            Some(max) => {
+                // read one byte past the limit to catch lying metadata
+                reader
+                    .take(max.saturating_add(1))
+                    .read_to_end(&mut buffer)
+                    .map_err(map_err)?;
+                if buffer.len() as u64 > max {
+                    return Err(Error::FileTooLarge(buffer.len() as u64, max));
+                }
+            }
+            None => {
+                let mut reader = reader;
+                reader.read_to_end(&mut buffer).map_err(map_err)?;
+            }
+        }
+        Ok(buffer)
+    }
+
+    fn get_archive(&self, archive_index: usize) -> Result<Guard<'_, OpenArchive>, Error> {
         use std::io::BufReader;
 
         let mut guard = self.archives[archive_index].lock();
@@ -46,51 +438,545 @@ impl FoRetriever {
                 .archives
                 .get(archive_index)
                 .ok_or(Error::InvalidArchiveIndex)?;
-            let archive_file = std::fs::File::open(&archive.path).path_err(&archive.path, Error::OpenArchive)?;
-            let archive_buf_reader = BufReader::with_capacity(1024, archive_file);
-            let archive = zip::ZipArchive::new(archive_buf_reader).map_err(Error::Zip)?;
-            *guard = Some(Box::new(archive));
+            let open = || {
+                Ok(match archive.kind() {
+                    ArchiveKind::Zip => {
+                        let archive_file = std::fs::File::open(&archive.path)
+                            .path_err(&archive.path, Error::OpenArchive)?;
+                        let password = self.data.passwords.get(&archive.path);
+                        let archive_buf_reader = BufReader::with_capacity(1024, archive_file);
+                        let zip = zip::ZipArchive::new(archive_buf_reader).map_err(Error::Zip)?;
+                        OpenArchive::Zip { zip, password }
+                    }
+                    ArchiveKind::Tar => {
+                        let file = std::fs::File::open(&archive.path)
+                            .path_err(&archive.path, Error::OpenArchive)?;
+                        OpenArchive::Tar {
+                            file,
+                            _unpacked: None,
+                        }
+                    }
+                    ArchiveKind::TarGz => {
+                        let (unpacked, file) = UnpackedTar::unpack(&archive.path)?;
+                        OpenArchive::Tar {
+                            file,
+                            _unpacked: Some(unpacked),
+                        }
+                    }
+                    kind @ (ArchiveKind::Dat1 | ArchiveKind::Dat2) => {
+                        let file = std::fs::File::open(&archive.path)
+                            .path_err(&archive.path, Error::OpenArchive)?;
+                        let version = match kind {
+                            ArchiveKind::Dat1 => DatVersion::Dat1,
+                            _ => DatVersion::Dat2,
+                        };
+                        OpenArchive::Dat { file, version }
+                    }
+                    ArchiveKind::Folder => return Err(Error::ArchiveKindMismatch),
+                })
+            };
+            let open_archive = measure(self.metrics.as_ref(), Operation::ArchiveOpen, open, |_| 0)?;
+            *guard = Some(Box::new(open_archive));
         }
         Ok(MutexGuard::map(guard, |option| {
             &mut **option.as_mut().expect("Should be some")
         }))
     }
 
+    pub fn file_by_hash(&self, hash: u32) -> Result<Vec<u8>, Error> {
+        let path = self.data.path_by_hash(hash).ok_or(Error::NotFound)?;
+        if let Some(data) = self.take_prefetched(path) {
+            return Ok(data);
+        }
+        let file_info = self.data.file_info(path).ok_or(Error::NotFound)?;
+        self.file_by_info(file_info)
+    }
+
     pub fn registry(&self) -> &Arc<FoRegistry> {
         &self.data
     }
 
+    fn local_path(&self, folder_index: u32, file_info: &crate::FileInfo) -> Result<PathBuf, Error> {
+        let folder = self
+            .data
+            .archives
+            .get(folder_index as usize)
+            .ok_or(Error::InvalidArchiveIndex)?;
+        Ok(folder.path.join(&file_info.original_path))
+    }
+
     pub fn file_by_info(&self, file_info: &crate::FileInfo) -> Result<Vec<u8>, Error> {
-        use std::io::Read;
+        #[cfg(feature = "tracing")]
+        let start = std::time::Instant::now();
+        let read = || self.read_file(file_info);
+        let result = measure(self.metrics.as_ref(), Operation::FileRead, read, Vec::len);
+        #[cfg(feature = "tracing")]
+        self.trace_slow_read(file_info, start.elapsed(), &result);
+        result
+    }
+
+    #[cfg(feature = "tracing")]
+    fn trace_slow_read(
+        &self,
+        file_info: &crate::FileInfo,
+        elapsed: std::time::Duration,
+        result: &Result<Vec<u8>, Error>,
+    ) {
+        match self.slow_threshold {
+            Some(threshold) if elapsed >= threshold => {}
+            _ => return,
+        }
+        tracing::warn!(
+            path = %file_info.original_path,
+            archive = ?file_info.location(&self.data),
+            compressed_size = file_info.compressed_size,
+            uncompressed_size = file_info.uncompressed_size,
+            read = result.as_ref().map_or(0, Vec::len),
+            error = ?result.as_ref().err(),
+            elapsed_ms = elapsed.as_millis() as u64,
+            "slow file read"
+        );
+    }
+
+    fn read_file(&self, file_info: &crate::FileInfo) -> Result<Vec<u8>, Error> {
+        match file_info.location {
+            FileLocation::Archive { archive, entry } => {
+                let mut archive = self.get_archive(archive as usize)?;
+
+                let file = archive.zip_entry(entry)?;
+                let size = file.size();
+                self.read_limited(file, size, Error::ArchiveRead)
+            }
+            FileLocation::Tar { archive, offset } => {
+                let mut archive = self.get_archive(archive as usize)?;
+                let size = file_info.compressed_size;
+                let entry = archive.tar_entry(offset, size)?;
+                self.read_limited(entry, size, Error::ArchiveRead)
+            }
+            FileLocation::Dat {
+                archive,
+                offset,
+                packed,
+            } => {
+                let mut archive = self.get_archive(archive as usize)?;
+                let (stored, size) = (file_info.compressed_size, file_info.uncompressed_size);
+                // LZSS entries are decompressed before they are read
+                self.check_size(size)?;
+                let entry = archive.dat_entry(offset, stored, size, packed)?;
+                self.read_limited(entry, file_info.uncompressed_size, Error::ArchiveRead)
+            }
+            FileLocation::Local(folder_index) => {
+                let path = self.local_path(folder_index, file_info)?;
+                let file = std::fs::File::open(&path).path_err(&path, Error::LocalRead)?;
+                let size = file.metadata().path_err(&path, Error::LocalRead)?.len();
+                self.read_limited(file, size, |err| Error::LocalRead(path.clone(), err))
+            }
+        }
+    }
 
+    /// Streams file into `writer` without buffering it in memory and without size limit.
+    pub fn write_file_by_info(
+        &self,
+        file_info: &crate::FileInfo,
+        writer: &mut impl std::io::Write,
+    ) -> Result<u64, Error> {
         match file_info.location {
-            FileLocation::Archive(archive_index) => {
-                let mut archive = self.get_archive(archive_index as usize)?;
+            FileLocation::Archive { archive, entry } => {
+                let mut archive = self.get_archive(archive as usize)?;
 
-                let mut file = archive
-                    .by_name(&file_info.original_path)
-                    .map_err(Error::Zip)?;
-                let mut buffer = Vec::with_capacity(file.size() as usize);
-                file.read_to_end(&mut buffer).map_err(Error::ArchiveRead)?;
-                Ok(buffer)
+                let mut file = archive.zip_entry(entry)?;
+                std::io::copy(&mut file, writer).map_err(Error::ArchiveRead)
+            }
+            FileLocation::Tar { archive, offset } => {
+                let mut archive = self.get_archive(archive as usize)?;
+                let mut entry = archive.tar_entry(offset, file_info.compressed_size)?;
+                std::io::copy(&mut entry, writer).map_err(Error::ArchiveRead)
+            }
+            FileLocation::Dat {
+                archive,
+                offset,
+                packed,
+            } => {
+                let mut archive = self.get_archive(archive as usize)?;
+                let (stored, size) = (file_info.compressed_size, file_info.uncompressed_size);
+                let mut entry = archive.dat_entry(offset, stored, size, packed)?;
+                std::io::copy(&mut entry, writer).map_err(Error::ArchiveRead)
+            }
+            FileLocation::Local(folder_index) => {
+                let path = self.local_path(folder_index, file_info)?;
+                let mut file = std::fs::File::open(&path).path_err(&path, Error::LocalRead)?;
+                std::io::copy(&mut file, writer).path_err(&path, Error::LocalRead)
             }
-            _ => Err(Error::UnsupportedFileLocation),
         }
     }
 }
 
+impl FoRetriever {
+    /// Reader of the file. Local files, tar entries and stored or deflated zip and DAT entries
+    /// are read from their own file handle without size limit, so streams don't lock
+    /// the archive. Encrypted entries, gzipped tarballs and LZSS entries are read into memory
+    /// and fail with [`Error::FileTooLarge`] above [`FoRetriever::with_max_file_size`].
+    pub fn stream_by_info(
+        &self,
+        file_info: &crate::FileInfo,
+    ) -> Result<FileStream<'static>, Error> {
+        use std::io::{BufReader, Read, Seek, SeekFrom};
+
+        let buffered = || Ok(self.read_file(file_info)?.into());
+        let stored = file_info.compressed_size;
+        let kind = || {
+            let archive = file_info.location.archive_index() as usize;
+            self.data.archives.get(archive).map(crate::FoArchive::kind)
+        };
+        let (archive, offset, size, packing) = match file_info.location {
+            FileLocation::Local(folder_index) => {
+                let path = self.local_path(folder_index, file_info)?;
+                let file = std::fs::File::open(&path).path_err(&path, Error::LocalRead)?;
+                return Ok(FileStream::Seekable(Box::new(file)));
+            }
+            FileLocation::Archive { archive, entry } => {
+                match self.get_archive(archive as usize)?.zip_range(entry)? {
+                    Some((offset, size, packing)) => (archive, offset, size, packing),
+                    None => return buffered(),
+                }
+            }
+            FileLocation::Tar { archive, offset } => match kind() {
+                Some(ArchiveKind::Tar) => (archive, offset, stored, Packing::Stored),
+                _ => return buffered(),
+            },
+            FileLocation::Dat {
+                archive,
+                offset,
+                packed,
+            } => match (kind(), packed) {
+                (Some(ArchiveKind::Dat2), true) => (archive, offset, stored, Packing::Zlib),
+                (Some(ArchiveKind::Dat1 | ArchiveKind::Dat2), false) => {
+                    (archive, offset, stored, Packing::Stored)
+                }
+                _ => return buffered(),
+            },
+        };
+        let path = &self
+            .data
+            .archives
+            .get(archive as usize)
+            .ok_or(Error::InvalidArchiveIndex)?
+            .path;
+        let mut file = std::fs::File::open(path).path_err(path, Error::OpenArchive)?;
+        file.seek(SeekFrom::Start(offset))
+            .map_err(Error::ArchiveRead)?;
+        let data = BufReader::new(file.take(size));
+        Ok(FileStream::Sequential(match packing {
+            Packing::Stored => Box::new(data),
+            Packing::Deflate => Box::new(flate2::read::DeflateDecoder::new(data)),
+            Packing::Zlib => Box::new(flate2::read::ZlibDecoder::new(data)),
+        }))
+    }
+}
+
 impl super::Retriever for FoRetriever {
     type Error = Error;
 
     fn file_by_path(&self, path: &str) -> Result<Vec<u8>, Self::Error> {
+        if let Some(data) = self.take_prefetched(path) {
+            return Ok(data);
+        }
         let file_info = self.data.file_info(path).ok_or(Error::NotFound)?;
 
         self.file_by_info(&file_info)
     }
+
+    fn stream_by_path(&self, path: &str) -> Result<FileStream<'_>, Self::Error> {
+        if let Some(data) = self.take_prefetched(path) {
+            return Ok(data.into());
+        }
+        let file_info = self.data.file_info(path).ok_or(Error::NotFound)?;
+        self.stream_by_info(file_info)
+    }
+
+    fn path_by_hash(&self, hash: u32) -> Option<&str> {
+        self.data.path_by_hash(hash)
+    }
+
+    fn path_rules(&self) -> &crate::paths::PathRules {
+        self.data.path_rules()
+    }
+
+    /// Location and sizes of the file and change time of its archive,
+    /// local files also add their own change time and size.
+    fn source_stamp(&self, path: &str) -> Option<u64> {
+        let file_info = self.data.file_info(path)?;
+        let archive = self
+            .data
+            .archives
+            .get(file_info.location.archive_index() as usize)?;
+        let mut hasher = crate::converter::FingerprintHasher::new();
+        hasher.write(&bincode::serialize(&(file_info, archive.changed)).ok()?);
+        if let FileLocation::Local(folder_index) = file_info.location {
+            let path = self.local_path(folder_index, file_info).ok()?;
+            let metadata = path.metadata().ok()?;
+            hasher.write(&bincode::serialize(&(metadata.modified().ok()?, metadata.len())).ok()?);
+        }
+        Some(hasher.finish().0)
+    }
 }
 
-impl Into<crate::GetImageError> for Error {
-    fn into(self) -> crate::GetImageError {
-        crate::GetImageError::FoRetrieve(self)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempData};
+
+    #[test]
+    fn read_limited_rejects_lying_metadata() {
+        let retriever =
+            FoRetriever::new(Arc::new(FoRegistry::stub())).with_max_file_size(Some(4));
+        let data = retriever
+            .read_limited(&b"1234"[..], 4, Error::ArchiveRead)
+            .unwrap();
+        assert_eq!(data, b"1234");
+        assert!(matches!(
+            retriever.read_limited(&b"12345"[..], 1, Error::ArchiveRead),
+            Err(Error::FileTooLarge(5, 4))
+        ));
+        assert!(matches!(
+            retriever.read_limited(&b""[..], u64::MAX, Error::ArchiveRead),
+            Err(Error::FileTooLarge(u64::MAX, 4))
+        ));
+
+        let unlimited =
+            FoRetriever::new(Arc::new(FoRegistry::stub())).with_max_file_size(Some(u64::MAX));
+        let data = unlimited
+            .read_limited(&b"12345"[..], 5, Error::ArchiveRead)
+            .unwrap();
+        assert_eq!(data, b"12345");
+    }
+
+    fn tar_registry(data: &TempData, name: &str, gzip: bool) -> FoRegistry {
+        let path = data.root().join(name);
+        let file = std::fs::File::create(&path).unwrap();
+        let writer: Box<dyn std::io::Write> = if gzip {
+            Box::new(flate2::write::GzEncoder::new(file, Default::default()))
+        } else {
+            Box::new(file)
+        };
+        let mut builder = tar::Builder::new(writer);
+        for (name, data) in &[("Art/A.txt", &b"first"[..]), ("art/b.txt", &b"second"[..])] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            builder.append_data(&mut header, name, *data).unwrap();
+        }
+        builder.into_inner().unwrap().flush().unwrap();
+        testing::registry(vec![testing::archive(path, None)])
+    }
+
+    #[test]
+    fn read_from_tarballs() {
+        use crate::Retriever;
+
+        let data = TempData::new(&[]);
+        for &(name, gzip) in &[("data.tar", false), ("data.tar.gz", true)] {
+            let retriever = tar_registry(&data, name, gzip).into_retriever();
+            assert_eq!(retriever.file_by_path("art/a.txt").unwrap(), b"first");
+            assert_eq!(retriever.file_by_path("art/b.txt").unwrap(), b"second");
+            let hash = crate::hash::name_hash("Art\\B.txt");
+            assert_eq!(retriever.path_by_hash(hash), Some("art/b.txt"));
+            assert_eq!(retriever.file_by_hash(hash).unwrap(), b"second");
+        }
+    }
+
+    #[test]
+    fn read_from_zip64() {
+        use std::io::Write;
+
+        use crate::Retriever;
+
+        let data = TempData::new(&[]);
+        let path = data.root().join("data.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+        let options = zip::write::FileOptions::default().large_file(true);
+        zip.start_file("Art/Big.txt", options).unwrap();
+        zip.write_all(b"zip64 entry").unwrap();
+        zip.finish().unwrap();
+
+        let retriever = testing::registry(vec![testing::archive(path, None)]).into_retriever();
+        assert_eq!(retriever.file_by_path("art/big.txt").unwrap(), b"zip64 entry");
+        let info = retriever.registry().file_info("art/big.txt").unwrap();
+        assert_eq!(info.uncompressed_size(), 11);
+        assert!(info.compressed_size() > 0);
+    }
+
+    /// Zip with a single stored entry encrypted with ZipCrypto, `ZipWriter` can't encrypt.
+    fn zip_crypto_archive(name: &str, data: &[u8], password: &[u8]) -> Vec<u8> {
+        let crc_table: Vec<u32> = (0..256)
+            .map(|byte| {
+                (0..8).fold(byte, |crc, _| {
+                    if crc & 1 == 1 {
+                        0xedb8_8320 ^ (crc >> 1)
+                    } else {
+                        crc >> 1
+                    }
+                })
+            })
+            .collect();
+        let crc32 = |crc: u32, byte: u8| {
+            (crc >> 8) ^ crc_table[((crc ^ u32::from(byte)) & 0xff) as usize]
+        };
+        let mut keys = [0x1234_5678u32, 0x2345_6789, 0x3456_7890];
+        let update = |keys: &mut [u32; 3], byte: u8| {
+            keys[0] = crc32(keys[0], byte);
+            keys[1] = (keys[1].wrapping_add(keys[0] & 0xff))
+                .wrapping_mul(134_775_813)
+                .wrapping_add(1);
+            keys[2] = crc32(keys[2], (keys[1] >> 24) as u8);
+        };
+        for &byte in password {
+            update(&mut keys, byte);
+        }
+        let crc = crc32fast::hash(data);
+        let mut plain = b"random head".to_vec();
+        plain.push((crc >> 24) as u8);
+        plain.extend_from_slice(data);
+        let encrypted: Vec<u8> = plain
+            .into_iter()
+            .map(|byte| {
+                let temp = (keys[2] | 2) as u16;
+                let stream = (temp.wrapping_mul(temp ^ 1) >> 8) as u8;
+                update(&mut keys, byte);
+                byte ^ stream
+            })
+            .collect();
+
+        let mut zip = Vec::new();
+        // version needed, encrypted flag, stored, time, date 1980-01-01
+        let common = |zip: &mut Vec<u8>| {
+            for field in [20u16, 1, 0, 0, 0x21] {
+                zip.extend_from_slice(&field.to_le_bytes());
+            }
+            for field in [crc, encrypted.len() as u32, data.len() as u32] {
+                zip.extend_from_slice(&field.to_le_bytes());
+            }
+            zip.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            zip.extend_from_slice(&0u16.to_le_bytes());
+        };
+        zip.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        common(&mut zip);
+        zip.extend_from_slice(name.as_bytes());
+        zip.extend_from_slice(&encrypted);
+        let central = zip.len() as u32;
+        zip.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        zip.extend_from_slice(&20u16.to_le_bytes());
+        common(&mut zip);
+        // comment, disk, internal and external attributes, local header offset
+        zip.extend_from_slice(&[0; 10]);
+        zip.extend_from_slice(&0u32.to_le_bytes());
+        zip.extend_from_slice(name.as_bytes());
+        let central_size = zip.len() as u32 - central;
+        zip.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+        zip.extend_from_slice(&[0, 0, 0, 0, 1, 0, 1, 0]);
+        zip.extend_from_slice(&central_size.to_le_bytes());
+        zip.extend_from_slice(&central.to_le_bytes());
+        zip.extend_from_slice(&0u16.to_le_bytes());
+        zip
+    }
+
+    #[test]
+    fn read_encrypted_zip() {
+        use crate::Retriever;
+
+        let archive = zip_crypto_archive("Art/Secret.txt", b"hidden data", b"fonline");
+        let data = TempData::new(&[("secret.zip", &archive)]);
+        let path = data.root().join("secret.zip");
+        let registry = testing::registry(vec![testing::archive(&path, None)]);
+        let retriever = |password: Option<&[u8]>| {
+            let mut passwords = crate::passwords::Passwords::default();
+            if let Some(password) = password {
+                passwords.insert(path.clone(), password.to_vec());
+            }
+            FoRegistry {
+                archives: registry.archives.clone(),
+                files: registry.files.clone(),
+                passwords,
+                ..FoRegistry::stub()
+            }
+            .into_retriever()
+        };
+
+        let secret = retriever(Some(b"fonline")).file_by_path("art/secret.txt");
+        assert_eq!(secret.unwrap(), b"hidden data");
+        let wrong = retriever(Some(b"wrong")).file_by_path("art/secret.txt");
+        assert!(matches!(wrong, Err(Error::InvalidPassword)), "{:?}", wrong);
+        let missing = retriever(None).file_by_path("art/secret.txt");
+        assert!(matches!(missing, Err(Error::Zip(_))), "{:?}", missing);
+    }
+
+    #[test]
+    fn stream_archive_entries() {
+        use std::io::{Read, Write};
+
+        use crate::Retriever;
+
+        let data = TempData::new(&[]);
+        let read = |retriever: &FoRetriever, path: &str| {
+            let mut stream = retriever.stream_by_path(path).unwrap();
+            let seekable = stream.seekable().is_some();
+            let mut data = Vec::new();
+            stream.read_to_end(&mut data).unwrap();
+            (data, seekable)
+        };
+        let retriever = tar_registry(&data, "data.tar", false).into_retriever();
+        assert_eq!(read(&retriever, "art/b.txt"), (b"second".to_vec(), false));
+        let retriever = tar_registry(&data, "data.tar.gz", true).into_retriever();
+        assert_eq!(read(&retriever, "art/a.txt"), (b"first".to_vec(), true));
+
+        let path = data.root().join("sound.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+        let sound = b"riff".repeat(1000);
+        for (name, method) in [
+            ("Sound/Big.acm", zip::CompressionMethod::Deflated),
+            ("sound/raw.acm", zip::CompressionMethod::Stored),
+        ] {
+            let options = zip::write::FileOptions::default().compression_method(method);
+            zip.start_file(name, options).unwrap();
+            zip.write_all(&sound).unwrap();
+        }
+        zip.finish().unwrap();
+        let retriever = testing::registry(vec![testing::archive(path, None)])
+            .into_retriever()
+            .with_max_file_size(Some(16));
+        assert_eq!(read(&retriever, "sound/big.acm"), (sound.clone(), false));
+        assert_eq!(read(&retriever, "sound/raw.acm"), (sound, false));
+        assert!(matches!(retriever.stream_by_path("sound/none.acm"), Err(Error::NotFound)));
+    }
+
+    #[test]
+    fn prefetch_tarball() {
+        use crate::Retriever;
+
+        let data = TempData::new(&[]);
+        let retriever = tar_registry(&data, "data.tar.gz", true).into_retriever();
+        let paths = vec!["art/a.txt", "art/missing.txt"];
+        let handle = retriever.prefetch(paths, PrefetchMode::OpenArchives);
+        assert_eq!(handle.wait(), 1);
+        assert!(retriever.archives[0].lock().is_some());
+        assert!(retriever.prefetched.lock().files.is_empty());
+
+        let handle = retriever.prefetch(vec!["art/a.txt", "art/b.txt"], PrefetchMode::Decompress);
+        assert_eq!(handle.wait(), 2);
+        assert_eq!(retriever.prefetched.lock().size, 11);
+        assert_eq!(retriever.file_by_path("art/a.txt").unwrap(), b"first");
+        assert_eq!(retriever.prefetched.lock().size, 6);
+        assert_eq!(retriever.file_by_path("art/a.txt").unwrap(), b"first");
+
+        let budget = MemoryBudget::new(8);
+        let retriever = retriever.with_memory_budget(&budget);
+        assert_eq!(budget.usage().used, 6);
+        let handle = retriever.prefetch(vec!["art/a.txt"], PrefetchMode::Decompress);
+        assert_eq!(handle.wait(), 1);
+        assert_eq!(budget.usage().used, 5);
+
+        let retriever = retriever.with_max_prefetched_size(4);
+        let handle = retriever.prefetch(vec!["art/b.txt"], PrefetchMode::Decompress);
+        assert_eq!(handle.wait(), 0);
     }
 }
@@ -0,0 +1,67 @@
+//! Shared shape of retrievers backed by embedded key-value databases.
+//!
+//! Schema: `paths` table maps conventional path to file index,
+//! `files` table maps file index to file data.
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error<E> {
+    #[error("can't open database: {0}")]
+    Init(E),
+    #[error("database not found: {0:?}")]
+    DatabaseNotFound(std::path::PathBuf),
+    #[error("can't get file index by path: {0}")]
+    GetFileIndexByPath(E),
+    #[error("path not found")]
+    PathNotFound,
+    #[error("can't get file by index: {0}")]
+    GetFileByIndex(E),
+    #[error("file index not found")]
+    FileIndexNotFound,
+    #[error("can't get metadata: {0}")]
+    GetMetadata(E),
+    #[error("metadata deserialization error: {0}")]
+    MetadataDecode(bincode::Error),
+    #[error("metadata serialization error: {0}")]
+    MetadataEncode(bincode::Error),
+    #[error("database is opened read-only")]
+    ReadOnly,
+    #[error("database write error: {0}")]
+    Write(E),
+    #[error("source retriever error: {0}")]
+    Source(crate::retriever::fo::Error),
+}
+
+pub const PATHS_TABLE: &str = "paths";
+pub const FILES_TABLE: &str = "files";
+
+pub trait KvRetriever {
+    type BackendError;
+    type Value: AsRef<[u8]>;
+
+    fn file_index(&self, path: &str) -> Result<Option<Self::Value>, Self::BackendError>;
+    fn file_by_index(&self, index: &[u8]) -> Result<Option<Self::Value>, Self::BackendError>;
+
+    fn kv_value_by_path(&self, path: &str) -> Result<Self::Value, Error<Self::BackendError>> {
+        let index = self
+            .file_index(path)
+            .map_err(Error::GetFileIndexByPath)?
+            .ok_or(Error::PathNotFound)?;
+        self.file_by_index(index.as_ref())
+            .map_err(Error::GetFileByIndex)?
+            .ok_or(Error::FileIndexNotFound)
+    }
+
+    fn kv_file_by_path(&self, path: &str) -> Result<Vec<u8>, Error<Self::BackendError>> {
+        Ok(self.kv_value_by_path(path)?.as_ref().to_owned())
+    }
+
+    /// Keeps the database value alive instead of copying it.
+    fn kv_file_bytes_by_path(&self, path: &str) -> Result<bytes::Bytes, Error<Self::BackendError>>
+    where
+        Self::Value: Send + 'static,
+    {
+        Ok(bytes::Bytes::from_owner(self.kv_value_by_path(path)?))
+    }
+}
@@ -0,0 +1,182 @@
+//! Files kept in memory: for tests, WASM builds and programs that generate data on the fly.
+
+use std::collections::HashMap;
+
+use bytes::Bytes;
+use thiserror::Error;
+
+use crate::{converter::FingerprintHasher, hash::HashFunction, paths::PathRules};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("path {0:?} not found")]
+    NotFound(String),
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    data: Bytes,
+    /// Hash of the content, stable between runs.
+    stamp: u64,
+}
+
+/// Paths are made conventional with the retriever's rules on insert and lookup.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryRetriever {
+    files: HashMap<String, Entry>,
+    by_hash: HashMap<u32, String>,
+    rules: PathRules,
+    hash_function: HashFunction,
+}
+
+impl MemoryRetriever {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Files already inserted are renamed by the new rules.
+    pub fn with_path_rules(mut self, rules: PathRules) -> Self {
+        self.rules = rules;
+        let files = std::mem::take(&mut self.files);
+        self.by_hash.clear();
+        for (path, entry) in files {
+            self.insert_entry(path, entry);
+        }
+        self
+    }
+
+    /// Name hash for [`Retriever::path_by_hash`](super::Retriever::path_by_hash).
+    pub fn with_hash_function(mut self, hash_function: HashFunction) -> Self {
+        self.hash_function = hash_function;
+        self.by_hash = self
+            .files
+            .keys()
+            .map(|path| (hash_function.hash(path), path.clone()))
+            .collect();
+        self
+    }
+
+    pub fn with_file(mut self, path: &str, data: impl Into<Bytes>) -> Self {
+        self.insert(path, data);
+        self
+    }
+
+    /// Returns the replaced file.
+    pub fn insert(&mut self, path: &str, data: impl Into<Bytes>) -> Option<Bytes> {
+        let data = data.into();
+        let mut hasher = FingerprintHasher::new();
+        hasher.write(&data);
+        let entry = Entry {
+            data,
+            stamp: hasher.finish().0,
+        };
+        self.insert_entry(path.to_owned(), entry)
+    }
+
+    fn insert_entry(&mut self, path: String, entry: Entry) -> Option<Bytes> {
+        let path = self.rules.normalize(&path);
+        self.by_hash.insert(self.hash_function.hash(&path), path.clone());
+        self.files.insert(path, entry).map(|old| old.data)
+    }
+
+    pub fn remove(&mut self, path: &str) -> Option<Bytes> {
+        let path = self.rules.normalize(path);
+        let entry = self.files.remove(&path)?;
+        self.by_hash.remove(&self.hash_function.hash(&path));
+        Some(entry.data)
+    }
+
+    /// File without copying its data.
+    pub fn get(&self, path: &str) -> Option<&Bytes> {
+        self.files
+            .get(&self.rules.normalize(path))
+            .map(|entry| &entry.data)
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Conventional paths in path order.
+    pub fn paths(&self) -> impl '_ + Iterator<Item = &str> {
+        let mut paths: Vec<_> = self.files.keys().map(String::as_str).collect();
+        paths.sort_unstable();
+        paths.into_iter()
+    }
+}
+
+impl super::Retriever for MemoryRetriever {
+    type Error = Error;
+
+    fn file_by_path(&self, path: &str) -> Result<Vec<u8>, Self::Error> {
+        self.get(path)
+            .map(|data| data.to_vec())
+            .ok_or_else(|| Error::NotFound(path.to_owned()))
+    }
+
+    fn file_bytes_by_path(&self, path: &str) -> Result<Bytes, Self::Error> {
+        self.get(path)
+            .cloned()
+            .ok_or_else(|| Error::NotFound(path.to_owned()))
+    }
+
+    fn stream_by_path(&self, path: &str) -> Result<super::FileStream<'_>, Self::Error> {
+        let data = self.get(path).ok_or_else(|| Error::NotFound(path.to_owned()))?;
+        Ok(super::FileStream::Seekable(Box::new(std::io::Cursor::new(&data[..]))))
+    }
+
+    fn path_by_hash(&self, hash: u32) -> Option<&str> {
+        self.by_hash.get(&hash).map(String::as_str)
+    }
+
+    fn path_rules(&self) -> &PathRules {
+        &self.rules
+    }
+
+    fn source_stamp(&self, path: &str) -> Option<u64> {
+        self.files
+            .get(&self.rules.normalize(path))
+            .map(|entry| entry.stamp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Retriever;
+
+    #[test]
+    fn insert_and_lookup() {
+        let mut retriever = MemoryRetriever::new().with_file("Art\\A.png", &b"a"[..]);
+        assert_eq!(retriever.file_by_path("art/a.png").unwrap(), b"a");
+        assert_eq!(retriever.file_by_path("ART/A.PNG").unwrap(), b"a");
+        retriever.insert("Art\\0.png", &b"0"[..]);
+        assert_eq!(retriever.paths().collect::<Vec<_>>(), ["art/0.png", "art/a.png"]);
+        retriever.remove("art/0.png");
+        let shared = retriever.file_bytes_by_path("art/a.png").unwrap();
+        assert_eq!(shared.as_ptr(), retriever.get("art/a.png").unwrap().as_ptr());
+        let hash = crate::hash::name_hash("art/a.png");
+        assert_eq!(retriever.path_by_hash(hash), Some("art/a.png"));
+
+        let stamp = retriever.source_stamp("art/a.png").unwrap();
+        assert_eq!(retriever.insert("art/a.png", &b"b"[..]).as_deref(), Some(&b"a"[..]));
+        assert_ne!(retriever.source_stamp("art/a.png"), Some(stamp));
+
+        let retriever = retriever.with_hash_function(HashFunction::Crc32);
+        let hash = HashFunction::Crc32.hash("art/a.png");
+        assert_eq!(retriever.path_by_hash(hash), Some("art/a.png"));
+
+        let mut retriever = retriever.with_path_rules(PathRules {
+            case: crate::paths::CaseRule::Preserve,
+            ..PathRules::FONLINE
+        });
+        assert!(retriever.file_by_path("ART/A.PNG").is_err());
+        assert_eq!(retriever.remove("art/a.png").as_deref(), Some(&b"b"[..]));
+        assert_eq!(retriever.path_by_hash(hash), None);
+        assert!(retriever.is_empty());
+    }
+}
@@ -0,0 +1,160 @@
+use std::path::Path;
+
+use redb::TableDefinition;
+
+use super::kv::{KvRetriever, FILES_TABLE, PATHS_TABLE};
+
+const PATHS: TableDefinition<&str, &[u8]> = TableDefinition::new(PATHS_TABLE);
+const FILES: TableDefinition<&[u8], &[u8]> = TableDefinition::new(FILES_TABLE);
+
+/// Same schema as [`super::sled::SledRetriever`], stored in a redb database file.
+pub struct RedbRetriever {
+    db: redb::Database,
+}
+
+/// Backend errors are boxed, `redb::Error` is several times larger than the other variants.
+pub type Error = super::kv::Error<Box<redb::Error>>;
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl RedbRetriever {
+    /// Opens the database, creating an empty one if it's missing, same as
+    /// [`super::sled::SledRetriever::init`].
+    pub fn init<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let db = redb::Database::create(path).map_err(init_error)?;
+        // tables of a new database exist, so its paths are just not found
+        let transaction = db.begin_write().map_err(init_error)?;
+        transaction.open_table(PATHS).map_err(init_error)?;
+        transaction.open_table(FILES).map_err(init_error)?;
+        transaction.commit().map_err(init_error)?;
+        Ok(Self { db })
+    }
+
+    /// Copies every file of the registry behind `source` into the database in one
+    /// transaction. Files are indexed by their content hash, so identical files are
+    /// stored once.
+    pub fn ingest(&self, source: &super::fo::FoRetriever) -> Result<usize> {
+        let transaction = self.db.begin_write().map_err(write_error)?;
+        let mut count = 0;
+        {
+            let mut paths = transaction.open_table(PATHS).map_err(write_error)?;
+            let mut files = transaction.open_table(FILES).map_err(write_error)?;
+            for (path, file_info) in source.registry().files() {
+                let data = source.file_by_info(file_info).map_err(Error::Source)?;
+                let index = blake3::hash(&data);
+                let index = &index.as_bytes()[..];
+                files.insert(index, &data[..]).map_err(write_error)?;
+                paths.insert(path, index).map_err(write_error)?;
+                count += 1;
+            }
+        }
+        transaction.commit().map_err(write_error)?;
+        Ok(count)
+    }
+}
+
+fn boxed(err: impl Into<redb::Error>) -> Box<redb::Error> {
+    Box::new(err.into())
+}
+
+fn init_error(err: impl Into<redb::Error>) -> Error {
+    Error::Init(boxed(err))
+}
+
+fn write_error(err: impl Into<redb::Error>) -> Error {
+    Error::Write(boxed(err))
+}
+
+impl KvRetriever for RedbRetriever {
+    type BackendError = Box<redb::Error>;
+    type Value = Vec<u8>;
+
+    fn file_index(&self, path: &str) -> Result<Option<Self::Value>, Self::BackendError> {
+        let transaction = self.db.begin_read().map_err(boxed)?;
+        let table = transaction.open_table(PATHS).map_err(boxed)?;
+        let index = table.get(path).map_err(boxed)?;
+        Ok(index.map(|index| index.value().to_owned()))
+    }
+
+    fn file_by_index(&self, index: &[u8]) -> Result<Option<Self::Value>, Self::BackendError> {
+        let transaction = self.db.begin_read().map_err(boxed)?;
+        let table = transaction.open_table(FILES).map_err(boxed)?;
+        let data = table.get(index).map_err(boxed)?;
+        Ok(data.map(|data| data.value().to_owned()))
+    }
+}
+
+impl super::Retriever for RedbRetriever {
+    type Error = Error;
+
+    fn file_by_path(&self, path: &str) -> Result<Vec<u8>, Self::Error> {
+        self.kv_file_by_path(path)
+    }
+
+    fn file_bytes_by_path(&self, path: &str) -> Result<bytes::Bytes, Self::Error> {
+        self.kv_file_bytes_by_path(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::TempData, Retriever};
+
+    #[test]
+    fn read_file_by_path() {
+        let path = std::env::temp_dir().join("fo_data_test.redb");
+        let _ = std::fs::remove_file(&path);
+        {
+            let db = redb::Database::create(&path).unwrap();
+            let transaction = db.begin_write().unwrap();
+            {
+                let mut paths = transaction.open_table(PATHS).unwrap();
+                paths.insert("art/a.frm", &[0u8][..]).unwrap();
+                let mut files = transaction.open_table(FILES).unwrap();
+                files.insert(&[0u8][..], &b"data"[..]).unwrap();
+            }
+            transaction.commit().unwrap();
+        }
+
+        let retriever = RedbRetriever::init(&path).unwrap();
+        assert_eq!(retriever.file_by_path("art/a.frm").unwrap(), b"data");
+        assert_eq!(retriever.file_bytes_by_path("art/a.frm").unwrap(), &b"data"[..]);
+        assert!(matches!(
+            retriever.file_by_path("art/b.frm"),
+            Err(Error::PathNotFound)
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn ingest_round_trip() {
+        let data = TempData::new(&[
+            ("art/a.frm", b"frame"),
+            ("art/b.frm", b"frame"),
+            ("art/c.txt", b"text"),
+        ]);
+        let source = data.retriever();
+
+        let databases = TempData::new(&[]);
+        let path = databases.root().join("data.redb");
+        let created = RedbRetriever::init(&path).unwrap();
+        assert!(matches!(created.file_by_path("art/a.frm"), Err(Error::PathNotFound)));
+        assert_eq!(created.ingest(&source).unwrap(), 3);
+        drop(created);
+
+        let retriever = RedbRetriever::init(&path).unwrap();
+        for (file, data) in [("art/a.frm", &b"frame"[..]), ("art/c.txt", b"text")] {
+            assert_eq!(retriever.file_by_path(file).unwrap(), data);
+            assert_eq!(retriever.file_by_path(file).unwrap(), source.file_by_path(file).unwrap());
+        }
+        // identical files are stored once
+        let index = |file| retriever.file_index(file).unwrap().unwrap();
+        assert_eq!(index("art/a.frm"), index("art/b.frm"));
+        assert_ne!(index("art/a.frm"), index("art/c.txt"));
+        assert!(matches!(retriever.file_by_path("art/d.txt"), Err(Error::PathNotFound)));
+
+        databases.write("broken.redb", b"not a database");
+        let broken = databases.root().join("broken.redb");
+        assert!(matches!(RedbRetriever::init(&broken), Err(Error::Init(_))));
+    }
+}
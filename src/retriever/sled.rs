@@ -1,59 +1,244 @@
 use std::path::Path;
 
+use serde::{Deserialize, Serialize};
+
+use super::kv::{KvRetriever, FILES_TABLE, PATHS_TABLE};
+use crate::FileType;
+
+const METADATA_TREE: &str = "metadata";
+
 pub struct SledRetriever {
-    _db: sled::Db,
+    db: sled::Db,
     paths: sled::Tree,
     files: sled::Tree,
+    metadata: sled::Tree,
+    read_only: bool,
 }
 
-#[derive(Debug)]
-pub enum Error {
-    Init(sled::Error),
-    GetFileIndexByPath(sled::Error),
-    PathNotFound,
-    GetFileByIndex(sled::Error),
-    FileIndexNotFound,
+/// Stored per path at ingest, so it's available without decoding the file itself.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SledMetadata {
+    pub file_type: FileType,
+    pub size: u64,
+    /// Size of the first frame for images.
+    pub dimensions: Option<(u32, u32)>,
+    /// Blake3 hash of the original file, also used as its file index.
+    pub source_hash: [u8; 32],
 }
+
+pub type Error = super::kv::Error<sled::Error>;
 type Result<T, E = Error> = std::result::Result<T, E>;
 
+#[derive(Debug, Clone)]
+pub struct SledConfig {
+    /// Page cache size in bytes.
+    pub cache_capacity: u64,
+    /// Zstd compression level from 1 to 22, `None` disables compression.
+    pub compression_factor: Option<i32>,
+    /// Fail instead of creating a missing database and never flush in background.
+    /// Sled itself has no read-only mode, retriever just never writes.
+    pub read_only: bool,
+    /// `None` disables periodic flushing.
+    pub flush_every_ms: Option<u64>,
+}
+
+impl Default for SledConfig {
+    fn default() -> Self {
+        Self {
+            cache_capacity: 128 * 1024 * 1024,
+            compression_factor: Some(5),
+            read_only: false,
+            flush_every_ms: Some(500),
+        }
+    }
+}
+
 impl SledRetriever {
     pub fn init<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let config = sled::Config::new()
+        Self::init_with_config(path, &SledConfig::default())
+    }
+
+    pub fn init_with_config<P: AsRef<Path>>(path: P, config: &SledConfig) -> Result<Self> {
+        let path = path.as_ref();
+        if config.read_only && !path.exists() {
+            return Err(Error::DatabaseNotFound(path.to_owned()));
+        }
+        let mut sled_config = sled::Config::new()
             .path(path)
-            .cache_capacity(128 * 1024 * 1024)
-            .use_compression(true);
-        //.compression_factor(22);
-        let db = config.open().map_err(Error::Init)?;
-        let paths = db.open_tree("paths").map_err(Error::Init)?;
-        let files = db.open_tree("files").map_err(Error::Init)?;
+            .cache_capacity(config.cache_capacity)
+            .use_compression(config.compression_factor.is_some())
+            .flush_every_ms(if config.read_only {
+                None
+            } else {
+                config.flush_every_ms
+            });
+        if let Some(compression_factor) = config.compression_factor {
+            sled_config = sled_config.compression_factor(compression_factor);
+        }
+        let db = sled_config.open().map_err(Error::Init)?;
+        let paths = db.open_tree(PATHS_TABLE).map_err(Error::Init)?;
+        let files = db.open_tree(FILES_TABLE).map_err(Error::Init)?;
+        let metadata = db.open_tree(METADATA_TREE).map_err(Error::Init)?;
         Ok(Self {
-            _db: db,
+            db,
             paths,
             files,
+            metadata,
+            read_only: config.read_only,
         })
     }
+
+    pub fn metadata_by_path(&self, path: &str) -> Result<Option<SledMetadata>> {
+        let bytes = match self.metadata.get(path).map_err(Error::GetMetadata)? {
+            Some(bytes) => bytes,
+            None => return Ok(None),
+        };
+        bincode::deserialize(&bytes)
+            .map(Some)
+            .map_err(Error::MetadataDecode)
+    }
+
+    pub fn file_type_by_path(&self, path: &str) -> Result<Option<FileType>> {
+        Ok(self.metadata_by_path(path)?.map(|metadata| metadata.file_type))
+    }
+
+    pub fn size_by_path(&self, path: &str) -> Result<Option<u64>> {
+        Ok(self.metadata_by_path(path)?.map(|metadata| metadata.size))
+    }
+
+    pub fn dimensions_by_path(&self, path: &str) -> Result<Option<(u32, u32)>> {
+        Ok(self
+            .metadata_by_path(path)?
+            .and_then(|metadata| metadata.dimensions))
+    }
+
+    /// Copies every file of the registry behind `source` into the database, with metadata.
+    /// Files are indexed by their content hash, so identical files are stored once.
+    pub fn ingest(&self, source: &super::fo::FoRetriever) -> Result<usize> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        let mut count = 0;
+        for (path, file_info) in source.registry().files() {
+            let data = source.file_by_info(file_info).map_err(Error::Source)?;
+            let file_type = file_info.file_type().clone();
+            let metadata = SledMetadata {
+                dimensions: image_dimensions(&file_type, &data),
+                file_type,
+                size: data.len() as u64,
+                source_hash: *blake3::hash(&data).as_bytes(),
+            };
+            let metadata_bytes = bincode::serialize(&metadata).map_err(Error::MetadataEncode)?;
+            let index = &metadata.source_hash[..];
+            self.files.insert(index, data).map_err(Error::Write)?;
+            self.paths.insert(path, index).map_err(Error::Write)?;
+            self.metadata
+                .insert(path, metadata_bytes)
+                .map_err(Error::Write)?;
+            count += 1;
+        }
+        self.db.flush().map_err(Error::Write)?;
+        Ok(count)
+    }
+}
+
+fn image_dimensions(file_type: &FileType, data: &[u8]) -> Option<(u32, u32)> {
+    match file_type {
+        FileType::Png => image::io::Reader::with_format(
+            std::io::Cursor::new(data),
+            image::ImageFormat::Png,
+        )
+        .into_dimensions()
+        .ok(),
+        FileType::Frm => {
+            let frm = crate::frm::frm(data).ok()?;
+            let frame = frm.directions.first()?.frames.first()?;
+            Some((frame.width as u32, frame.height as u32))
+        }
+        _ => None,
+    }
+}
+
+impl KvRetriever for SledRetriever {
+    type BackendError = sled::Error;
+    type Value = sled::IVec;
+
+    fn file_index(&self, path: &str) -> Result<Option<Self::Value>, Self::BackendError> {
+        self.paths.get(path)
+    }
+
+    fn file_by_index(&self, index: &[u8]) -> Result<Option<Self::Value>, Self::BackendError> {
+        self.files.get(index)
+    }
 }
 
 impl super::Retriever for SledRetriever {
     type Error = Error;
 
     fn file_by_path(&self, path: &str) -> Result<Vec<u8>, Self::Error> {
-        let index = self
-            .paths
-            .get(path)
-            .map_err(Error::GetFileIndexByPath)?
-            .ok_or(Error::PathNotFound)?;
-        let data = self
-            .files
-            .get(index)
-            .map_err(Error::GetFileByIndex)?
-            .ok_or(Error::FileIndexNotFound)?;
-        Ok(data.as_ref().to_owned())
-    }
-}
-
-impl Into<crate::GetImageError> for Error {
-    fn into(self) -> crate::GetImageError {
-        crate::GetImageError::SledRetrieve(self)
+        self.kv_file_by_path(path)
+    }
+
+    fn file_bytes_by_path(&self, path: &str) -> Result<bytes::Bytes, Self::Error> {
+        self.kv_file_bytes_by_path(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::TempData, Retriever};
+
+    #[test]
+    fn ingest_with_metadata() {
+        let mut png = std::io::Cursor::new(Vec::new());
+        image::DynamicImage::ImageRgba8(image::RgbaImage::new(3, 2))
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+        let data = TempData::new(&[
+            ("art/tile.png", &png.into_inner()),
+            ("art/a.txt", b"same"),
+            ("art/b.txt", b"same"),
+        ]);
+        let source = data.retriever();
+
+        let read_only = SledConfig {
+            read_only: true,
+            ..SledConfig::default()
+        };
+        let databases = TempData::new(&[]);
+        let db = databases.root().join("db");
+        assert!(matches!(
+            SledRetriever::init_with_config(&db, &read_only),
+            Err(Error::DatabaseNotFound(_))
+        ));
+        let retriever = SledRetriever::init(&db).unwrap();
+        assert_eq!(retriever.ingest(&source).unwrap(), 3);
+        // identical files are stored once
+        assert_eq!(retriever.files.len(), 2);
+        assert_eq!(retriever.file_by_path("art/b.txt").unwrap(), b"same");
+        assert!(matches!(retriever.file_by_path("art/c.txt"), Err(Error::PathNotFound)));
+
+        let tile = retriever.metadata_by_path("art/tile.png").unwrap().unwrap();
+        assert_eq!(tile.file_type, FileType::Png);
+        assert_eq!(tile.dimensions, Some((3, 2)));
+        let text = retriever.file_type_by_path("art/a.txt").unwrap().unwrap();
+        assert!(matches!(text, FileType::Unsupported(_) | FileType::Unknown), "{:?}", text);
+        assert_eq!(retriever.size_by_path("art/a.txt").unwrap(), Some(4));
+        assert_eq!(retriever.dimensions_by_path("art/a.txt").unwrap(), None);
+        assert!(retriever.metadata_by_path("art/c.txt").unwrap().is_none());
+
+        retriever.metadata.insert("art/broken.txt", &b"\xff"[..]).unwrap();
+        assert!(matches!(
+            retriever.metadata_by_path("art/broken.txt"),
+            Err(Error::MetadataDecode(_))
+        ));
+        drop(retriever);
+
+        // background threads of sled may hold the lock of `db` for a while
+        let empty = databases.root().join("empty");
+        std::fs::create_dir_all(&empty).unwrap();
+        let retriever = SledRetriever::init_with_config(&empty, &read_only).unwrap();
+        assert!(matches!(retriever.ingest(&source), Err(Error::ReadOnly)));
     }
 }
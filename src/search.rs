@@ -0,0 +1,141 @@
+//! Fuzzy search over conventional paths, for quick-open fields of tools built on the registry.
+//!
+//! Every whitespace separated term of the query must match the path as a subsequence,
+//! so `fom tiles` finds `art/tiles/fom1000.frm`. Scoring follows skim's V2 algorithm:
+//! the best alignment of a term rewards consecutive characters and characters at the start
+//! of a path segment or a word, and penalizes gaps between them.
+
+use crate::FoRegistry;
+
+const MATCH: i64 = 16;
+const GAP_START: i64 = 3;
+const GAP_EXTENSION: i64 = 1;
+const BONUS_CONSECUTIVE: i64 = 8;
+/// Start of a path segment.
+const BONUS_SEGMENT: i64 = 10;
+/// Start of a word after `_`, `-`, `.` or a space.
+const BONUS_WORD: i64 = 6;
+/// Characters of the file name, the last segment, rank over the folders.
+const BONUS_FILE_NAME: i64 = 1;
+const NONE: i64 = i64::MIN / 2;
+
+/// Score of the best alignment of every term of `query` in `text`, higher is better,
+/// `None` if a term doesn't match. Both must already be in the same case.
+pub fn fuzzy_score(query: &str, text: &str) -> Option<i64> {
+    let text: Vec<char> = text.chars().collect();
+    let file_name = text.iter().rposition(|&c| c == '/').map_or(0, |slash| slash + 1);
+    let bonuses: Vec<i64> = (0..text.len())
+        .map(|index| {
+            let boundary = match index.checked_sub(1).map(|previous| text[previous]) {
+                None | Some('/') => BONUS_SEGMENT,
+                Some('_' | '-' | '.' | ' ') => BONUS_WORD,
+                Some(_) => 0,
+            };
+            boundary + if index >= file_name { BONUS_FILE_NAME } else { 0 }
+        })
+        .collect();
+    let mut terms = query.split_whitespace().peekable();
+    terms.peek()?;
+    terms.map(|term| term_score(term, &text, &bonuses)).sum()
+}
+
+fn term_score(term: &str, text: &[char], bonuses: &[i64]) -> Option<i64> {
+    let mut previous = vec![NONE; text.len()];
+    let mut current = vec![NONE; text.len()];
+    for (index, pattern) in term.chars().enumerate() {
+        // Best previous match followed by a gap of at least one character.
+        let mut gap = NONE;
+        for column in 0..text.len() {
+            if column >= 2 {
+                gap = (gap - GAP_EXTENSION).max(previous[column - 2] - GAP_START);
+            }
+            current[column] = if text[column] != pattern {
+                NONE
+            } else if index == 0 {
+                MATCH + bonuses[column]
+            } else {
+                let consecutive = match column {
+                    0 => NONE,
+                    column => previous[column - 1] + BONUS_CONSECUTIVE,
+                };
+                match consecutive.max(gap) {
+                    best if best <= NONE / 2 => NONE,
+                    best => best + MATCH + bonuses[column],
+                }
+            };
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous.into_iter().max().filter(|&score| score > NONE / 2)
+}
+
+/// Paths of the registry matching `query`, best first, then shorter and alphabetical.
+/// The query is normalized by the path rules of the registry.
+pub fn search<'a>(registry: &'a FoRegistry, query: &str, limit: usize) -> Vec<(&'a str, i64)> {
+    let query = registry.path_rules().normalize(query);
+    let mut found: Vec<_> = registry
+        .files()
+        .filter_map(|(path, _)| Some((path, fuzzy_score(&query, path)?)))
+        .collect();
+    found.sort_by(|(a_path, a), (b_path, b)| {
+        b.cmp(a)
+            .then_with(|| a_path.len().cmp(&b_path.len()))
+            .then_with(|| a_path.cmp(b_path))
+    });
+    found.truncate(limit);
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_paths() {
+        assert!(fuzzy_score("frm", "art/a.frm").is_some());
+        assert_eq!(fuzzy_score("mrf", "art/a.frm"), None);
+        assert_eq!(fuzzy_score(" ", "art/a.frm"), None);
+        // Consecutive characters and word starts beat scattered ones.
+        let tight = fuzzy_score("fom", "art/fom.frm").unwrap();
+        assert!(tight > fuzzy_score("fom", "art/f_o_m.frm").unwrap());
+        assert!(tight > fuzzy_score("fom", "art/xfom.frm").unwrap());
+
+        let mut registry = FoRegistry::stub();
+        for path in [
+            "art/tiles/fom1000.frm",
+            "art/tiles/edg1001.frm",
+            "art/critters/hmfom.frm",
+            "art/fonts/tiles_menu.fofrm",
+        ] {
+            let info = crate::FileInfo {
+                location: crate::FileLocation::Local(0),
+                original_path: path.to_owned(),
+                compressed_size: 0,
+                uncompressed_size: 0,
+                file_type: crate::retriever::recognize_type(path),
+            };
+            registry.insert_file(path.to_owned(), info);
+        }
+        let found: Vec<_> = search(&registry, "FOM Tiles", 10)
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(found, ["art/tiles/fom1000.frm", "art/fonts/tiles_menu.fofrm"]);
+        assert_eq!(search(&registry, "fom", 1)[0].0, "art/tiles/fom1000.frm");
+        assert!(search(&registry, "sound", 10).is_empty());
+    }
+
+    #[test]
+    fn empty_queries_and_paths() {
+        assert_eq!(fuzzy_score("", "art/a.frm"), None);
+        assert_eq!(fuzzy_score("a", ""), None);
+        assert_eq!(fuzzy_score("", ""), None);
+        assert_eq!(fuzzy_score("ab", "a"), None);
+        assert!(fuzzy_score("a", "a").is_some());
+        assert!(fuzzy_score("ёж", "art/ёжик.frm").is_some());
+
+        let registry = FoRegistry::stub();
+        assert!(search(&registry, "art", 10).is_empty());
+        assert!(search(&registry, "", 10).is_empty());
+    }
+}
@@ -0,0 +1,221 @@
+//! Long-lived conversion service: a fixed pool of workers fed through a bounded
+//! queue, so interactive tools don't spike memory with unbounded bursts.
+
+use std::{
+    sync::{mpsc, Arc},
+    thread::JoinHandle,
+};
+
+use parking_lot::Mutex;
+
+use crate::{
+    ConvertOptions, ConvertScratch, FileData, FoData, GetImageError, RetrieveError, Retriever,
+};
+
+type JobResult = Result<FileData, GetImageError>;
+
+struct Job {
+    path: String,
+    options: ConvertOptions,
+    result: mpsc::Sender<JobResult>,
+}
+
+/// Result of a submitted job.
+pub struct JobHandle {
+    result: mpsc::Receiver<JobResult>,
+}
+
+impl JobHandle {
+    /// Blocks until the job is done.
+    pub fn wait(self) -> JobResult {
+        self.result.recv().unwrap_or(Err(GetImageError::ServiceStopped))
+    }
+
+    /// `None` while the job is queued or in progress.
+    pub fn try_get(&self) -> Option<JobResult> {
+        match self.result.try_recv() {
+            Ok(result) => Some(result),
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(mpsc::TryRecvError::Disconnected) => Some(Err(GetImageError::ServiceStopped)),
+        }
+    }
+}
+
+/// Job wasn't accepted, the path is handed back.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubmitError {
+    /// Queue is full, retry later.
+    Full(String),
+    Stopped(String),
+}
+
+/// Dropping the service finishes queued jobs, then stops workers.
+pub struct ConvertService {
+    queue: Option<mpsc::SyncSender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ConvertService {
+    /// Starts `workers` threads, at most `queue_len` jobs wait for a free worker.
+    pub fn new<R>(data: Arc<FoData<R>>, workers: usize, queue_len: usize) -> Self
+    where
+        R: Retriever + Send + Sync + 'static,
+        R::Error: Into<RetrieveError>,
+    {
+        let (queue, jobs) = mpsc::sync_channel::<Job>(queue_len);
+        let jobs = Arc::new(Mutex::new(jobs));
+        let workers = (0..workers.max(1))
+            .map(|_| {
+                let data = Arc::clone(&data);
+                let jobs = Arc::clone(&jobs);
+                std::thread::spawn(move || {
+                    let converter = data.converter();
+                    let mut scratch = ConvertScratch::default();
+                    loop {
+                        // lock is released before conversion starts
+                        let job = match jobs.lock().recv() {
+                            Ok(job) => job,
+                            Err(_) => break,
+                        };
+                        let result =
+                            converter.get_with_scratch(&job.path, &job.options, &mut scratch);
+                        let _ = job.result.send(result);
+                    }
+                })
+            })
+            .collect();
+        Self {
+            queue: Some(queue),
+            workers,
+        }
+    }
+
+    fn job(path: String, options: ConvertOptions) -> (Job, JobHandle) {
+        let (sender, result) = mpsc::channel();
+        let job = Job {
+            path,
+            options,
+            result: sender,
+        };
+        (job, JobHandle { result })
+    }
+
+    /// Blocks while the queue is full.
+    pub fn submit(
+        &self,
+        path: impl Into<String>,
+        options: ConvertOptions,
+    ) -> Result<JobHandle, SubmitError> {
+        let (job, handle) = Self::job(path.into(), options);
+        let queue = self.queue.as_ref().expect("Queue is only taken on drop");
+        queue
+            .send(job)
+            .map_err(|mpsc::SendError(job)| SubmitError::Stopped(job.path))?;
+        Ok(handle)
+    }
+
+    /// Fails instead of blocking when the queue is full.
+    pub fn try_submit(
+        &self,
+        path: impl Into<String>,
+        options: ConvertOptions,
+    ) -> Result<JobHandle, SubmitError> {
+        let (job, handle) = Self::job(path.into(), options);
+        let queue = self.queue.as_ref().expect("Queue is only taken on drop");
+        queue.try_send(job).map_err(|err| match err {
+            mpsc::TrySendError::Full(job) => SubmitError::Full(job.path),
+            mpsc::TrySendError::Disconnected(job) => SubmitError::Stopped(job.path),
+        })?;
+        Ok(handle)
+    }
+}
+
+impl Drop for ConvertService {
+    fn drop(&mut self) {
+        self.queue.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataType, Palette};
+
+    /// Blocks reads until the gate is unlocked, reports every read that started.
+    struct Gate {
+        open: Mutex<()>,
+        entered: Mutex<mpsc::Sender<()>>,
+    }
+
+    impl Retriever for Gate {
+        type Error = std::io::Error;
+
+        fn file_by_path(&self, path: &str) -> Result<Vec<u8>, Self::Error> {
+            let _ = self.entered.lock().send(());
+            drop(self.open.lock());
+            if path != "art/a.png" {
+                return Err(std::io::ErrorKind::NotFound.into());
+            }
+            let image = image::RgbaImage::from_pixel(3, 2, image::Rgba([1, 2, 3, 255]));
+            let mut png = std::io::Cursor::new(Vec::new());
+            image::DynamicImage::ImageRgba8(image)
+                .write_to(&mut png, image::ImageFormat::Png)
+                .unwrap();
+            Ok(png.into_inner())
+        }
+    }
+
+    #[test]
+    fn bounded_queue() {
+        let (entered, reads) = mpsc::channel();
+        let retriever = Gate {
+            open: Mutex::new(()),
+            entered: Mutex::new(entered),
+        };
+        let data = Arc::new(FoData::with_retriever(retriever, Palette::default()));
+        let closed = data.retriever.open.lock();
+        let service = ConvertService::new(Arc::clone(&data), 1, 1);
+        let options = ConvertOptions::builder().output(DataType::Rgba).build();
+
+        let first = service.submit("art/a.png", options.clone()).unwrap();
+        // the only worker is busy with the first job, the second one stays in queue
+        reads.recv().unwrap();
+        let second = service.submit("art/missing.png", options.clone()).unwrap();
+        assert_eq!(
+            service.try_submit("art/a.png", options.clone()).err(),
+            Some(SubmitError::Full("art/a.png".into()))
+        );
+        assert!(first.try_get().is_none());
+
+        drop(closed);
+        drop(data);
+        assert_eq!(first.wait().unwrap().dimensions, (3, 2));
+        assert!(second.wait().is_err());
+        drop(service);
+    }
+
+    #[test]
+    fn no_workers_and_dropped_jobs() {
+        let (entered, _reads) = mpsc::channel();
+        let retriever = Gate {
+            open: Mutex::new(()),
+            entered: Mutex::new(entered),
+        };
+        let data = Arc::new(FoData::with_retriever(retriever, Palette::default()));
+        // at least one worker is started, an empty queue hands jobs over directly
+        let service = ConvertService::new(data, 0, 0);
+        let options = ConvertOptions::builder().output(DataType::Rgba).build();
+        let missing = service.submit("art/missing.png", options.clone()).unwrap();
+        assert!(matches!(missing.wait(), Err(GetImageError::Retrieve(_))));
+        let image = service.submit("art/a.png", options.clone()).unwrap().wait();
+        assert_eq!(image.unwrap().dimensions, (3, 2));
+
+        let (job, handle) = ConvertService::job("art/a.png".into(), options);
+        drop(job);
+        assert!(matches!(handle.try_get(), Some(Err(GetImageError::ServiceStopped))));
+        assert!(matches!(handle.wait(), Err(GetImageError::ServiceStopped)));
+    }
+}
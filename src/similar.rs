@@ -0,0 +1,203 @@
+//! Perceptual hashes of converted images, to find visually identical art saved under
+//! different names or formats, which byte comparison misses.
+//!
+//! The hash is a 64-bit dHash: the image is shrunk to 9x8 grayscale and every bit tells
+//! whether a pixel is brighter than its right neighbour. Similar images differ in few bits.
+
+use crate::{
+    ConvertOptions, Converter, FileType, FoRegistry, GetImageError, RetrieveError, Retriever,
+};
+
+/// dHash of the image, transparent pixels count as black.
+pub fn dhash(image: &image::RgbaImage) -> u64 {
+    const WIDTH: u32 = 9;
+    const HEIGHT: u32 = 8;
+    if image.width() == 0 || image.height() == 0 {
+        return 0;
+    }
+    let small = image::imageops::resize(
+        image,
+        WIDTH,
+        HEIGHT,
+        image::imageops::FilterType::Triangle,
+    );
+    let luma = |x, y| {
+        let [red, green, blue, alpha] = small.get_pixel(x, y).0;
+        let luma = 299 * red as u32 + 587 * green as u32 + 114 * blue as u32;
+        luma * alpha as u32
+    };
+    let mut hash = 0;
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH - 1 {
+            hash = hash << 1 | (luma(x, y) > luma(x + 1, y)) as u64;
+        }
+    }
+    hash
+}
+
+/// Number of differing bits, 0 for identical looking images and up to 64.
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+#[derive(Debug, Default)]
+pub struct ImageHashes {
+    /// Sorted by path.
+    hashes: Vec<(String, u64)>,
+    failed: Vec<(String, GetImageError)>,
+}
+
+impl ImageHashes {
+    /// Hashes the first frame of every png, gif, frm and fofrm of the registry.
+    pub fn build<R: Retriever>(
+        converter: &Converter<'_, '_, R>,
+        registry: &FoRegistry,
+        options: &ConvertOptions,
+    ) -> Self
+    where
+        R::Error: Into<RetrieveError>,
+    {
+        let mut paths: Vec<&str> = registry
+            .files()
+            .filter(|(_, info)| {
+                let file_type = info.file_type();
+                matches!(file_type, FileType::Png | FileType::Gif | FileType::Frm | FileType::FoFrm)
+            })
+            .map(|(path, _)| path)
+            .collect();
+        paths.sort_unstable();
+        let mut hashes = Self::default();
+        for path in paths {
+            match converter.get_rgba_with(path, options) {
+                Ok(raw) => hashes.hashes.push((path.to_owned(), dhash(&raw.image))),
+                Err(err) => hashes.failed.push((path.to_owned(), err)),
+            }
+        }
+        hashes
+    }
+
+    pub fn hash_of(&self, path: &str) -> Option<u64> {
+        let index = self
+            .hashes
+            .binary_search_by(|(probe, _)| probe.as_str().cmp(path))
+            .ok()?;
+        Some(self.hashes[index].1)
+    }
+
+    /// Images within `max_distance` of `hash`, closest first.
+    pub fn similar_to(&self, hash: u64, max_distance: u32) -> Vec<(&str, u32)> {
+        let mut similar: Vec<_> = self
+            .hashes
+            .iter()
+            .map(|(path, other)| (path.as_str(), distance(hash, *other)))
+            .filter(|(_, distance)| *distance <= max_distance)
+            .collect();
+        similar.sort_by_key(|&(path, distance)| (distance, path));
+        similar
+    }
+
+    /// Groups of two or more images linked by chains of hashes within `max_distance`,
+    /// sorted. Compares every pair, which is fine for tens of thousands of images.
+    pub fn groups(&self, max_distance: u32) -> Vec<Vec<&str>> {
+        let mut parents: Vec<usize> = (0..self.hashes.len()).collect();
+        fn root(parents: &mut [usize], mut index: usize) -> usize {
+            while parents[index] != index {
+                parents[index] = parents[parents[index]];
+                index = parents[index];
+            }
+            index
+        }
+        for (first, (_, a)) in self.hashes.iter().enumerate() {
+            for (second, (_, b)) in self.hashes.iter().enumerate().skip(first + 1) {
+                if distance(*a, *b) <= max_distance {
+                    let (a, b) = (root(&mut parents, first), root(&mut parents, second));
+                    parents[a.max(b)] = a.min(b);
+                }
+            }
+        }
+        let mut groups: std::collections::BTreeMap<usize, Vec<&str>> = Default::default();
+        for (index, (path, _)) in self.hashes.iter().enumerate() {
+            let root = root(&mut parents, index);
+            groups.entry(root).or_default().push(path);
+        }
+        groups.into_values().filter(|group| group.len() > 1).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// Images that couldn't be converted and have no hash.
+    pub fn failed(&self) -> &[(String, GetImageError)] {
+        &self.failed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{frm::FrameHeader, testing::FrmFixture};
+
+    #[test]
+    fn similar_images() {
+        let header = |size| FrameHeader {
+            width: size,
+            height: size,
+            offset_x: 0,
+            offset_y: 0,
+        };
+        // Four vertical stripes at any size.
+        let stripes = |size: u16| {
+            let pixel = move |x, _| [200, 20][(x * 4 / size % 2) as usize];
+            FrmFixture::new(0)
+                .direction((0, 0))
+                .frame_with(header(size), pixel)
+                .build()
+        };
+        let gradient = FrmFixture::new(0)
+            .direction((0, 0))
+            .frame_with(header(16), |x, y| (x * 16 + y) as u8)
+            .build();
+        let data = crate::testing::TempData::new(&[
+            ("art/stripes.frm", &stripes(16)),
+            ("art/copy/stripes_big.frm", &stripes(32)),
+            ("art/gradient.frm", &gradient),
+            ("art/broken.frm", b"frm"),
+        ]);
+        let retriever = data.retriever();
+        let palette = crate::testing::gradient_palette();
+        let converter = Converter::new(&retriever, &palette);
+        let options = ConvertOptions::default();
+        let hashes = ImageHashes::build(&converter, retriever.registry(), &options);
+        assert_eq!(hashes.len(), 3);
+        assert_eq!(hashes.failed().len(), 1);
+        assert_eq!(hashes.failed()[0].0, "art/broken.frm");
+
+        let stripes = hashes.hash_of("art/stripes.frm").unwrap();
+        assert_eq!(hashes.hash_of("art/copy/stripes_big.frm"), Some(stripes));
+        let similar = hashes.similar_to(stripes, 4);
+        assert_eq!(similar, [("art/copy/stripes_big.frm", 0), ("art/stripes.frm", 0)]);
+        assert_eq!(hashes.groups(4), [["art/copy/stripes_big.frm", "art/stripes.frm"]]);
+        assert!(distance(stripes, hashes.hash_of("art/gradient.frm").unwrap()) > 4);
+    }
+
+    #[test]
+    fn empty_images_and_hashes() {
+        assert_eq!(dhash(&image::RgbaImage::new(0, 5)), 0);
+        // transparent pixels hash the same as black ones, whatever their color
+        let transparent =
+            image::RgbaImage::from_fn(9, 8, |x, _| image::Rgba([x as u8 * 20, 0, 0, 0]));
+        assert_eq!(dhash(&transparent), 0);
+        assert_eq!(distance(0, u64::MAX), 64);
+
+        let hashes = ImageHashes::default();
+        assert!(hashes.is_empty());
+        assert_eq!(hashes.hash_of("art/a.frm"), None);
+        assert!(hashes.similar_to(0, 64).is_empty());
+        assert!(hashes.groups(64).is_empty());
+    }
+}
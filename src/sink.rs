@@ -0,0 +1,133 @@
+//! Reproducible zip output for tools that write archives of registry files.
+//!
+//! [`ZipSink`] collects files first and writes them sorted by conventional path, with the
+//! same timestamp and permissions for every entry, so the same files always give the same
+//! bytes regardless of the order they were produced in.
+
+use std::{
+    collections::BTreeMap,
+    io::{Seek, Write},
+    path::Path,
+};
+
+use zip::{result::ZipResult, write::FileOptions, CompressionMethod, DateTime, ZipWriter};
+
+#[derive(Debug, Clone)]
+pub struct ZipSink {
+    files: BTreeMap<String, Vec<u8>>,
+    compression: CompressionMethod,
+}
+
+impl Default for ZipSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ZipSink {
+    pub fn new() -> Self {
+        Self {
+            files: BTreeMap::new(),
+            compression: CompressionMethod::Deflated,
+        }
+    }
+
+    /// Deflate by default, `Stored` is faster for already compressed art.
+    pub fn with_compression(mut self, compression: CompressionMethod) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Adds or replaces a file, `path` must be conventional.
+    pub fn add(&mut self, path: String, data: Vec<u8>) -> Option<Vec<u8>> {
+        self.files.insert(path, data)
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Writes the zip and returns the writer, entries are sorted and dated 1980-01-01.
+    pub fn write<W: Write + Seek>(&self, writer: W) -> ZipResult<W> {
+        let mut zip = ZipWriter::new(writer);
+        for (path, data) in &self.files {
+            let options = FileOptions::default()
+                .compression_method(self.compression)
+                .last_modified_time(DateTime::default())
+                .unix_permissions(0o644)
+                .large_file(data.len() as u64 >= u32::MAX as u64);
+            zip.start_file(path.as_str(), options)?;
+            zip.write_all(data)?;
+        }
+        zip.finish()
+    }
+
+    pub fn write_to_file(&self, path: &Path) -> ZipResult<()> {
+        let file = std::fs::File::create(path)?;
+        self.write(std::io::BufWriter::new(file))?.flush()?;
+        Ok(())
+    }
+}
+
+impl Extend<(String, Vec<u8>)> for ZipSink {
+    fn extend<T: IntoIterator<Item = (String, Vec<u8>)>>(&mut self, files: T) {
+        self.files.extend(files);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reproducible_zip() {
+        let files = [
+            ("text/game.msg", b"{1}{}{hi}".to_vec()),
+            ("art/a.frm", b"frm".repeat(100)),
+            ("art/b.png", Vec::new()),
+        ];
+        let mut forward = ZipSink::new();
+        forward.extend(files.iter().cloned().map(|(path, data)| (path.to_owned(), data)));
+        let mut backward = ZipSink::new();
+        for (path, data) in files.iter().rev() {
+            backward.add(path.to_string(), data.clone());
+        }
+        assert_eq!(backward.add("art/b.png".into(), Vec::new()), Some(Vec::new()));
+        assert_eq!(forward.len(), 3);
+        let zip = forward.write(std::io::Cursor::new(Vec::new())).unwrap().into_inner();
+        let again = backward.write(std::io::Cursor::new(Vec::new())).unwrap().into_inner();
+        assert_eq!(zip, again);
+
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(zip)).unwrap();
+        let names: Vec<_> = archive.file_names().collect();
+        assert_eq!(names.len(), 3);
+        let first = archive.by_index(0).unwrap();
+        assert_eq!(first.name(), "art/a.frm");
+        assert_eq!(first.last_modified().year(), 1980);
+        assert_eq!(first.compression(), CompressionMethod::Deflated);
+
+        let stored = ZipSink::new().with_compression(CompressionMethod::Stored);
+        assert!(stored.is_empty());
+        assert!(stored.write(std::io::Cursor::new(Vec::new())).is_ok());
+    }
+
+    #[test]
+    fn empty_zip_and_missing_folder() {
+        let zip = ZipSink::new().write(std::io::Cursor::new(Vec::new())).unwrap();
+        let archive = zip::ZipArchive::new(std::io::Cursor::new(zip.into_inner())).unwrap();
+        assert!(archive.is_empty());
+
+        let root = std::env::temp_dir().join("fo_data_test_sink_missing");
+        let _ = std::fs::remove_dir_all(&root);
+        let mut sink = ZipSink::new();
+        sink.add("art/a.frm".into(), b"frm".to_vec());
+        assert!(matches!(
+            sink.write_to_file(&root.join("out.zip")),
+            Err(zip::result::ZipError::Io(_))
+        ));
+    }
+}
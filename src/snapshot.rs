@@ -0,0 +1,130 @@
+//! Cheap copies of the registry state, so editor sessions can add and remove
+//! files and then revert without crawling archives again.
+
+use std::{
+    io::{Read, Write},
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ChangeTime, Dirs, FileInfo, FoArchive, FoRegistry, PathMap};
+
+/// Shares file maps with the registry until either of them is mutated.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistrySnapshot {
+    name: String,
+    changed: ChangeTime,
+    archives: Vec<FoArchive>,
+    files: Arc<PathMap<String, FileInfo>>,
+    dirs: Arc<Dirs>,
+    shadowed: Arc<PathMap<String, Vec<FileInfo>>>,
+}
+
+impl RegistrySnapshot {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn count_files(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn write_to(&self, writer: impl Write) -> bincode::Result<()> {
+        bincode::serialize_into(writer, self)
+    }
+
+    pub fn read_from(reader: impl Read) -> bincode::Result<Self> {
+        bincode::deserialize_from(reader)
+    }
+}
+
+impl FoRegistry {
+    pub fn snapshot(&self, name: impl Into<String>) -> RegistrySnapshot {
+        RegistrySnapshot {
+            name: name.into(),
+            changed: self.changed,
+            archives: self.archives.clone(),
+            files: Arc::clone(&self.files),
+            dirs: Arc::clone(&self.dirs),
+            shadowed: Arc::clone(&self.shadowed),
+        }
+    }
+
+    /// Returns registry to the state of the snapshot, passwords are kept.
+    pub fn restore(&mut self, snapshot: &RegistrySnapshot) {
+        self.changed = snapshot.changed;
+        self.archives = snapshot.archives.clone();
+        self.files = Arc::clone(&snapshot.files);
+        self.dirs = Arc::clone(&snapshot.dirs);
+        self.shadowed = Arc::clone(&snapshot.shadowed);
+        self.by_hash = Default::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FileLocation;
+
+    fn local_file(original_path: &str) -> FileInfo {
+        FileInfo {
+            location: FileLocation::Local(0),
+            original_path: original_path.to_owned(),
+            compressed_size: 0,
+            uncompressed_size: 0,
+            file_type: crate::retriever::recognize_type(original_path),
+        }
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        let mut registry = FoRegistry::stub();
+        registry.insert_file("art/a.frm".into(), local_file("art/A.FRM"));
+        let snapshot = registry.snapshot("clean");
+        assert!(Arc::ptr_eq(&registry.files, &snapshot.files));
+
+        registry.insert_file("art/items/b.frm".into(), local_file("art/items/b.frm"));
+        registry.remove_file("art/a.frm");
+        assert_eq!(registry.count_files(), 1);
+        assert!(registry.file_info("art/a.frm").is_none());
+        assert_eq!(
+            registry.ls_dir("art").unwrap().collect::<Vec<_>>(),
+            ["art/items"]
+        );
+        assert_eq!(snapshot.count_files(), 1);
+
+        let mut serialized = Vec::new();
+        snapshot.write_to(&mut serialized).unwrap();
+        let snapshot = RegistrySnapshot::read_from(&serialized[..]).unwrap();
+        assert_eq!(snapshot.name(), "clean");
+
+        registry.restore(&snapshot);
+        assert!(registry.file_info("art/a.frm").is_some());
+        assert!(registry.file_info("art/items/b.frm").is_none());
+        assert_eq!(
+            registry.ls_dir("art").unwrap().collect::<Vec<_>>(),
+            ["art/a.frm"]
+        );
+        assert!(registry.metadata("art/items").is_none());
+    }
+
+    #[test]
+    fn empty_and_truncated_snapshots() {
+        let mut registry = FoRegistry::stub();
+        let snapshot = registry.snapshot("");
+        let mut serialized = Vec::new();
+        snapshot.write_to(&mut serialized).unwrap();
+        let empty = RegistrySnapshot::read_from(&serialized[..]).unwrap();
+        assert_eq!((empty.name(), empty.count_files()), ("", 0));
+
+        registry.insert_file("art/a.frm".into(), local_file("art/a.frm"));
+        let mut serialized = Vec::new();
+        registry.snapshot("full").write_to(&mut serialized).unwrap();
+        for len in [0, 1, serialized.len() / 2, serialized.len() - 1] {
+            assert!(RegistrySnapshot::read_from(&serialized[..len]).is_err());
+        }
+        registry.restore(&empty);
+        assert_eq!(registry.count_files(), 0);
+    }
+}
@@ -0,0 +1,511 @@
+//! Helpers for regression tests without a game client, enabled by the `testing` feature:
+//! fixture files for a [`MemoryRetriever`](crate::MemoryRetriever), tiny registries of
+//! temporary data folders and comparison of converted images with golden pngs.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use thiserror::Error;
+
+use crate::{
+    frm::FrameHeader, palette::Color, DataType, FileData, FoArchive, FoRegistry, FoRetriever,
+    Palette,
+};
+
+/// Environment variable that makes [`assert_golden`] write goldens instead of comparing.
+pub const BLESS_VAR: &str = "FO_DATA_BLESS";
+
+/// Palette with a distinct color for every index: `(i, 255 - i, i / 2)`.
+pub fn gradient_palette() -> Palette {
+    let colors = (0..=255u8)
+        .map(|index| Color {
+            red: index,
+            green: 255 - index,
+            blue: index / 2,
+        })
+        .collect();
+    Palette { colors }
+}
+
+/// Version 4 FRM with a single direction of `frames`, every pixel is palette index `fill`.
+pub fn frm_fixture(fps: u16, shift: (i16, i16), frames: &[FrameHeader], fill: u8) -> Vec<u8> {
+    frames
+        .iter()
+        .fold(FrmFixture::new(fps).direction(shift), |frm, frame| {
+            frm.frame(*frame, fill)
+        })
+        .build()
+}
+
+/// Builder of valid version 4 FRMs, same input always gives the same bytes.
+/// Every direction must have the same number of frames.
+#[derive(Debug, Clone)]
+pub struct FrmFixture {
+    fps: u16,
+    action_frame: u16,
+    directions: Vec<FixtureDirection>,
+}
+
+#[derive(Debug, Clone)]
+struct FixtureDirection {
+    shift: (i16, i16),
+    frames: Vec<(FrameHeader, Vec<u8>)>,
+}
+
+impl FrmFixture {
+    pub fn new(fps: u16) -> Self {
+        Self {
+            fps,
+            action_frame: 0,
+            directions: Vec::new(),
+        }
+    }
+
+    pub fn action_frame(mut self, action_frame: u16) -> Self {
+        self.action_frame = action_frame;
+        self
+    }
+
+    /// Starts the next direction, up to 6.
+    pub fn direction(mut self, shift: (i16, i16)) -> Self {
+        assert!(self.directions.len() < 6, "FRM has at most 6 directions");
+        self.directions.push(FixtureDirection {
+            shift,
+            frames: Vec::new(),
+        });
+        self
+    }
+
+    /// Adds a frame of palette index `fill` to the last direction.
+    pub fn frame(self, header: FrameHeader, fill: u8) -> Self {
+        self.frame_with(header, |_, _| fill)
+    }
+
+    /// Adds a frame with palette index `pixel(x, y)` to the last direction.
+    pub fn frame_with(mut self, header: FrameHeader, pixel: impl Fn(u16, u16) -> u8) -> Self {
+        if self.directions.is_empty() {
+            self = self.direction((0, 0));
+        }
+        let pixels = (0..header.height)
+            .flat_map(|y| (0..header.width).map(move |x| (x, y)))
+            .map(|(x, y)| pixel(x, y))
+            .collect();
+        self.directions
+            .last_mut()
+            .unwrap()
+            .frames
+            .push((header, pixels));
+        self
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let first = self
+            .directions
+            .first()
+            .expect("FRM needs at least one direction");
+        let frames = first.frames.len();
+        assert!(
+            self.directions.iter().all(|dir| dir.frames.len() == frames),
+            "Every direction of FRM needs the same number of frames"
+        );
+        let direction_sizes: Vec<usize> = self
+            .directions
+            .iter()
+            .map(|dir| dir.frames.iter().map(|(_, pixels)| 12 + pixels.len()).sum())
+            .collect();
+
+        let mut frm = Vec::new();
+        frm.extend_from_slice(&4u32.to_be_bytes());
+        frm.extend_from_slice(&self.fps.to_be_bytes());
+        frm.extend_from_slice(&self.action_frame.to_be_bytes());
+        frm.extend_from_slice(&(frames as u16).to_be_bytes());
+        for axis in 0..2 {
+            for dir in 0..6 {
+                let shift = self.directions.get(dir).map_or((0, 0), |dir| dir.shift);
+                let shift = if axis == 0 { shift.0 } else { shift.1 };
+                frm.extend_from_slice(&shift.to_be_bytes());
+            }
+        }
+        let mut offset = 0;
+        for dir in 0..6 {
+            frm.extend_from_slice(&(offset as u32).to_be_bytes());
+            offset += direction_sizes.get(dir).copied().unwrap_or(0);
+        }
+        frm.extend_from_slice(&(offset as u32).to_be_bytes());
+        for (header, pixels) in self.directions.iter().flat_map(|dir| &dir.frames) {
+            frm.extend_from_slice(&header.width.to_be_bytes());
+            frm.extend_from_slice(&header.height.to_be_bytes());
+            frm.extend_from_slice(&(pixels.len() as u32).to_be_bytes());
+            frm.extend_from_slice(&header.offset_x.to_be_bytes());
+            frm.extend_from_slice(&header.offset_y.to_be_bytes());
+            frm.extend_from_slice(pixels);
+        }
+        frm
+    }
+}
+
+/// Builder of FOFRM texts, same input always gives the same text.
+#[derive(Debug, Clone, Default)]
+pub struct FoFrmFixture {
+    fps: Option<u16>,
+    offset: Option<(i16, i16)>,
+    directions: Vec<Vec<(String, (i16, i16))>>,
+}
+
+impl FoFrmFixture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn fps(mut self, fps: u16) -> Self {
+        self.fps = Some(fps);
+        self
+    }
+
+    pub fn offset(mut self, offset: (i16, i16)) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// Starts the next direction, up to 6.
+    pub fn direction(mut self) -> Self {
+        assert!(self.directions.len() < 6, "FOFRM has at most 6 directions");
+        self.directions.push(Vec::new());
+        self
+    }
+
+    /// Adds a frame referencing `path` to the last direction, `next` shifts the following frame.
+    pub fn frame(mut self, path: &str, next: (i16, i16)) -> Self {
+        if self.directions.is_empty() {
+            self = self.direction();
+        }
+        self.directions
+            .last_mut()
+            .unwrap()
+            .push((path.to_owned(), next));
+        self
+    }
+
+    pub fn build(&self) -> String {
+        use std::fmt::Write;
+
+        let mut text = String::new();
+        if let Some(fps) = self.fps {
+            writeln!(text, "fps={}", fps).unwrap();
+        }
+        if let Some((x, y)) = self.offset {
+            writeln!(text, "offs_x={}\noffs_y={}", x, y).unwrap();
+        }
+        for (dir, frames) in self.directions.iter().enumerate() {
+            writeln!(text, "[dir_{}]", dir).unwrap();
+            for (frame, (path, (x, y))) in frames.iter().enumerate() {
+                writeln!(text, "frm_{}={}", frame, path).unwrap();
+                writeln!(text, "next_x_{0}={1}\nnext_y_{0}={2}", frame, x, y).unwrap();
+            }
+        }
+        text
+    }
+}
+
+/// Png of a single color.
+pub fn png_fixture(width: u32, height: u32, rgba: [u8; 4]) -> Vec<u8> {
+    let image = image::RgbaImage::from_pixel(width, height, image::Rgba(rgba));
+    let mut png = Vec::new();
+    crate::converter::encode_png(&image, &mut png).expect("Encode png into memory");
+    png
+}
+
+/// Archive at `path`, a data folder or an archive file, with its files registered under
+/// `mount`.
+pub fn archive(path: impl Into<PathBuf>, mount: Option<&str>) -> FoArchive {
+    FoArchive {
+        changed: crate::ChangeTime::now(),
+        path: path.into(),
+        mount: mount.map(str::to_owned),
+        kind: Default::default(),
+    }
+}
+
+/// Registry of crawled `archives`, the last one wins.
+pub fn registry(archives: Vec<FoArchive>) -> FoRegistry {
+    let files = crate::crawler::gather_paths(&archives).expect("Crawl fixture archives");
+    FoRegistry {
+        archives,
+        files: Arc::new(files),
+        ..FoRegistry::stub()
+    }
+}
+
+/// Temporary folder with fixture files, unique for every instance and removed on drop.
+#[derive(Debug)]
+pub struct TempData {
+    root: PathBuf,
+}
+
+impl TempData {
+    /// Writes `files`, paths relative to the folder with their contents.
+    pub fn new(files: &[(&str, &[u8])]) -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "fo_data_test_{}_{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        );
+        let data = Self {
+            root: std::env::temp_dir().join(name),
+        };
+        let _ = std::fs::remove_dir_all(&data.root);
+        std::fs::create_dir_all(&data.root).expect("Create temporary folder");
+        for (path, contents) in files {
+            data.write(path, contents);
+        }
+        data
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Replaces the file, creating parent folders if needed.
+    pub fn write(&self, path: &str, contents: &[u8]) {
+        let path = self.root.join(path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).expect("Create fixture folder");
+        }
+        std::fs::write(path, contents).expect("Write fixture file");
+    }
+
+    /// Registry with the folder as its only data folder.
+    pub fn registry(&self) -> FoRegistry {
+        registry(vec![archive(&self.root, None)])
+    }
+
+    pub fn retriever(&self) -> FoRetriever {
+        self.registry().into_retriever()
+    }
+}
+
+impl Drop for TempData {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Allowed difference between a converted image and its golden.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tolerance {
+    /// Pixels whose channels differ by at most this much are equal.
+    pub channel: u8,
+    /// Number of pixels allowed to differ by more than `channel`.
+    pub pixels: usize,
+}
+
+impl Tolerance {
+    pub const EXACT: Tolerance = Tolerance {
+        channel: 0,
+        pixels: 0,
+    };
+}
+
+#[derive(Debug, Error)]
+pub enum GoldenError {
+    #[error("can't read golden {0:?}: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("can't write golden {0:?}: {1}")]
+    Write(PathBuf, std::io::Error),
+    #[error("can't decode image: {0}")]
+    Decode(image::ImageError),
+    #[error("dimensions {actual:?} differ from golden {expected:?}")]
+    Dimensions {
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+    #[error("{differing} pixels differ from golden, by up to {max_difference}")]
+    Pixels { differing: usize, max_difference: u8 },
+}
+
+/// Pixels of converted data, whatever its output type.
+pub fn decode(file: &FileData) -> Result<image::RgbaImage, GoldenError> {
+    let load = |format| {
+        image::load_from_memory_with_format(&file.data, format)
+            .map(|image| image.to_rgba8())
+            .map_err(GoldenError::Decode)
+    };
+    match file.data_type {
+        DataType::Png => load(image::ImageFormat::Png),
+        #[cfg(feature = "webp")]
+        DataType::WebP => load(image::ImageFormat::WebP),
+        // fails unless `image` is built with its `avif-decoder` feature
+        #[cfg(feature = "avif")]
+        DataType::Avif => load(image::ImageFormat::Avif),
+        DataType::Rgba => {
+            let (width, height) = file.dimensions;
+            image::RgbaImage::from_raw(width, height, file.data.to_vec()).ok_or_else(|| {
+                GoldenError::Decode(image::ImageError::Parameter(
+                    image::error::ParameterError::from_kind(
+                        image::error::ParameterErrorKind::DimensionMismatch,
+                    ),
+                ))
+            })
+        }
+    }
+}
+
+/// Compares `file` with the png at `golden`, or writes the golden if `bless` is set.
+pub fn compare_golden(
+    file: &FileData,
+    golden: impl AsRef<Path>,
+    tolerance: Tolerance,
+    bless: bool,
+) -> Result<(), GoldenError> {
+    let golden = golden.as_ref();
+    let actual = decode(file)?;
+    if bless {
+        let mut png = Vec::new();
+        crate::converter::encode_png(&actual, &mut png).map_err(GoldenError::Decode)?;
+        let write = || -> std::io::Result<()> {
+            if let Some(parent) = golden.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(golden, png)
+        };
+        return write().map_err(|err| GoldenError::Write(golden.to_owned(), err));
+    }
+
+    let data = std::fs::read(golden).map_err(|err| GoldenError::Read(golden.to_owned(), err))?;
+    let expected = image::load_from_memory_with_format(&data, image::ImageFormat::Png)
+        .map_err(GoldenError::Decode)?
+        .to_rgba8();
+    if expected.dimensions() != actual.dimensions() {
+        return Err(GoldenError::Dimensions {
+            expected: expected.dimensions(),
+            actual: actual.dimensions(),
+        });
+    }
+    let differences = expected.pixels().zip(actual.pixels()).map(|(expected, actual)| {
+        expected
+            .0
+            .iter()
+            .zip(&actual.0)
+            .map(|(expected, actual)| (*expected as i16 - *actual as i16).unsigned_abs() as u8)
+            .max()
+            .unwrap_or(0)
+    });
+    let (differing, max_difference) = differences
+        .filter(|difference| *difference > tolerance.channel)
+        .fold((0, 0), |(count, max), difference| (count + 1, max.max(difference)));
+    if differing > tolerance.pixels {
+        return Err(GoldenError::Pixels {
+            differing,
+            max_difference,
+        });
+    }
+    Ok(())
+}
+
+/// Panics if `file` doesn't match the png at `golden`.
+/// Goldens are written instead when [`BLESS_VAR`] is set, to record intended changes.
+pub fn assert_golden(file: &FileData, golden: impl AsRef<Path>, tolerance: Tolerance) {
+    let golden = golden.as_ref();
+    let bless = std::env::var_os(BLESS_VAR).is_some();
+    if let Err(err) = compare_golden(file, golden, tolerance, bless) {
+        panic!("Golden {:?}: {}", golden, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Converter, MemoryRetriever};
+
+    #[test]
+    fn fixtures_parse() {
+        let frame = |width, height| FrameHeader {
+            width,
+            height,
+            offset_x: width as i16,
+            offset_y: -(height as i16),
+        };
+        let fixture = FrmFixture::new(8)
+            .action_frame(1)
+            .direction((1, 2))
+            .frame_with(frame(3, 2), |x, y| (x + y * 3) as u8)
+            .frame(frame(1, 1), 7)
+            .direction((-3, 4))
+            .frame(frame(2, 2), 9)
+            .frame(frame(4, 1), 10);
+        let data = fixture.build();
+        assert_eq!(data, fixture.build());
+
+        let frm = crate::frm::frm(&data).unwrap();
+        assert_eq!((frm.fps, frm.action_frame, frm.directions.len()), (8, 1, 2));
+        assert_eq!((frm.directions[1].shift_x, frm.directions[1].shift_y), (-3, 4));
+        assert_eq!(frm.directions[0].frames[0].data, &[0, 1, 2, 3, 4, 5]);
+        assert_eq!(frm.directions[1].frames[1].header(), frame(4, 1));
+        let lazy = crate::frm::LazyFrm::parse(data.clone()).unwrap();
+        assert_eq!(lazy.get_frame(1, 0).unwrap().data, &[9; 4]);
+
+        let text = FoFrmFixture::new()
+            .fps(5)
+            .offset((-1, 2))
+            .frame("a.frm", (0, 0))
+            .frame("b.png", (3, -4))
+            .direction()
+            .frame("../c.frm", (1, 1))
+            .build();
+        let fofrm = crate::fofrm::parse_verbose(&text).unwrap();
+        assert_eq!((fofrm.fps, fofrm.offset_x, fofrm.offset_y), (Some(5), Some(-1), Some(2)));
+        assert_eq!(fofrm.directions.len(), 2);
+        assert_eq!(fofrm.directions[0].frames[1].frm, Some("b.png"));
+        assert_eq!(fofrm.directions[0].frames[1].next_y, Some(-4));
+        assert_eq!(fofrm.directions[1].frames[0].frm, Some("../c.frm"));
+    }
+
+    #[test]
+    fn golden_comparison() {
+        let frame = FrameHeader {
+            width: 3,
+            height: 2,
+            offset_x: 0,
+            offset_y: 0,
+        };
+        let retriever = MemoryRetriever::new()
+            .with_file("art/a.frm", frm_fixture(10, (0, 0), &[frame], 40))
+            .with_file("art/b.png", png_fixture(3, 2, [40, 215, 20, 255]));
+        let palette = gradient_palette();
+        let converter = Converter::new(&retriever, &palette);
+        let converted = converter.get_png("art/a.frm").unwrap();
+
+        let golden = std::env::temp_dir().join("fo_data_test_golden/a.png");
+        compare_golden(&converted, &golden, Tolerance::EXACT, true).unwrap();
+        compare_golden(&converted, &golden, Tolerance::EXACT, false).unwrap();
+        let png = converter.get_png("art/b.png").unwrap();
+        compare_golden(&png, &golden, Tolerance::EXACT, false).unwrap();
+
+        let mut pixels = decode(&converted).unwrap();
+        pixels.get_pixel_mut(1, 1).0[2] += 3;
+        let changed = FileData {
+            data: pixels.into_raw().into(),
+            data_type: DataType::Rgba,
+            ..converted
+        };
+        assert!(matches!(
+            compare_golden(&changed, &golden, Tolerance::EXACT, false),
+            Err(GoldenError::Pixels {
+                differing: 1,
+                max_difference: 3
+            })
+        ));
+        let tolerance = Tolerance {
+            channel: 3,
+            pixels: 0,
+        };
+        compare_golden(&changed, &golden, tolerance, false).unwrap();
+        std::fs::remove_dir_all(golden.parent().unwrap()).unwrap();
+    }
+}
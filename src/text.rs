@@ -0,0 +1,71 @@
+//! Decoding of legacy text files, configs of older clients are often in cp1251 or cp866.
+
+use encoding_rs::{Encoding, IBM866, UTF_8, WINDOWS_1251};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextEncoding {
+    Utf8,
+    Cp1251,
+    Cp866,
+    /// BOM if present, then UTF-8 if valid, then cp1251 or cp866 by byte statistics.
+    Auto,
+}
+
+impl TextEncoding {
+    fn encoding(self) -> Option<&'static Encoding> {
+        match self {
+            TextEncoding::Utf8 => Some(UTF_8),
+            TextEncoding::Cp1251 => Some(WINDOWS_1251),
+            TextEncoding::Cp866 => Some(IBM866),
+            TextEncoding::Auto => None,
+        }
+    }
+}
+
+/// Never returns `Auto`.
+pub fn detect_encoding(bytes: &[u8]) -> TextEncoding {
+    if bytes.starts_with(b"\xEF\xBB\xBF") || std::str::from_utf8(bytes).is_ok() {
+        return TextEncoding::Utf8;
+    }
+    // cp866 has lowercase letters in 0xA0..=0xAF and uppercase in 0x80..=0x9F, where cp1251
+    // has mostly rare punctuation; cp1251 letters in 0xC0..=0xDF and 0xF0..=0xFF are
+    // box drawing and rare symbols in cp866
+    let count = |range: std::ops::RangeInclusive<u8>| {
+        bytes.iter().filter(|byte| range.contains(byte)).count()
+    };
+    if count(0x80..=0xAF) > count(0xC0..=0xDF) + count(0xF0..=0xFF) {
+        TextEncoding::Cp866
+    } else {
+        TextEncoding::Cp1251
+    }
+}
+
+/// Invalid sequences are replaced, BOM is stripped.
+pub fn decode_text(bytes: &[u8], encoding: TextEncoding) -> String {
+    let encoding = match encoding {
+        TextEncoding::Auto => detect_encoding(bytes),
+        encoding => encoding,
+    };
+    let encoding = encoding.encoding().expect("Detected encoding is not auto");
+    let (text, _had_errors) = encoding.decode_with_bom_removal(bytes);
+    text.into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_and_decode() {
+        let text = "Привет, мир";
+        let (cp1251, _, _) = WINDOWS_1251.encode(text);
+        let (cp866, _, _) = IBM866.encode(text);
+
+        assert_eq!(detect_encoding(text.as_bytes()), TextEncoding::Utf8);
+        assert_eq!(detect_encoding(&cp1251), TextEncoding::Cp1251);
+        assert_eq!(detect_encoding(&cp866), TextEncoding::Cp866);
+        assert_eq!(decode_text(&cp1251, TextEncoding::Auto), text);
+        assert_eq!(decode_text(&cp866, TextEncoding::Auto), text);
+        assert_eq!(decode_text(b"\xEF\xBB\xBFkey=1", TextEncoding::Auto), "key=1");
+    }
+}
@@ -0,0 +1,128 @@
+//! Compositing of map tiles into a single image, for minimaps and map previews.
+
+use std::collections::HashMap;
+
+use crate::{
+    blend::Blender, ConvertOptions, Converter, GetImageError, RawImage, RetrieveError, Retriever,
+};
+
+/// How tile coordinates map to pixel positions of tile sprites.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TileGrid {
+    /// Coordinates are already pixel positions.
+    Screen,
+    /// Fallout square tile grid of 80x36 isometric tiles: +x moves tile by (48, -12) pixels,
+    /// +y by (32, 24).
+    Square,
+}
+
+impl TileGrid {
+    pub fn position(&self, (x, y): (i32, i32)) -> (i32, i32) {
+        match self {
+            TileGrid::Screen => (x, y),
+            TileGrid::Square => (48 * x + 32 * y, 24 * y - 12 * x),
+        }
+    }
+}
+
+/// Converts tiles and draws them in the iteration order onto a canvas that fits all of them.
+/// Offset of the result is the position of canvas top left corner in grid pixel coordinates.
+/// Each distinct tile path is converted once, tiles are blended in linear light.
+pub fn stitch<'a, R: Retriever>(
+    converter: &Converter<R>,
+    tiles: impl IntoIterator<Item = (&'a str, (i32, i32))>,
+    grid: TileGrid,
+    options: &ConvertOptions,
+) -> Result<RawImage, GetImageError>
+where
+    R::Error: Into<RetrieveError>,
+{
+    let mut images: HashMap<&str, RawImage> = HashMap::new();
+    let mut placed = Vec::new();
+    for (path, coords) in tiles {
+        if !images.contains_key(path) {
+            images.insert(path, converter.get_rgba_with(path, options)?);
+        }
+        let image = &images[path];
+        let (x, y) = grid.position(coords);
+        placed.push((path, x + image.offset_x as i32, y + image.offset_y as i32));
+    }
+
+    let bounds = placed.iter().fold(None, |bounds, &(path, x, y)| {
+        let (width, height) = images[path].image.dimensions();
+        let (right, bottom) = (x + width as i32, y + height as i32);
+        Some(match bounds {
+            None => (x, y, right, bottom),
+            Some((left, top, old_right, old_bottom)) => (
+                x.min(left),
+                y.min(top),
+                right.max(old_right),
+                bottom.max(old_bottom),
+            ),
+        })
+    });
+    let (left, top, right, bottom) = bounds.unwrap_or((0, 0, 0, 0));
+
+    let mut canvas = image::RgbaImage::new((right - left) as u32, (bottom - top) as u32);
+    let blender = Blender::default();
+    for (path, x, y) in placed {
+        let (x, y) = ((x - left) as i64, (y - top) as i64);
+        blender.overlay(&mut canvas, &images[path].image, x, y);
+    }
+    Ok(RawImage {
+        image: canvas,
+        offset_x: left as i16,
+        offset_y: top as i16,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn square_grid_positions() {
+        assert_eq!(TileGrid::Square.position((0, 0)), (0, 0));
+        assert_eq!(TileGrid::Square.position((1, 0)), (48, -12));
+        assert_eq!(TileGrid::Square.position((0, 1)), (32, 24));
+        assert_eq!(TileGrid::Screen.position((5, 7)), (5, 7));
+    }
+
+    struct OneTile;
+
+    impl Retriever for OneTile {
+        type Error = std::io::Error;
+
+        fn file_by_path(&self, path: &str) -> Result<Vec<u8>, Self::Error> {
+            if path != "art/tiles/tile.png" {
+                return Err(std::io::ErrorKind::NotFound.into());
+            }
+            let image = image::RgbaImage::from_pixel(2, 1, image::Rgba([9, 9, 9, 255]));
+            let mut png = std::io::Cursor::new(Vec::new());
+            image::DynamicImage::ImageRgba8(image)
+                .write_to(&mut png, image::ImageFormat::Png)
+                .unwrap();
+            Ok(png.into_inner())
+        }
+    }
+
+    #[test]
+    fn stitch_tiles_into_bounds() {
+        let palette = crate::Palette::default();
+        let converter = Converter::new(&OneTile, &palette);
+        let tiles = vec![
+            ("art/tiles/tile.png", (-1, 2)),
+            ("art/tiles/tile.png", (2, 0)),
+        ];
+        let options = ConvertOptions::default();
+        let stitched = stitch(&converter, tiles, TileGrid::Screen, &options).unwrap();
+        assert_eq!((stitched.offset_x, stitched.offset_y), (-1, 0));
+        assert_eq!(stitched.image.dimensions(), (5, 3));
+        assert_eq!(stitched.image.get_pixel(0, 2)[3], 255);
+        assert_eq!(stitched.image.get_pixel(4, 0)[3], 255);
+        assert_eq!(stitched.image.get_pixel(2, 1)[3], 0);
+
+        let missing = vec![("art/tiles/missing.png", (0, 0))];
+        assert!(stitch(&converter, missing, TileGrid::Square, &options).is_err());
+    }
+}
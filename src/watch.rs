@@ -0,0 +1,271 @@
+//! Watching archives of a registry and updating it in place, behind the `watch` feature.
+//!
+//! [`RegistryWatcher`] maps filesystem events to the archives they touch and crawls only
+//! those again with [`FoRegistry::refresh_archives`], instead of building the registry and
+//! its cache from scratch. Data folders are watched recursively, archive files and archive
+//! lists through their parent folders, so files replaced by editors are noticed too.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use thiserror::Error;
+
+use crate::{ArchiveKind, Changes, DataInitError, FoRegistry};
+
+#[derive(Debug, Error)]
+pub enum WatchError {
+    #[error("can't watch {0:?}: {1}")]
+    Watch(PathBuf, notify::Error),
+    #[error("filesystem watcher failed: {0}")]
+    Event(notify::Error),
+    #[error("can't update the registry: {0}")]
+    Refresh(DataInitError),
+    /// The registry has other archives than the watcher was created for.
+    #[error("registry has {registry} archives, {watched} are watched")]
+    ArchivesChanged { registry: usize, watched: usize },
+}
+
+/// What a batch of filesystem events did.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WatchEvent {
+    /// Files of changed archives were updated, same as [`FoRegistry::last_changes`].
+    Updated(Changes),
+    /// A watched archive list like `DataFiles.cfg` changed, the registry must be built
+    /// again to follow it. Other changes of the batch are not applied.
+    ArchiveListChanged(PathBuf),
+}
+
+struct WatchedArchive {
+    /// As the registry has it.
+    path: PathBuf,
+    /// As the watcher reports it.
+    canonical: PathBuf,
+}
+
+#[derive(Default)]
+struct Pending {
+    archives: Vec<usize>,
+    touched: Vec<PathBuf>,
+    archive_list: Option<PathBuf>,
+}
+
+pub struct RegistryWatcher {
+    watcher: RecommendedWatcher,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+    archives: Vec<WatchedArchive>,
+    archive_lists: Vec<PathBuf>,
+    watched_folders: Vec<PathBuf>,
+    settle: Duration,
+}
+
+impl RegistryWatcher {
+    /// Watches every archive of `registry`, they must exist.
+    pub fn new(registry: &FoRegistry) -> Result<Self, WatchError> {
+        let (sender, events) = mpsc::channel();
+        let watcher = notify::recommended_watcher(sender)
+            .map_err(|err| WatchError::Watch(PathBuf::new(), err))?;
+        let mut this = Self {
+            watcher,
+            events,
+            archives: Vec::with_capacity(registry.archives.len()),
+            archive_lists: Vec::new(),
+            watched_folders: Vec::new(),
+            settle: Duration::from_millis(50),
+        };
+        for archive in &registry.archives {
+            let canonical = archive.path.canonicalize().unwrap_or_else(|_| archive.path.clone());
+            if archive.kind() == ArchiveKind::Folder {
+                this.watch(&canonical, RecursiveMode::Recursive)?;
+            } else {
+                this.watch_parent(&canonical)?;
+            }
+            this.archives.push(WatchedArchive {
+                path: archive.path.clone(),
+                canonical,
+            });
+        }
+        Ok(this)
+    }
+
+    /// Also reports changes of an archive list, e.g. `DataFiles.cfg` of the client.
+    pub fn watch_archive_list(&mut self, path: impl AsRef<Path>) -> Result<(), WatchError> {
+        let path = path.as_ref();
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+        self.watch_parent(&canonical)?;
+        self.archive_lists.push(canonical);
+        Ok(())
+    }
+
+    /// Events arriving within `settle` of each other are applied together, 50 ms by default.
+    /// Editors and unpackers touch files several times in a row.
+    pub fn with_settle_time(mut self, settle: Duration) -> Self {
+        self.settle = settle;
+        self
+    }
+
+    /// Applies events received so far without blocking. `registry` must be the one the
+    /// watcher was created for, `None` means nothing in it changed.
+    pub fn poll(&mut self, registry: &mut FoRegistry) -> Result<Option<WatchEvent>, WatchError> {
+        let mut pending = Pending::default();
+        while let Ok(event) = self.events.try_recv() {
+            self.sort(event, &mut pending)?;
+        }
+        self.apply(registry, pending)
+    }
+
+    /// Same as [`RegistryWatcher::poll`], blocks up to `timeout` for the first event.
+    pub fn wait(
+        &mut self,
+        registry: &mut FoRegistry,
+        timeout: Duration,
+    ) -> Result<Option<WatchEvent>, WatchError> {
+        let deadline = Instant::now() + timeout;
+        let mut pending = Pending::default();
+        while pending.archives.is_empty() && pending.archive_list.is_none() {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.events.recv_timeout(left) {
+                Ok(event) => self.sort(event, &mut pending)?,
+                Err(_) => return Ok(None),
+            }
+        }
+        while let Ok(event) = self.events.recv_timeout(self.settle) {
+            self.sort(event, &mut pending)?;
+        }
+        self.apply(registry, pending)
+    }
+
+    fn watch(&mut self, path: &Path, mode: RecursiveMode) -> Result<(), WatchError> {
+        if self.watched_folders.iter().any(|folder| folder == path) {
+            return Ok(());
+        }
+        self.watcher
+            .watch(path, mode)
+            .map_err(|err| WatchError::Watch(path.to_owned(), err))?;
+        self.watched_folders.push(path.to_owned());
+        Ok(())
+    }
+
+    fn watch_parent(&mut self, path: &Path) -> Result<(), WatchError> {
+        let parent = path.parent().unwrap_or(path);
+        self.watch(parent, RecursiveMode::NonRecursive)
+    }
+
+    fn sort(
+        &self,
+        event: notify::Result<notify::Event>,
+        pending: &mut Pending,
+    ) -> Result<(), WatchError> {
+        let event = event.map_err(WatchError::Event)?;
+        // reads, crawling the registry again causes them too
+        if let EventKind::Access(_) = event.kind {
+            return Ok(());
+        }
+        for path in &event.paths {
+            if self.archive_lists.contains(path) {
+                pending.archive_list = Some(path.clone());
+            }
+            for (index, archive) in self.archives.iter().enumerate() {
+                let relative = match path.strip_prefix(&archive.canonical) {
+                    Ok(relative) => relative,
+                    Err(_) => continue,
+                };
+                pending.archives.push(index);
+                if !relative.as_os_str().is_empty() {
+                    pending.touched.push(archive.path.join(relative));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(
+        &self,
+        registry: &mut FoRegistry,
+        mut pending: Pending,
+    ) -> Result<Option<WatchEvent>, WatchError> {
+        if registry.archives.len() != self.archives.len() {
+            return Err(WatchError::ArchivesChanged {
+                registry: registry.archives.len(),
+                watched: self.archives.len(),
+            });
+        }
+        if let Some(archive_list) = pending.archive_list {
+            return Ok(Some(WatchEvent::ArchiveListChanged(archive_list)));
+        }
+        if pending.archives.is_empty() {
+            return Ok(None);
+        }
+        pending.archives.sort_unstable();
+        pending.archives.dedup();
+        let changes = registry
+            .refresh_archives(&pending.archives, &pending.touched)
+            .map_err(WatchError::Refresh)?;
+        Ok((!changes.is_empty()).then(|| WatchEvent::Updated(changes.clone())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempData};
+
+    #[test]
+    fn watch_data_folder() {
+        let data = TempData::new(&[("data/art/tile.frm", b"tile"), ("DataFiles.cfg", b"data\n")]);
+        let root = data.root();
+        let mut registry = testing::registry(vec![testing::archive(root.join("data"), None)]);
+        let mut watcher = RegistryWatcher::new(&registry).unwrap();
+        watcher.watch_archive_list(root.join("DataFiles.cfg")).unwrap();
+        assert_eq!(watcher.poll(&mut registry).unwrap(), None);
+
+        data.write("data/art/new.frm", b"new");
+        data.write("data/art/tile.frm", b"edit");
+        let timeout = Duration::from_secs(5);
+        let event = watcher.wait(&mut registry, timeout).unwrap();
+        let changes = match event {
+            Some(WatchEvent::Updated(changes)) => changes,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(changes.added, ["art/new.frm"]);
+        assert_eq!(changes.modified, ["art/tile.frm"]);
+        assert!(registry.file_info("art/new.frm").is_some());
+
+        data.write("DataFiles.cfg", b"data\nmod\n");
+        let event = watcher.wait(&mut registry, timeout).unwrap();
+        let expected = root.join("DataFiles.cfg").canonicalize().unwrap();
+        assert_eq!(event, Some(WatchEvent::ArchiveListChanged(expected)));
+
+        let archive = registry.archives[0].clone();
+        registry.archives.push(archive);
+        assert!(matches!(
+            watcher.poll(&mut registry),
+            Err(WatchError::ArchivesChanged {
+                registry: 2,
+                watched: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn missing_archive() {
+        let data = TempData::new(&[]);
+        let root = data.root().join("missing");
+        let registry = FoRegistry {
+            archives: vec![testing::archive(root.join("data"), None)],
+            ..FoRegistry::stub()
+        };
+        assert!(matches!(
+            RegistryWatcher::new(&registry),
+            Err(WatchError::Watch(path, _)) if path == root
+        ));
+
+        let mut empty = FoRegistry::stub();
+        let mut watcher = RegistryWatcher::new(&empty).unwrap();
+        assert_eq!(watcher.poll(&mut empty).unwrap(), None);
+        assert_eq!(watcher.wait(&mut empty, Duration::from_millis(10)).unwrap(), None);
+    }
+}
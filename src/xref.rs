@@ -0,0 +1,140 @@
+//! Cross-reference of client and server data: art referenced by one side that the other
+//! side doesn't have. Server protos and maps pointing to art missing on the client are
+//! the most common cause of invisible items and scenery in game.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{references, FoRetriever};
+
+/// Formats loaded by both client and server, art they reference must exist on both sides.
+/// Interface layouts and animations are client-only, so they're not cross-referenced.
+const SHARED_EXTENSIONS: &[&str] = &["fomap", "fopro", "msg"];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct XrefReport {
+    /// Art referenced by server data but missing on the client, with referencing server files.
+    pub missing_on_client: BTreeMap<String, Vec<String>>,
+    /// Art referenced by client data but missing on the server, with referencing client files.
+    pub missing_on_server: BTreeMap<String, Vec<String>>,
+    /// Referencing files that can't be read or parsed, with the reason. Sorted by path.
+    pub unparsable: Vec<(String, String)>,
+}
+
+impl XrefReport {
+    pub fn is_empty(&self) -> bool {
+        self.missing_on_client.is_empty()
+            && self.missing_on_server.is_empty()
+            && self.unparsable.is_empty()
+    }
+}
+
+fn is_shared(path: &str) -> bool {
+    let ext = path.rsplit_once('.').map_or("", |(_, ext)| ext);
+    SHARED_EXTENSIONS
+        .iter()
+        .any(|shared| shared.eq_ignore_ascii_case(ext))
+}
+
+/// Art referenced by shared files of `from` that `to` doesn't have.
+fn missing(
+    from: &FoRetriever,
+    to: &FoRetriever,
+    unparsable: &mut Vec<(String, String)>,
+) -> BTreeMap<String, Vec<String>> {
+    let target = to.registry();
+    let exists = |path: &str| target.file_info(path).is_some();
+    let mut missing: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+    for (path, info) in from.registry().files() {
+        if !is_shared(path) || !references::can_reference(path) {
+            continue;
+        }
+        let data = match from.file_by_info(info) {
+            Ok(data) => data,
+            Err(err) => {
+                unparsable.push((path.to_owned(), format!("can't read: {}", err)));
+                continue;
+            }
+        };
+        let text = crate::text::decode_text(&data, crate::TextEncoding::Auto);
+        match references::art_references_of(path, &text) {
+            Err(err) => unparsable.push((path.to_owned(), err)),
+            Ok(deps) => {
+                for dep in deps {
+                    if !references::exists_with_counterparts(&dep, exists) {
+                        missing.entry(dep).or_default().push(path.to_owned());
+                    }
+                }
+            }
+        }
+    }
+    for referencing in missing.values_mut() {
+        referencing.sort_unstable();
+    }
+    missing
+}
+
+/// Checks art references of maps, protos and texts of each registry against the other one.
+pub fn cross_reference(client: &FoRetriever, server: &FoRetriever) -> XrefReport {
+    let mut unparsable = Vec::new();
+    let missing_on_client = missing(server, client, &mut unparsable);
+    let missing_on_server = missing(client, server, &mut unparsable);
+    unparsable.sort();
+    XrefReport {
+        missing_on_client,
+        missing_on_server,
+        unparsable,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempData;
+
+    #[test]
+    fn client_and_server() {
+        let client = TempData::new(&[
+            ("art/items/armor.frm", b""),
+            ("art/items/flare.fr0", b""),
+            ("art/intrface/missing.fofrm", b"frm_0=nothing.png\n"),
+            ("maps/cached.fomap", b"[Header]\n[Tiles]\ntile 1 1 art/tiles/floor.frm\n"),
+        ]);
+        let server = TempData::new(&[
+            ("art/tiles/floor.frm", b""),
+            ("proto/items/armor.fopro", b"[Proto]\nPicMap=art\\items\\armor.frm\n"),
+            ("proto/items/flare.fopro", b"PicMap=art/items/flare.frm\nPicInv=inv.frm\n"),
+            ("proto/items/pipe.fopro", b"PicInv=inv.frm\n"),
+            ("text/broken.msg", b"{1}{}"),
+        ]);
+        let (client, server) = (client.retriever(), server.retriever());
+        let report = cross_reference(&client, &server);
+        let missing_on_client: Vec<_> = report
+            .missing_on_client
+            .iter()
+            .map(|(art, referencing)| (art.as_str(), referencing.len()))
+            .collect();
+        assert_eq!(missing_on_client, [("inv.frm", 2)]);
+        assert!(report.missing_on_server.is_empty());
+        assert_eq!(report.unparsable.len(), 1);
+        assert_eq!(report.unparsable[0].0, "text/broken.msg");
+        assert!(!report.is_empty());
+    }
+
+    #[test]
+    fn empty_and_unreadable() {
+        let empty = crate::FoRegistry::stub().into_retriever();
+        assert!(cross_reference(&empty, &empty).is_empty());
+
+        let data = TempData::new(&[("maps/gone.fomap", b"")]);
+        let client = data.retriever();
+        std::fs::remove_file(data.root().join("maps/gone.fomap")).unwrap();
+        let report = cross_reference(&client, &empty);
+        assert!(report.missing_on_server.is_empty());
+        assert_eq!(report.unparsable.len(), 1);
+        assert_eq!(report.unparsable[0].0, "maps/gone.fomap");
+        assert!(report.unparsable[0].1.starts_with("can't read: "));
+    }
+}